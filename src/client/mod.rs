@@ -1,36 +1,136 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
+use dashmap::DashMap;
 use futures::stream::{FuturesUnordered, StreamExt};
-use quinn::{Connection, Endpoint, VarInt};
-use tokio::sync::broadcast;
+use quinn::{Connecting, Connection, Endpoint, RecvStream, SendStream, VarInt};
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch};
 use tokio::{signal, task};
 use tracing::{debug, error, info, info_span, warn, Instrument};
 
+use crate::common::datagram::{DatagramRouter, Datagrams};
+use crate::common::listener::{LocalListener, Uplink, UplinkWatch};
+use crate::common::proxy::{http_connect, ProxyConfig, RelayTarget};
 use crate::common::quic::{
-    client_server_name, create_client_endpoint, create_client_endpoint_via_proxy,
+    client_server_name, create_client_endpoint, create_client_endpoint_over_tcp,
+    create_client_endpoint_via_proxy, new_session_cache, rebind_client_endpoint, SessionCache,
 };
 use crate::common::remote::{
-    Direction, DynamicTarget, OpenConnResponse, RemoteKind, RemoteRequest, SessionHello,
+    Capability, ConnResume, Direction, DynamicTarget, HostPort, PeerInfo, Protocol, RemoteKind,
+    RemoteRequest, ResumeToken, SessionHello, StreamEnd, TunnelControl, TunnelControlResponse,
+    TunnelStatus,
 };
-use crate::common::socks::tunnel_socks_client;
-use crate::common::tcp::{tunnel_stdio_client, tunnel_tcp_client, tunnel_tcp_server};
-use crate::common::tunnel::{client_send_session_hello, receive_open_conn, reply_open_conn};
-use crate::common::udp::{tunnel_udp_client, tunnel_udp_server};
-use crate::{ClientConfig, ReconnectConfig};
+use crate::common::resumable::Reattach;
+use crate::common::routing::{RouteRules, RouteStats};
+use crate::common::socks::{tunnel_socks_bind_server, SocksUsers};
+use crate::common::tcp::{tunnel_stdio_client, tunnel_tcp_server, tunnel_unix_server};
+use crate::common::tunnel::{
+    accept_open_conn, client_send_session_hello, client_send_tunnel_control, receive_open_conn,
+    refuse_open_conn, OpenConnError,
+};
+use crate::common::udp::{accept_datagram_flow, tunnel_udp_server};
+use crate::common::utils::route_source;
+use crate::{ClientConfig, ReconnectConfig, TcpFallback};
+
+/// Counters a client keeps while it runs, readable through the
+/// [`ClientConfig::stats`] it was started with.
+#[derive(Debug, Default)]
+pub struct ClientStats {
+    migrations: AtomicU64,
+    /// The split-tunnel rules of the client running with these stats.
+    routes: Mutex<RouteRules>,
+}
+
+impl ClientStats {
+    /// How many times a network change moved the connection to a new
+    /// local socket instead of costing a reconnect.
+    pub fn migrations(&self) -> u64 {
+        self.migrations.load(Ordering::Relaxed)
+    }
 
+    /// Each split-tunnel rule's hits, and the bytes of the `direct`
+    /// conns it routed, in the order of [`ClientConfig::socks_rules`].
+    /// Conns that go around the tunnel never reach the server, so this
+    /// is the only place they're counted.
+    pub fn routes(&self) -> Vec<RouteStats> {
+        self.routes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .stats()
+    }
+}
+
+/// Connect to the server and run the configured tunnels until ^C.
 pub async fn run_async(config: ClientConfig) -> Result<()> {
+    let (_, updates) = watch::channel(config.remotes.clone());
+    run_async_with_updates(config, updates).await
+}
+
+/// [`run_async`], re-reading the remote set with `reload` on every
+/// SIGHUP (unix only) and applying the difference to the live session.
+/// A failed reload is logged and leaves the tunnels as they were.
+pub async fn run_async_with_reload<F>(config: ClientConfig, reload: F) -> Result<()>
+where
+    F: Fn() -> Result<Vec<RemoteRequest>> + Send + 'static,
+{
+    let (tx, updates) = watch::channel(config.remotes.clone());
+    #[cfg(unix)]
+    {
+        // Install the handler before connecting: SIGHUP's default
+        // disposition terminates the process.
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match reload() {
+                    Ok(remotes) => {
+                        info!(count = remotes.len(), "reloaded remotes");
+                        tx.send_replace(remotes);
+                    }
+                    Err(e) => warn!(error = %e, "reload failed, keeping current tunnels"),
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (tx, reload);
+    }
+    run_async_with_updates(config, updates).await
+}
+
+/// [`run_async`], following `updates` for the set of remotes to run.
+/// Each new value is diffed against the tunnels the session has and
+/// applied with `AddTunnel` / `RemoveTunnel` on the live connection,
+/// so unchanged tunnels and their in-flight conns are left alone.
+/// `config.remotes` is still what the first session hello declares.
+pub async fn run_async_with_updates(
+    config: ClientConfig,
+    updates: watch::Receiver<Vec<RemoteRequest>>,
+) -> Result<()> {
+    *config
+        .stats
+        .routes
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = config.socks_rules.clone();
+
+    // One TLS session-ticket store for the whole process, so every
+    // reconnect can resume and put its SessionHello in 0-RTT early data.
+    let sessions = new_session_cache();
+
     // Direct connections share QUIC endpoints across reconnects (one per
     // address family) so we don't pay the bind-syscall cost on every retry.
     // SOCKS5-proxied connections can't share — each retry requires a fresh
     // UDP ASSOCIATE — so the pool is built lazily per attempt instead.
     let mut endpoints = match &config.proxy {
-        None => Some(EndpointPool::new(&config)?),
+        None => Some(EndpointPool::new(&config, sessions.clone())?),
         Some(p) => {
-            info!(proxy = %p, "routing QUIC through SOCKS5 proxy");
+            info!(proxy = %p, "routing QUIC through proxy");
             None
         }
     };
@@ -48,7 +148,15 @@ pub async fn run_async(config: ClientConfig) -> Result<()> {
         }
     });
 
-    let result = run_with_reconnect(endpoints.as_mut(), &server_name, &config, &shutdown_tx).await;
+    let result = run_with_reconnect(
+        endpoints.as_mut(),
+        &sessions,
+        &server_name,
+        &config,
+        updates,
+        &shutdown_tx,
+    )
+    .await;
 
     if let Some(pool) = endpoints.as_ref() {
         pool.wait_idle().await;
@@ -65,19 +173,21 @@ pub async fn run_async(config: ClientConfig) -> Result<()> {
 /// `Endpoint::client(...)` syscalls.
 struct EndpointPool<'a> {
     config: &'a ClientConfig,
+    sessions: SessionCache,
     v4: Option<Endpoint>,
     v6: Option<Endpoint>,
 }
 
 impl<'a> EndpointPool<'a> {
-    fn new(config: &'a ClientConfig) -> Result<Self> {
+    fn new(config: &'a ClientConfig, sessions: SessionCache) -> Result<Self> {
         // Validate the TLS config eagerly by building one endpoint up front.
         // Catches bad cert paths / parse errors at startup instead of after
         // the first reconnect cycle.
         let primary = config.server.primary();
-        let endpoint = create_client_endpoint(&config.tls, config.congestion, primary)?;
+        let endpoint = create_client_endpoint(&config.tls, config.congestion, primary, &sessions)?;
         let mut pool = Self {
             config,
+            sessions,
             v4: None,
             v6: None,
         };
@@ -89,6 +199,15 @@ impl<'a> EndpointPool<'a> {
         Ok(pool)
     }
 
+    /// The endpoint already built for `addr`'s address family, if any.
+    fn endpoint_for(&self, addr: SocketAddr) -> Option<Endpoint> {
+        if addr.is_ipv6() {
+            self.v6.clone()
+        } else {
+            self.v4.clone()
+        }
+    }
+
     /// Get (or lazily build) the endpoint matching `addr`'s address family.
     fn get_for(&mut self, addr: SocketAddr) -> Result<&Endpoint> {
         let slot = if addr.is_ipv6() {
@@ -101,6 +220,7 @@ impl<'a> EndpointPool<'a> {
                 &self.config.tls,
                 self.config.congestion,
                 addr,
+                &self.sessions,
             )?);
         }
         Ok(slot.as_ref().expect("endpoint just inserted"))
@@ -123,13 +243,50 @@ impl<'a> EndpointPool<'a> {
 /// when the first attempt is just a few RTTs slow.
 const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
 
+/// How long a connect attempt waits on QUIC over UDP before also trying
+/// the TCP fallback transport. Well past a normal handshake, well short
+/// of the idle timeout that would otherwise end a UDP attempt into a
+/// network that drops it.
+const TCP_FALLBACK_DELAY: Duration = Duration::from_secs(3);
+
+/// How often a running connection checks which local address the OS
+/// would reach the server from; see [`follow_network_changes`].
+const PATH_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a conn accepted on a forward listener while the client is
+/// reconnecting waits for the next session before it's dropped; also
+/// how long a resumable conn waits for one to carry on over.
+const FORWARD_HOLD: Duration = Duration::from_secs(30);
+
 /// Outer loop: connect, run a connection until it dies, then reconnect with
 /// exponential backoff. Returns once shutdown is signalled, the connection
 /// completes cleanly, or `max_retries` is exhausted.
+///
+/// Each reconnect declares the remotes the previous session ended up
+/// with, so a tunnel the server refused isn't declared again (with
+/// `strict_tunnels` it would fail the whole hello); the session then
+/// catches up with `updates` itself.
+///
+/// Each hello also carries the previous session's resume token, so a
+/// server still holding that session hands its reverse listeners over
+/// instead of binding them afresh.
+///
+/// Forward listeners outlive their session: between sessions they stay
+/// bound and hold what they accept for up to [`FORWARD_HOLD`], and the
+/// next session picks them up again. They close when this returns.
+///
+/// With `resumable_conns`, TCP conns outlive their session too: they
+/// follow the connection each session publishes to `connections`.
+///
+/// A direct connection isn't given up when the client's network
+/// changes: it's migrated to a new local socket (see
+/// [`follow_network_changes`]) and the session carries on.
 async fn run_with_reconnect(
     endpoints: Option<&mut EndpointPool<'_>>,
+    sessions: &SessionCache,
     server_name: &str,
     config: &ClientConfig,
+    mut updates: watch::Receiver<Vec<RemoteRequest>>,
     shutdown_tx: &broadcast::Sender<()>,
 ) -> Result<()> {
     let ReconnectConfig {
@@ -141,9 +298,24 @@ async fn run_with_reconnect(
     let mut backoff = initial_backoff;
     let mut attempt: u32 = 0;
     let mut endpoints = endpoints;
+    let mut remotes = config.remotes.clone();
+    let mut parked = ParkedTunnels::default();
+    let mut resume = None;
+    let (connections, _) = watch::channel(None);
+    let peer = if config.resumable_conns {
+        PeerInfo::local()
+    } else {
+        PeerInfo::local().without(Capability::ResumableConns)
+    };
 
     loop {
         let mut shutdown_rx = shutdown_tx.subscribe();
+        let hello = SessionHello {
+            strict: config.strict_tunnels,
+            resume,
+            peer: peer.clone(),
+            ..SessionHello::new(remotes.clone())
+        };
 
         info!(server = %config.server, sni = %server_name, "connecting");
 
@@ -156,28 +328,65 @@ async fn run_with_reconnect(
         let connect_outcome = tokio::select! {
             res = async {
                 if let Some(proxy) = &config.proxy {
-                    proxied_connect(proxy, config, server_name).await
+                    proxied_connect(proxy, config, sessions, server_name, &hello).await
                 } else {
                     let pool = endpoints
                         .as_deref_mut()
                         .expect("endpoint pool is built when no proxy is configured");
-                    happy_eyeballs_connect(pool, &config.server.addrs, server_name).await
+                    match &config.tcp_fallback {
+                        None => {
+                            happy_eyeballs_connect(pool, &config.server.addrs, server_name, &hello)
+                                .await
+                        }
+                        Some(fallback) => {
+                            connect_with_fallback(pool, fallback, config, sessions, server_name, &hello)
+                                .await
+                        }
+                    }
                 }
             } => Some(res),
             _ = shutdown_rx.recv() => return Ok(()),
         };
 
         match connect_outcome {
-            Some(Ok(connection)) => {
-                let peer = connection.remote_address();
+            Some(Ok(session)) => {
+                let peer = session.connection.remote_address();
                 info!(peer = %peer, "connected");
                 attempt = 0;
                 backoff = initial_backoff;
+                resume = session.resume_token;
 
                 let session_span = info_span!("session", peer = %peer);
-                let outcome = run_connection(connection, config, shutdown_tx)
-                    .instrument(session_span)
-                    .await;
+                // A proxied connection's path is the proxy's to keep, and
+                // one over TCP moves with its TCP connection or not at all.
+                let migration = endpoints
+                    .as_deref()
+                    .filter(|_| !session.over_tcp)
+                    .and_then(|pool| pool.endpoint_for(peer))
+                    .map(|endpoint| {
+                        let follow = follow_network_changes(
+                            endpoint,
+                            peer,
+                            config.stats.clone(),
+                            route_source,
+                        );
+                        tokio::spawn(follow.instrument(session_span.clone()))
+                    });
+                let outcome = run_connection(
+                    session,
+                    &mut remotes,
+                    &mut parked,
+                    &mut updates,
+                    &connections,
+                    &config.socks_users,
+                    &config.socks_rules,
+                    shutdown_tx,
+                )
+                .instrument(session_span)
+                .await;
+                if let Some(migration) = migration {
+                    migration.abort();
+                }
                 match outcome {
                     SessionOutcome::Shutdown => return Ok(()),
                     SessionOutcome::Disconnected(reason) => {
@@ -218,9 +427,73 @@ async fn run_with_reconnect(
     }
 }
 
+/// The local end of the path to the server, as last probed.
+struct PathWatch {
+    source: Option<IpAddr>,
+}
+
+impl PathWatch {
+    /// Record the latest probe. Returns the previous source when the
+    /// connection should move: the route now leaves from a different
+    /// address, or is back after a spell without one (sends were
+    /// failing, and a NAT on the way may have dropped our mapping).
+    fn observe(&mut self, source: Option<IpAddr>) -> Option<Option<IpAddr>> {
+        if source == self.source {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.source, source);
+        match source {
+            Some(_) => Some(previous),
+            None => {
+                debug!("no route to server");
+                None
+            }
+        }
+    }
+}
+
+/// While a connection to `server` on `endpoint` runs, watch for the
+/// client's network changing under it and migrate the connection
+/// instead of letting it time out: the endpoint is rebound to a fresh
+/// socket, and QUIC carries the connection, its tunnels and their conns
+/// over to the new path. `route` is [`route_source`] outside of tests.
+async fn follow_network_changes(
+    endpoint: Endpoint,
+    server: SocketAddr,
+    stats: Arc<ClientStats>,
+    route: impl Fn(SocketAddr) -> Option<IpAddr>,
+) {
+    let mut path = PathWatch {
+        source: route(server),
+    };
+    let mut probe = tokio::time::interval(PATH_PROBE_INTERVAL);
+    probe.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        probe.tick().await;
+        let Some(previous) = path.observe(route(server)) else {
+            continue;
+        };
+        match rebind_client_endpoint(&endpoint) {
+            Ok(local) => {
+                let migrations = stats.migrations.fetch_add(1, Ordering::Relaxed) + 1;
+                info!(
+                    from = ?previous,
+                    to = ?path.source,
+                    local = %local,
+                    migrations,
+                    "network changed, migrated connection"
+                );
+            }
+            Err(e) => warn!(error = %e, "network changed, rebinding failed"),
+        }
+    }
+}
+
 /// RFC 8305 Happy Eyeballs v2 connect: launch one connect attempt per
 /// resolved address, staggered by [`HAPPY_EYEBALLS_DELAY`], and return the
-/// first one that succeeds. The remaining in-flight attempts get cancelled
+/// first one that gets through [`establish_session`] (so an attempt only
+/// "wins" once the server has answered the session hello — with 0-RTT the
+/// QUIC connection exists before anything has reached the peer). The remaining in-flight attempts get cancelled
 /// when the [`FuturesUnordered`] is dropped.
 ///
 /// We deliberately do *not* short-circuit when `addrs.len() == 1` so the
@@ -230,7 +503,8 @@ async fn happy_eyeballs_connect(
     endpoints: &mut EndpointPool<'_>,
     addrs: &[SocketAddr],
     server_name: &str,
-) -> Result<Connection> {
+    hello: &SessionHello,
+) -> Result<Session> {
     if addrs.is_empty() {
         return Err(anyhow!("no candidate addresses to connect to"));
     }
@@ -261,15 +535,15 @@ async fn happy_eyeballs_connect(
             if !stagger.is_zero() {
                 tokio::time::sleep(stagger).await;
             }
-            (addr, connecting.await)
+            (addr, establish_session(connecting, hello).await)
         });
     }
 
     while let Some((addr, res)) = races.next().await {
         match res {
-            Ok(conn) => {
+            Ok(session) => {
                 debug!(addr = %addr, "happy eyeballs winner");
-                return Ok(conn);
+                return Ok(session);
             }
             Err(e) => {
                 debug!(addr = %addr, error = %e, "happy eyeballs candidate failed");
@@ -283,28 +557,207 @@ async fn happy_eyeballs_connect(
     ))
 }
 
-/// SOCKS5-proxied connect: do a fresh UDP ASSOCIATE handshake against the
-/// proxy, build a one-shot QUIC endpoint that wraps every datagram in SOCKS5
-/// UDP framing, and connect to the (proxy-relayed) server. Happy Eyeballs is
-/// not applicable here — the proxy is responsible for routing to the
-/// destination, so we just use the first resolved address as the SOCKS5
-/// `DST.ADDR` / `DST.PORT`.
+/// Connect with the TCP fallback transport configured: over UDP with
+/// [`happy_eyeballs_connect`], racing TCP against it once UDP has had
+/// [`TCP_FALLBACK_DELAY`] to get through, or straight over TCP with
+/// `only`. Whichever establishes a session first is used.
+async fn connect_with_fallback(
+    endpoints: &mut EndpointPool<'_>,
+    fallback: &TcpFallback,
+    config: &ClientConfig,
+    sessions: &SessionCache,
+    server_name: &str,
+    hello: &SessionHello,
+) -> Result<Session> {
+    if fallback.only {
+        return tcp_connect(fallback.port, None, config, sessions, server_name, hello).await;
+    }
+    let quic = happy_eyeballs_connect(endpoints, &config.server.addrs, server_name, hello);
+    let tcp = async {
+        tokio::time::sleep(TCP_FALLBACK_DELAY).await;
+        debug!("no answer over UDP yet, trying TCP fallback");
+        tcp_connect(fallback.port, None, config, sessions, server_name, hello).await
+    };
+    tokio::pin!(quic, tcp);
+    tokio::select! {
+        res = &mut quic => match res {
+            Ok(session) => Ok(session),
+            Err(e) => tcp
+                .await
+                .map_err(|t| anyhow!("{e}; TCP fallback: {t}")),
+        },
+        res = &mut tcp => match res {
+            Ok(session) => {
+                info!("UDP got no answer, connected over TCP fallback");
+                Ok(session)
+            }
+            Err(t) => quic
+                .await
+                .map_err(|e| anyhow!("{e}; TCP fallback: {t}")),
+        },
+    }
+}
+
+/// Connect over the TCP fallback transport: QUIC carried over TLS to
+/// `port` on the server's first resolved address, or through the HTTP
+/// `proxy` to `port` on the server's host, which the proxy resolves (the
+/// client may not have, and then has no address for it). QUIC only needs
+/// some peer address over a tunnel; the proxy's stands in for the
+/// server's.
+async fn tcp_connect(
+    port: u16,
+    proxy: Option<&ProxyConfig>,
+    config: &ClientConfig,
+    sessions: &SessionCache,
+    server_name: &str,
+    hello: &SessionHello,
+) -> Result<Session> {
+    let (endpoint, server) = match proxy {
+        None => {
+            let server = SocketAddr::new(config.server.primary().ip(), port);
+            debug!(server = %server, "connecting over TCP fallback");
+            let tcp = TcpStream::connect(server).await?;
+            tcp.set_nodelay(true)?;
+            let local = tcp.local_addr()?;
+            let endpoint = create_client_endpoint_over_tcp(
+                &config.tls,
+                config.congestion,
+                tcp,
+                local,
+                server,
+                server_name,
+                sessions,
+            )
+            .await?;
+            (endpoint, server)
+        }
+        Some(proxy) => {
+            // Let the proxy resolve the name: it may be the only one
+            // that can.
+            let host = &config.server.host;
+            let target = if host.contains(':') {
+                format!("[{host}]:{port}")
+            } else {
+                format!("{host}:{port}")
+            };
+            debug!(target = %target, proxy = %proxy, "opening HTTP CONNECT tunnel for QUIC");
+            let (stream, local, peer) =
+                http_connect(proxy, config.proxy_ca.as_deref(), &target).await?;
+            let endpoint = create_client_endpoint_over_tcp(
+                &config.tls,
+                config.congestion,
+                stream,
+                local,
+                peer,
+                server_name,
+                sessions,
+            )
+            .await?;
+            (endpoint, peer)
+        }
+    };
+    let mut session = establish_session(endpoint.connect(server, server_name)?, hello).await?;
+    session.over_tcp = true;
+    Ok(session)
+}
+
+/// Proxied connect. Through SOCKS5 or MASQUE: do a fresh UDP ASSOCIATE
+/// or `CONNECT-UDP` handshake against the proxy, build a one-shot QUIC
+/// endpoint that wraps every datagram in the proxy's framing, and connect
+/// to the (proxy-relayed) server. Happy Eyeballs is not applicable here —
+/// the proxy is responsible for routing to the destination, so we just use
+/// the first resolved address as the tunnel's target (through `socks5h://`,
+/// the server's name, for the proxy to resolve). Through an HTTP
+/// proxy, which can't carry UDP: [`tcp_connect`] to the server's TCP
+/// fallback port (`tcp_fallback`'s, else the same number as its UDP
+/// port) over a `CONNECT` tunnel.
 async fn proxied_connect(
-    proxy: &crate::common::proxy::ProxyConfig,
+    proxy: &ProxyConfig,
     config: &ClientConfig,
+    sessions: &SessionCache,
     server_name: &str,
-) -> Result<Connection> {
-    let server = config
-        .server
-        .addrs
-        .first()
-        .copied()
-        .ok_or_else(|| anyhow!("no candidate addresses to connect to"))?;
-    debug!(server = %server, proxy = %proxy, "opening SOCKS5 UDP ASSOCIATE for QUIC");
-    let endpoint =
-        create_client_endpoint_via_proxy(&config.tls, config.congestion, server, proxy).await?;
-    let connection = endpoint.connect(server, server_name)?.await?;
-    Ok(connection)
+    hello: &SessionHello,
+) -> Result<Session> {
+    if !proxy.carries_udp() {
+        let port = config
+            .tcp_fallback
+            .map_or(config.server.port, |fallback| fallback.port);
+        return tcp_connect(port, Some(proxy), config, sessions, server_name, hello).await;
+    }
+    let target = if proxy.resolves_server_name() {
+        RelayTarget::Name(config.server.host.clone(), config.server.port)
+    } else {
+        let server = config
+            .server
+            .addrs
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("no candidate addresses to connect to"))?;
+        RelayTarget::Addr(server)
+    };
+    debug!(server = %target, proxy = %proxy, "opening proxy UDP relay for QUIC");
+    let (endpoint, peer) = create_client_endpoint_via_proxy(
+        &config.tls,
+        config.congestion,
+        target,
+        proxy,
+        config.proxy_ca.as_deref(),
+        sessions,
+    )
+    .await?;
+    establish_session(endpoint.connect(peer, server_name)?, hello).await
+}
+
+/// A connection whose session hello the server has accepted.
+struct Session {
+    connection: Connection,
+    /// The server's answer for each remote, in the order the hello
+    /// declared them.
+    tunnels: Vec<TunnelStatus>,
+    /// What the server said about itself in its hello response.
+    server: PeerInfo,
+    /// Capabilities both this hello and the server advertised.
+    capabilities: Vec<Capability>,
+    /// The hello bi-stream, kept open to carry [`TunnelControl`]s.
+    control: (SendStream, RecvStream),
+    /// Lets the next hello resume this session if its connection is
+    /// lost; `None` if the server won't hold it.
+    resume_token: Option<ResumeToken>,
+    /// Carried over the TCP fallback transport rather than UDP.
+    over_tcp: bool,
+}
+
+/// Finish the QUIC handshake and negotiate the tunnel set, returning the
+/// server's per-tunnel answers alongside the connection.
+///
+/// When the session cache holds a ticket for this server, the
+/// [`SessionHello`] goes out as 0-RTT early data in the same flight as
+/// the TLS ClientHello and the server answers it in its first flight, so
+/// a reconnect has its tunnels back within one round trip. Nothing is
+/// handed to the data plane until the handshake has completed; if the
+/// server rejected the early data (restarted, ticket expired, …) the
+/// hello is simply sent again over the now-established connection.
+async fn establish_session(connecting: Connecting, hello: &SessionHello) -> Result<Session> {
+    let (connection, accepted) = match connecting.into_0rtt() {
+        Ok(early) => early,
+        Err(connecting) => {
+            let connection = connecting.await?;
+            return send_session_hello(&connection, hello).await;
+        }
+    };
+
+    debug!("sending session hello as 0-RTT early data");
+    let early_hello = send_session_hello(&connection, hello).await;
+    if accepted.await {
+        debug!("0-RTT accepted");
+        early_hello
+    } else {
+        if let Some(reason) = connection.close_reason() {
+            return Err(reason.into());
+        }
+        debug!("0-RTT rejected, resending session hello");
+        send_session_hello(&connection, hello).await
+    }
 }
 
 /// Double the current backoff up to the cap. Returns the cap if `max_backoff`
@@ -320,177 +773,559 @@ enum SessionOutcome {
     Disconnected(String),
 }
 
-/// Run one connected client: send the session hello, spawn forward
-/// listeners (one task per `--remote`), spawn the reverse-conn accept
-/// loop, then wait for either the connection to die or shutdown.
+/// Run one connected client whose session hello has already been
+/// accepted (see [`establish_session`]): spawn forward listeners (one
+/// task per `--remote`), spawn the reverse-conn accept loop, then wait
+/// for either the connection to die or shutdown, applying changes to
+/// the remote set from `updates` in the meantime.
+///
+/// `remotes` holds what the hello declared on entry and, on return,
+/// the set the session ended up with. Forward listeners still bound
+/// from an earlier session are taken from `parked` and re-pointed at
+/// this one; on return the session's own go back into it. The
+/// connection is published to `connections` while it runs.
+#[allow(clippy::too_many_arguments)]
 async fn run_connection(
-    connection: Connection,
-    config: &ClientConfig,
+    session: Session,
+    remotes: &mut Vec<RemoteRequest>,
+    parked: &mut ParkedTunnels,
+    updates: &mut watch::Receiver<Vec<RemoteRequest>>,
+    connections: &watch::Sender<Option<Connection>>,
+    socks_users: &SocksUsers,
+    socks_rules: &RouteRules,
     shutdown_tx: &broadcast::Sender<()>,
 ) -> SessionOutcome {
-    // Negotiate the whole tunnel set in one shot. If the server
-    // rejects (policy violation, version mismatch, …) we surface that
-    // as a Disconnect — the reconnect loop will keep retrying.
-    let tunnel_ids = match send_session_hello(&connection, &config.remotes).await {
-        Ok(ids) => ids,
-        Err(e) => {
-            return SessionOutcome::Disconnected(format!("session hello failed: {e}"));
-        }
-    };
-    info!(count = tunnel_ids.len(), "session established");
-    for (remote, tunnel_id) in config.remotes.iter().zip(tunnel_ids.iter().copied()) {
-        let dir = if matches!(remote.direction, Direction::Reverse) {
-            "reverse"
-        } else {
-            "forward"
-        };
-        info!(tunnel_id, dir, spec = %remote, "tunnel registered");
-    }
-
-    // Map tunnel_id → declared remote so the reverse-accept loop can
-    // resolve OpenConn frames the server pushes back.
-    let remotes_by_id: Arc<HashMap<u64, RemoteRequest>> = Arc::new(
-        tunnel_ids
-            .iter()
-            .copied()
-            .zip(config.remotes.iter().cloned())
-            .collect(),
-    );
-
-    let mut tasks = Vec::new();
-
-    // Spawn a per-forward-remote listener task. Reverse remotes have
-    // nothing to bind on the client — their listener runs on the
-    // server, and conns flow back through the accept loop below.
-    for (remote, tunnel_id) in config.remotes.iter().zip(tunnel_ids.iter().copied()) {
-        if matches!(remote.direction, Direction::Reverse) {
-            continue;
-        }
-        let remote = remote.clone();
-        let connection_clone = connection.clone();
-        let span = info_span!("tunnel", tunnel_id, dir = "forward", spec = %remote);
-        // Stdio tunnels are single-shot: when stdin EOFs (or the
-        // remote end closes), the user expects the whole client to
-        // exit cleanly — not silently keep running with no input. We
-        // hand the stdio task a shutdown handle so it can fire that
-        // signal itself when it returns.
-        let shutdown_for_task = remote.is_stdio().then(|| shutdown_tx.clone());
+    let Session {
+        connection,
+        tunnels,
+        server,
+        capabilities,
+        mut control,
+        ..
+    } = session;
+    connections.send_replace(Some(connection.clone()));
+    let reattach = capabilities
+        .contains(&Capability::ResumableConns)
+        .then(|| Reattach::Dial {
+            current: connections.subscribe(),
+            hold: FORWARD_HOLD,
+        });
 
-        let task = task::spawn(
-            async move {
-                if let Err(e) = handle_forward_tunnel(connection_clone, remote, tunnel_id).await {
-                    error!(error = %e, "forward tunnel failed");
-                }
-                if let Some(tx) = shutdown_for_task {
-                    let _ = tx.send(());
-                }
-                anyhow::Ok(())
+    let ctx = SessionContext {
+        connection: connection.clone(),
+        // Demultiplexes inbound QUIC DATAGRAM frames to the UDP conns of
+        // this connection, in both directions.
+        datagrams: DatagramRouter::spawn(
+            connection.clone(),
+            capabilities.contains(&Capability::Datagrams),
+        ),
+        remotes_by_id: Arc::new(DashMap::new()),
+        capabilities,
+        reattach,
+        // A stdio tunnel owns stdout; its data mustn't be interleaved
+        // with announcements.
+        announce_bound: !remotes.iter().any(RemoteRequest::is_stdio),
+        socks_users: socks_users.clone(),
+        socks_rules: socks_rules.clone(),
+        shutdown_tx,
+    };
+    // Start what the server accepted; the rest are logged and left out
+    // of the session (a later reload or reconnect tries them again).
+    let mut active: Vec<ActiveTunnel> = Vec::with_capacity(tunnels.len());
+    for (remote, status) in remotes.iter().zip(tunnels) {
+        match status {
+            TunnelStatus::Accepted { tunnel_id, bound } => {
+                let tunnel = match parked.take(remote) {
+                    Some(tunnel) => ctx.resume_tunnel(tunnel, tunnel_id),
+                    None => ctx.start_tunnel(remote.clone(), tunnel_id, bound),
+                };
+                active.push(tunnel);
             }
-            .instrument(span),
-        );
-        tasks.push(task);
+            TunnelStatus::Rejected(reason) => {
+                warn!(spec = %remote, reason = %reason, "tunnel rejected by server");
+            }
+            TunnelStatus::BindFailed(reason) => {
+                warn!(spec = %remote, reason = %reason, "tunnel failed to bind on server");
+            }
+        }
+    }
+    // Whatever is left wasn't accepted this time round.
+    for tunnel in parked.0.drain(..) {
+        info!(spec = %tunnel.remote, "forward listener released");
+        tunnel.close();
     }
+    info!(
+        accepted = active.len(),
+        declared = remotes.len(),
+        server_version = %server.software_version,
+        protocol = server.protocol_version,
+        "session established"
+    );
 
     let connection_clone = connection.clone();
-    let remotes_for_accept = remotes_by_id.clone();
+    let remotes_for_accept = ctx.remotes_by_id.clone();
+    let datagrams_for_accept = ctx.datagrams.clone();
+    let reattach_for_accept = ctx.reattach.clone();
     let accept_reverse_task = tokio::spawn(async move {
         loop {
             let quic_connection = connection_clone.clone();
             let remotes = remotes_for_accept.clone();
-            if let Err(e) = client_accept_reverse_conn(quic_connection, remotes).await {
+            let datagrams = datagrams_for_accept.clone();
+            let reattach = reattach_for_accept.clone();
+            if let Err(e) =
+                client_accept_reverse_conn(quic_connection, remotes, datagrams, reattach).await
+            {
                 debug!(error = %e, "reverse-accept loop ended");
                 break;
             }
         }
-        anyhow::Ok(())
     });
-    tasks.push(accept_reverse_task);
+
+    // Catch up with anything that changed while we were connecting,
+    // then follow further changes until the session ends.
+    let mut following = true;
+    let desired = updates.borrow_and_update().clone();
+    if desired != *remotes {
+        following = follow_remote_changes(&ctx, &mut active, &desired, &mut control).await;
+    }
 
     let mut shutdown_rx = shutdown_tx.subscribe();
-    let outcome = tokio::select! {
-        _ = shutdown_rx.recv() => {
-            info!("disconnecting and notifying server");
-            // Close the QUIC connection with a non-zero application code and
-            // a human-readable reason. The server logs this verbatim, so the
-            // operator on the other end sees "client closed (code 130, client
-            // received ^C)" instead of waiting out the idle timeout.
-            connection.close(VarInt::from_u32(130), b"client received ^C");
-            // Give quinn a moment to actually flush the CONNECTION_CLOSE
-            // frame before we tear down the endpoint in the caller —
-            // otherwise the close races with `wait_idle` and the server
-            // sometimes only learns about the disconnect via the idle
-            // timeout (which is exactly what we're trying to avoid).
-            let _ = tokio::time::timeout(
-                Duration::from_millis(500),
-                connection.closed(),
-            )
-            .await;
-            SessionOutcome::Shutdown
-        }
-        reason = connection.closed() => {
-            SessionOutcome::Disconnected(reason.to_string())
+    let outcome = loop {
+        tokio::select! {
+            _ = shutdown_rx.recv() => {
+                info!("disconnecting and notifying server");
+                // Close the QUIC connection with a non-zero application code and
+                // a human-readable reason. The server logs this verbatim, so the
+                // operator on the other end sees "client closed (code 130, client
+                // received ^C)" instead of waiting out the idle timeout.
+                connection.close(VarInt::from_u32(130), b"client received ^C");
+                // Give quinn a moment to actually flush the CONNECTION_CLOSE
+                // frame before we tear down the endpoint in the caller —
+                // otherwise the close races with `wait_idle` and the server
+                // sometimes only learns about the disconnect via the idle
+                // timeout (which is exactly what we're trying to avoid).
+                let _ = tokio::time::timeout(
+                    Duration::from_millis(500),
+                    connection.closed(),
+                )
+                .await;
+                break SessionOutcome::Shutdown;
+            }
+            reason = connection.closed() => {
+                break SessionOutcome::Disconnected(reason.to_string());
+            }
+            changed = updates.changed(), if following => {
+                if changed.is_err() {
+                    // Nobody can send further changes.
+                    following = false;
+                    continue;
+                }
+                let desired = updates.borrow_and_update().clone();
+                following = follow_remote_changes(&ctx, &mut active, &desired, &mut control).await;
+            }
         }
     };
 
-    for handle in tasks {
-        handle.abort();
+    accept_reverse_task.abort();
+    connections.send_replace(None);
+    *remotes = if ctx.supports(Capability::TunnelControl) {
+        active.iter().map(|t| t.remote.clone()).collect()
+    } else {
+        // Nothing was applied live; the next hello declares the latest set.
+        updates.borrow().clone()
+    };
+    for tunnel in active {
+        match &tunnel.uplink {
+            Some(uplink) => {
+                // Conns accepted from here on wait for the next session.
+                uplink.send_replace(None);
+                parked.0.push(tunnel);
+            }
+            None => tunnel.close(),
+        }
     }
     outcome
 }
 
-/// Open the very first bi-stream of this QUIC connection and exchange
-/// the [`SessionHello`] / [`SessionHelloResponse`] pair. Returns the
-/// server-assigned `tunnel_id`s, in the same order as `remotes`.
-async fn send_session_hello(
-    quic_connection: &Connection,
-    remotes: &[RemoteRequest],
-) -> Result<Vec<u64>> {
-    let (mut send, mut recv) = quic_connection.open_bi().await?;
-    let hello = SessionHello {
-        remotes: remotes.to_vec(),
-    };
-    client_send_session_hello(&hello, &mut send, &mut recv).await
+/// What the tunnels of one connection share.
+struct SessionContext<'a> {
+    connection: Connection,
+    datagrams: Datagrams,
+    /// tunnel_id → declared remote, so the reverse-accept loop can
+    /// resolve OpenConn frames the server pushes back.
+    remotes_by_id: Arc<DashMap<u64, RemoteRequest>>,
+    /// Capabilities negotiated with the server in the hello.
+    capabilities: Vec<Capability>,
+    /// How TCP conns carry on after a reconnect; set when
+    /// `resumable-conns` was negotiated.
+    reattach: Option<Reattach>,
+    /// Whether to print a [`BoundAnnouncement`] to stdout for each
+    /// reverse listener the server binds.
+    announce_bound: bool,
+    /// Credentials forward `socks` listeners accept.
+    socks_users: SocksUsers,
+    /// Split-tunnel rules forward `socks` listeners route CONNECTs by.
+    socks_rules: RouteRules,
+    shutdown_tx: &'a broadcast::Sender<()>,
 }
 
-/// Drive the local listener for one *forward* tunnel. Each accepted
-/// local connection opens a fresh bi-stream and announces itself with
-/// an [`OpenConn`] keyed by `tunnel_id`; from there the existing
-/// `tunnel_*_client` helpers handle the data plane.
-async fn handle_forward_tunnel(
-    quic_connection: Connection,
+/// One line of the client's machine-readable stdout: the address the
+/// server bound a reverse tunnel's listener on. Lets scripts pick up
+/// the port the server chose for an `R:0:…` remote.
+#[derive(Serialize)]
+struct BoundAnnouncement {
+    event: &'static str,
+    tunnel_id: u64,
+    spec: String,
+    bound_addr: SocketAddr,
+}
+
+/// One tunnel the session currently runs.
+struct ActiveTunnel {
     remote: RemoteRequest,
     tunnel_id: u64,
+    /// Local listener task; `None` for reverse tunnels, whose listener
+    /// runs on the server.
+    listener: Option<task::JoinHandle<()>>,
+    /// Points a forward listener at the session its conns go out over;
+    /// `None` while reconnecting. Absent for reverse and stdio tunnels,
+    /// which don't outlive their session.
+    uplink: Option<watch::Sender<Option<Uplink>>>,
+}
+
+impl ActiveTunnel {
+    fn close(self) {
+        if let Some(listener) = self.listener {
+            listener.abort();
+        }
+    }
+}
+
+/// Forward tunnels kept bound between sessions, waiting to be picked up
+/// by the next one. Dropping this closes their listeners.
+#[derive(Default)]
+struct ParkedTunnels(Vec<ActiveTunnel>);
+
+impl ParkedTunnels {
+    fn take(&mut self, remote: &RemoteRequest) -> Option<ActiveTunnel> {
+        let index = self.0.iter().position(|t| t.remote == *remote)?;
+        Some(self.0.swap_remove(index))
+    }
+}
+
+impl Drop for ParkedTunnels {
+    fn drop(&mut self) {
+        for tunnel in self.0.drain(..) {
+            tunnel.close();
+        }
+    }
+}
+
+impl SessionContext<'_> {
+    fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Put a tunnel the server just assigned `tunnel_id` to work.
+    /// `bound` is where the server bound a reverse tunnel's listener.
+    fn start_tunnel(
+        &self,
+        remote: RemoteRequest,
+        tunnel_id: u64,
+        bound: Option<SocketAddr>,
+    ) -> ActiveTunnel {
+        let reverse = matches!(remote.direction, Direction::Reverse);
+        let dir = if reverse { "reverse" } else { "forward" };
+        info!(tunnel_id, dir, spec = %remote, "tunnel registered");
+        self.remotes_by_id.insert(tunnel_id, remote.clone());
+        if let Some(bound_addr) = bound {
+            info!(tunnel_id, spec = %remote, bound = %bound_addr, "server bound reverse tunnel");
+            if self.announce_bound {
+                let line = BoundAnnouncement {
+                    event: "tunnel_bound",
+                    tunnel_id,
+                    spec: remote.to_string(),
+                    bound_addr,
+                };
+                if let Ok(json) = serde_json::to_string(&line) {
+                    println!("{json}");
+                }
+            }
+        }
+
+        // Reverse remotes have nothing to bind on the client — their
+        // listener runs on the server, and conns flow back through the
+        // reverse-accept loop.
+        let (listener, uplink) = if reverse {
+            (None, None)
+        } else if remote.is_stdio() {
+            (
+                Some(self.spawn_stdio_tunnel(remote.clone(), tunnel_id)),
+                None,
+            )
+        } else {
+            let (uplink, current) = watch::channel(Some(self.uplink(tunnel_id)));
+            let listener = spawn_forward_listener(
+                remote.clone(),
+                current,
+                self.socks_users.clone(),
+                self.socks_rules.clone(),
+            );
+            (Some(listener), Some(uplink))
+        };
+        ActiveTunnel {
+            remote,
+            tunnel_id,
+            listener,
+            uplink,
+        }
+    }
+
+    /// Put a forward tunnel still bound from an earlier session back to
+    /// work under the `tunnel_id` this session's hello assigned it.
+    fn resume_tunnel(&self, mut tunnel: ActiveTunnel, tunnel_id: u64) -> ActiveTunnel {
+        info!(tunnel_id, dir = "forward", spec = %tunnel.remote, "tunnel resumed");
+        self.remotes_by_id.insert(tunnel_id, tunnel.remote.clone());
+        tunnel.tunnel_id = tunnel_id;
+        if let Some(uplink) = &tunnel.uplink {
+            uplink.send_replace(Some(self.uplink(tunnel_id)));
+            // A listener that never came up (say its port was taken)
+            // gets another try, as it would have before it was kept.
+            if tunnel.listener.as_ref().is_some_and(|l| l.is_finished()) {
+                tunnel.listener = Some(spawn_forward_listener(
+                    tunnel.remote.clone(),
+                    uplink.subscribe(),
+                    self.socks_users.clone(),
+                    self.socks_rules.clone(),
+                ));
+            }
+        }
+        tunnel
+    }
+
+    fn uplink(&self, tunnel_id: u64) -> Uplink {
+        Uplink {
+            connection: self.connection.clone(),
+            tunnel_id,
+            datagrams: self.datagrams.clone(),
+            optimistic: self.supports(Capability::OptimisticOpen),
+            resumable: self.reattach.clone(),
+            socks_bind: self.supports(Capability::SocksBind),
+            protocol: None,
+        }
+    }
+
+    /// Take a tunnel out of service. Conns it already accepted run on
+    /// their own tasks and are left to finish.
+    fn stop_tunnel(&self, tunnel: ActiveTunnel) {
+        self.remotes_by_id.remove(&tunnel.tunnel_id);
+        tunnel.close();
+    }
+
+    fn spawn_stdio_tunnel(&self, remote: RemoteRequest, tunnel_id: u64) -> task::JoinHandle<()> {
+        let connection = self.connection.clone();
+        let span = info_span!(
+            "tunnel",
+            tunnel_id,
+            name = remote.name.as_deref().unwrap_or("-"),
+            dir = "forward",
+            spec = %remote
+        );
+        // Stdio tunnels are single-shot: when stdin EOFs (or the
+        // remote end closes), the user expects the whole client to
+        // exit cleanly — not silently keep running with no input. The
+        // task fires that signal itself when it returns.
+        let shutdown_tx = self.shutdown_tx.clone();
+
+        task::spawn(
+            async move {
+                if let Err(e) = tunnel_stdio_client(connection, tunnel_id).await {
+                    error!(error = %e, "forward tunnel failed");
+                }
+                let _ = shutdown_tx.send(());
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Bind a forward tunnel's local listener and serve it for as long as
+/// the client runs, sending each conn over whichever session `current`
+/// names when it's accepted.
+fn spawn_forward_listener(
+    remote: RemoteRequest,
+    current: watch::Receiver<Option<Uplink>>,
+    socks_users: SocksUsers,
+    socks_rules: RouteRules,
+) -> task::JoinHandle<()> {
+    // The tunnel id changes with every session, so it goes on the
+    // per-conn spans rather than this one.
+    let span = info_span!(
+        "tunnel",
+        name = remote.name.as_deref().unwrap_or("-"),
+        dir = "forward",
+        spec = %remote
+    );
+    task::spawn(
+        async move {
+            let uplink = UplinkWatch::new(current, FORWARD_HOLD);
+            if let Err(e) = handle_forward_tunnel(remote, uplink, socks_users, socks_rules).await {
+                error!(error = %e, "forward tunnel failed");
+            }
+        }
+        .instrument(span),
+    )
+}
+
+/// Apply a new remote set to the session, if the server supports live
+/// changes. Returns whether to keep following updates: `false` once the
+/// control stream has failed, after which changes wait for a reconnect.
+async fn follow_remote_changes(
+    ctx: &SessionContext<'_>,
+    active: &mut Vec<ActiveTunnel>,
+    desired: &[RemoteRequest],
+    control: &mut (SendStream, RecvStream),
+) -> bool {
+    if !ctx.supports(Capability::TunnelControl) {
+        info!("server doesn't support live tunnel changes; they will apply on reconnect");
+        return true;
+    }
+    match apply_remote_changes(ctx, active, desired, control).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(error = %e, "control stream failed; tunnel changes will apply on reconnect");
+            false
+        }
+    }
+}
+
+/// Bring the session's tunnels in line with `desired` over the control
+/// stream: remove the ones no longer wanted, then add the new ones
+/// (removing first frees local ports a new declaration may reuse).
+/// Tunnels present on both sides are left untouched. A change the
+/// server refuses is logged and skipped; only a broken control stream
+/// is an error.
+async fn apply_remote_changes(
+    ctx: &SessionContext<'_>,
+    active: &mut Vec<ActiveTunnel>,
+    desired: &[RemoteRequest],
+    (send, recv): &mut (SendStream, RecvStream),
 ) -> Result<()> {
-    // Stdio short-circuits the listener-bind path: there is no local
-    // socket to accept on, just a single bi-stream wired to the
-    // process's stdin/stdout. The server-side dispatch is unchanged
-    // (it sees a normal Tcp/Udp tunnel via the OpenConn frame).
-    if remote.is_stdio() {
-        return tunnel_stdio_client(quic_connection, tunnel_id).await;
+    // Multiset diff: each desired remote can keep at most one running
+    // tunnel, so duplicate declarations are matched one-for-one.
+    let mut wanted: Vec<Option<&RemoteRequest>> = desired.iter().map(Some).collect();
+    let mut stale = Vec::new();
+    for tunnel in std::mem::take(active) {
+        match wanted.iter_mut().find(|w| **w == Some(&tunnel.remote)) {
+            Some(slot) => {
+                *slot = None;
+                active.push(tunnel);
+            }
+            None => stale.push(tunnel),
+        }
     }
-    match &remote.kind {
-        RemoteKind::Socks5 { .. } => {
-            tunnel_socks_client(quic_connection, remote, None, tunnel_id).await?
+
+    let mut stale = stale.into_iter();
+    while let Some(tunnel) = stale.next() {
+        let request = TunnelControl::RemoveTunnel {
+            tunnel_id: tunnel.tunnel_id,
+        };
+        match client_send_tunnel_control(&request, send, recv).await {
+            Ok(TunnelControlResponse::Removed) => {
+                info!(tunnel_id = tunnel.tunnel_id, spec = %tunnel.remote, "tunnel removed");
+            }
+            // The server doesn't know the id, so there's nothing left
+            // to keep locally either.
+            Ok(TunnelControlResponse::Failed(reason)) => {
+                warn!(tunnel_id = tunnel.tunnel_id, reason = %reason, "tunnel removal failed");
+            }
+            Ok(other) => {
+                active.push(tunnel);
+                active.extend(stale);
+                return Err(anyhow!("unexpected control response {other:?}"));
+            }
+            Err(e) => {
+                active.push(tunnel);
+                active.extend(stale);
+                return Err(e);
+            }
         }
-        RemoteKind::Tcp { .. } => {
-            tunnel_tcp_client(quic_connection, remote, None, tunnel_id).await?
+        ctx.stop_tunnel(tunnel);
+    }
+
+    for remote in wanted.into_iter().flatten() {
+        if remote.is_stdio() {
+            warn!(spec = %remote, "stdio remotes can't be added to a running client");
+            continue;
         }
-        RemoteKind::Udp { .. } => {
-            tunnel_udp_client(quic_connection, remote, None, tunnel_id).await?
+        let request = TunnelControl::AddTunnel(remote.clone().for_server());
+        match client_send_tunnel_control(&request, send, recv).await? {
+            TunnelControlResponse::Added { tunnel_id, bound } => {
+                active.push(ctx.start_tunnel(remote.clone(), tunnel_id, bound));
+            }
+            TunnelControlResponse::Failed(reason) => {
+                warn!(spec = %remote, reason = %reason, "tunnel add rejected");
+            }
+            other => return Err(anyhow!("unexpected control response {other:?}")),
         }
     }
     Ok(())
 }
 
+/// Open the hello bi-stream of this QUIC connection (the very first one,
+/// unless a 0-RTT attempt was rejected) and exchange the
+/// [`SessionHello`] / [`SessionHelloResponse`] pair. The [`Session`]
+/// carries the server's status for each tunnel, in the same order as
+/// `hello.remotes`, and the still-open stream for later
+/// [`TunnelControl`]s.
+async fn send_session_hello(quic_connection: &Connection, hello: &SessionHello) -> Result<Session> {
+    let (mut send, mut recv) = quic_connection.open_bi().await?;
+    // A rejected hello (version mismatch, or any tunnel failing a strict
+    // hello) fails the connect attempt; the reconnect loop keeps
+    // retrying with backoff.
+    let (tunnels, server, resume_token) = client_send_session_hello(hello, &mut send, &mut recv)
+        .await
+        .map_err(|e| anyhow!("session hello failed: {e}"))?;
+    Ok(Session {
+        connection: quic_connection.clone(),
+        tunnels,
+        capabilities: server.negotiate_with(&hello.peer),
+        server,
+        control: (send, recv),
+        resume_token,
+        over_tcp: false,
+    })
+}
+
+/// Drive the local listener for one *forward* tunnel. Each accepted
+/// local connection opens a fresh bi-stream on the current session and
+/// announces itself with an [`OpenConn`] keyed by that session's tunnel
+/// id; from there the existing `tunnel_*_client` helpers handle the data
+/// plane.
+async fn handle_forward_tunnel(
+    remote: RemoteRequest,
+    uplink: UplinkWatch,
+    socks_users: SocksUsers,
+    socks_rules: RouteRules,
+) -> Result<()> {
+    LocalListener::bind(&remote)
+        .await?
+        .serve(uplink, remote, None, socks_users, socks_rules)
+        .await
+}
+
 /// Accept loop for *server-pushed* reverse conns. The server opens a
 /// bi-stream and sends an [`OpenConn`] frame; we look up the parent
 /// tunnel in `remotes_by_id` to decide how to dispatch (TCP/UDP, with
 /// a possible dynamic target for `R:socks`).
 async fn client_accept_reverse_conn(
     quic_connection: Connection,
-    remotes_by_id: Arc<HashMap<u64, RemoteRequest>>,
+    remotes_by_id: Arc<DashMap<u64, RemoteRequest>>,
+    datagrams: Datagrams,
+    reattach: Option<Reattach>,
 ) -> Result<()> {
     let (mut send, mut recv) = quic_connection.accept_bi().await?;
+    let server_addr = quic_connection.remote_address();
 
     tokio::spawn(async move {
         let open = match receive_open_conn(&mut recv).await {
@@ -501,12 +1336,17 @@ async fn client_accept_reverse_conn(
             }
         };
 
-        let parent = match remotes_by_id.get(&open.tunnel_id) {
-            Some(p) => p.clone(),
+        let parent = remotes_by_id.get(&open.tunnel_id).map(|p| p.clone());
+        let parent = match parent {
+            Some(p) => p,
             None => {
-                let _ = reply_open_conn(
+                let reason = format!("unknown tunnel id {}", open.tunnel_id);
+                refuse_open_conn(
+                    &open,
                     &mut send,
-                    &OpenConnResponse::Failed(format!("unknown tunnel id {}", open.tunnel_id)),
+                    &mut recv,
+                    OpenConnError::UnknownTunnel,
+                    &reason,
                 )
                 .await;
                 error!(
@@ -521,28 +1361,88 @@ async fn client_accept_reverse_conn(
         // plane handlers expect: static reverse tunnels reuse the
         // tunnel's declared kind; reverse SOCKS5 takes the target
         // from the OpenConn `dynamic` field instead.
-        let dispatch = match resolve_reverse_dispatch(&parent, open.dynamic) {
+        let dispatch = match resolve_reverse_dispatch(&parent, open.dynamic.clone(), open.protocol)
+        {
             Ok(d) => d,
             Err(e) => {
-                let _ = reply_open_conn(&mut send, &OpenConnResponse::Failed(e.to_string())).await;
+                let reason = e.to_string();
+                refuse_open_conn(
+                    &open,
+                    &mut send,
+                    &mut recv,
+                    OpenConnError::Rejected,
+                    &reason,
+                )
+                .await;
                 error!(error = %e, "reverse OpenConn dispatch error");
                 return;
             }
         };
 
-        if let Err(e) = reply_open_conn(&mut send, &OpenConnResponse::Ok).await {
+        // Register the datagram flow (UDP only) before acking, so the
+        // server can't get a datagram in ahead of the registration.
+        let flow = match &dispatch {
+            ReverseDispatch::Udp(_) => match accept_datagram_flow(&datagrams, open.datagram_flow) {
+                Ok(f) => f,
+                Err(e) => {
+                    let reason = e.to_string();
+                    refuse_open_conn(
+                        &open,
+                        &mut send,
+                        &mut recv,
+                        OpenConnError::Rejected,
+                        &reason,
+                    )
+                    .await;
+                    error!(error = %e, "reverse OpenConn datagram flow error");
+                    return;
+                }
+            },
+            ReverseDispatch::Tcp(_) | ReverseDispatch::Unix(_) | ReverseDispatch::Bind(_) => None,
+        };
+
+        // A resumable conn needs somewhere to carry on after a loss,
+        // which only a session that negotiated them has.
+        let resume = match (&dispatch, open.resume, reattach) {
+            (
+                ReverseDispatch::Tcp(_) | ReverseDispatch::Unix(_),
+                Some(ConnResume::New(token)),
+                Some(reattach),
+            ) => Some((token, reattach)),
+            (_, Some(_), _) => {
+                let reason = "resumable conns weren't negotiated";
+                refuse_open_conn(&open, &mut send, &mut recv, OpenConnError::Rejected, reason)
+                    .await;
+                error!(reason, "reverse OpenConn refused");
+                return;
+            }
+            _ => None,
+        };
+
+        if let Err(e) = accept_open_conn(&open, &mut send).await {
             error!(error = %e, "failed to ack reverse OpenConn");
             return;
         }
 
-        let span =
-            info_span!("conn", tunnel_id = open.tunnel_id, dir = "reverse", target = %dispatch);
+        let span = info_span!(
+            "conn",
+            tunnel_id = open.tunnel_id,
+            name = parent.name.as_deref().unwrap_or("-"),
+            dir = "reverse",
+            target = %dispatch
+        );
         async move {
             info!("conn opened");
             let started = std::time::Instant::now();
             let result = match dispatch {
-                ReverseDispatch::Tcp(req) => tunnel_tcp_server(recv, send, req, None).await,
-                ReverseDispatch::Udp(req) => tunnel_udp_server(recv, send, req, None).await,
+                ReverseDispatch::Tcp(req) => tunnel_tcp_server(recv, send, req, None, resume).await,
+                ReverseDispatch::Unix(path) => {
+                    tunnel_unix_server(recv, send, &path, None, resume).await
+                }
+                ReverseDispatch::Udp(req) => tunnel_udp_server(recv, send, req, None, flow).await,
+                ReverseDispatch::Bind(expected) => {
+                    tunnel_socks_bind_server(recv, send, expected, server_addr, None).await
+                }
             };
             let dur_ms = started.elapsed().as_millis() as u64;
             match &result {
@@ -559,12 +1459,18 @@ async fn client_accept_reverse_conn(
 enum ReverseDispatch {
     Tcp(RemoteRequest),
     Udp(RemoteRequest),
+    /// Dial the unix socket at this path.
+    Unix(PathBuf),
+    /// SOCKS5 BIND: accept one conn from this host.
+    Bind(HostPort),
 }
 
 impl std::fmt::Display for ReverseDispatch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ReverseDispatch::Tcp(r) | ReverseDispatch::Udp(r) => write!(f, "{r}"),
+            ReverseDispatch::Unix(path) => write!(f, "unix:{}", path.display()),
+            ReverseDispatch::Bind(expected) => write!(f, "BIND from {expected}"),
         }
     }
 }
@@ -572,6 +1478,7 @@ impl std::fmt::Display for ReverseDispatch {
 fn resolve_reverse_dispatch(
     parent: &RemoteRequest,
     dynamic: Option<DynamicTarget>,
+    protocol: Option<Protocol>,
 ) -> Result<ReverseDispatch> {
     if !matches!(parent.direction, Direction::Reverse) {
         return Err(anyhow!(
@@ -593,16 +1500,17 @@ fn resolve_reverse_dispatch(
                 remote: remote.clone(),
             },
         ))),
-        (RemoteKind::Socks5 { local }, Some(DynamicTarget::Tcp(target))) => {
-            Ok(ReverseDispatch::Tcp(RemoteRequest::new(
-                Direction::Reverse,
-                RemoteKind::Tcp {
-                    local: *local,
-                    remote: target,
-                },
-            )))
-        }
-        (RemoteKind::Socks5 { local }, Some(DynamicTarget::Udp(target))) => {
+        (
+            RemoteKind::Socks5 { local, .. } | RemoteKind::Http { local, .. },
+            Some(DynamicTarget::Tcp(target)),
+        ) => Ok(ReverseDispatch::Tcp(RemoteRequest::new(
+            Direction::Reverse,
+            RemoteKind::Tcp {
+                local: *local,
+                remote: target,
+            },
+        ))),
+        (RemoteKind::Socks5 { local, .. }, Some(DynamicTarget::Udp(target))) => {
             Ok(ReverseDispatch::Udp(RemoteRequest::new(
                 Direction::Reverse,
                 RemoteKind::Udp {
@@ -611,11 +1519,101 @@ fn resolve_reverse_dispatch(
                 },
             )))
         }
-        (RemoteKind::Socks5 { .. }, None) => Err(anyhow!(
-            "server pushed reverse SOCKS5 conn without a dynamic target"
+        (RemoteKind::Socks5 { .. }, Some(DynamicTarget::Bind(expected))) => {
+            Ok(ReverseDispatch::Bind(expected))
+        }
+        (
+            RemoteKind::Unix {
+                remote: StreamEnd::Tcp(remote),
+                ..
+            },
+            None,
+        ) => Ok(ReverseDispatch::Tcp(RemoteRequest::new(
+            Direction::Reverse,
+            RemoteKind::Tcp {
+                local: parent.kind.local(),
+                remote: remote.clone(),
+            },
+        ))),
+        (
+            RemoteKind::Unix {
+                remote: StreamEnd::Unix(path),
+                ..
+            },
+            None,
+        ) => Ok(ReverseDispatch::Unix(path.clone())),
+        (RemoteKind::Both { .. }, None) => match protocol.and_then(|p| parent.kind.half(p)) {
+            Some(kind @ RemoteKind::Tcp { .. }) => Ok(ReverseDispatch::Tcp(RemoteRequest::new(
+                Direction::Reverse,
+                kind,
+            ))),
+            Some(kind) => Ok(ReverseDispatch::Udp(RemoteRequest::new(
+                Direction::Reverse,
+                kind,
+            ))),
+            None => Err(anyhow!(
+                "server pushed conn on tcp+udp tunnel {parent} without naming its protocol"
+            )),
+        },
+        (RemoteKind::Socks5 { .. } | RemoteKind::Http { .. }, None) => Err(anyhow!(
+            "server pushed reverse proxy conn without a dynamic target"
         )),
         (_, Some(_)) => Err(anyhow!(
-            "server pushed unexpected dynamic target on non-SOCKS reverse tunnel"
+            "server pushed unexpected dynamic target on reverse tunnel {parent}"
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn path_watch_moves_on_a_new_source() {
+        let a = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+        let mut path = PathWatch { source: Some(a) };
+        assert_eq!(path.observe(Some(a)), None);
+        assert_eq!(path.observe(Some(b)), Some(Some(a)));
+        assert_eq!(path.source, Some(b));
+    }
+
+    #[test]
+    fn path_watch_moves_once_the_route_is_back() {
+        let a = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let mut path = PathWatch { source: Some(a) };
+        assert_eq!(path.observe(None), None);
+        assert_eq!(path.observe(None), None);
+        assert_eq!(path.observe(Some(a)), Some(None));
+    }
+
+    #[tokio::test]
+    async fn network_change_migrates_and_counts() {
+        let a = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+        let on_b = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let route = {
+            let on_b = on_b.clone();
+            move |_| Some(if on_b.load(Ordering::Relaxed) { b } else { a })
+        };
+        let endpoint = Endpoint::client((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+        let before = endpoint.local_addr().unwrap();
+        let stats = Arc::new(ClientStats::default());
+        let server = (Ipv4Addr::LOCALHOST, 9).into();
+        let follow = tokio::spawn(follow_network_changes(
+            endpoint.clone(),
+            server,
+            stats.clone(),
+            route,
+        ));
+
+        tokio::time::sleep(PATH_PROBE_INTERVAL / 2).await;
+        assert_eq!(stats.migrations(), 0);
+        on_b.store(true, Ordering::Relaxed);
+        tokio::time::sleep(PATH_PROBE_INTERVAL).await;
+        assert_eq!(stats.migrations(), 1);
+        assert_ne!(endpoint.local_addr().unwrap(), before);
+        follow.abort();
+    }
+}
@@ -0,0 +1,247 @@
+//! RFC 9221 unreliable datagrams for UDP conns.
+//!
+//! UDP tunnels used to frame every datagram onto their conn's bi-stream
+//! (see [`write_datagram`](super::udp::write_datagram)), which means one
+//! lost QUIC packet head-of-line blocks the whole flow and retransmits
+//! data the application already gave up on. Instead, each UDP conn now
+//! negotiates a *flow id* in its [`OpenConn`](super::remote::OpenConn)
+//! frame and the payload travels in QUIC DATAGRAM frames tagged with
+//! that id:
+//!
+//! ```text
+//!   +----------------+-------------------+
+//!   | flow id (u64)  | UDP payload       |
+//!   |  little-endian |                   |
+//!   +----------------+-------------------+
+//! ```
+//!
+//! The conn's bi-stream stays open for the lifetime of the flow: it
+//! scopes the flow (closing it ends the conn) and still carries any
+//! datagram that doesn't fit in `max_datagram_size`, or every datagram
+//! when the peer didn't advertise DATAGRAM support at all.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use quinn::{Connection, SendDatagramError};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Bytes of flow-id tag prepended to every DATAGRAM frame payload.
+const FLOW_HEADER_LEN: usize = 8;
+
+/// Per-flow inbound queue depth. Same trade-off as the per-source UDP
+/// channels: on overflow the datagram is dropped, never blocked on.
+const FLOW_CHANNEL_CAPACITY: usize = 4096;
+
+/// Connection-wide demultiplexer for inbound DATAGRAM frames. One per
+/// QUIC connection, shared by every UDP conn riding on it.
+pub struct DatagramRouter {
+    connection: Connection,
+    /// Whether this side's UDP conns propose datagram flows, i.e. the
+    /// peer negotiated the `datagrams` capability. Flows the peer
+    /// proposes are always accepted.
+    enabled: bool,
+    flows: DashMap<u64, mpsc::Sender<Bytes>>,
+}
+
+/// Shared handle to a connection's [`DatagramRouter`].
+pub type Datagrams = Arc<DatagramRouter>;
+
+impl DatagramRouter {
+    /// Build the router for `connection` and spawn its receive loop. The
+    /// loop exits on its own once the connection closes.
+    pub fn spawn(connection: Connection, enabled: bool) -> Datagrams {
+        let router = Arc::new(Self {
+            connection,
+            enabled,
+            flows: DashMap::new(),
+        });
+        tokio::spawn(router.clone().run());
+        router
+    }
+
+    /// Whether UDP conns opened on this side should propose a flow.
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Register `flow_id` so DATAGRAM frames tagged with it are routed to
+    /// the returned [`DatagramFlow`]. The registration is released when the
+    /// flow is dropped. Fails if the id is already in use on this
+    /// connection.
+    pub(crate) fn open_flow(self: &Arc<Self>, flow_id: u64) -> Result<DatagramFlow> {
+        let (tx, rx) = mpsc::channel(FLOW_CHANNEL_CAPACITY);
+        match self.flows.entry(flow_id) {
+            Entry::Occupied(_) => Err(anyhow!("datagram flow {flow_id} already registered")),
+            Entry::Vacant(v) => {
+                v.insert(tx);
+                Ok(DatagramFlow {
+                    registration: FlowRegistration {
+                        router: self.clone(),
+                        id: flow_id,
+                    },
+                    rx,
+                })
+            }
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            let datagram = match self.connection.read_datagram().await {
+                Ok(d) => d,
+                Err(e) => {
+                    debug!(error = %e, "datagram router stopped");
+                    return;
+                }
+            };
+            let Some((flow_id, payload)) = split_flow_header(datagram) else {
+                debug!("dropping runt datagram");
+                continue;
+            };
+            let Some(tx) = self.flows.get(&flow_id).map(|e| e.value().clone()) else {
+                // Stale flow (conn already torn down) or a datagram that
+                // raced ahead of the OpenConn registering it. Either way
+                // UDP semantics allow us to drop it.
+                debug!(flow_id, "dropping datagram for unknown flow");
+                continue;
+            };
+            if let Err(e) = tx.try_send(payload) {
+                debug!(flow_id, error = %e, "dropping datagram");
+            }
+        }
+    }
+}
+
+/// One registered datagram flow: the receive side of the router's
+/// per-flow queue plus what's needed to send on the same flow id.
+pub struct DatagramFlow {
+    registration: FlowRegistration,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl DatagramFlow {
+    pub(crate) fn id(&self) -> u64 {
+        self.registration.id
+    }
+
+    /// Split into independent send / receive halves so the two pump
+    /// directions of a conn can run concurrently. The registration
+    /// travels with the receiver.
+    pub(crate) fn split(self) -> (FlowSender, FlowReceiver) {
+        let sender = FlowSender {
+            connection: self.registration.router.connection.clone(),
+            id: self.registration.id,
+        };
+        let receiver = FlowReceiver {
+            _registration: self.registration,
+            rx: self.rx,
+        };
+        (sender, receiver)
+    }
+}
+
+/// Deregisters its flow id from the router on drop.
+struct FlowRegistration {
+    router: Datagrams,
+    id: u64,
+}
+
+impl Drop for FlowRegistration {
+    fn drop(&mut self) {
+        self.router.flows.remove(&self.id);
+    }
+}
+
+pub(crate) struct FlowReceiver {
+    _registration: FlowRegistration,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl FlowReceiver {
+    /// Next inbound payload on this flow. Cancel-safe. `None` once the
+    /// router has stopped (the connection closed).
+    pub(crate) async fn recv(&mut self) -> Option<Bytes> {
+        self.rx.recv().await
+    }
+}
+
+pub(crate) struct FlowSender {
+    connection: Connection,
+    id: u64,
+}
+
+impl FlowSender {
+    /// Try to send `payload` as a DATAGRAM frame on this flow. Returns
+    /// `Ok(false)` when the datagram path can't carry it (peer has no
+    /// DATAGRAM support, or the payload exceeds the current
+    /// `max_datagram_size`) so the caller can fall back to stream framing.
+    pub(crate) async fn send(&self, payload: &[u8]) -> Result<bool> {
+        let fits = self
+            .connection
+            .max_datagram_size()
+            .is_some_and(|max| payload.len() + FLOW_HEADER_LEN <= max);
+        if !fits {
+            return Ok(false);
+        }
+        let frame = encode_flow_datagram(self.id, payload);
+        // `send_datagram_wait` (rather than `send_datagram`, which evicts
+        // the oldest queued datagram) so a fast local sender is paced by
+        // the congestion controller the same way the stream path is.
+        match self.connection.send_datagram_wait(frame).await {
+            Ok(()) => Ok(true),
+            // The MTU can shrink between the size check and the send.
+            Err(SendDatagramError::TooLarge)
+            | Err(SendDatagramError::UnsupportedByPeer)
+            | Err(SendDatagramError::Disabled) => Ok(false),
+            Err(SendDatagramError::ConnectionLost(e)) => Err(e.into()),
+        }
+    }
+}
+
+fn encode_flow_datagram(flow_id: u64, payload: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(FLOW_HEADER_LEN + payload.len());
+    buf.put_u64_le(flow_id);
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+fn split_flow_header(mut datagram: Bytes) -> Option<(u64, Bytes)> {
+    if datagram.len() < FLOW_HEADER_LEN {
+        return None;
+    }
+    let header = datagram.split_to(FLOW_HEADER_LEN);
+    let mut id = [0u8; FLOW_HEADER_LEN];
+    id.copy_from_slice(&header);
+    Some((u64::from_le_bytes(id), datagram))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flow_header_roundtrip() {
+        let frame = encode_flow_datagram(0x0102_0304_0506_0708, b"hello");
+        assert_eq!(frame.len(), FLOW_HEADER_LEN + 5);
+        let (id, payload) = split_flow_header(frame).unwrap();
+        assert_eq!(id, 0x0102_0304_0506_0708);
+        assert_eq!(&payload[..], b"hello");
+    }
+
+    #[test]
+    fn flow_header_allows_empty_payload() {
+        let (id, payload) = split_flow_header(encode_flow_datagram(7, b"")).unwrap();
+        assert_eq!(id, 7);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn flow_header_rejects_runt() {
+        assert!(split_flow_header(Bytes::from_static(&[1, 2, 3])).is_none());
+    }
+}
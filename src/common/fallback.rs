@@ -0,0 +1,421 @@
+//! QUIC over TLS-over-TCP: the fallback transport for networks that
+//! drop UDP.
+//!
+//! Nothing above the endpoint changes. The QUIC packets a connection
+//! would have sent as UDP datagrams are written instead, each with a
+//! `u16` (BE) length prefix, into a TLS stream over TCP, and read back
+//! out of it on the other side. The outer TLS layer makes the flow look
+//! like ordinary HTTPS and is verified with the same settings as QUIC's
+//! own handshake (which still runs, inside, as usual).
+//!
+//! On the client a [`TcpDatagramSocket`] stands in for the endpoint's
+//! UDP socket, with the server as its only peer. On the server a
+//! [`FallbackSocket`] wraps the real UDP socket and adds each accepted
+//! TCP connection as one more peer, addressed by the TCP peer's
+//! address, so the same endpoint — and everything past it, session
+//! state and admin API included — serves both transports alike.
+//!
+//! Packets go through bounded queues; when one is full the packet is
+//! dropped, which QUIC's loss recovery already handles for UDP.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+
+use anyhow::Result;
+use bytes::Bytes;
+use dashmap::DashMap;
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, Runtime, TokioRuntime, UdpPoller};
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, info, warn};
+
+/// ALPN the outer TLS layer offers, as an HTTPS client would.
+pub(crate) const ALPN_FALLBACK: &[u8] = b"http/1.1";
+
+/// Packets queued per direction of one TCP connection.
+const QUEUE: usize = 1024;
+
+/// Carry packets between `stream` and the endpoint: each one `outbound`
+/// yields is framed onto the stream, each frame read off it goes to
+/// `inbound`. Returns once either side is done.
+async fn pump<S>(
+    stream: S,
+    mut outbound: mpsc::Receiver<Bytes>,
+    inbound: impl Fn(Bytes) -> bool,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let write = async {
+        let mut batch = Vec::new();
+        while let Some(packet) = outbound.recv().await {
+            // Coalesce whatever else is already queued into one write,
+            // so a burst costs one TLS record rather than one per packet.
+            batch.clear();
+            frame(&mut batch, &packet);
+            while let Ok(packet) = outbound.try_recv() {
+                frame(&mut batch, &packet);
+            }
+            writer.write_all(&batch).await?;
+            writer.flush().await?;
+        }
+        writer.shutdown().await
+    };
+    let read = async {
+        let mut len = [0u8; 2];
+        loop {
+            match reader.read_exact(&mut len).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let mut packet = vec![0u8; u16::from_be_bytes(len) as usize];
+            reader.read_exact(&mut packet).await?;
+            if !inbound(packet.into()) {
+                return Ok(());
+            }
+        }
+    };
+    tokio::select! {
+        r = write => r,
+        r = read => r,
+    }
+}
+
+fn frame(buf: &mut Vec<u8>, packet: &[u8]) {
+    buf.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    buf.extend_from_slice(packet);
+}
+
+/// Hand one outgoing transmit to `queue`, split back into its packets
+/// if it was batched for GSO. A full queue drops them.
+fn queue_transmit(queue: &mpsc::Sender<Bytes>, transmit: &Transmit) {
+    let size = transmit.segment_size.unwrap_or(transmit.contents.len());
+    for packet in transmit.contents.chunks(size.max(1)) {
+        if queue.try_send(Bytes::copy_from_slice(packet)).is_err() {
+            debug!("fallback send queue full, dropping packet");
+        }
+    }
+}
+
+/// Fill quinn's first receive slot with `packet` from `addr`.
+pub(crate) fn deliver(
+    packet: Bytes,
+    addr: SocketAddr,
+    bufs: &mut [io::IoSliceMut<'_>],
+    meta: &mut [RecvMeta],
+) -> usize {
+    let len = packet.len().min(bufs[0].len());
+    bufs[0][..len].copy_from_slice(&packet[..len]);
+    meta[0] = RecvMeta {
+        addr,
+        len,
+        stride: len,
+        ecn: None,
+        dst_ip: None,
+    };
+    1
+}
+
+/// Client side: the endpoint's only "UDP socket", carrying its packets
+/// to and from the server over one TLS-over-TCP connection. Dropping it
+/// closes that connection.
+#[derive(Debug)]
+pub struct TcpDatagramSocket {
+    server: SocketAddr,
+    local: SocketAddr,
+    outbound: mpsc::Sender<Bytes>,
+    inbound: Mutex<mpsc::Receiver<Bytes>>,
+    io: JoinHandle<()>,
+}
+
+impl TcpDatagramSocket {
+    /// Complete the outer TLS handshake for `server_name` over `stream`,
+    /// a connection to the server's fallback port at `server` (direct,
+    /// or tunnelled through an HTTP proxy) whose local end is `local`.
+    pub async fn connect<S>(
+        stream: S,
+        local: SocketAddr,
+        server: SocketAddr,
+        server_name: &str,
+        tls: Arc<rustls::ClientConfig>,
+    ) -> Result<Arc<Self>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let name = ServerName::try_from(server_name.to_owned())?;
+        let stream = TlsConnector::from(tls).connect(name, stream).await?;
+        debug!(server = %server, "TCP fallback transport connected");
+
+        let (outbound, outbound_rx) = mpsc::channel(QUEUE);
+        let (inbound_tx, inbound) = mpsc::channel(QUEUE);
+        let io = tokio::spawn(async move {
+            let delivered = |packet| match inbound_tx.try_send(packet) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => true,
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            };
+            if let Err(e) = pump(stream, outbound_rx, delivered).await {
+                debug!(error = %e, "TCP fallback transport failed");
+            }
+        });
+        Ok(Arc::new(Self {
+            server,
+            local,
+            outbound,
+            inbound: Mutex::new(inbound),
+            io,
+        }))
+    }
+}
+
+impl Drop for TcpDatagramSocket {
+    fn drop(&mut self) {
+        self.io.abort();
+    }
+}
+
+impl AsyncUdpSocket for TcpDatagramSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(AlwaysWritable)
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        queue_transmit(&self.outbound, transmit);
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        if bufs.is_empty() || meta.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let mut inbound = self.inbound.lock().unwrap_or_else(PoisonError::into_inner);
+        match inbound.poll_recv(cx) {
+            Poll::Ready(Some(packet)) => Poll::Ready(Ok(deliver(packet, self.server, bufs, meta))),
+            // The TCP connection is gone; QUIC notices through its idle
+            // timeout, as it would for a UDP path gone silent.
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+/// Writes never block: packets that don't fit the queue are dropped.
+#[derive(Debug)]
+pub(crate) struct AlwaysWritable;
+
+impl UdpPoller for AlwaysWritable {
+    fn poll_writable(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Server side: the endpoint's UDP socket plus every client connected
+/// over the TCP fallback transport, each addressed by its TCP peer
+/// address.
+#[derive(Debug)]
+pub struct FallbackSocket {
+    udp: Arc<dyn AsyncUdpSocket>,
+    /// Outbound queue of each TCP-connected client.
+    peers: Arc<DashMap<SocketAddr, mpsc::Sender<Bytes>>>,
+    inbound_tx: mpsc::Sender<(SocketAddr, Bytes)>,
+    inbound: Mutex<mpsc::Receiver<(SocketAddr, Bytes)>>,
+    /// Each TCP-connected client's pump. They don't hold the socket, so
+    /// it is dropped with its endpoint, and dropping it aborts them.
+    pumps: Mutex<JoinSet<()>>,
+}
+
+impl FallbackSocket {
+    pub fn new(udp: std::net::UdpSocket) -> io::Result<Arc<Self>> {
+        let udp = TokioRuntime.wrap_udp_socket(udp)?;
+        let (inbound_tx, inbound) = mpsc::channel(QUEUE);
+        Ok(Arc::new(Self {
+            udp,
+            peers: Arc::default(),
+            inbound_tx,
+            inbound: Mutex::new(inbound),
+            pumps: Mutex::new(JoinSet::new()),
+        }))
+    }
+
+    /// Accept clients on `listener` and feed their packets to the
+    /// endpoint until the listener fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener, tls: Arc<rustls::ServerConfig>) {
+        let acceptor = TlsAcceptor::from(tls);
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "TCP fallback listener failed");
+                    return;
+                }
+            };
+            let peers = self.peers.clone();
+            let inbound = self.inbound_tx.clone();
+            let acceptor = acceptor.clone();
+            let mut pumps = self.pumps.lock().unwrap_or_else(PoisonError::into_inner);
+            while pumps.try_join_next().is_some() {}
+            pumps.spawn(async move {
+                let _ = tcp.set_nodelay(true);
+                let stream = match acceptor.accept(tcp).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        debug!(peer = %peer, error = %e, "TCP fallback TLS handshake failed");
+                        return;
+                    }
+                };
+                info!(peer = %peer, "client connected over TCP fallback");
+                let (outbound, outbound_rx) = mpsc::channel(QUEUE);
+                peers.insert(peer, outbound);
+                let delivered = |packet| match inbound.try_send((peer, packet)) {
+                    Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => true,
+                    Err(mpsc::error::TrySendError::Closed(_)) => false,
+                };
+                let result = pump(stream, outbound_rx, delivered).await;
+                peers.remove(&peer);
+                match result {
+                    Ok(()) => debug!(peer = %peer, "TCP fallback connection closed"),
+                    Err(e) => debug!(peer = %peer, error = %e, "TCP fallback connection failed"),
+                }
+            });
+        }
+    }
+}
+
+impl AsyncUdpSocket for FallbackSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.udp.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        match self.peers.get(&transmit.destination) {
+            Some(peer) => {
+                queue_transmit(&peer, transmit);
+                Ok(())
+            }
+            None => self.udp.try_send(transmit),
+        }
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        if bufs.is_empty() || meta.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let mut inbound = self.inbound.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Poll::Ready(Some((peer, packet))) = inbound.poll_recv(cx) {
+            return Poll::Ready(Ok(deliver(packet, peer, bufs, meta)));
+        }
+        drop(inbound);
+        self.udp.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.udp.max_transmit_segments()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.udp.max_receive_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.udp.may_fragment()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_length_prefixed() {
+        let mut buf = Vec::new();
+        frame(&mut buf, b"abc");
+        frame(&mut buf, b"");
+        assert_eq!(buf, [0, 3, b'a', b'b', b'c', 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn gso_batches_are_split_into_packets() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let contents = [1u8; 10];
+        let transmit = Transmit {
+            destination: "127.0.0.1:1".parse().expect("addr"),
+            ecn: None,
+            contents: &contents,
+            segment_size: Some(4),
+            src_ip: None,
+        };
+        queue_transmit(&tx, &transmit);
+        let lens: Vec<usize> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|p| p.len())
+            .collect();
+        assert_eq!(lens, [4, 4, 2]);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_socket_aborts_its_pumps() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".into()]).expect("cert");
+        let key =
+            rustls::pki_types::PrivatePkcs8KeyDer::from(generated.signing_key.serialize_der());
+        let tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .expect("protocol versions")
+        .with_no_client_auth()
+        .with_single_cert(vec![generated.cert.der().clone()], key.into())
+        .expect("tls config");
+
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").expect("udp bind");
+        let socket = FallbackSocket::new(udp).expect("socket");
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("tcp bind");
+        let addr = listener.local_addr().expect("addr");
+        let serve = tokio::spawn(socket.clone().serve(listener, Arc::new(tls)));
+
+        // A client that never finishes its TLS handshake.
+        let mut client = tokio::net::TcpStream::connect(addr).await.expect("connect");
+        while socket.pumps.lock().expect("pumps").is_empty() {
+            tokio::task::yield_now().await;
+        }
+        serve.abort();
+        let _ = serve.await;
+        drop(socket);
+
+        let mut buf = [0u8; 1];
+        let n = tokio::time::timeout(std::time::Duration::from_secs(5), client.read(&mut buf))
+            .await
+            .expect("pump outlived its socket")
+            .expect("read");
+        assert_eq!(n, 0);
+    }
+}
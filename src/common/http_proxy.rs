@@ -0,0 +1,583 @@
+//! HTTP proxy listeners (`http` remotes).
+//!
+//! Each accepted conn sends one request head: a `CONNECT host:port`, or a
+//! plain request with an absolute `http://` URI. Either way the target
+//! reaches the peer as an [`OpenConn`] with a [`DynamicTarget::Tcp`], just
+//! like a SOCKS CONNECT, so the far side gates and accounts for it the same
+//! way. A CONNECT is answered `200` and the conn becomes a raw tunnel. A
+//! plain request is rewritten to origin form and sent on with
+//! `Connection: close`, so each conn carries exactly one request.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use quinn::{RecvStream, SendStream};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use super::listener::{Uplink, UplinkWatch};
+use super::remote::{DynamicTarget, HostPort, OpenConn, RemoteRequest};
+use super::socks::SocksUsers;
+use super::tcp::{tunnel_tcp_stream, Counters, TunnelHandleOpt};
+use super::tunnel::send_open_conn;
+
+/// Cap on a client's request line plus headers.
+const MAX_REQUEST_HEAD: usize = 64 * 1024;
+
+/// How long a client gets to send its whole request head.
+const REQUEST_HEAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that only concern the hop to us, dropped from plain requests
+/// before they go on to the origin server along with any the request's
+/// `Connection` header names (RFC 9110 §7.6.1).
+const HOP_BY_HOP: [&str; 7] = [
+    "proxy-authorization",
+    "proxy-connection",
+    "connection",
+    "keep-alive",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// Serve an HTTP proxy on the already-bound `listener` of an http remote,
+/// carrying each request over the uplink current once its head is read.
+/// With any `users` (or credentials in `remote` itself), clients must send
+/// Basic `Proxy-Authorization` for one of them.
+pub async fn tunnel_http_client(
+    listener: TcpListener,
+    remote: RemoteRequest,
+    uplink: UplinkWatch,
+    handle: TunnelHandleOpt,
+    users: SocksUsers,
+) -> Result<()> {
+    let users = users.for_remote(&remote);
+    info!(
+        addr = %listener.local_addr()?,
+        proto = "http",
+        auth = !users.is_empty(),
+        "listening"
+    );
+
+    let local_counter = AtomicUsize::new(0);
+
+    loop {
+        let (mut local_conn, peer) = listener.accept().await?;
+        let uplink = uplink.clone();
+        let tunnel_handle = handle.clone();
+        let users = users.clone();
+        let local_id = (local_counter.fetch_add(1, Ordering::Relaxed) + 1) as u64;
+
+        tokio::spawn(async move {
+            let (user, request, early) = match http_handshake(&mut local_conn, &users).await {
+                Ok(r) => r,
+                Err(e) => {
+                    let span = info_span!("http", peer = %peer);
+                    let _g = span.enter();
+                    warn!(error = %e, "request rejected");
+                    return;
+                }
+            };
+            let Uplink {
+                connection,
+                tunnel_id,
+                ..
+            } = match uplink.get().await {
+                Ok(u) => u,
+                Err(e) => {
+                    let span = info_span!("http", peer = %peer);
+                    let _g = span.enter();
+                    let _ = write_status(&mut local_conn, 503, "Service Unavailable").await;
+                    info!(error = %e, "conn dropped");
+                    return;
+                }
+            };
+            let (send, recv) = match connection.open_bi().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let span = info_span!("http", peer = %peer);
+                    let _g = span.enter();
+                    let _ = write_status(&mut local_conn, 502, "Bad Gateway").await;
+                    error!(error = %e, "failed to open quic stream");
+                    return;
+                }
+            };
+
+            let target = request.target().clone();
+            let conn_guard = tunnel_handle
+                .as_ref()
+                .map(|h| h.open_conn(Some(format!("{peer}=>{target}")), user.clone()));
+            let conn_id = conn_guard.as_ref().map(|g| g.id()).unwrap_or(local_id);
+            let counters = conn_guard.as_ref().map(|g| g.counters());
+
+            let span = info_span!(
+                "conn",
+                conn_id,
+                tunnel_id,
+                peer = %peer,
+                target = %target,
+                proto = request.proto(),
+            );
+            let open = OpenConn {
+                tunnel_id,
+                dynamic: Some(DynamicTarget::Tcp(target)),
+                datagram_flow: None,
+                optimistic: false,
+                resume: None,
+                user,
+                protocol: None,
+            };
+            async move {
+                info!("conn opened");
+                let started = std::time::Instant::now();
+                let result = start_http_tunnel(
+                    local_conn,
+                    send,
+                    recv,
+                    open,
+                    request,
+                    early,
+                    counters.clone(),
+                )
+                .await;
+                let dur_ms = started.elapsed().as_millis() as u64;
+                let snap = counters.as_ref().map(|c| c.snapshot());
+                match (&result, snap) {
+                    (Ok(()), Some((bytes_in, bytes_out))) => {
+                        info!(bytes_in, bytes_out, dur_ms, "conn closed")
+                    }
+                    (Ok(()), None) => info!(dur_ms, "conn closed"),
+                    (Err(e), _) => warn!(dur_ms, error = %e, "conn closed (error)"),
+                }
+                drop(conn_guard);
+            }
+            .instrument(span)
+            .await;
+        });
+    }
+}
+
+async fn start_http_tunnel(
+    mut local_conn: TcpStream,
+    mut send_channel: SendStream,
+    mut recv_channel: RecvStream,
+    open: OpenConn,
+    request: ProxyRequest,
+    early: Vec<u8>,
+    counters: Counters,
+) -> Result<()> {
+    if let Err(e) = send_open_conn(&open, &mut send_channel, &mut recv_channel).await {
+        let _ = write_status(&mut local_conn, 502, "Bad Gateway").await;
+        return Err(e);
+    }
+
+    match request {
+        ProxyRequest::Connect(_) => {
+            local_conn
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await?
+        }
+        ProxyRequest::Forward(_, head) => {
+            send_channel.write_all(&head).await?;
+            if let Some(c) = &counters {
+                c.add_out(head.len() as u64);
+            }
+        }
+    }
+    if !early.is_empty() {
+        send_channel.write_all(&early).await?;
+        if let Some(c) = &counters {
+            c.add_out(early.len() as u64);
+        }
+    }
+
+    tunnel_tcp_stream(local_conn, send_channel, recv_channel, counters).await
+}
+
+/// A proxy client's request, once its head has been read.
+#[derive(Debug, PartialEq, Eq)]
+enum ProxyRequest {
+    /// `CONNECT host:port`: a raw tunnel once the target is reached.
+    Connect(HostPort),
+    /// A plain request, its head already rewritten for the origin server.
+    Forward(HostPort, Vec<u8>),
+}
+
+impl ProxyRequest {
+    fn target(&self) -> &HostPort {
+        match self {
+            Self::Connect(target) | Self::Forward(target, _) => target,
+        }
+    }
+
+    fn proto(&self) -> &'static str {
+        match self {
+            Self::Connect(_) => "http/connect",
+            Self::Forward(..) => "http",
+        }
+    }
+}
+
+/// Why a request head was turned away, and how the client is told.
+#[derive(Debug, PartialEq, Eq)]
+enum Rejection {
+    /// 400, with what was wrong.
+    BadRequest(String),
+    /// 407: no credentials, or not ones `users` accepts.
+    AuthRequired,
+}
+
+/// Read the client's request head and check its credentials, answering
+/// it with an error status if it's turned away. Returns the username it
+/// authenticated as (`None` when `users` is empty), and whatever the
+/// client sent after the head (a body, or the start of a CONNECT's
+/// tunnel) for the caller to pass on.
+async fn http_handshake(
+    conn: &mut TcpStream,
+    users: &SocksUsers,
+) -> Result<(Option<String>, ProxyRequest, Vec<u8>)> {
+    let (head, early) = match tokio::time::timeout(REQUEST_HEAD_TIMEOUT, read_head(conn)).await {
+        Ok(Ok(Some(read))) => read,
+        Ok(Ok(None)) => {
+            let _ = write_status(conn, 431, "Request Header Fields Too Large").await;
+            bail!("request head over {MAX_REQUEST_HEAD} bytes");
+        }
+        Ok(Err(e)) => return Err(e),
+        Err(_) => {
+            let _ = write_status(conn, 408, "Request Timeout").await;
+            bail!("no full request head within {REQUEST_HEAD_TIMEOUT:?}");
+        }
+    };
+    match parse_request(&head, users) {
+        Ok((user, request)) => Ok((user, request, early)),
+        Err(Rejection::BadRequest(reason)) => {
+            let _ = write_status(conn, 400, "Bad Request").await;
+            Err(anyhow!("bad request: {reason}"))
+        }
+        Err(Rejection::AuthRequired) => {
+            let response = "HTTP/1.1 407 Proxy Authentication Required\r\n\
+                            Proxy-Authenticate: Basic realm=\"rusnel\"\r\n\
+                            Content-Length: 0\r\nConnection: close\r\n\r\n";
+            let _ = conn.write_all(response.as_bytes()).await;
+            Err(anyhow!("authentication failed"))
+        }
+    }
+}
+
+/// Read a request head through its blank line, a line at a time. Returns
+/// it and the bytes read past it, or `None` if it runs over
+/// [`MAX_REQUEST_HEAD`].
+async fn read_head(conn: &mut TcpStream) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let mut reader = BufReader::new(conn);
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let room = MAX_REQUEST_HEAD - head.len();
+        if room == 0 {
+            return Ok(None);
+        }
+        let n = (&mut reader)
+            .take(room as u64)
+            .read_until(b'\n', &mut head)
+            .await?;
+        if n == 0 {
+            bail!("client closed the connection before a full request");
+        }
+    }
+    Ok(Some((head, reader.buffer().to_vec())))
+}
+
+/// Parse a request head (through its blank line), check its
+/// `Proxy-Authorization` against `users`, and work out where it's going.
+fn parse_request(
+    head: &[u8],
+    users: &SocksUsers,
+) -> Result<(Option<String>, ProxyRequest), Rejection> {
+    let bad = |reason: &str| Rejection::BadRequest(reason.to_string());
+    let head = std::str::from_utf8(head).map_err(|_| bad("request head isn't UTF-8"))?;
+    let mut lines = head.split("\r\n").take_while(|l| !l.is_empty());
+    let request_line = lines.next().ok_or_else(|| bad("empty request"))?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(uri), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(bad("not an HTTP/1.x request"));
+    }
+    let mut headers = Vec::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| bad("malformed header line"))?;
+        headers.push((name.trim(), value.trim()));
+    }
+
+    let user = if users.is_empty() {
+        None
+    } else {
+        let credentials = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("proxy-authorization"))
+            .and_then(|(_, value)| basic_credentials(value));
+        match credentials {
+            Some((user, pass)) if users.verify(&user, &pass) => Some(user),
+            _ => return Err(Rejection::AuthRequired),
+        }
+    };
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let target = parse_authority(uri, None).map_err(|e| bad(&e.to_string()))?;
+        return Ok((user, ProxyRequest::Connect(target)));
+    }
+
+    let rest = uri
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        .map(|_| &uri[7..])
+        .ok_or_else(|| bad("only absolute http:// URIs can be proxied"))?;
+    let split = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(split);
+    // Userinfo has no place in a request to the origin.
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+    let target = parse_authority(authority, Some(80)).map_err(|e| bad(&e.to_string()))?;
+    let path = match path {
+        "" => "/".to_string(),
+        p if p.starts_with('?') => format!("/{p}"),
+        p => p.to_string(),
+    };
+
+    let connection_options: Vec<&str> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim)
+        .collect();
+    let mut rewritten = format!("{method} {path} {version}\r\n");
+    if !headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("host")) {
+        rewritten.push_str(&format!("Host: {authority}\r\n"));
+    }
+    for (name, value) in &headers {
+        let hop_by_hop = HOP_BY_HOP.iter().chain(&connection_options);
+        if !hop_by_hop.into_iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            rewritten.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    rewritten.push_str("Connection: close\r\n\r\n");
+    Ok((user, ProxyRequest::Forward(target, rewritten.into_bytes())))
+}
+
+/// `(user, pass)` from a `Basic` authorization value.
+fn basic_credentials(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(BASE64_STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, pass) = decoded.split_once(':')?;
+    Some((user.to_string(), pass.to_string()))
+}
+
+/// `host:port` or `[v6]:port`; the port may only be left out when there's
+/// a `default_port`.
+fn parse_authority(authority: &str, default_port: Option<u16>) -> Result<HostPort> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed
+                .split_once(']')
+                .ok_or_else(|| anyhow!("unterminated IPv6 literal in `{authority}`"))?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        bail!("no host in `{authority}`");
+    }
+    let port = match (port, default_port) {
+        (Some(port), _) => port
+            .parse::<u16>()
+            .ok()
+            .filter(|p| *p != 0)
+            .ok_or_else(|| anyhow!("invalid port in `{authority}`"))?,
+        (None, Some(port)) => port,
+        (None, None) => bail!("no port in `{authority}`"),
+    };
+    Ok(HostPort::new(host, port))
+}
+
+/// Answer with a bodyless `status` and close.
+async fn write_status(conn: &mut TcpStream, status: u16, reason: &str) -> Result<()> {
+    let response =
+        format!("HTTP/1.1 {status} {reason}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    conn.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &str) -> Result<(Option<String>, ProxyRequest), Rejection> {
+        parse_request(head.as_bytes(), &SocksUsers::default())
+    }
+
+    fn forwarded(head: &str) -> (HostPort, String) {
+        match parse(head).unwrap() {
+            (_, ProxyRequest::Forward(target, head)) => (target, String::from_utf8(head).unwrap()),
+            (_, other) => panic!("expected a plain request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_connect() {
+        let (user, request) = parse("CONNECT example.com:443 HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        assert_eq!(user, None);
+        assert_eq!(
+            request,
+            ProxyRequest::Connect(HostPort::new("example.com", 443))
+        );
+        let (_, request) = parse("CONNECT [::1]:22 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request, ProxyRequest::Connect(HostPort::new("::1", 22)));
+        assert!(matches!(
+            parse("CONNECT example.com HTTP/1.1\r\n\r\n"),
+            Err(Rejection::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rewrites_absolute_requests_to_origin_form() {
+        let (target, head) = forwarded(
+            "GET http://example.com/a/b?c=d HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Proxy-Connection: keep-alive\r\n\
+             Accept: */*\r\n\r\n",
+        );
+        assert_eq!(target, HostPort::new("example.com", 80));
+        assert_eq!(
+            head,
+            "GET /a/b?c=d HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+        );
+
+        let (target, head) = forwarded("GET http://10.0.0.1:8080?q HTTP/1.0\r\n\r\n");
+        assert_eq!(target, HostPort::new("10.0.0.1", 8080));
+        assert_eq!(
+            head,
+            "GET /?q HTTP/1.0\r\nHost: 10.0.0.1:8080\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn rejects_requests_it_cannot_proxy() {
+        for head in [
+            "GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            "GET https://example.com/ HTTP/1.1\r\n\r\n",
+            "GET http://example.com/ SPDY/3\r\n\r\n",
+            "GET http://example.com/\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse(head), Err(Rejection::BadRequest(_))),
+                "accepted {head:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn requires_basic_credentials_when_users_are_configured() {
+        let users = SocksUsers::parse("alice:s3:cret").unwrap();
+        let head = "CONNECT example.com:443 HTTP/1.1\r\n\r\n";
+        assert_eq!(
+            parse_request(head.as_bytes(), &users),
+            Err(Rejection::AuthRequired)
+        );
+
+        let with = |credentials: &str| {
+            let encoded = BASE64_STANDARD.encode(credentials);
+            format!(
+                "CONNECT example.com:443 HTTP/1.1\r\nproxy-authorization: basic {encoded}\r\n\r\n"
+            )
+        };
+        assert_eq!(
+            parse_request(with("alice:guess").as_bytes(), &users),
+            Err(Rejection::AuthRequired)
+        );
+        let (user, _) = parse_request(with("alice:s3:cret").as_bytes(), &users).unwrap();
+        assert_eq!(user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn strips_proxy_credentials_from_forwarded_requests() {
+        let users = SocksUsers::parse("alice:pw").unwrap();
+        let encoded = BASE64_STANDARD.encode("alice:pw");
+        let head = format!(
+            "POST http://example.com/form HTTP/1.1\r\n\
+             Proxy-Authorization: Basic {encoded}\r\n\
+             Content-Length: 3\r\n\r\n"
+        );
+        let (user, request) = parse_request(head.as_bytes(), &users).unwrap();
+        assert_eq!(user.as_deref(), Some("alice"));
+        let ProxyRequest::Forward(_, head) = request else {
+            panic!("expected a plain request");
+        };
+        assert_eq!(
+            String::from_utf8(head).unwrap(),
+            "POST /form HTTP/1.1\r\nHost: example.com\r\nContent-Length: 3\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn strips_hop_by_hop_headers() {
+        let (_, head) = forwarded(
+            "GET http://example.com/ HTTP/1.1\r\n\
+             Connection: Upgrade, X-Hop\r\n\
+             Upgrade: websocket\r\n\
+             TE: trailers\r\n\
+             Trailer: X-Sum\r\n\
+             X-Hop: 1\r\n\
+             Accept: */*\r\n\r\n",
+        );
+        assert_eq!(
+            head,
+            "GET / HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    async fn conn_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn head_read_keeps_what_follows_it() {
+        let (mut client, mut server) = conn_pair().await;
+        let head = "POST http://example.com/ HTTP/1.1\r\nContent-Length: 3\r\n\r\n";
+        client
+            .write_all(format!("{head}abc").as_bytes())
+            .await
+            .unwrap();
+        let (read, early) = read_head(&mut server).await.unwrap().unwrap();
+        assert_eq!(read, head.as_bytes());
+        assert_eq!(early, b"abc");
+    }
+
+    #[tokio::test]
+    async fn head_read_is_capped() {
+        let (mut client, mut server) = conn_pair().await;
+        let line = format!("X-Filler: {}\r\n", "a".repeat(1000));
+        tokio::spawn(async move {
+            let start = b"GET http://example.com/ HTTP/1.1\r\n";
+            let _ = client.write_all(start).await;
+            while client.write_all(line.as_bytes()).await.is_ok() {}
+        });
+        assert!(read_head(&mut server).await.unwrap().is_none());
+    }
+}
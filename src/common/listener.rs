@@ -0,0 +1,349 @@
+//! Local sockets a tunnel accepts on.
+//!
+//! Binding is split from serving so a listener can be bound before the
+//! tunnel is reported as up: the server binds every reverse tunnel's
+//! socket before answering the session hello (or a live `AddTunnel`),
+//! so a port that's already taken reaches the client as a per-tunnel
+//! failure instead of only showing up in the server's log.
+//!
+//! Serving is split from the session, too: a listener sends its conns
+//! over whatever [`Uplink`] its [`UplinkWatch`] currently holds. The
+//! server's reverse listeners have one fixed uplink; the client's
+//! forward listeners outlive reconnects and are re-pointed at each new
+//! session, holding the conns they accept in between.
+
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use quinn::Connection;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::watch;
+
+use crate::common::datagram::Datagrams;
+use crate::common::http_proxy::tunnel_http_client;
+use crate::common::remote::{Protocol, RemoteKind, RemoteRequest, StreamEnd};
+use crate::common::resumable::Reattach;
+use crate::common::routing::RouteRules;
+use crate::common::socks::{tunnel_socks_client, SocksUsers};
+#[cfg(unix)]
+use crate::common::tcp::tunnel_unix_client;
+use crate::common::tcp::{tunnel_tcp_client, TunnelHandleOpt};
+use crate::common::udp::tunnel_udp_client;
+
+/// How many OS-picked ports [`bind_both`] tries before giving up.
+const BIND_BOTH_ATTEMPTS: usize = 8;
+
+/// The session a tunnel's conns currently go out over.
+#[derive(Clone)]
+pub struct Uplink {
+    pub connection: Connection,
+    /// The tunnel's id within this session.
+    pub tunnel_id: u64,
+    pub datagrams: Datagrams,
+    /// Pipeline `OpenConn` on static TCP conns; only set when the peer
+    /// negotiated the `optimistic-open` capability.
+    pub optimistic: bool,
+    /// How static TCP conns carry on after a reconnect; only set when
+    /// the peers negotiated `resumable-conns`.
+    pub resumable: Option<Reattach>,
+    /// SOCKS5 BIND requests can be carried; only set when the peer
+    /// negotiated `socks-bind`.
+    pub socks_bind: bool,
+    /// Which half of a `/both` tunnel the conn belongs to, stamped by
+    /// [`UplinkWatch::for_protocol`].
+    pub protocol: Option<Protocol>,
+}
+
+/// A listener's view of its [`Uplink`], which may come and go.
+#[derive(Clone)]
+pub struct UplinkWatch {
+    current: watch::Receiver<Option<Uplink>>,
+    /// How long a conn waits for an uplink before giving up.
+    hold: Duration,
+    protocol: Option<Protocol>,
+}
+
+impl UplinkWatch {
+    /// An uplink that never changes.
+    pub fn fixed(uplink: Uplink) -> Self {
+        let (_, current) = watch::channel(Some(uplink));
+        Self {
+            current,
+            hold: Duration::ZERO,
+            protocol: None,
+        }
+    }
+
+    /// Follow `current`; while it's `None` (or its connection has
+    /// closed), conns wait up to `hold` for a new one.
+    pub fn new(current: watch::Receiver<Option<Uplink>>, hold: Duration) -> Self {
+        Self {
+            current,
+            hold,
+            protocol: None,
+        }
+    }
+
+    /// The same uplink, for the `protocol` listener of a `/both` tunnel.
+    pub fn for_protocol(self, protocol: Protocol) -> Self {
+        Self {
+            protocol: Some(protocol),
+            ..self
+        }
+    }
+
+    /// The uplink to open a conn on, waiting up to `hold` for one.
+    pub async fn get(&self) -> Result<Uplink> {
+        let mut current = self.current.clone();
+        let live = |u: &Option<Uplink>| {
+            u.as_ref()
+                .is_some_and(|u| u.connection.close_reason().is_none())
+        };
+        let uplink = match tokio::time::timeout(self.hold, current.wait_for(live)).await {
+            Ok(Ok(uplink)) => uplink.clone(),
+            Ok(Err(_)) => return Err(anyhow!("tunnel closed")),
+            Err(_) => return Err(anyhow!("peer didn't reconnect within {:?}", self.hold)),
+        };
+        let mut uplink = uplink.ok_or_else(|| anyhow!("uplink vanished while waiting"))?;
+        uplink.protocol = self.protocol;
+        Ok(uplink)
+    }
+}
+
+/// The bound local side of one tunnel.
+pub enum LocalListener {
+    /// TCP, SOCKS5 and HTTP proxy remotes, and unix socket remotes
+    /// whose local end is TCP.
+    Tcp(TcpListener),
+    /// UDP remotes.
+    Udp(UdpSocket),
+    /// `/both` remotes: a TCP listener and a UDP socket on one port.
+    Both(TcpListener, UdpSocket),
+    /// Unix socket remotes whose local end is a unix socket.
+    #[cfg(unix)]
+    Unix(UnixListener, SocketFile),
+}
+
+impl LocalListener {
+    /// Bind the local address `remote` declares.
+    pub async fn bind(remote: &RemoteRequest) -> Result<Self> {
+        if let RemoteKind::Unix {
+            local: StreamEnd::Unix(path),
+            ..
+        } = &remote.kind
+        {
+            #[cfg(unix)]
+            return bind_unix(path)
+                .map(|(listener, file)| Self::Unix(listener, file))
+                .with_context(|| format!("failed to bind unix:{}", path.display()));
+            #[cfg(not(unix))]
+            return Err(anyhow!(
+                "can't bind unix:{}: unix sockets aren't supported on this platform",
+                path.display()
+            ));
+        }
+        // Use SocketAddr's Display so IPv6 literals come out bracketed
+        // (`[::1]:8080`) — a manual `format!("{ip}:{port}")` on an IPv6
+        // `IpAddr` produces `::1:8080`, which `TcpListener::bind` rejects.
+        let addr = remote.local_socket_addr();
+        let listener = match remote.kind {
+            RemoteKind::Udp { .. } => UdpSocket::bind(addr).await.map(Self::Udp),
+            RemoteKind::Both { .. } => bind_both(addr).await,
+            RemoteKind::Tcp { .. }
+            | RemoteKind::Socks5 { .. }
+            | RemoteKind::Http { .. }
+            | RemoteKind::Unix { .. } => TcpListener::bind(addr).await.map(Self::Tcp),
+        };
+        listener.with_context(|| format!("failed to bind {addr}"))
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(match self {
+            Self::Tcp(l) | Self::Both(l, _) => l.local_addr()?,
+            Self::Udp(s) => s.local_addr()?,
+            #[cfg(unix)]
+            Self::Unix(..) => return Err(anyhow!("a unix socket has no inet address")),
+        })
+    }
+
+    /// Run `remote`'s tunnel on this listener until it fails, sending
+    /// each conn over the uplink current when it's accepted. A SOCKS5 or
+    /// HTTP proxy listener requires clients to log in as one of
+    /// `socks_users`, if there are any; a SOCKS5 listener routes
+    /// CONNECTs by `socks_rules`.
+    pub async fn serve(
+        self,
+        uplink: UplinkWatch,
+        remote: RemoteRequest,
+        handle: TunnelHandleOpt,
+        socks_users: SocksUsers,
+        socks_rules: RouteRules,
+    ) -> Result<()> {
+        match (self, &remote.kind) {
+            (Self::Tcp(listener), RemoteKind::Tcp { .. } | RemoteKind::Unix { .. }) => {
+                tunnel_tcp_client(listener, uplink, handle).await
+            }
+            #[cfg(unix)]
+            (Self::Unix(listener, _file), RemoteKind::Unix { .. }) => {
+                tunnel_unix_client(listener, uplink, handle).await
+            }
+            (Self::Tcp(listener), RemoteKind::Socks5 { .. }) => {
+                tunnel_socks_client(listener, remote, uplink, handle, socks_users, socks_rules)
+                    .await
+            }
+            (Self::Tcp(listener), RemoteKind::Http { .. }) => {
+                tunnel_http_client(listener, remote, uplink, handle, socks_users).await
+            }
+            (Self::Udp(socket), RemoteKind::Udp { .. }) => {
+                tunnel_udp_client(socket, uplink, handle).await
+            }
+            (Self::Both(listener, socket), RemoteKind::Both { .. }) => {
+                let half = |protocol| {
+                    let handle = handle.as_ref().map(|h| Arc::new(h.for_protocol(protocol)));
+                    (uplink.clone().for_protocol(protocol), handle)
+                };
+                let (tcp_uplink, tcp_handle) = half(Protocol::Tcp);
+                let (udp_uplink, udp_handle) = half(Protocol::Udp);
+                tokio::try_join!(
+                    tunnel_tcp_client(listener, tcp_uplink, tcp_handle),
+                    tunnel_udp_client(socket, udp_uplink, udp_handle),
+                )
+                .map(|_| ())
+            }
+            _ => Err(anyhow!("listener doesn't match remote {remote}")),
+        }
+    }
+}
+
+/// Bind a TCP listener and a UDP socket on the same port of `addr`. When
+/// the port is left to the OS, the UDP socket takes whichever one the TCP
+/// listener got, and the pair is retried if that one's taken for UDP.
+async fn bind_both(addr: SocketAddr) -> std::io::Result<LocalListener> {
+    let mut attempts = if addr.port() == 0 {
+        BIND_BOTH_ATTEMPTS
+    } else {
+        1
+    };
+    loop {
+        let listener = TcpListener::bind(addr).await?;
+        match UdpSocket::bind(listener.local_addr()?).await {
+            Ok(socket) => return Ok(LocalListener::Both(listener, socket)),
+            Err(e) if attempts == 1 => return Err(e),
+            Err(_) => attempts -= 1,
+        }
+    }
+}
+
+/// Bind a unix socket listener at `path`. A socket file left behind by
+/// an earlier listener, which nothing accepts on any more, is replaced;
+/// anything else at `path` is left alone and the bind fails.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> std::io::Result<(UnixListener, SocketFile)> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let listener = match UnixListener::bind(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            let is_socket =
+                std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket());
+            if !is_socket || std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        }
+        bound => bound?,
+    };
+    let file = SocketFile::new(path)?;
+    Ok((listener, file))
+}
+
+/// The file a unix listener is bound at, removed when the listener goes
+/// so a closed tunnel doesn't leave it behind. If something else has
+/// been bound at the path since, that is left alone.
+#[cfg(unix)]
+pub struct SocketFile {
+    path: PathBuf,
+    /// Device and inode of the socket we bound.
+    id: (u64, u64),
+}
+
+#[cfg(unix)]
+impl SocketFile {
+    fn new(path: &Path) -> std::io::Result<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = std::fs::symlink_metadata(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            id: (meta.dev(), meta.ino()),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        use std::os::unix::fs::MetadataExt;
+
+        let ours = std::fs::symlink_metadata(&self.path)
+            .is_ok_and(|meta| (meta.dev(), meta.ino()) == self.id);
+        if ours {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = PathBuf::from(format!(
+            "/tmp/rusnel-listener-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn unix_remote(path: &Path) -> RemoteRequest {
+        RemoteRequest::from_str(&format!("unix:{}", path.display())).expect("unix remote")
+    }
+
+    #[tokio::test]
+    async fn unix_listener_removes_its_socket_file() {
+        let path = socket_path("removed.sock");
+        let listener = LocalListener::bind(&unix_remote(&path))
+            .await
+            .expect("bind");
+        assert!(path.exists());
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn unix_listener_replaces_only_stale_sockets() {
+        // A stale socket file: nothing accepts on it any more.
+        let path = socket_path("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).expect("bind"));
+        let listener = LocalListener::bind(&unix_remote(&path))
+            .await
+            .expect("stale socket replaced");
+        assert!(LocalListener::bind(&unix_remote(&path)).await.is_err());
+        drop(listener);
+
+        let path = socket_path("regular");
+        std::fs::write(&path, b"keep me").expect("write");
+        assert!(LocalListener::bind(&unix_remote(&path)).await.is_err());
+        assert_eq!(std::fs::read(&path).expect("read"), b"keep me");
+        std::fs::remove_file(&path).expect("remove");
+    }
+}
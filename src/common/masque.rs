@@ -0,0 +1,379 @@
+//! QUIC through an HTTP/3 MASQUE proxy: RFC 9298 `CONNECT-UDP`.
+//!
+//! The client opens an HTTP/3 connection to the proxy and sends an
+//! extended `CONNECT` (`:protocol connect-udp`) naming the QUIC server
+//! in its path. Once the proxy answers 2xx, the request stream stays
+//! open for the life of the tunnel and every UDP payload travels as an
+//! HTTP datagram (RFC 9297) on the same connection: a QUIC DATAGRAM
+//! frame that starts with the request stream's quarter ID and context
+//! ID 0, followed by the payload. The proxy relays each payload to the
+//! server and back.
+//!
+//! A [`MasqueUdpSocket`] stands in for the client endpoint's UDP
+//! socket, with the server as its only peer, like the SOCKS5 adapter in
+//! [`crate::common::proxy`]. Capsules on the request stream are never
+//! sent and are ignored when received.
+//!
+//! The tunnelled QUIC connection's packets must fit in the proxy
+//! connection's datagrams, so the latter starts from a larger MTU than
+//! QUIC's 1200-byte minimum; paths that can't carry that drop the
+//! connection's first packets and the connect fails.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use bytes::{Buf, Bytes, BytesMut};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, Endpoint, TransportConfig, UdpPoller};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::common::fallback::{deliver, AlwaysWritable};
+use crate::common::proxy::{proxy_host, proxy_tls, ProxyConfig};
+
+/// ALPN of HTTP/3.
+const ALPN_H3: &[u8] = b"h3";
+
+/// The proxy connection's initial MTU: room for a 1200-byte QUIC packet
+/// plus the DATAGRAM frame and HTTP datagram headers around it.
+const OUTER_MTU: u16 = 1400;
+
+/// Payloads queued from the proxy before quinn reads them.
+const QUEUE: usize = 1024;
+
+type RequestStream = h3::client::RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+type SendRequest = h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+/// The client endpoint's only "UDP socket", carrying its packets to and
+/// from the server through one `CONNECT-UDP` tunnel. Dropping it closes
+/// the tunnel and the connection to the proxy.
+pub struct MasqueUdpSocket {
+    server: SocketAddr,
+    local: SocketAddr,
+    conn: quinn::Connection,
+    /// Prepended to every payload: quarter stream ID and context ID 0.
+    prefix: Bytes,
+    inbound: Mutex<mpsc::Receiver<Bytes>>,
+    tasks: [JoinHandle<()>; 2],
+    /// Closing the request stream ends the tunnel, so it's held for the
+    /// socket's lifetime, as is the h3 handle it was opened from.
+    _request: RequestStream,
+    _h3: SendRequest,
+    _endpoint: Endpoint,
+}
+
+impl std::fmt::Debug for MasqueUdpSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasqueUdpSocket")
+            .field("server", &self.server)
+            .field("local", &self.local)
+            .field("proxy", &self.conn.remote_address())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MasqueUdpSocket {
+    /// Connect to the MASQUE proxy `proxy` and open a `CONNECT-UDP`
+    /// tunnel to `server`.
+    pub async fn connect(
+        proxy: &ProxyConfig,
+        proxy_ca: Option<&Path>,
+        server: SocketAddr,
+    ) -> Result<Arc<Self>> {
+        let ProxyConfig::Masque { addr, auth } = proxy else {
+            bail!("{proxy} is not a MASQUE proxy");
+        };
+        let proxy_addr = tokio::net::lookup_host(addr.as_str())
+            .await
+            .with_context(|| format!("failed to resolve MASQUE proxy {addr}"))?
+            .next()
+            .ok_or_else(|| anyhow!("MASQUE proxy {addr} resolved to no addresses"))?;
+
+        let mut tls = proxy_tls(proxy_ca)?;
+        tls.alpn_protocols = vec![ALPN_H3.to_vec()];
+        let mut client_config =
+            quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls)?));
+        let mut transport = TransportConfig::default();
+        transport.initial_mtu(OUTER_MTU);
+        client_config.transport_config(Arc::new(transport));
+
+        let bind: SocketAddr = match proxy_addr {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse().expect("static"),
+            SocketAddr::V6(_) => "[::]:0".parse().expect("static"),
+        };
+        let endpoint = Endpoint::client(bind)
+            .with_context(|| format!("failed to bind local UDP socket {bind}"))?;
+        let conn = endpoint
+            .connect_with(client_config, proxy_addr, proxy_host(addr))?
+            .await
+            .with_context(|| format!("failed to connect to MASQUE proxy at {addr}"))?;
+        if conn.max_datagram_size().is_none() {
+            bail!("MASQUE proxy at {addr} doesn't support QUIC datagrams");
+        }
+
+        let (mut driver, mut h3) = h3::client::builder()
+            .enable_extended_connect(true)
+            .enable_datagram(true)
+            .build::<_, _, Bytes>(h3_quinn::Connection::new(conn.clone()))
+            .await
+            .with_context(|| format!("HTTP/3 handshake with MASQUE proxy at {addr} failed"))?;
+        let drive = tokio::spawn(async move {
+            let e = std::future::poll_fn(|cx| driver.poll_close(cx)).await;
+            debug!(error = %e, "MASQUE proxy connection closed");
+        });
+
+        let mut request = h3
+            .send_request(connect_udp_request(addr, auth.as_ref(), server)?)
+            .await
+            .with_context(|| format!("failed to send CONNECT-UDP to MASQUE proxy at {addr}"))?;
+        let response = request
+            .recv_response()
+            .await
+            .with_context(|| format!("MASQUE proxy at {addr} didn't answer CONNECT-UDP"))?;
+        let status = response.status();
+        if status == http::StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+            if auth.is_some() {
+                bail!("MASQUE proxy rejected the credentials ({status})");
+            }
+            bail!("MASQUE proxy requires authentication ({status})");
+        }
+        if !status.is_success() {
+            bail!("MASQUE proxy refused CONNECT-UDP to {server} ({status})");
+        }
+        debug!(proxy = %proxy, target = %server, "CONNECT-UDP tunnel established");
+
+        let quarter_id = request.id().into_inner() / 4;
+        let mut prefix = BytesMut::new();
+        put_varint(&mut prefix, quarter_id);
+        put_varint(&mut prefix, 0);
+
+        let (inbound_tx, inbound) = mpsc::channel(QUEUE);
+        let recv = tokio::spawn({
+            let conn = conn.clone();
+            async move {
+                while let Ok(datagram) = conn.read_datagram().await {
+                    let Some(payload) = http_datagram_payload(datagram, quarter_id) else {
+                        continue;
+                    };
+                    if let Err(mpsc::error::TrySendError::Closed(_)) = inbound_tx.try_send(payload)
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Arc::new(Self {
+            server,
+            local: endpoint.local_addr()?,
+            conn,
+            prefix: prefix.freeze(),
+            inbound: Mutex::new(inbound),
+            tasks: [drive, recv],
+            _request: request,
+            _h3: h3,
+            _endpoint: endpoint,
+        }))
+    }
+}
+
+impl Drop for MasqueUdpSocket {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.conn.close(0u32.into(), b"");
+    }
+}
+
+impl AsyncUdpSocket for MasqueUdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(AlwaysWritable)
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let mut datagram = BytesMut::with_capacity(self.prefix.len() + transmit.contents.len());
+        datagram.extend_from_slice(&self.prefix);
+        datagram.extend_from_slice(transmit.contents);
+        // A packet too big for the proxy connection (an MTU probe) is
+        // lost like any other; quinn's MTU discovery copes.
+        if let Err(e) = self.conn.send_datagram(datagram.freeze()) {
+            debug!(error = %e, "dropping packet the MASQUE proxy can't take");
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [io::IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        if bufs.is_empty() || meta.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let mut inbound = self.inbound.lock().unwrap_or_else(PoisonError::into_inner);
+        match inbound.poll_recv(cx) {
+            Poll::Ready(Some(packet)) => Poll::Ready(Ok(deliver(packet, self.server, bufs, meta))),
+            // The proxy connection is gone; QUIC notices through its
+            // idle timeout, as it would for a UDP path gone silent.
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+/// The extended `CONNECT` for a UDP tunnel to `target` through the proxy
+/// at `proxy_addr`, using RFC 9298's default URI template.
+fn connect_udp_request(
+    proxy_addr: &str,
+    auth: Option<&(String, String)>,
+    target: SocketAddr,
+) -> Result<http::Request<()>> {
+    // IPv6 colons are percent-encoded in the path segment.
+    let host = target.ip().to_string().replace(':', "%3A");
+    let uri = format!(
+        "https://{proxy_addr}/.well-known/masque/udp/{host}/{}/",
+        target.port()
+    );
+    let mut request = http::Request::builder()
+        .method(http::Method::CONNECT)
+        .uri(uri)
+        .header("capsule-protocol", "?1")
+        .extension(h3::ext::Protocol::CONNECT_UDP);
+    if let Some((user, pass)) = auth {
+        let credentials = BASE64_STANDARD.encode(format!("{user}:{pass}"));
+        request = request.header("proxy-authorization", format!("Basic {credentials}"));
+    }
+    Ok(request.body(())?)
+}
+
+/// The UDP payload of an HTTP datagram for the request stream with
+/// `quarter_id`, or `None` if it's for another stream or context.
+fn http_datagram_payload(mut datagram: Bytes, quarter_id: u64) -> Option<Bytes> {
+    if get_varint(&mut datagram)? != quarter_id {
+        return None;
+    }
+    if get_varint(&mut datagram)? != 0 {
+        return None;
+    }
+    Some(datagram)
+}
+
+/// Append `v` as a QUIC variable-length integer (RFC 9000 §16).
+pub fn put_varint(buf: &mut BytesMut, v: u64) {
+    if v < 1 << 6 {
+        buf.extend_from_slice(&[v as u8]);
+    } else if v < 1 << 14 {
+        buf.extend_from_slice(&(v as u16 | 0x4000).to_be_bytes());
+    } else if v < 1 << 30 {
+        buf.extend_from_slice(&(v as u32 | 0x8000_0000).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&(v | 0xc000_0000_0000_0000).to_be_bytes());
+    }
+}
+
+/// Take a QUIC variable-length integer off the front of `buf`.
+pub fn get_varint(buf: &mut Bytes) -> Option<u64> {
+    let first = *buf.first()?;
+    let len = 1usize << (first >> 6);
+    if buf.len() < len {
+        return None;
+    }
+    let mut v = u64::from(first & 0x3f);
+    for &b in &buf[1..len] {
+        v = (v << 8) | u64::from(b);
+    }
+    buf.advance(len);
+    Some(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_round_trip() {
+        for v in [
+            0,
+            63,
+            64,
+            16_383,
+            16_384,
+            (1 << 30) - 1,
+            1 << 30,
+            (1 << 62) - 1,
+        ] {
+            let mut buf = BytesMut::new();
+            put_varint(&mut buf, v);
+            let mut buf = buf.freeze();
+            assert_eq!(get_varint(&mut buf), Some(v));
+            assert!(buf.is_empty());
+        }
+        // RFC 9000 §A.1's examples.
+        let mut buf = Bytes::from_static(&[0x7b, 0xbd]);
+        assert_eq!(get_varint(&mut buf), Some(15_293));
+        let mut buf = Bytes::from_static(&[0x40]);
+        assert_eq!(get_varint(&mut buf), None);
+    }
+
+    #[test]
+    fn http_datagrams_are_filtered_by_stream_and_context() {
+        let mut datagram = BytesMut::new();
+        put_varint(&mut datagram, 100);
+        put_varint(&mut datagram, 0);
+        datagram.extend_from_slice(b"quic");
+        let datagram = datagram.freeze();
+        assert_eq!(
+            http_datagram_payload(datagram.clone(), 100).as_deref(),
+            Some(&b"quic"[..])
+        );
+        assert_eq!(http_datagram_payload(datagram, 4), None);
+
+        let mut other_context = BytesMut::new();
+        put_varint(&mut other_context, 100);
+        put_varint(&mut other_context, 2);
+        other_context.extend_from_slice(b"quic");
+        assert_eq!(http_datagram_payload(other_context.freeze(), 100), None);
+    }
+
+    #[test]
+    fn connect_udp_request_follows_the_default_template() {
+        let auth = ("user".to_string(), "pass".to_string());
+        let request = connect_udp_request(
+            "proxy:443",
+            Some(&auth),
+            "[2001:db8::1]:4433".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(request.method(), http::Method::CONNECT);
+        assert_eq!(
+            request.uri(),
+            "https://proxy:443/.well-known/masque/udp/2001%3Adb8%3A%3A1/4433/"
+        );
+        assert_eq!(request.headers()["capsule-protocol"], "?1");
+        assert_eq!(
+            request.headers()["proxy-authorization"],
+            "Basic dXNlcjpwYXNz"
+        );
+        assert_eq!(
+            request.extensions().get::<h3::ext::Protocol>(),
+            Some(&h3::ext::Protocol::CONNECT_UDP)
+        );
+    }
+}
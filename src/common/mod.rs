@@ -1,7 +1,14 @@
 pub mod counted;
+pub mod datagram;
+pub mod fallback;
+pub mod http_proxy;
+pub mod listener;
+pub mod masque;
 pub mod proxy;
 pub mod quic;
 pub mod remote;
+pub mod resumable;
+pub mod routing;
 pub mod socks;
 pub mod tcp;
 pub mod tls;
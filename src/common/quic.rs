@@ -1,18 +1,23 @@
 use anyhow::{anyhow, Context, Result};
 use quinn::congestion::BbrConfig;
 use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
-use quinn::{ClientConfig, Endpoint};
+use quinn::{AsyncUdpSocket, ClientConfig, Endpoint, EndpointConfig, TokioRuntime};
 use quinn::{IdleTimeout, ServerConfig, TransportConfig, VarInt};
 use rcgen::generate_simple_self_signed;
+use rustls::client::{ClientSessionMemoryCache, ClientSessionStore, Resumption};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
 use rustls::{ClientConfig as TlsClientConfig, ServerConfig as TlsServerConfig};
 use std::fs;
 use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
 use std::{sync::Arc, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tracing::{debug, info, warn};
 
-use crate::common::proxy::{create_socks5_proxied_socket, ProxyConfig};
+use crate::common::fallback::{FallbackSocket, TcpDatagramSocket, ALPN_FALLBACK};
+use crate::common::masque::MasqueUdpSocket;
+use crate::common::proxy::{create_socks5_proxied_socket, ProxyConfig, RelayTarget};
 use crate::common::tls::{cert_sha256, format_fingerprint, ClientTlsConfig, ServerTlsConfig};
 
 // Use the HTTP/3 ALPN identifier so handshake fingerprints look like a real
@@ -21,6 +26,24 @@ use crate::common::tls::{cert_sha256, format_fingerprint, ClientTlsConfig, Serve
 // passive observers and DPI middleboxes far less likely to flag the traffic.
 static ALPN_QUIC_HTTP: &[&[u8]] = &[b"h3"];
 
+/// How many servers' worth of TLS session tickets the client keeps. A
+/// client only ever talks to one server (and one SNI), so this is just a
+/// comfortable upper bound for the v4/v6 endpoint pair.
+const SESSION_CACHE_SIZE: usize = 32;
+
+/// Client-side TLS session ticket store, shared by every QUIC endpoint a
+/// `rusnel client` process builds so a reconnect — even one that has to
+/// rebuild its endpoint, like the SOCKS5-proxied path — can resume the
+/// previous session and send its `SessionHello` as 0-RTT early data.
+///
+/// In-memory only: rustls doesn't expose a stable encoding for its
+/// TLS 1.3 ticket values, so tickets don't survive a process restart.
+pub type SessionCache = Arc<dyn ClientSessionStore>;
+
+pub fn new_session_cache() -> SessionCache {
+    Arc::new(ClientSessionMemoryCache::new(SESSION_CACHE_SIZE))
+}
+
 /// Congestion control algorithm used by the QUIC transport. Selectable
 /// per-endpoint at startup via `--congestion`.
 ///
@@ -72,7 +95,15 @@ fn build_transport_config(congestion: Congestion) -> Arc<TransportConfig> {
         .keep_alive_interval(Some(Duration::from_secs(15)))
         .max_idle_timeout(Some(idle_timeout))
         .max_concurrent_bidi_streams(VarInt::from_u32(1024))
-        .max_concurrent_uni_streams(VarInt::from_u32(0));
+        .max_concurrent_uni_streams(VarInt::from_u32(0))
+        // UDP conns ride RFC 9221 DATAGRAM frames (see
+        // `common::datagram`). quinn's default receive buffer matches the
+        // 1.25 MB stream window, which a burst of small datagrams across
+        // many flows overruns long before the router drains it; size it
+        // like the stream window instead. Datagrams that still don't fit
+        // are dropped, which is UDP semantics anyway.
+        .datagram_receive_buffer_size(Some(16 * 1024 * 1024))
+        .datagram_send_buffer_size(4 * 1024 * 1024);
     if let Congestion::Bbr = congestion {
         tc.congestion_controller_factory(Arc::new(BbrConfig::default()));
     }
@@ -94,12 +125,70 @@ pub fn create_server_endpoint(
     Ok(Endpoint::server(server_config, addr)?)
 }
 
+/// The TCP side of a server endpoint built by
+/// [`create_server_endpoint_with_fallback`]: run [`serve`](Self::serve)
+/// to accept clients on it.
+pub struct FallbackListener {
+    socket: Arc<FallbackSocket>,
+    listener: TcpListener,
+    tls: Arc<TlsServerConfig>,
+}
+
+impl FallbackListener {
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn serve(self) {
+        self.socket.serve(self.listener, self.tls).await
+    }
+}
+
+/// [`create_server_endpoint`], also accepting QUIC over TLS-over-TCP on
+/// `tcp_port` (see [`crate::common::fallback`]). Both transports feed
+/// the same endpoint, so its connections are alike whichever way they
+/// came in.
+pub async fn create_server_endpoint_with_fallback(
+    host: IpAddr,
+    port: u16,
+    tcp_port: u16,
+    tls: &ServerTlsConfig,
+    congestion: Congestion,
+) -> Result<(Endpoint, FallbackListener)> {
+    let (cert, key) = load_server_identity(tls)?;
+    let mut tcp_tls = build_tls_server_config(tls, cert.clone(), key.clone_key())?;
+    tcp_tls.alpn_protocols = vec![ALPN_FALLBACK.to_vec()];
+    let mut server_config = build_quic_server_config(tls, cert, key)?;
+    server_config.transport_config(build_transport_config(congestion));
+
+    let udp = std::net::UdpSocket::bind(SocketAddr::new(host, port))?;
+    let socket = FallbackSocket::new(udp)?;
+    let endpoint = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        Some(server_config),
+        socket.clone(),
+        Arc::new(TokioRuntime),
+    )?;
+    let listener = TcpListener::bind(SocketAddr::new(host, tcp_port))
+        .await
+        .with_context(|| format!("failed to bind TCP fallback port {tcp_port}"))?;
+    Ok((
+        endpoint,
+        FallbackListener {
+            socket,
+            listener,
+            tls: Arc::new(tcp_tls),
+        },
+    ))
+}
+
 pub fn create_client_endpoint(
     tls: &ClientTlsConfig,
     congestion: Congestion,
     server_addr: SocketAddr,
+    sessions: &SessionCache,
 ) -> Result<Endpoint> {
-    let mut client_config = build_quic_client_config(tls)?;
+    let mut client_config = build_quic_client_config(tls, sessions)?;
     client_config.transport_config(build_transport_config(congestion));
     // Bind to a wildcard address in the *same family* as the server we're
     // trying to reach. quinn's `Endpoint::client("0.0.0.0:0")` convenience
@@ -118,24 +207,90 @@ pub fn create_client_endpoint(
     Ok(endpoint)
 }
 
+/// Move a client endpoint onto a fresh UDP socket (wildcard address, same
+/// family, new ephemeral port), keeping its connections. Their next
+/// packets leave from the new socket and the server follows them there
+/// with QUIC path validation, so tunnels and conns carry on instead of
+/// the client reconnecting. Returns the new local address.
+pub fn rebind_client_endpoint(endpoint: &Endpoint) -> Result<SocketAddr> {
+    let bind_addr: SocketAddr = match endpoint.local_addr()? {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+    let socket = std::net::UdpSocket::bind(bind_addr)?;
+    socket.set_nonblocking(true)?;
+    endpoint.rebind(socket)?;
+    Ok(endpoint.local_addr()?)
+}
+
+/// Like [`create_client_endpoint`], but carries the connection over
+/// TLS on `stream`, a TCP connection to the server's fallback port at
+/// `server` (see [`crate::common::fallback`]) — made directly or through
+/// an HTTP proxy — whose local end is `local`. Single-use, like the
+/// proxied endpoint.
+pub async fn create_client_endpoint_over_tcp<S>(
+    tls: &ClientTlsConfig,
+    congestion: Congestion,
+    stream: S,
+    local: SocketAddr,
+    server: SocketAddr,
+    server_name: &str,
+    sessions: &SessionCache,
+) -> Result<Endpoint>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut client_config = build_quic_client_config(tls, sessions)?;
+    client_config.transport_config(build_transport_config(congestion));
+    let mut tcp_tls = build_tls_client_config(tls)?;
+    tcp_tls.alpn_protocols = vec![ALPN_FALLBACK.to_vec()];
+
+    let socket =
+        TcpDatagramSocket::connect(stream, local, server, server_name, Arc::new(tcp_tls)).await?;
+    let mut endpoint = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(TokioRuntime),
+    )?;
+    endpoint.set_default_client_config(client_config);
+    Ok(endpoint)
+}
+
 /// Like [`create_client_endpoint`], but routes every QUIC datagram through a
-/// SOCKS5 proxy via UDP ASSOCIATE. Async because the SOCKS5 control-plane
-/// handshake is performed up-front; the returned endpoint is single-use
-/// (one QUIC connection per association — disconnect tears the proxy
-/// relay down, requiring a fresh handshake on reconnect).
+/// SOCKS5 proxy via UDP ASSOCIATE, or a MASQUE proxy via `CONNECT-UDP`.
+/// Async because the proxy handshake is performed up-front; the returned
+/// endpoint is single-use (one QUIC connection per association — disconnect
+/// tears the proxy relay down, requiring a fresh handshake on reconnect).
+/// Also returns the address to connect the endpoint to, which for a
+/// server named to a `socks5h://` proxy is the proxy's relay.
 pub async fn create_client_endpoint_via_proxy(
     tls: &ClientTlsConfig,
     congestion: Congestion,
-    server_addr: SocketAddr,
+    target: RelayTarget,
     proxy: &ProxyConfig,
-) -> Result<Endpoint> {
-    use quinn::{EndpointConfig, TokioRuntime};
+    proxy_ca: Option<&Path>,
+    sessions: &SessionCache,
+) -> Result<(Endpoint, SocketAddr)> {
     use std::sync::Arc;
 
-    let mut client_config = build_quic_client_config(tls)?;
+    let mut client_config = build_quic_client_config(tls, sessions)?;
     client_config.transport_config(build_transport_config(congestion));
 
-    let socket = create_socks5_proxied_socket(proxy, server_addr).await?;
+    let (socket, peer): (Arc<dyn AsyncUdpSocket>, _) = match (proxy, target) {
+        (ProxyConfig::Masque { .. }, RelayTarget::Addr(server)) => (
+            MasqueUdpSocket::connect(proxy, proxy_ca, server).await?,
+            server,
+        ),
+        (ProxyConfig::Masque { .. }, RelayTarget::Name(..)) => {
+            return Err(anyhow!("{proxy} needs the server's address"));
+        }
+        (_, target) => {
+            let socket = create_socks5_proxied_socket(proxy, target).await?;
+            let peer = socket.peer();
+            (socket, peer)
+        }
+    };
     let mut endpoint = Endpoint::new_with_abstract_socket(
         EndpointConfig::default(),
         None,
@@ -143,7 +298,7 @@ pub async fn create_client_endpoint_via_proxy(
         Arc::new(TokioRuntime),
     )?;
     endpoint.set_default_client_config(client_config);
-    Ok(endpoint)
+    Ok((endpoint, peer))
 }
 
 /// Resolve the server's certificate + key from the TLS configuration. Also
@@ -230,7 +385,7 @@ fn load_pem_identity(
 
 /// Load all PEM-encoded certificates from `path`. Useful for CA bundles, which
 /// may contain multiple certs.
-fn load_pem_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+pub(crate) fn load_pem_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
     let pem =
         fs::read(path).with_context(|| format!("failed to read cert file {}", path.display()))?;
     let mut reader = std::io::BufReader::new(pem.as_slice());
@@ -274,12 +429,14 @@ fn write_secret_file(path: &Path, contents: &[u8]) -> Result<()> {
     Ok(())
 }
 
-fn build_quic_server_config(
+/// The server's TLS settings (identity, client auth) shared by QUIC and
+/// the TCP fallback transport; each sets its own ALPN on top.
+fn build_tls_server_config(
     tls: &ServerTlsConfig,
     cert: Vec<CertificateDer<'static>>,
     key: PrivateKeyDer<'static>,
-) -> Result<ServerConfig> {
-    let mut server_crypto: TlsServerConfig = match tls {
+) -> Result<TlsServerConfig> {
+    Ok(match tls {
         ServerTlsConfig::Insecure
         | ServerTlsConfig::SelfSigned { .. }
         | ServerTlsConfig::Provided { .. } => TlsServerConfig::builder()
@@ -295,26 +452,36 @@ fn build_quic_server_config(
                 .with_client_cert_verifier(verifier)
                 .with_single_cert(cert, key)?
         }
-    };
+    })
+}
 
+fn build_quic_server_config(
+    tls: &ServerTlsConfig,
+    cert: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig> {
+    let mut server_crypto = build_tls_server_config(tls, cert, key)?;
     server_crypto.alpn_protocols = ALPN_QUIC_HTTP.iter().map(|&x| x.into()).collect();
+    // Accept 0-RTT from resuming clients (QUIC requires the sentinel
+    // `u32::MAX`; the real limit is the transport's flow control). The
+    // default stateful session cache hands each ticket out once, so a
+    // captured 0-RTT flight can't be replayed against us; the session
+    // handler still defers everything with side effects until the
+    // handshake completes (see `server::handle_client_connection`).
+    server_crypto.max_early_data_size = u32::MAX;
     Ok(ServerConfig::with_crypto(Arc::new(
         QuicServerConfig::try_from(server_crypto)?,
     )))
 }
 
-fn build_quic_client_config(tls: &ClientTlsConfig) -> Result<ClientConfig> {
-    let mut client_crypto = match tls {
-        ClientTlsConfig::Insecure => {
-            warn!(
-                "starting client in --insecure mode: skipping server certificate verification. \
-                 MITM-vulnerable; for testing only."
-            );
-            TlsClientConfig::builder()
-                .dangerous()
-                .with_custom_certificate_verifier(SkipServerVerification::new())
-                .with_no_client_auth()
-        }
+/// The client's TLS settings (server verification, client cert) shared
+/// by QUIC and the TCP fallback transport; each sets its own ALPN on top.
+fn build_tls_client_config(tls: &ClientTlsConfig) -> Result<TlsClientConfig> {
+    Ok(match tls {
+        ClientTlsConfig::Insecure => TlsClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(SkipServerVerification::new())
+            .with_no_client_auth(),
         ClientTlsConfig::Fingerprint { sha256, .. } => TlsClientConfig::builder()
             .dangerous()
             .with_custom_certificate_verifier(FingerprintVerifier::new(*sha256))
@@ -336,9 +503,23 @@ fn build_quic_client_config(tls: &ClientTlsConfig) -> Result<ClientConfig> {
                 .with_client_auth_cert(cert_chain, key)
                 .context("failed to install client auth cert")?
         }
-    };
+    })
+}
 
+fn build_quic_client_config(
+    tls: &ClientTlsConfig,
+    sessions: &SessionCache,
+) -> Result<ClientConfig> {
+    if let ClientTlsConfig::Insecure = tls {
+        warn!(
+            "starting client in --insecure mode: skipping server certificate verification. \
+             MITM-vulnerable; for testing only."
+        );
+    }
+    let mut client_crypto = build_tls_client_config(tls)?;
     client_crypto.alpn_protocols = ALPN_QUIC_HTTP.iter().map(|&x| x.into()).collect();
+    client_crypto.resumption = Resumption::store(sessions.clone());
+    client_crypto.enable_early_data = true;
     Ok(ClientConfig::new(Arc::new(QuicClientConfig::try_from(
         client_crypto,
     )?)))
@@ -3,6 +3,7 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 /// Wire-level protocol selector. Kept as a separate enum so the parser and
@@ -58,32 +59,145 @@ impl fmt::Display for HostPort {
 
 /// What kind of tunnel this remote represents. The `local` socket is always
 /// the address the *initiating* client (or, for reverse remotes, the server)
-/// listens on; the `remote` host:port is the peer's connect target. SOCKS5
-/// has no static remote — the target is supplied per-connection by the SOCKS
-/// handshake.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// listens on; the `remote` host:port is the peer's connect target. The
+/// proxy listeners (SOCKS5 and HTTP) have no static remote — the target is
+/// supplied per-connection by the proxy request. Their `auth`, typed as
+/// `user:pass@` in front of the remote, is a `(username, password)` pair the
+/// listener requires (RFC 1929 for SOCKS5, Basic for HTTP) on top of any
+/// from the listening side's `--socks-auth-file`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum RemoteKind {
-    Tcp { local: SocketAddr, remote: HostPort },
-    Udp { local: SocketAddr, remote: HostPort },
-    Socks5 { local: SocketAddr },
+    Tcp {
+        local: SocketAddr,
+        remote: HostPort,
+    },
+    Udp {
+        local: SocketAddr,
+        remote: HostPort,
+    },
+    Socks5 {
+        local: SocketAddr,
+        #[serde(default)]
+        auth: Option<(String, String)>,
+    },
+    /// HTTP proxy: `CONNECT host:port` and absolute-URI plain requests.
+    Http {
+        local: SocketAddr,
+        #[serde(default)]
+        auth: Option<(String, String)>,
+    },
+    /// TCP and UDP on the same ports (`53:dns:53/both`): one tunnel
+    /// whose listening side binds a socket for each, and whose conns say
+    /// which one they came in on with [`OpenConn::protocol`].
+    Both {
+        local: SocketAddr,
+        remote: HostPort,
+    },
+    /// A stream tunnel like `Tcp` with a unix domain socket at one end
+    /// or both: `unix:/tmp/docker.sock:unix:/var/run/docker.sock`,
+    /// `R:9000:unix:/run/app.sock`.
+    Unix {
+        local: StreamEnd<SocketAddr>,
+        remote: StreamEnd<HostPort>,
+    },
+}
+
+// As derived, but without the proxy listeners' passwords.
+impl fmt::Debug for RemoteKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let auth =
+            |auth: &Option<(String, String)>| auth.as_ref().map(|(user, _)| (user.clone(), ".."));
+        match self {
+            RemoteKind::Tcp { local, remote } => f
+                .debug_struct("Tcp")
+                .field("local", local)
+                .field("remote", remote)
+                .finish(),
+            RemoteKind::Udp { local, remote } => f
+                .debug_struct("Udp")
+                .field("local", local)
+                .field("remote", remote)
+                .finish(),
+            RemoteKind::Socks5 { local, auth: a } => f
+                .debug_struct("Socks5")
+                .field("local", local)
+                .field("auth", &auth(a))
+                .finish(),
+            RemoteKind::Http { local, auth: a } => f
+                .debug_struct("Http")
+                .field("local", local)
+                .field("auth", &auth(a))
+                .finish(),
+            RemoteKind::Both { local, remote } => f
+                .debug_struct("Both")
+                .field("local", local)
+                .field("remote", remote)
+                .finish(),
+            RemoteKind::Unix { local, remote } => f
+                .debug_struct("Unix")
+                .field("local", local)
+                .field("remote", remote)
+                .finish(),
+        }
+    }
+}
+
+/// One end of a [`RemoteKind::Unix`] tunnel: a TCP address, or the path
+/// of a unix domain socket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum StreamEnd<A> {
+    Tcp(A),
+    Unix(PathBuf),
+}
+
+impl<A> StreamEnd<A> {
+    pub fn unix_path(&self) -> Option<&Path> {
+        match self {
+            StreamEnd::Tcp(_) => None,
+            StreamEnd::Unix(path) => Some(path),
+        }
+    }
 }
 
 impl RemoteKind {
+    /// The local TCP/UDP address. A unix socket listener has none; it
+    /// reports the dummy `0.0.0.0:0`, which is never bound.
     pub fn local(&self) -> SocketAddr {
         match self {
             RemoteKind::Tcp { local, .. }
             | RemoteKind::Udp { local, .. }
-            | RemoteKind::Socks5 { local } => *local,
+            | RemoteKind::Both { local, .. }
+            | RemoteKind::Socks5 { local, .. }
+            | RemoteKind::Http { local, .. }
+            | RemoteKind::Unix {
+                local: StreamEnd::Tcp(local),
+                ..
+            } => *local,
+            RemoteKind::Unix {
+                local: StreamEnd::Unix(_),
+                ..
+            } => SocketAddr::new(any_v4(), 0),
         }
     }
 
+    /// The one protocol the tunnel carries; `None` for the proxy
+    /// listeners, whose targets pick it, and for `Both`.
     pub fn protocol(&self) -> Option<Protocol> {
         match self {
-            RemoteKind::Tcp { .. } => Some(Protocol::Tcp),
+            RemoteKind::Tcp { .. } | RemoteKind::Unix { .. } => Some(Protocol::Tcp),
             RemoteKind::Udp { .. } => Some(Protocol::Udp),
-            RemoteKind::Socks5 { .. } => None,
+            RemoteKind::Socks5 { .. } | RemoteKind::Http { .. } | RemoteKind::Both { .. } => None,
         }
     }
+
+    /// The `protocol` half of a `Both` tunnel, as a plain `Tcp` or `Udp`
+    /// one. `None` for every other kind.
+    pub fn half(&self, protocol: Protocol) -> Option<RemoteKind> {
+        let RemoteKind::Both { local, remote } = self else {
+            return None;
+        };
+        Some(make_host_port_kind(*local, remote.clone(), Some(protocol)))
+    }
 }
 
 /// A single tunnel description (a *tunnel declaration*). One of these per
@@ -105,6 +219,29 @@ pub struct RemoteRequest {
     /// `kind.local` (always `0.0.0.0:0`) is never bound.
     #[serde(default)]
     pub stdio: bool,
+    /// The port range this remote was expanded from, when the user typed
+    /// one (`5000-5010:host:5000-5010`). Purely descriptive: each port of
+    /// the range is its own tunnel.
+    #[serde(default)]
+    pub range: Option<PortRange>,
+    /// The label the user gave this tunnel (`ssh=R:2222:localhost:22`, or
+    /// `name` in the config file's table form). The server keeps it on the
+    /// tunnel so logs and `rusnel ctl` can refer to it by name.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// A `first-last` port range on both sides of a remote, which must span
+/// the same number of ports. Every remote [`RemoteRequest::expand`]
+/// produces for it carries the same `PortRange`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    /// First local port.
+    pub local: u16,
+    /// First remote port.
+    pub remote: u16,
+    /// Ports in the range.
+    pub len: u16,
 }
 
 impl RemoteRequest {
@@ -113,7 +250,80 @@ impl RemoteRequest {
             direction,
             kind,
             stdio: false,
+            range: None,
+            name: None,
+        }
+    }
+
+    /// Parse a remote spec that may carry a port range, returning one
+    /// remote per port: `5000-5002:host:6000-6002` is `5000:host:6000`,
+    /// `5001:host:6001` and `5002:host:6002`. A spec without a range
+    /// comes back as a single remote, exactly as `from_str` parses it.
+    /// A named range names each remote after its local port: `rtp=…` gives
+    /// `rtp-5000`, `rtp-5001`, and so on.
+    pub fn expand(input: &str) -> Result<Vec<RemoteRequest>> {
+        let (name, rest) = parse_name(input)?;
+        let (direction, auth, suffix, tokens) = split_spec(rest)?;
+        let mut ranges = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if let Some(range) = parse_port_range(token) {
+                ranges.push((i, range?));
+            }
+        }
+        if ranges.is_empty() {
+            let mut req = build_request(direction, auth, suffix, &tokens)?;
+            req.name = name;
+            return Ok(vec![req]);
         }
+        if tokens[0] == STDIO_KEYWORD
+            || tokens.contains(&UNIX_KEYWORD)
+            || tokens.last().is_some_and(|t| is_proxy_keyword(t))
+        {
+            return Err(anyhow!(
+                "Invalid format: port ranges only apply to tcp and udp remotes"
+            ));
+        }
+        // A single port token (`5000-5010`, `host:5000-5010`) is both
+        // the local and the remote port; otherwise both need a range.
+        let ((local_slot, local), (remote_slot, remote)) =
+            match (port_slots(tokens.len()), ranges.as_slice()) {
+                ([slot], [(i, range)]) if slot == i => ((*i, *range), (*i, *range)),
+                ([l, r], [(i, lr), (j, rr)]) if l == i && r == j => ((*i, *lr), (*j, *rr)),
+                ([_, _], [_]) => {
+                    return Err(anyhow!(
+                        "Invalid format: a port range needs a matching range on the other side"
+                    ))
+                }
+                _ => return Err(anyhow!("Invalid format: port range in place of a host")),
+            };
+        let span = local.1 - local.0;
+        if remote.1 - remote.0 != span {
+            return Err(anyhow!(
+                "Invalid format: port ranges {}-{} and {}-{} differ in length",
+                local.0,
+                local.1,
+                remote.0,
+                remote.1
+            ));
+        }
+        let range = PortRange {
+            local: local.0,
+            remote: remote.0,
+            len: span + 1,
+        };
+        (0..=span)
+            .map(|offset| {
+                let local_port = (local.0 + offset).to_string();
+                let remote_port = (remote.0 + offset).to_string();
+                let mut tokens = tokens.clone();
+                tokens[local_slot] = &local_port;
+                tokens[remote_slot] = &remote_port;
+                let mut req = build_request(direction, auth.clone(), suffix, &tokens)?;
+                req.name = name.as_ref().map(|name| format!("{name}-{local_port}"));
+                req.range = Some(range);
+                Ok(req)
+            })
+            .collect()
     }
 
     /// `true` if this remote represents SOCKS5 traffic — a standalone
@@ -128,6 +338,13 @@ impl RemoteRequest {
         matches!(self.kind, RemoteKind::Socks5 { .. })
     }
 
+    /// `true` for an `http` proxy listener declaration. Its CONNECTs and
+    /// plain requests travel as [`OpenConn::dynamic`] TCP targets, just
+    /// like SOCKS5's, and `--allow-socks` gates it the same way.
+    pub fn is_http(&self) -> bool {
+        matches!(self.kind, RemoteKind::Http { .. })
+    }
+
     pub fn is_reversed(&self) -> bool {
         matches!(self.direction, Direction::Reverse)
     }
@@ -136,6 +353,33 @@ impl RemoteRequest {
         self.stdio
     }
 
+    /// This remote as the server is told about it. A forward proxy
+    /// listener's credentials are only checked on the client, so they
+    /// stay there; a reverse one's go along for the server to enforce.
+    pub fn for_server(mut self) -> Self {
+        if !self.is_reversed() {
+            if let RemoteKind::Socks5 { auth, .. } | RemoteKind::Http { auth, .. } = &mut self.kind
+            {
+                *auth = None;
+            }
+        }
+        self
+    }
+
+    /// The unix socket the server touches for this remote, if any: the
+    /// target a forward remote dials, or the listener a reverse one
+    /// binds. The server gates these behind `--allow-unix`.
+    pub fn server_unix_path(&self) -> Option<&Path> {
+        let RemoteKind::Unix { local, remote } = &self.kind else {
+            return None;
+        };
+        if self.is_reversed() {
+            local.unix_path()
+        } else {
+            remote.unix_path()
+        }
+    }
+
     /// `local_host:local_port` as a `SocketAddr`. Use the resulting value's
     /// `Display` for binding/listening — that path brackets IPv6 literals
     /// correctly (`[::1]:8080`).
@@ -145,15 +389,53 @@ impl RemoteRequest {
 
     /// `remote_host:remote_port` formatted for `TcpStream::connect`,
     /// `UdpSocket::send_to`, and friends. Brackets bare IPv6 literals.
-    /// Returns `None` for SOCKS5 remotes, which have no static target.
+    /// Returns `None` for proxy remotes, which have no static target,
+    /// and for unix socket targets.
     pub fn remote_addr_string(&self) -> Option<String> {
         match &self.kind {
-            RemoteKind::Tcp { remote, .. } | RemoteKind::Udp { remote, .. } => {
-                Some(remote.to_addr_string())
-            }
-            RemoteKind::Socks5 { .. } => None,
+            RemoteKind::Tcp { remote, .. }
+            | RemoteKind::Udp { remote, .. }
+            | RemoteKind::Both { remote, .. }
+            | RemoteKind::Unix {
+                remote: StreamEnd::Tcp(remote),
+                ..
+            } => Some(remote.to_addr_string()),
+            RemoteKind::Socks5 { .. }
+            | RemoteKind::Http { .. }
+            | RemoteKind::Unix {
+                remote: StreamEnd::Unix(_),
+                ..
+            } => None,
         }
     }
+
+    /// The whole range this remote was expanded from, in the same shape
+    /// as its `Display`: `5000-5010=>host:5000-5010/tcp`. `None` for a
+    /// remote that wasn't part of a range.
+    pub fn range_spec(&self) -> Option<String> {
+        let range = self.range?;
+        let (remote, protocol) = match &self.kind {
+            RemoteKind::Tcp { remote, .. } => (remote, "tcp"),
+            RemoteKind::Udp { remote, .. } => (remote, "udp"),
+            RemoteKind::Both { remote, .. } => (remote, "both"),
+            RemoteKind::Socks5 { .. } | RemoteKind::Http { .. } | RemoteKind::Unix { .. } => {
+                return None
+            }
+        };
+        let last = |first: u16| first + (range.len - 1);
+        let addr = remote.to_addr_string();
+        let host = addr
+            .rsplit_once(':')
+            .map_or(addr.as_str(), |(host, _)| host);
+        Some(format!(
+            "{}{}-{}=>{host}:{}-{}/{protocol}",
+            if self.is_reversed() { "R:" } else { "" },
+            range.local,
+            last(range.local),
+            range.remote,
+            last(range.remote)
+        ))
+    }
 }
 
 impl fmt::Display for Protocol {
@@ -181,17 +463,48 @@ impl fmt::Display for RemoteRequest {
                 RemoteKind::Udp { remote, .. } => {
                     write!(f, "stdio=>{}/udp", remote.to_addr_string())
                 }
+                RemoteKind::Both { remote, .. } => {
+                    write!(f, "stdio=>{}/both", remote.to_addr_string())
+                }
                 RemoteKind::Socks5 { .. } => write!(f, "stdio=>socks"),
+                RemoteKind::Http { .. } => write!(f, "stdio=>http"),
+                RemoteKind::Unix { remote, .. } => write!(f, "stdio=>{remote}"),
             };
         }
         match &self.kind {
-            RemoteKind::Socks5 { local } => write!(f, "{}=>socks", local.port()),
+            // Don't print the password.
+            RemoteKind::Socks5 {
+                local,
+                auth: Some(_),
+            } => write!(f, "<auth>@{}=>socks", local.port()),
+            RemoteKind::Socks5 { local, .. } => write!(f, "{}=>socks", local.port()),
+            RemoteKind::Http {
+                local,
+                auth: Some(_),
+            } => write!(f, "<auth>@{}=>http", local.port()),
+            RemoteKind::Http { local, .. } => write!(f, "{}=>http", local.port()),
             RemoteKind::Tcp { local, remote } => {
                 write!(f, "{}=>{}/tcp", local.port(), remote.to_addr_string())
             }
             RemoteKind::Udp { local, remote } => {
                 write!(f, "{}=>{}/udp", local.port(), remote.to_addr_string())
             }
+            RemoteKind::Both { local, remote } => {
+                write!(f, "{}=>{}/both", local.port(), remote.to_addr_string())
+            }
+            RemoteKind::Unix { local, remote } => match local {
+                StreamEnd::Tcp(local) => write!(f, "{}=>{remote}", local.port()),
+                StreamEnd::Unix(path) => write!(f, "unix:{}=>{remote}", path.display()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for StreamEnd<HostPort> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamEnd::Tcp(remote) => f.write_str(&remote.to_addr_string()),
+            StreamEnd::Unix(path) => write!(f, "unix:{}", path.display()),
         }
     }
 }
@@ -204,33 +517,285 @@ impl SerdeHelper for RemoteRequest {}
 //
 // Right after the QUIC connection is established the client opens its
 // first bi-stream and sends a [`SessionHello`] carrying *every* tunnel
-// declaration it wants. The server validates them all in one shot
-// against `--allow-reverse` / `--allow-socks` and either accepts the
-// whole batch — assigning one `tunnel_id` per declaration in the same
-// order — or rejects the entire session. Subsequent bi-streams (in
-// either direction) start with an [`OpenConn`] frame that names the
-// `tunnel_id` they belong to, so neither side ever re-sends a full
-// [`RemoteRequest`] on the data plane.
+// declaration it wants. The server validates each against
+// `--allow-reverse` / `--allow-socks`, binds the listeners of reverse
+// ones, and answers with one [`TunnelStatus`] per declaration in the
+// same order — an assigned `tunnel_id` or the reason it isn't running.
+// A strict hello turns any refusal into a rejection of the session.
+// Subsequent bi-streams (in either direction) start with an
+// [`OpenConn`] frame that names the `tunnel_id` they belong to, so
+// neither side ever re-sends a full [`RemoteRequest`] on the data
+// plane.
+
+/// Revision of the control-plane wire format spoken by this build. Bumped
+/// whenever a frame changes shape in a way an older peer can't decode.
+/// Builds that predate version negotiation send no version at all and
+/// are treated as protocol 0. Protocol 2 added [`RemoteKind`] variants
+/// and [`OpenConn`] fields, which protocol 1 peers can't decode.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest peer protocol this build still interoperates with.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Optional wire features. Each side advertises the ones it implements
+/// in its [`PeerInfo`]; a feature is only used on a connection when
+/// both sides advertised it. Travels by [`name`](Self::name) so a peer
+/// can advertise features this build has never heard of — those are
+/// simply not negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// UDP conn payload in flow-tagged QUIC DATAGRAM frames (see
+    /// [`crate::common::datagram`]). Still subject to the peer enabling
+    /// DATAGRAM support at the transport level.
+    Datagrams,
+    /// Pipelined [`OpenConn`] on static TCP tunnels
+    /// ([`OpenConn::optimistic`]).
+    OptimisticOpen,
+    /// [`TunnelControl`] requests on the hello stream.
+    TunnelControl,
+    /// The server holds a dropped session for a grace period and hands
+    /// it to a later hello carrying its [`ResumeToken`].
+    SessionResume,
+    /// TCP conns of static tunnels can carry on over a later connection
+    /// ([`OpenConn::resume`]). Opt-in on the client.
+    ResumableConns,
+    /// SOCKS5 BIND over dynamic tunnels ([`DynamicTarget::Bind`]).
+    SocksBind,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Datagrams,
+        Capability::OptimisticOpen,
+        Capability::TunnelControl,
+        Capability::SessionResume,
+        Capability::ResumableConns,
+        Capability::SocksBind,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Datagrams => "datagrams",
+            Capability::OptimisticOpen => "optimistic-open",
+            Capability::TunnelControl => "tunnel-control",
+            Capability::SessionResume => "session-resume",
+            Capability::ResumableConns => "resumable-conns",
+            Capability::SocksBind => "socks-bind",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What one side of a session says about itself in the hello exchange.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// [`PROTOCOL_VERSION`] of the sender. 0 for peers that predate
+    /// negotiation (the field is missing from their frames).
+    pub protocol_version: u32,
+    /// Crate version of the sender, for logs and the admin API only.
+    pub software_version: String,
+    /// [`Capability`] names the sender implements.
+    pub capabilities: Vec<String>,
+}
+
+impl PeerInfo {
+    /// This build's own description.
+    pub fn local() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: Capability::ALL
+                .iter()
+                .map(|c| c.name().to_string())
+                .collect(),
+        }
+    }
+
+    /// `Err` with a human-readable reason when this build can't talk to
+    /// a peer that described itself as `self`.
+    pub fn check_compatible(&self) -> Result<(), String> {
+        if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol_version) {
+            return Ok(());
+        }
+        let software = if self.software_version.is_empty() {
+            "unknown"
+        } else {
+            &self.software_version
+        };
+        Err(format!(
+            "incompatible protocol version {} (peer rusnel {software}); this side speaks \
+             {MIN_PROTOCOL_VERSION}..={PROTOCOL_VERSION} (rusnel {})",
+            self.protocol_version,
+            env!("CARGO_PKG_VERSION"),
+        ))
+    }
+
+    /// Capabilities both this build and the peer described by `self`
+    /// implement, in [`Capability::ALL`] order.
+    pub fn negotiate(&self) -> Vec<Capability> {
+        self.negotiate_with(&Self::local())
+    }
+
+    /// Capabilities both `local` and the peer described by `self`
+    /// advertised, for a side that left some of its own out.
+    pub fn negotiate_with(&self, local: &PeerInfo) -> Vec<Capability> {
+        Capability::ALL
+            .into_iter()
+            .filter(|c| {
+                let advertised = |p: &PeerInfo| p.capabilities.iter().any(|n| n == c.name());
+                advertised(self) && advertised(local)
+            })
+            .collect()
+    }
+
+    /// This description without `capability`.
+    pub fn without(mut self, capability: Capability) -> Self {
+        self.capabilities.retain(|n| n != capability.name());
+        self
+    }
+}
 
 /// First control frame the client sends on a fresh QUIC connection.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SessionHello {
     pub remotes: Vec<RemoteRequest>,
+    /// The client's version and capabilities. Missing (protocol 0) from
+    /// pre-negotiation clients, which the server then rejects with a
+    /// `Failed` they can still decode.
+    #[serde(default)]
+    pub peer: PeerInfo,
+    /// All-or-nothing: fail the whole session if any remote isn't
+    /// accepted, instead of running the ones that are.
+    #[serde(default)]
+    pub strict: bool,
+    /// Token of an earlier session to take over: its reverse listeners
+    /// and tunnel ids carry over to the remotes this hello declares
+    /// again. Ignored if the server no longer holds that session.
+    #[serde(default)]
+    pub resume: Option<ResumeToken>,
+}
+
+impl SessionHello {
+    /// Non-strict hello declaring `remotes` (as
+    /// [`RemoteRequest::for_server`]), describing this build.
+    pub fn new(remotes: Vec<RemoteRequest>) -> Self {
+        Self {
+            remotes: remotes.into_iter().map(RemoteRequest::for_server).collect(),
+            peer: PeerInfo::local(),
+            strict: false,
+            resume: None,
+        }
+    }
+}
+
+/// Names a session the server may hold onto after its connection is
+/// lost (see [`Capability::SessionResume`]). Issued in the hello
+/// response and good for one resumption; the resumed session gets a
+/// new one. Resumable conns are named the same way, by their opener
+/// ([`ConnResume::New`]).
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResumeToken([u8; 16]);
+
+impl ResumeToken {
+    /// A fresh token from the TLS provider's secure RNG.
+    pub fn generate() -> Result<Self> {
+        let mut bytes = [0u8; 16];
+        rustls::crypto::ring::default_provider()
+            .secure_random
+            .fill(&mut bytes)
+            .map_err(|_| anyhow!("no randomness for a resume token"))?;
+        Ok(Self(bytes))
+    }
+}
+
+// Tokens are bearer credentials; keep them out of logs.
+impl fmt::Debug for ResumeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResumeToken(..)")
+    }
 }
 
 impl SerdeHelper for SessionHello {}
 
-/// Server's reply to [`SessionHello`]. On success, `tunnel_ids[i]` is
-/// the server-assigned id for `hello.remotes[i]`. On failure, the
-/// server closes the connection.
+/// What became of one remote declared in a [`SessionHello`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TunnelStatus {
+    /// Running under the server-assigned `tunnel_id`. For a reverse
+    /// remote this means the server's listener is already bound, and
+    /// `bound` is its actual address — the one to use when the remote
+    /// asked for port 0.
+    Accepted {
+        tunnel_id: u64,
+        #[serde(default)]
+        bound: Option<SocketAddr>,
+    },
+    /// Refused by the server's policy (`--allow-reverse`, `--allow-socks`).
+    Rejected(String),
+    /// A reverse remote whose listener the server couldn't bind.
+    BindFailed(String),
+}
+
+/// Server's reply to [`SessionHello`]. On success, `tunnels[i]` is the
+/// outcome for `hello.remotes[i]` and `peer` describes the server; the
+/// session runs with whichever remotes were accepted, and
+/// `resume_token` is set if the server will hold it for resumption. `Failed` rejects
+/// the whole session (incompatible protocol version, or a strict hello
+/// with a remote that wasn't accepted) and the server closes the
+/// connection.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SessionHelloResponse {
-    Ok { tunnel_ids: Vec<u64> },
+    Ok {
+        tunnels: Vec<TunnelStatus>,
+        #[serde(default)]
+        peer: PeerInfo,
+        #[serde(default)]
+        resume_token: Option<ResumeToken>,
+    },
     Failed(String),
 }
 
 impl SerdeHelper for SessionHelloResponse {}
 
+/// Live tunnel change, sent by the client on the hello bi-stream after
+/// the session is established. That stream stays open for the lifetime
+/// of the connection so tunnels can be added and removed without
+/// reconnecting; requests are answered strictly in order with one
+/// [`TunnelControlResponse`] each.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum TunnelControl {
+    /// Declare one more tunnel. Validated against the same policy as
+    /// the remotes in a [`SessionHello`].
+    AddTunnel(RemoteRequest),
+    /// Tear down a tunnel negotiated earlier on this connection. Conns
+    /// already running through it are left to finish on their own.
+    RemoveTunnel { tunnel_id: u64 },
+}
+
+impl SerdeHelper for TunnelControl {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum TunnelControlResponse {
+    /// `bound` as in [`TunnelStatus::Accepted`].
+    Added {
+        tunnel_id: u64,
+        #[serde(default)]
+        bound: Option<SocketAddr>,
+    },
+    Removed,
+    Failed(String),
+}
+
+impl SerdeHelper for TunnelControlResponse {}
+
 /// Per-conn opener. Sent on the first 4 + body bytes of every
 /// data-plane bi-stream (in either direction) so the receiving side
 /// knows which tunnel this conn belongs to without consulting any
@@ -238,27 +803,86 @@ impl SerdeHelper for SessionHelloResponse {}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OpenConn {
     pub tunnel_id: u64,
-    /// SOCKS5-only: the per-CONNECT (TCP) or per-target (UDP) address
-    /// the SOCKS handshake just resolved. `None` for static tunnels
-    /// (the receiving side uses the parent tunnel's declared `kind`).
+    /// Proxy remotes only: the per-CONNECT (TCP), per-BIND or per-target
+    /// (UDP) address the SOCKS handshake or HTTP request just resolved.
+    /// `None` for static tunnels (the receiving side uses the parent
+    /// tunnel's declared `kind`).
     pub dynamic: Option<DynamicTarget>,
+    /// UDP-only: the datagram flow id the opener registered for this
+    /// conn (its bi-stream id). When set, either side may carry the
+    /// conn's payload in QUIC DATAGRAM frames tagged with this id
+    /// instead of framing it onto the stream — see
+    /// [`crate::common::datagram`].
+    #[serde(default)]
+    pub datagram_flow: Option<u64>,
+    /// The opener is streaming payload right behind this frame instead
+    /// of waiting for an [`OpenConnResponse`]. The receiver sends none on
+    /// success and resets the stream with an
+    /// [`OpenConnError`](crate::common::tunnel::OpenConnError) code on
+    /// failure.
+    #[serde(default)]
+    pub optimistic: bool,
+    /// TCP only, with [`Capability::ResumableConns`]: the conn can
+    /// carry on over a later connection, and its payload is framed
+    /// (see [`crate::common::resumable`]).
+    #[serde(default)]
+    pub resume: Option<ConnResume>,
+    /// Proxy remotes only: the username the SOCKS (RFC 1929) or HTTP
+    /// (Basic) client authenticated as, for the receiver's admin API.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// `/both` tunnels only: which of the tunnel's two listeners
+    /// accepted the conn, so the receiver knows how to carry it.
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
 }
 
 impl SerdeHelper for OpenConn {}
 
+/// How an [`OpenConn`] relates to a resumable conn.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ConnResume {
+    /// A new conn, named by `token` from now on.
+    New(ResumeToken),
+    /// This stream carries on the conn `token` names, whose connection
+    /// was lost. Only the client sends these, never optimistically;
+    /// `tunnel_id` is ignored. `received` is how much of the server's
+    /// side the client has delivered, and the server answers with
+    /// [`OpenConnResponse::Attached`].
+    Attach { token: ResumeToken, received: u64 },
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DynamicTarget {
     Tcp(HostPort),
     Udp(HostPort),
+    /// SOCKS5 BIND, with [`Capability::SocksBind`]: the receiver listens
+    /// for one conn from this host (any host if it's unspecified) and
+    /// reports on the stream with [`BindReply`]s before carrying it.
+    Bind(HostPort),
 }
 
+/// What the receiver of a [`DynamicTarget::Bind`] conn writes on the
+/// stream after `OpenConnResponse::Ok`: where it's listening, then who
+/// connected. The stream carries that conn from there on.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum BindReply {
+    Listening(SocketAddr),
+    Accepted(SocketAddr),
+}
+
+impl SerdeHelper for BindReply {}
+
 /// Reply to an [`OpenConn`]. `Ok` = "go ahead, start streaming"; `Failed`
 /// surfaces a server-side reason (unknown tunnel id, dispatch error,
 /// …) so the conn can be torn down cleanly instead of timing out.
+/// `Attached` answers [`ConnResume::Attach`] with how much of the
+/// client's side the server has delivered.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum OpenConnResponse {
     Ok,
     Failed(String),
+    Attached { received: u64 },
 }
 
 impl SerdeHelper for OpenConnResponse {}
@@ -271,6 +895,13 @@ impl SerdeHelper for OpenConnResponse {}
 /// request a SOCKS5 dynamic tunnel.
 const SOCKS_KEYWORD: &str = "socks";
 
+/// Marker token, like [`SOCKS_KEYWORD`], for an HTTP proxy listener.
+const HTTP_KEYWORD: &str = "http";
+
+/// Marker token that takes the place of a `host:port`, local or remote,
+/// and is followed by the absolute path of a unix domain socket.
+const UNIX_KEYWORD: &str = "unix";
+
 /// Marker token that replaces the local-side `host:port` to request that
 /// the client pipe its own stdin/stdout to/from the tunnel instead of
 /// binding a local listener. Forward-only — `R:stdio:...` is rejected.
@@ -279,56 +910,178 @@ const STDIO_KEYWORD: &str = "stdio";
 /// Default address for things that historically defaulted to `0.0.0.0` (the
 /// IPv4 wildcard).
 const ANY_V4: &str = "0.0.0.0";
-/// Default address for SOCKS5's local listener.
-const SOCKS_DEFAULT_LOCAL: &str = "127.0.0.1";
+/// Default address for the proxy listeners (SOCKS5 and HTTP).
+const PROXY_DEFAULT_LOCAL: &str = "127.0.0.1";
 const SOCKS_DEFAULT_PORT: u16 = 1080;
+const HTTP_DEFAULT_PORT: u16 = 3128;
 /// Default `remote_host` used when the user omits it in a `stdio:port`
 /// short-hand, matching chisel's behavior (`3000` resolves to
 /// `127.0.0.1:3000` on the remote side).
 const STDIO_DEFAULT_REMOTE_HOST: &str = "127.0.0.1";
+/// Most ports one range may span. Each is a tunnel declaration of its
+/// own in the session hello, and a few hundred of those already take a
+/// good part of a control frame.
+const MAX_PORT_RANGE: u16 = 512;
 
 /// Decomposed input as a sequence of structured layers. The parser pipes the
 /// raw string through these sub-parses in order:
 ///
+///   0. `parse_name` strips the optional `name=` label.
 ///   1. `parse_direction` strips the optional `R:` / `R/` prefix.
-///   2. `parse_protocol` strips the optional `/tcp` / `/udp` suffix.
-///   3. `split_addr_tokens` tokenizes the residual `host:port:host:port`
+///   2. `parse_auth` strips the optional `user:pass@` credentials (proxy
+///      remotes only).
+///   3. `parse_protocol` strips the optional `/tcp` / `/udp` / `/both`
+///      suffix.
+///   4. `split_addr_tokens` tokenizes the residual `host:port:host:port`
 ///      respecting `[…]` so IPv6 literals stay atomic.
-///   4. `tokens_to_kind` dispatches on the token count and the SOCKS keyword
-///      to build the final [`RemoteKind`].
+///   5. `tokens_to_kind` dispatches on the token count and the proxy
+///      keywords to build the final [`RemoteKind`].
+///
+/// A port range stands for several remotes, so it only parses through
+/// [`RemoteRequest::expand`]; here it's an error unless it spans a single
+/// port.
 impl FromStr for RemoteRequest {
     type Err = anyhow::Error;
 
     fn from_str(input: &str) -> Result<RemoteRequest> {
-        let (direction, after_dir) = parse_direction(input)?;
-        let (protocol_hint, body) = parse_protocol(after_dir)?;
-        let tokens = split_addr_tokens(body)?;
-
-        // Detect a leading `stdio` token. Stdio replaces the local
-        // listener with the client's own stdin/stdout, so it only
-        // makes sense in the *first* token slot and only on forward
-        // tunnels (the server side of a reverse tunnel can't write to
-        // the client's stdout).
-        let (stdio, addr_tokens) = if !tokens.is_empty() && tokens[0] == STDIO_KEYWORD {
-            if matches!(direction, Direction::Reverse) {
-                return Err(anyhow!("Invalid format: stdio cannot be reversed"));
+        let mut remotes = RemoteRequest::expand(input)?;
+        if remotes.len() > 1 {
+            return Err(anyhow!(
+                "Invalid format: port range expands to {} remotes",
+                remotes.len()
+            ));
+        }
+        Ok(remotes.remove(0))
+    }
+}
+
+/// A remote's protocol suffix: `/tcp`, `/udp`, or `/both` (also spelled
+/// `/tcp+udp`) for a tunnel that carries the two side by side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Suffix {
+    Only(Protocol),
+    Both,
+}
+
+type SpecParts<'a> = (
+    Direction,
+    Option<(String, String)>,
+    Option<Suffix>,
+    Vec<&'a str>,
+);
+
+/// Steps 1-4 of the parse: everything up to the address tokens.
+fn split_spec(input: &str) -> Result<SpecParts<'_>> {
+    let (direction, after_dir) = parse_direction(input)?;
+    let (auth, after_auth) = parse_auth(after_dir)?;
+    let (suffix, body) = parse_protocol(after_auth)?;
+    let tokens = split_addr_tokens(body)?;
+    Ok((direction, auth, suffix, tokens))
+}
+
+/// Step 5: build the remote from its parts.
+fn build_request(
+    direction: Direction,
+    auth: Option<(String, String)>,
+    suffix: Option<Suffix>,
+    tokens: &[&str],
+) -> Result<RemoteRequest> {
+    let protocol_hint = match suffix {
+        Some(Suffix::Only(protocol)) => Some(protocol),
+        Some(Suffix::Both) | None => None,
+    };
+    // Detect a leading `stdio` token. Stdio replaces the local
+    // listener with the client's own stdin/stdout, so it only
+    // makes sense in the *first* token slot and only on forward
+    // tunnels (the server side of a reverse tunnel can't write to
+    // the client's stdout).
+    let (stdio, addr_tokens) = if !tokens.is_empty() && tokens[0] == STDIO_KEYWORD {
+        if matches!(direction, Direction::Reverse) {
+            return Err(anyhow!("Invalid format: stdio cannot be reversed"));
+        }
+        (true, &tokens[1..])
+    } else {
+        (false, tokens)
+    };
+
+    let mut kind = if stdio {
+        tokens_to_stdio_kind(addr_tokens, protocol_hint)?
+    } else if addr_tokens.contains(&UNIX_KEYWORD) {
+        tokens_to_unix_kind(addr_tokens, protocol_hint)?
+    } else {
+        tokens_to_kind(addr_tokens, protocol_hint)?
+    };
+    if suffix == Some(Suffix::Both) {
+        kind = match kind {
+            RemoteKind::Tcp { local, remote } if !stdio => RemoteKind::Both { local, remote },
+            _ => {
+                return Err(anyhow!(
+                    "Invalid format: /both only applies to host:port remotes with a listener"
+                ))
             }
-            (true, &tokens[1..])
-        } else {
-            (false, tokens.as_slice())
         };
-
-        let kind = if stdio {
-            tokens_to_stdio_kind(addr_tokens, protocol_hint)?
-        } else {
-            tokens_to_kind(addr_tokens, protocol_hint)?
+    }
+    if auth.is_some() {
+        let (RemoteKind::Socks5 { auth: slot, .. } | RemoteKind::Http { auth: slot, .. }) =
+            &mut kind
+        else {
+            return Err(anyhow!(
+                "Invalid format: credentials only apply to socks and http remotes"
+            ));
         };
-        Ok(RemoteRequest {
-            direction,
-            kind,
-            stdio,
-        })
+        *slot = auth;
+    }
+    Ok(RemoteRequest {
+        direction,
+        kind,
+        stdio,
+        range: None,
+        name: None,
+    })
+}
+
+/// Strip a leading `name=` label. Only a run of letters, digits, `-`, `_`
+/// and `.` before the first `=` counts, so `user:pa=ss@socks` is still
+/// read as credentials; a username with `=` in it needs an explicit name
+/// in front. All-digit names are refused, since `rusnel ctl` would take
+/// them for a tunnel id.
+fn parse_name(s: &str) -> Result<(Option<String>, &str)> {
+    let Some((name, rest)) = s.split_once('=') else {
+        return Ok((None, s));
+    };
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Ok((None, s));
+    }
+    validate_name(name)?;
+    if rest.is_empty() {
+        return Err(anyhow!("Invalid format: Missing details after {name}="));
+    }
+    Ok((Some(name.to_string()), rest))
+}
+
+/// Check a tunnel name: 1-64 letters, digits, `-`, `_` or `.`, and not
+/// all digits.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64 {
+        return Err(anyhow!("Invalid name: must be 1-64 characters"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(anyhow!(
+            "Invalid name `{name}`: only letters, digits, '-', '_' and '.' are allowed"
+        ));
+    }
+    if name.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow!(
+            "Invalid name `{name}`: a name can't be all digits, those are tunnel ids"
+        ));
     }
+    Ok(())
 }
 
 /// Strip a leading `R:` ("reverse") marker. Matches chisel's syntax exactly:
@@ -346,16 +1099,59 @@ fn parse_direction(s: &str) -> Result<(Direction, &str)> {
     Ok((Direction::Forward, s))
 }
 
-/// Pop a trailing `/tcp` or `/udp` if present. Returns the protocol the
-/// caller asked for (or `None` if no suffix), plus the residual address
-/// portion. Errors out on any other suffix so silent typos don't slip
-/// through as TCP.
-fn parse_protocol(s: &str) -> Result<(Option<Protocol>, &str)> {
-    match s.rsplit_once('/') {
-        Some((head, "tcp")) => Ok((Some(Protocol::Tcp), head)),
-        Some((head, "udp")) => Ok((Some(Protocol::Udp), head)),
-        Some(_) => Err(anyhow!("Invalid protocol: Must be 'tcp' or 'udp'")),
-        None => Ok((None, s)),
+/// Strip leading `user:pass@` credentials. The password runs to the last
+/// `@` before any `unix:` path, since a socket path may contain `@` of
+/// its own (`/run/app@1.sock`) but no other address part can.
+fn parse_auth(s: &str) -> Result<(Option<(String, String)>, &str)> {
+    let head = s
+        .match_indices("unix:")
+        .map(|(i, _)| i)
+        .find(|&i| i == 0 || matches!(s.as_bytes()[i - 1], b':' | b'@'))
+        .map_or(s, |i| &s[..i]);
+    let Some((creds, _)) = head.rsplit_once('@') else {
+        return Ok((None, s));
+    };
+    let rest = &s[creds.len() + 1..];
+    let (user, pass) = creds
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid format: credentials must be user:pass@"))?;
+    // RFC 1929 sends each as a length-prefixed byte string.
+    if user.is_empty() || user.len() > 255 || pass.len() > 255 {
+        return Err(anyhow!(
+            "Invalid format: username must be 1-255 bytes, password at most 255"
+        ));
+    }
+    if rest.is_empty() {
+        return Err(anyhow!("Invalid format: Missing details after credentials"));
+    }
+    Ok((Some((user.to_string(), pass.to_string())), rest))
+}
+
+/// Pop a trailing `/tcp`, `/udp`, `/both` or `/tcp+udp` if present.
+/// Returns the suffix the caller asked for (or `None` if there's none),
+/// plus the residual address portion. Errors out on any other suffix so
+/// silent typos don't slip through as TCP. A unix socket path in the
+/// last slot has slashes of its own, so there only an exact `/tcp`,
+/// `/udp`, `/both` or `/tcp+udp` tail counts, and it always counts: a
+/// socket whose path ends in one of those names takes a `/tcp` after
+/// it (`unix:/run/svc/tcp/tcp`).
+fn parse_protocol(s: &str) -> Result<(Option<Suffix>, &str)> {
+    let last = s.rsplit(':').next().unwrap_or(s);
+    let is_path = last.starts_with('/');
+    let Some((head, tail)) = last.rsplit_once('/') else {
+        return Ok((None, s));
+    };
+    let suffix = match tail {
+        _ if is_path && head.is_empty() => None,
+        "tcp" => Some(Suffix::Only(Protocol::Tcp)),
+        "udp" => Some(Suffix::Only(Protocol::Udp)),
+        "both" | "tcp+udp" => Some(Suffix::Both),
+        _ => None,
+    };
+    match suffix {
+        Some(suffix) => Ok((Some(suffix), &s[..s.len() - tail.len() - 1])),
+        None if is_path => Ok((None, s)),
+        None => Err(anyhow!("Invalid protocol: Must be 'tcp', 'udp' or 'both'")),
     }
 }
 
@@ -422,6 +1218,45 @@ fn parse_port(s: &str, what: &str) -> Result<u16> {
     s.parse::<u16>().map_err(|_| anyhow!("Invalid {what}"))
 }
 
+/// `Some` if `s` is written as a `first-last` port range, with the
+/// range or why it's invalid. Port 0 ("any free port") has no place in
+/// a range.
+fn parse_port_range(s: &str) -> Option<Result<(u16, u16)>> {
+    let (first, last) = s.split_once('-')?;
+    let numeric = |p: &str| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit());
+    if !numeric(first) || !numeric(last) {
+        return None;
+    }
+    let range = (|| {
+        let first = parse_port(first, "port range")?;
+        let last = parse_port(last, "port range")?;
+        if first == 0 || first > last {
+            return Err(anyhow!(
+                "Invalid port range {s}: must be first-last, from 1 up"
+            ));
+        }
+        if last - first >= MAX_PORT_RANGE {
+            return Err(anyhow!(
+                "Invalid port range {s}: spans more than {MAX_PORT_RANGE} ports"
+            ));
+        }
+        Ok((first, last))
+    })();
+    Some(range)
+}
+
+/// Positions of the port tokens in each address shape
+/// [`tokens_to_kind`] accepts, local port first.
+fn port_slots(tokens: usize) -> &'static [usize] {
+    match tokens {
+        1 => &[0],
+        2 => &[1],
+        3 => &[0, 2],
+        4 => &[1, 3],
+        _ => &[],
+    }
+}
+
 /// `0.0.0.0` as an `IpAddr`. Cheap; called from the per-arity branches
 /// below for the v4 wildcard default.
 fn any_v4() -> IpAddr {
@@ -432,24 +1267,44 @@ fn any_v4() -> IpAddr {
         .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED))
 }
 
-fn socks_default_local() -> IpAddr {
-    SOCKS_DEFAULT_LOCAL
+fn proxy_default_local() -> IpAddr {
+    PROXY_DEFAULT_LOCAL
         .parse::<IpAddr>()
         .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST))
 }
 
+fn is_proxy_keyword(token: &str) -> bool {
+    token == SOCKS_KEYWORD || token == HTTP_KEYWORD
+}
+
+/// The proxy listener `keyword` asks for, on `local_host` (default
+/// [`PROXY_DEFAULT_LOCAL`]) and `local_port` (default per keyword).
+fn proxy_kind(keyword: &str, local_host: Option<IpAddr>, local_port: Option<u16>) -> RemoteKind {
+    let host = local_host.unwrap_or_else(proxy_default_local);
+    if keyword == HTTP_KEYWORD {
+        RemoteKind::Http {
+            local: SocketAddr::new(host, local_port.unwrap_or(HTTP_DEFAULT_PORT)),
+            auth: None,
+        }
+    } else {
+        RemoteKind::Socks5 {
+            local: SocketAddr::new(host, local_port.unwrap_or(SOCKS_DEFAULT_PORT)),
+            auth: None,
+        }
+    }
+}
+
 /// Combine a tokenized address with an optional protocol hint into a
-/// [`RemoteKind`]. SOCKS5 ignores the protocol hint (it's TCP-only by
-/// definition); the `tcp`/`udp` discriminator only matters for the
-/// host:port shapes.
+/// [`RemoteKind`]. The proxy keywords ignore the protocol hint; the
+/// `tcp`/`udp` discriminator only matters for the host:port shapes.
 fn tokens_to_kind(tokens: &[&str], protocol: Option<Protocol>) -> Result<RemoteKind> {
     if tokens.is_empty() {
         return Err(anyhow!("Invalid format: Missing parts"));
     }
 
-    // Each shape ends in either a `socks` keyword (build a Socks5) or a
-    // host:port quadruple/triple/double/single (build Tcp/Udp). The shape
-    // is fully determined by token count.
+    // Each shape ends in either a `socks` / `http` keyword (build a proxy
+    // listener) or a host:port quadruple/triple/double/single (build
+    // Tcp/Udp). The shape is fully determined by token count.
     match tokens.len() {
         1 => parse_one_token(tokens[0], protocol),
         2 => parse_two_tokens(tokens, protocol),
@@ -462,10 +1317,8 @@ fn tokens_to_kind(tokens: &[&str], protocol: Option<Protocol>) -> Result<RemoteK
 }
 
 fn parse_one_token(token: &str, protocol: Option<Protocol>) -> Result<RemoteKind> {
-    if token == SOCKS_KEYWORD {
-        return Ok(RemoteKind::Socks5 {
-            local: SocketAddr::new(socks_default_local(), SOCKS_DEFAULT_PORT),
-        });
+    if is_proxy_keyword(token) {
+        return Ok(proxy_kind(token, None, None));
     }
     let port = parse_port(token, "remote port")?;
     Ok(make_host_port_kind(
@@ -476,11 +1329,9 @@ fn parse_one_token(token: &str, protocol: Option<Protocol>) -> Result<RemoteKind
 }
 
 fn parse_two_tokens(tokens: &[&str], protocol: Option<Protocol>) -> Result<RemoteKind> {
-    if tokens[1] == SOCKS_KEYWORD {
+    if is_proxy_keyword(tokens[1]) {
         let local_port = parse_port(tokens[0], "remote port")?;
-        return Ok(RemoteKind::Socks5 {
-            local: SocketAddr::new(socks_default_local(), local_port),
-        });
+        return Ok(proxy_kind(tokens[1], None, Some(local_port)));
     }
     let remote_host = unbracket(tokens[0]).to_string();
     let remote_port = parse_port(tokens[1], "remote port")?;
@@ -492,12 +1343,10 @@ fn parse_two_tokens(tokens: &[&str], protocol: Option<Protocol>) -> Result<Remot
 }
 
 fn parse_three_tokens(tokens: &[&str], protocol: Option<Protocol>) -> Result<RemoteKind> {
-    if tokens[2] == SOCKS_KEYWORD {
+    if is_proxy_keyword(tokens[2]) {
         let local_host = parse_ip(tokens[0], "local host")?;
         let local_port = parse_port(tokens[1], "local port")?;
-        return Ok(RemoteKind::Socks5 {
-            local: SocketAddr::new(local_host, local_port),
-        });
+        return Ok(proxy_kind(tokens[2], Some(local_host), Some(local_port)));
     }
     let local_port = parse_port(tokens[0], "local port")?;
     let remote_host = unbracket(tokens[1]).to_string();
@@ -523,12 +1372,18 @@ fn parse_four_tokens(tokens: &[&str], protocol: Option<Protocol>) -> Result<Remo
 
 /// Build a [`RemoteKind`] for a `stdio:...` declaration. The `local`
 /// `SocketAddr` is a dummy `0.0.0.0:0` — the client's stdio dispatcher
-/// never binds it. Stdio is incompatible with SOCKS5 (no `socks` keyword
-/// allowed in the remainder) since stdio is a single-conn pipe.
+/// never binds it. Stdio is incompatible with the proxy listeners (no
+/// `socks` or `http` keyword allowed in the remainder) since stdio is a
+/// single-conn pipe.
 fn tokens_to_stdio_kind(tokens: &[&str], protocol: Option<Protocol>) -> Result<RemoteKind> {
-    if tokens.contains(&SOCKS_KEYWORD) {
+    if tokens.iter().any(|t| is_proxy_keyword(t)) {
         return Err(anyhow!(
-            "Invalid format: stdio cannot be combined with socks"
+            "Invalid format: stdio cannot be combined with socks or http"
+        ));
+    }
+    if tokens.contains(&UNIX_KEYWORD) {
+        return Err(anyhow!(
+            "Invalid format: stdio cannot be combined with unix sockets"
         ));
     }
     let local = SocketAddr::new(any_v4(), 0);
@@ -561,6 +1416,75 @@ fn tokens_to_stdio_kind(tokens: &[&str], protocol: Option<Protocol>) -> Result<R
     }
 }
 
+/// Build a [`RemoteKind::Unix`] from an address with a `unix:<path>` in
+/// place of the local end, the remote end, or both. The TCP end takes
+/// the usual shapes: `[<local-host>:]<local-port>` locally,
+/// `[<remote-host>:]<remote-port>` remotely. A lone `unix:<path>` is the
+/// same path on both sides.
+fn tokens_to_unix_kind(tokens: &[&str], protocol: Option<Protocol>) -> Result<RemoteKind> {
+    if protocol == Some(Protocol::Udp) {
+        return Err(anyhow!("Invalid format: unix sockets are stream-only"));
+    }
+    if tokens.iter().any(|t| is_proxy_keyword(t)) {
+        return Err(anyhow!(
+            "Invalid format: unix sockets cannot be combined with socks or http"
+        ));
+    }
+    let unix_path = |tokens: &[&str]| -> Result<Option<PathBuf>> {
+        match tokens {
+            [UNIX_KEYWORD, path] if path.starts_with('/') => Ok(Some(PathBuf::from(path))),
+            [UNIX_KEYWORD, ..] => Err(anyhow!(
+                "Invalid format: unix must be followed by an absolute socket path"
+            )),
+            _ => Ok(None),
+        }
+    };
+    // The local end is at most two tokens, so the split is wherever
+    // a leading unix end stops or a trailing one starts.
+    let split = if tokens[0] == UNIX_KEYWORD {
+        2.min(tokens.len())
+    } else {
+        tokens
+            .iter()
+            .rposition(|t| *t == UNIX_KEYWORD)
+            .unwrap_or(tokens.len())
+    };
+    let (local_tokens, remote_tokens) = tokens.split_at(split);
+    let local = match unix_path(local_tokens)? {
+        Some(path) => StreamEnd::Unix(path),
+        None => StreamEnd::Tcp(match local_tokens {
+            [port] => SocketAddr::new(any_v4(), parse_port(port, "local port")?),
+            [host, port] => SocketAddr::new(
+                parse_ip(host, "local host")?,
+                parse_port(port, "local port")?,
+            ),
+            _ => {
+                return Err(anyhow!(
+                    "Invalid format: Unexpected number of address parts"
+                ))
+            }
+        }),
+    };
+    let remote = match (unix_path(remote_tokens)?, remote_tokens) {
+        (Some(path), _) => StreamEnd::Unix(path),
+        (None, []) => match &local {
+            StreamEnd::Unix(path) => StreamEnd::Unix(path.clone()),
+            StreamEnd::Tcp(_) => return Err(anyhow!("Invalid format: Missing parts")),
+        },
+        (None, [port]) => StreamEnd::Tcp(HostPort::new(ANY_V4, parse_port(port, "remote port")?)),
+        (None, [host, port]) => StreamEnd::Tcp(HostPort::new(
+            unbracket(host),
+            parse_port(port, "remote port")?,
+        )),
+        (None, _) => {
+            return Err(anyhow!(
+                "Invalid format: Unexpected number of address parts"
+            ))
+        }
+    };
+    Ok(RemoteKind::Unix { local, remote })
+}
+
 fn make_host_port_kind(
     local: SocketAddr,
     remote: HostPort,
@@ -591,7 +1515,11 @@ mod tests {
         match &r.kind {
             RemoteKind::Tcp { local, remote } => (*local, remote, Protocol::Tcp),
             RemoteKind::Udp { local, remote } => (*local, remote, Protocol::Udp),
-            RemoteKind::Socks5 { .. } => panic!("expected host:port remote, got socks"),
+            RemoteKind::Socks5 { .. } | RemoteKind::Http { .. } => {
+                panic!("expected host:port remote, got a proxy")
+            }
+            RemoteKind::Unix { .. } => panic!("expected host:port remote, got a unix socket"),
+            RemoteKind::Both { .. } => panic!("expected a single-protocol remote, got /both"),
         }
     }
 
@@ -693,6 +1621,45 @@ mod tests {
         assert_eq!(r.local_socket_addr().port(), 5000);
     }
 
+    #[test]
+    fn http_default_local() {
+        let r = parse("http");
+        assert!(r.is_http());
+        assert!(!r.is_socks());
+        assert_eq!(
+            r.local_socket_addr(),
+            SocketAddr::new(ip("127.0.0.1"), 3128)
+        );
+        assert_eq!(r.to_string(), "3128=>http");
+    }
+
+    #[test]
+    fn http_custom_local() {
+        let r = parse("8080:http");
+        assert!(r.is_http());
+        assert!(!r.is_reversed());
+        assert_eq!(
+            r.local_socket_addr(),
+            SocketAddr::new(ip("127.0.0.1"), 8080)
+        );
+        let r = parse("R:0.0.0.0:3128:http");
+        assert!(r.is_reversed());
+        assert!(r.is_http());
+        assert_eq!(r.local_socket_addr(), SocketAddr::new(ip("0.0.0.0"), 3128));
+        assert!(r.remote_addr_string().is_none());
+        assert!(r.kind.protocol().is_none());
+    }
+
+    #[test]
+    fn reverse_port_zero_leaves_port_to_server() {
+        let r = parse("R:0:localhost:22");
+        assert!(r.is_reversed());
+        assert_eq!(r.local_socket_addr().port(), 0);
+        let r = parse("R:0:socks");
+        assert!(r.is_socks());
+        assert_eq!(r.local_socket_addr(), SocketAddr::new(ip("127.0.0.1"), 0));
+    }
+
     #[test]
     fn rejects_unknown_protocol() {
         let err = RemoteRequest::from_str("1.1.1.1:53/sctp").unwrap_err();
@@ -930,6 +1897,358 @@ mod tests {
                 .contains("stdio cannot be combined with socks"),
             "unexpected error: {err}"
         );
+        assert!(RemoteRequest::from_str("stdio:http").is_err());
+    }
+
+    #[test]
+    fn socks_credentials() {
+        let r = parse("R:alice:p@ss:w0rd@0.0.0.0:1080:socks");
+        assert!(r.is_reversed());
+        assert_eq!(
+            r.kind,
+            RemoteKind::Socks5 {
+                local: SocketAddr::new(ip("0.0.0.0"), 1080),
+                auth: Some(("alice".into(), "p@ss:w0rd".into())),
+            }
+        );
+        assert_eq!(r.to_string(), "R:<auth>@1080=>socks");
+    }
+
+    #[test]
+    fn proxy_passwords_stay_out_of_debug_and_forward_hellos() {
+        for spec in ["alice:hunter2@1080:socks", "alice:hunter2@3128:http"] {
+            let r = parse(spec);
+            let debug = format!("{r:?}");
+            assert!(
+                debug.contains("alice") && !debug.contains("hunter2"),
+                "{debug}"
+            );
+
+            let hello = SessionHello::new(vec![r.clone(), parse(&format!("R:{spec}"))]);
+            let (RemoteKind::Socks5 { auth: forward, .. } | RemoteKind::Http { auth: forward, .. }) =
+                &hello.remotes[0].kind
+            else {
+                panic!("unexpected kind {:?}", hello.remotes[0].kind);
+            };
+            assert_eq!(forward, &None);
+            assert_eq!(hello.remotes[1].kind, parse(&format!("R:{spec}")).kind);
+        }
+    }
+
+    #[test]
+    fn http_credentials() {
+        let r = parse("alice:pw@8080:http");
+        assert_eq!(
+            r.kind,
+            RemoteKind::Http {
+                local: SocketAddr::new(ip("127.0.0.1"), 8080),
+                auth: Some(("alice".into(), "pw".into())),
+            }
+        );
+        assert_eq!(r.to_string(), "<auth>@8080=>http");
+    }
+
+    #[test]
+    fn rejects_credentials_on_non_socks_remote() {
+        for input in ["alice:pw@3000:localhost:22", "alice:pw@stdio:localhost:22"] {
+            let err = RemoteRequest::from_str(input).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("only apply to socks and http remotes"),
+                "unexpected error for {input}: {err}"
+            );
+        }
+        let err = RemoteRequest::from_str("alice@socks").unwrap_err();
+        assert!(
+            err.to_string().contains("user:pass@"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn port_range_expands_per_port() {
+        let remotes = RemoteRequest::expand("5000-5002:example.com:6000-6002").unwrap();
+        let pairs: Vec<_> = remotes
+            .iter()
+            .map(|r| {
+                let (local, remote, _) = unwrap_hp(r);
+                (local.port(), remote.host.as_str(), remote.port)
+            })
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (5000, "example.com", 6000),
+                (5001, "example.com", 6001),
+                (5002, "example.com", 6002)
+            ]
+        );
+        let range = PortRange {
+            local: 5000,
+            remote: 6000,
+            len: 3,
+        };
+        assert!(remotes.iter().all(|r| r.range == Some(range)));
+        assert_eq!(remotes[1].to_string(), "5001=>example.com:6001/tcp");
+        assert_eq!(
+            remotes[1].range_spec().as_deref(),
+            Some("5000-5002=>example.com:6000-6002/tcp")
+        );
+        assert_eq!(parse("5000:example.com:6000").range_spec(), None);
+    }
+
+    #[test]
+    fn port_range_shapes() {
+        let remotes = RemoteRequest::expand("R:6000-6100:localhost:6000-6100/udp").unwrap();
+        assert_eq!(remotes.len(), 101);
+        assert!(remotes.iter().all(|r| r.is_reversed()));
+        assert!(matches!(remotes[100].kind, RemoteKind::Udp { .. }));
+        assert_eq!(
+            remotes[0].range_spec().as_deref(),
+            Some("R:6000-6100=>localhost:6000-6100/udp")
+        );
+
+        let remotes = RemoteRequest::expand("[::1]:7000-7001").unwrap();
+        let (local, remote, _) = unwrap_hp(&remotes[1]);
+        assert_eq!((local.port(), remote.port), (7001, 7001));
+        assert_eq!(
+            remotes[0].range_spec().as_deref(),
+            Some("7000-7001=>[::1]:7000-7001/tcp")
+        );
+        let remotes = RemoteRequest::expand("127.0.0.1:8000-8001:my-host:9000-9001").unwrap();
+        assert_eq!(
+            remotes[0].local_socket_addr(),
+            "127.0.0.1:8000".parse().unwrap()
+        );
+        assert_eq!(
+            remotes[1].remote_addr_string().as_deref(),
+            Some("my-host:9001")
+        );
+        assert_eq!(RemoteRequest::expand("5000:my-host:22").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_port_ranges() {
+        for (input, expected) in [
+            ("5000-5010:host:6000-6005", "differ in length"),
+            ("5000-5010:host:6000", "matching range on the other side"),
+            ("5000:host:6000-6010", "matching range on the other side"),
+            ("5010-5000", "first-last"),
+            ("0-10", "first-last"),
+            ("1000-2000", "more than 512 ports"),
+            ("5000-70000", "Invalid port range"),
+            ("5000-5010:socks", "only apply to tcp and udp"),
+            ("stdio:host:5000-5010", "only apply to tcp and udp"),
+        ] {
+            let err = RemoteRequest::expand(input).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "unexpected error for {input}: {err}"
+            );
+        }
+        let err = RemoteRequest::from_str("5000-5001").unwrap_err();
+        assert!(err.to_string().contains("expands to 2 remotes"), "{err}");
+    }
+
+    #[test]
+    fn unix_socket_shapes() {
+        fn unix<A>(path: &str) -> StreamEnd<A> {
+            StreamEnd::Unix(PathBuf::from(path))
+        }
+        let tcp = |host: &str, port| StreamEnd::Tcp(HostPort::new(host, port));
+        let bound = |host: &str, port| StreamEnd::Tcp(SocketAddr::new(ip(host), port));
+        for (input, local, remote) in [
+            (
+                "unix:/tmp/a.sock:unix:/var/run/b.sock",
+                unix("/tmp/a.sock"),
+                unix("/var/run/b.sock"),
+            ),
+            (
+                "9000:unix:/run/app.sock",
+                bound("0.0.0.0", 9000),
+                unix("/run/app.sock"),
+            ),
+            (
+                "127.0.0.1:2375:unix:/var/run/docker.sock",
+                bound("127.0.0.1", 2375),
+                unix("/var/run/docker.sock"),
+            ),
+            (
+                "unix:/tmp/pg.sock:db:5432",
+                unix("/tmp/pg.sock"),
+                tcp("db", 5432),
+            ),
+            (
+                "unix:/tmp/app@1.sock:unix:/run/app@1.sock",
+                unix("/tmp/app@1.sock"),
+                unix("/run/app@1.sock"),
+            ),
+            (
+                "9000:unix:/run/user@1000/app.sock",
+                bound("0.0.0.0", 9000),
+                unix("/run/user@1000/app.sock"),
+            ),
+            (
+                "unix:/tmp/pg.sock:5432",
+                unix("/tmp/pg.sock"),
+                tcp(ANY_V4, 5432),
+            ),
+            (
+                "unix:/run/app.sock",
+                unix("/run/app.sock"),
+                unix("/run/app.sock"),
+            ),
+            // A trailing `/tcp` is the protocol suffix, even after a path.
+            (
+                "unix:/tmp/a.sock:unix:/run/svc/tcp",
+                unix("/tmp/a.sock"),
+                unix("/run/svc"),
+            ),
+            (
+                "unix:/tmp/a.sock:unix:/run/svc/tcp/tcp",
+                unix("/tmp/a.sock"),
+                unix("/run/svc/tcp"),
+            ),
+        ] {
+            let r = parse(input);
+            assert_eq!(r.kind, RemoteKind::Unix { local, remote }, "{input}");
+            assert_eq!(r.kind.protocol(), Some(Protocol::Tcp));
+        }
+        let r = parse("R:9000:unix:/run/app.sock");
+        assert!(r.is_reversed());
+        assert!(matches!(r.kind, RemoteKind::Unix { .. }));
+    }
+
+    #[test]
+    fn unix_socket_display_and_server_path() {
+        let r = parse("unix:/tmp/a.sock:unix:/var/run/b.sock");
+        assert_eq!(r.to_string(), "unix:/tmp/a.sock=>unix:/var/run/b.sock");
+        assert_eq!(r.server_unix_path(), Some(Path::new("/var/run/b.sock")));
+        let r = parse("R:9000:unix:/run/app.sock");
+        assert_eq!(r.to_string(), "R:9000=>unix:/run/app.sock");
+        assert_eq!(r.server_unix_path(), None);
+        let r = parse("R:unix:/run/in.sock:localhost:80");
+        assert_eq!(r.server_unix_path(), Some(Path::new("/run/in.sock")));
+        assert_eq!(parse("8080").server_unix_path(), None);
+    }
+
+    #[test]
+    fn rejects_bad_unix_sockets() {
+        for (input, expected) in [
+            ("unix:a.sock", "absolute socket path"),
+            ("9000:unix", "absolute socket path"),
+            ("unix:/tmp/a.sock:unix:/tmp/b.sock/udp", "stream-only"),
+            ("unix:/tmp/a.sock:socks", "cannot be combined"),
+            ("stdio:unix:/tmp/a.sock", "stdio"),
+            ("unix:/tmp/a.sock:1:2:3", "Unexpected number"),
+            ("alice:pw@unix:/tmp/a@1.sock", "credentials only apply"),
+        ] {
+            let err = RemoteRequest::from_str(input).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "unexpected error for {input}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn both_suffix_declares_one_tunnel() {
+        for input in ["53:dns:53/both", "53:dns:53/tcp+udp"] {
+            let r = parse(input);
+            assert_eq!(
+                r.kind,
+                RemoteKind::Both {
+                    local: SocketAddr::new(ip("0.0.0.0"), 53),
+                    remote: HostPort::new("dns", 53),
+                }
+            );
+            assert_eq!(r.kind.protocol(), None);
+            assert_eq!(r.to_string(), "53=>dns:53/both");
+        }
+        let r = parse("R:127.0.0.1:8053:[::1]:53/both");
+        assert_eq!(r.to_string(), "R:8053=>[::1]:53/both");
+        assert_eq!(
+            r.kind.half(Protocol::Udp),
+            Some(RemoteKind::Udp {
+                local: SocketAddr::new(ip("127.0.0.1"), 8053),
+                remote: HostPort::new("::1", 53),
+            })
+        );
+        assert_eq!(parse("53/udp").kind.half(Protocol::Udp), None);
+
+        let remotes = RemoteRequest::expand("88-89:kdc:88-89/both").unwrap();
+        assert_eq!(remotes.len(), 2);
+        assert!(matches!(remotes[1].kind, RemoteKind::Both { .. }));
+        assert_eq!(
+            remotes[0].range_spec().as_deref(),
+            Some("88-89=>kdc:88-89/both")
+        );
+    }
+
+    #[test]
+    fn rejects_both_without_a_listener_pair() {
+        for input in [
+            "socks/both",
+            "5000:http/both",
+            "stdio:host:53/both",
+            "unix:/tmp/a.sock:53/both",
+        ] {
+            let err = RemoteRequest::from_str(input).unwrap_err();
+            assert!(
+                err.to_string().contains("/both only applies"),
+                "unexpected error for {input}: {err}"
+            );
+        }
+        let err = RemoteRequest::from_str("53/sctp").unwrap_err();
+        assert!(err.to_string().contains("'both'"), "{err}");
+    }
+
+    #[test]
+    fn name_prefix_labels_the_remote() {
+        let r = parse("ssh=R:2222:localhost:22");
+        assert_eq!(r.name.as_deref(), Some("ssh"));
+        assert!(r.is_reversed());
+        assert_eq!(r.to_string(), "R:2222=>localhost:22/tcp");
+        assert_eq!(
+            parse("db.main_1=5432:db:5432").name.as_deref(),
+            Some("db.main_1")
+        );
+        assert_eq!(parse("R:2222:localhost:22").name, None);
+
+        // `=` inside credentials isn't a name...
+        let r = parse("alice:pa=ss@socks");
+        assert_eq!(r.name, None);
+        let RemoteKind::Socks5 { auth, .. } = &r.kind else {
+            panic!("expected socks, got {:?}", r.kind);
+        };
+        assert_eq!(auth, &Some(("alice".into(), "pa=ss".into())));
+        // ...and an explicit name still lets a username carry one.
+        let r = parse("proxy=al=ice:secret@socks");
+        assert_eq!(r.name.as_deref(), Some("proxy"));
+        assert!(
+            matches!(&r.kind, RemoteKind::Socks5 { auth: Some((user, _)), .. } if user == "al=ice")
+        );
+
+        let remotes = RemoteRequest::expand("rtp=5000-5001:media:6000-6001/udp").unwrap();
+        let names: Vec<_> = remotes.iter().map(|r| r.name.as_deref()).collect();
+        assert_eq!(names, [Some("rtp-5000"), Some("rtp-5001")]);
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        for input in [
+            "=2222:host:22",
+            "22=2222:host:22",
+            "ssh=",
+            &format!("{}=22", "a".repeat(65)),
+        ] {
+            assert!(
+                RemoteRequest::from_str(input).is_err(),
+                "expected `{input}` to be rejected"
+            );
+        }
+        assert!(validate_name("web-1").is_ok());
+        assert!(validate_name("web 1").is_err());
     }
 
     #[test]
@@ -956,4 +2275,110 @@ mod tests {
         assert_eq!(remote.host, "示例網站.com");
         assert_eq!(remote.port, 80);
     }
+
+    #[test]
+    fn peer_info_local_is_compatible() {
+        assert!(PeerInfo::local().check_compatible().is_ok());
+        assert_eq!(PeerInfo::local().negotiate(), Capability::ALL);
+    }
+
+    #[test]
+    fn peer_info_rejects_out_of_range_versions() {
+        for version in [MIN_PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+            let peer = PeerInfo {
+                protocol_version: version,
+                ..PeerInfo::local()
+            };
+            let err = peer.check_compatible().unwrap_err();
+            assert!(
+                err.contains(&format!("incompatible protocol version {version}")),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn negotiate_ignores_unknown_capabilities() {
+        let peer = PeerInfo {
+            capabilities: vec!["from-the-future".into(), "tunnel-control".into()],
+            ..PeerInfo::local()
+        };
+        assert_eq!(peer.negotiate(), [Capability::TunnelControl]);
+    }
+
+    #[test]
+    fn capability_names_roundtrip() {
+        for c in Capability::ALL {
+            assert_eq!(Capability::from_name(c.name()), Some(c));
+        }
+        assert_eq!(Capability::from_name("nope"), None);
+    }
+
+    #[test]
+    fn resume_tokens_are_distinct_and_roundtrip() {
+        let a = ResumeToken::generate().unwrap();
+        let b = ResumeToken::generate().unwrap();
+        assert_ne!(a, b);
+        let hello = SessionHello {
+            resume: Some(a),
+            ..SessionHello::new(vec![parse("8080")])
+        };
+        let decoded = SessionHello::from_bytes(hello.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.resume, Some(a));
+        assert_eq!(format!("{a:?}"), "ResumeToken(..)");
+    }
+
+    /// A hello from a client that predates negotiation still decodes,
+    /// as protocol 0, so the server can reject it with a reason.
+    #[test]
+    fn legacy_session_hello_decodes_as_protocol_zero() {
+        #[derive(Serialize)]
+        struct LegacySessionHello {
+            remotes: Vec<RemoteRequest>,
+        }
+        let bytes = rmp_serde::to_vec(&LegacySessionHello {
+            remotes: vec![parse("8080")],
+        })
+        .unwrap();
+        let hello = SessionHello::from_bytes(bytes).unwrap();
+        assert_eq!(hello.remotes.len(), 1);
+        assert_eq!(hello.peer, PeerInfo::default());
+        assert!(hello.peer.check_compatible().is_err());
+    }
+
+    /// A version-1 client from before tunnel names sends remotes whose
+    /// array ends at `range`; they still decode, unnamed.
+    #[test]
+    fn unnamed_remote_request_decodes() {
+        #[derive(Serialize)]
+        struct UnnamedRemoteRequest {
+            direction: Direction,
+            kind: RemoteKind,
+            stdio: bool,
+            range: Option<PortRange>,
+        }
+        #[derive(Serialize)]
+        struct UnnamedSessionHello {
+            remotes: Vec<UnnamedRemoteRequest>,
+            peer: PeerInfo,
+            strict: bool,
+            resume: Option<ResumeToken>,
+        }
+        let r = parse("stdio:example.com:22");
+        let bytes = rmp_serde::to_vec(&UnnamedSessionHello {
+            remotes: vec![UnnamedRemoteRequest {
+                direction: r.direction,
+                kind: r.kind.clone(),
+                stdio: r.stdio,
+                range: r.range,
+            }],
+            peer: PeerInfo::local(),
+            strict: true,
+            resume: None,
+        })
+        .unwrap();
+        let hello = SessionHello::from_bytes(bytes).unwrap();
+        assert_eq!(hello.remotes, vec![r]);
+        assert!(hello.strict);
+    }
 }
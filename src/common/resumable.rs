@@ -0,0 +1,597 @@
+//! TCP conns that survive a reconnect.
+//!
+//! A resumable conn ([`OpenConn::resume`](crate::common::remote::OpenConn::resume))
+//! doesn't hand its bi-stream to a plain byte copy. Each side frames
+//! what it sends — payload, a FIN, and cumulative acks of what it has
+//! delivered to its local socket — and keeps the payload the peer
+//! hasn't acked in a bounded replay buffer. When the QUIC connection is
+//! lost, both local sockets stay open: the client attaches a fresh
+//! stream to the conn on its next connection
+//! ([`ConnResume::Attach`](crate::common::remote::ConnResume::Attach)),
+//! the two sides swap how much they've delivered, and each replays the
+//! rest from its buffer.
+//!
+//! The conn is closed instead if the server doesn't see it attached
+//! within its reconnect grace, if the client gets no new connection
+//! within its hold, or if the bytes the peer is missing have already
+//! been pushed out of the replay buffer by newer ones.
+//!
+//! Each frame is a tag byte followed by
+//!
+//! * `DATA`: a `u32` length (LE) and that many payload bytes;
+//! * `ACK`: a `u64` (LE) count of the peer's payload bytes delivered,
+//!   plus one once its FIN has been;
+//! * `FIN`: nothing.
+//!
+//! Once both FINs are acked each side finishes its half of the stream.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use quinn::{
+    Connection, ConnectionError, ReadError, ReadExactError, RecvStream, SendStream, VarInt,
+    WriteError,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info};
+
+use crate::common::remote::{OpenConnResponse, ResumeToken};
+use crate::common::tcp::{Counters, LocalStream};
+use crate::common::tunnel::{reply_open_conn, send_attach_conn, OpenConnError};
+
+/// Unacked payload each side keeps for replay, per conn. Once more
+/// than this is in flight the oldest bytes are dropped, and the conn
+/// can only be resumed if the peer turns out not to need them.
+pub const REPLAY_BUFFER: usize = 4 * 1024 * 1024;
+
+/// Largest payload in one `DATA` frame.
+const MAX_FRAME: usize = 64 * 1024;
+
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+const FIN: u8 = 2;
+
+/// RESET_STREAM / STOP_SENDING code for a conn torn down on a local
+/// socket error, as in [`crate::common::tcp`].
+const ABORT_CODE: u32 = 0;
+
+/// One bi-stream carrying a resumable conn.
+pub struct Leg {
+    pub send: SendStream,
+    pub recv: RecvStream,
+    /// How much of this side's payload the peer had delivered when the
+    /// stream was attached (0 for the stream that opened the conn).
+    pub peer_received: u64,
+}
+
+/// How a side gets its resumable conns a new [`Leg`] after a loss.
+#[derive(Clone)]
+pub enum Reattach {
+    /// Client: attach a stream on the next connection `current` holds,
+    /// waiting up to `hold` for one.
+    Dial {
+        current: watch::Receiver<Option<Connection>>,
+        hold: Duration,
+    },
+    /// Server: wait up to `grace` for the client to attach one through
+    /// `points`.
+    Await {
+        points: AttachPoints,
+        grace: Duration,
+    },
+}
+
+/// The server's resumable conns by token, for the client's attach
+/// streams to find.
+#[derive(Clone, Default)]
+pub struct AttachPoints {
+    slots: Arc<Mutex<HashMap<ResumeToken, mpsc::Sender<Leg>>>>,
+}
+
+impl AttachPoints {
+    fn register(&self, token: ResumeToken) -> Attachment {
+        let (tx, legs) = mpsc::channel(1);
+        self.lock().insert(token, tx);
+        Attachment {
+            token,
+            points: self.clone(),
+            legs,
+        }
+    }
+
+    /// Hand `leg` to the conn `token` names. Gives it back if there's
+    /// no such conn (any more).
+    pub fn attach(&self, token: ResumeToken, leg: Leg) -> Result<(), Leg> {
+        let Some(slot) = self.lock().get(&token).cloned() else {
+            return Err(leg);
+        };
+        slot.try_send(leg).map_err(|e| e.into_inner())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ResumeToken, mpsc::Sender<Leg>>> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A conn's entry in [`AttachPoints`], removed on drop.
+struct Attachment {
+    token: ResumeToken,
+    points: AttachPoints,
+    legs: mpsc::Receiver<Leg>,
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        self.points.lock().remove(&self.token);
+    }
+}
+
+/// How much of the peer's side has been written to the local socket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Delivered {
+    bytes: u64,
+    fin: bool,
+}
+
+impl Delivered {
+    /// The value of an `ACK` frame for this.
+    fn ack(self) -> u64 {
+        self.bytes + u64::from(self.fin)
+    }
+}
+
+/// What this side has sent, as far as the peer may still need it.
+#[derive(Debug)]
+struct Outbound {
+    /// Payload from offset `start` on.
+    buf: VecDeque<u8>,
+    start: u64,
+    /// Offset just past the last payload byte read from the socket.
+    sent: u64,
+    /// The local socket has reached EOF.
+    fin: bool,
+    cap: usize,
+}
+
+impl Outbound {
+    fn new(cap: usize) -> Self {
+        Self {
+            buf: VecDeque::new(),
+            start: 0,
+            sent: 0,
+            fin: false,
+            cap,
+        }
+    }
+
+    /// Offset just past the FIN, once there is one.
+    fn end(&self) -> u64 {
+        self.sent + u64::from(self.fin)
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.sent += data.len() as u64;
+        self.buf.extend(data);
+        if self.buf.len() > self.cap {
+            let excess = self.buf.len() - self.cap;
+            self.buf.drain(..excess);
+            self.start += excess as u64;
+        }
+    }
+
+    /// The peer has delivered everything before `ack`. `false` if that's
+    /// more than was ever sent.
+    fn ack(&mut self, ack: u64) -> bool {
+        if ack > self.end() {
+            return false;
+        }
+        let done = ack.min(self.sent).saturating_sub(self.start) as usize;
+        self.buf.drain(..done);
+        self.start += done as u64;
+        true
+    }
+
+    /// What to send again to a peer that has delivered everything before
+    /// `from`: the payload after it, and whether the FIN is missing too.
+    fn replay(&mut self, from: u64) -> Result<(Vec<u8>, bool)> {
+        if !self.ack(from) {
+            return Err(anyhow!(
+                "peer claims {from} bytes delivered, only {} were sent",
+                self.end()
+            ));
+        }
+        if from < self.start {
+            return Err(anyhow!(
+                "replay buffer overflowed: {} unacked bytes are gone",
+                self.start - from
+            ));
+        }
+        Ok((
+            self.buf.iter().copied().collect(),
+            self.fin && from <= self.sent,
+        ))
+    }
+}
+
+/// Why a leg ended before the conn was done.
+enum LegError {
+    /// Its connection went away; the conn may carry on over another.
+    Lost(ConnectionError),
+    /// The conn can't go on: a local socket failed, the peer gave up on
+    /// it, or the stream broke protocol.
+    Failed(anyhow::Error),
+}
+
+impl From<WriteError> for LegError {
+    fn from(e: WriteError) -> Self {
+        match e {
+            WriteError::ConnectionLost(e) => Self::Lost(e),
+            WriteError::Stopped(code) => Self::Failed(refusal(code, "peer stopped the conn")),
+            other => Self::Failed(other.into()),
+        }
+    }
+}
+
+impl From<ReadError> for LegError {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::ConnectionLost(e) => Self::Lost(e),
+            ReadError::Reset(code) => Self::Failed(refusal(code, "peer reset the conn")),
+            other => Self::Failed(other.into()),
+        }
+    }
+}
+
+impl From<ReadExactError> for LegError {
+    fn from(e: ReadExactError) -> Self {
+        match e {
+            ReadExactError::FinishedEarly(_) => Self::Failed(anyhow!("stream ended mid-frame")),
+            ReadExactError::ReadError(e) => e.into(),
+        }
+    }
+}
+
+/// A peer refusing the conn at open time resets it with an
+/// [`OpenConnError`] code; anything else is a plain abort.
+fn refusal(code: VarInt, what: &str) -> anyhow::Error {
+    match OpenConnError::from_code(code) {
+        Some(refused) => anyhow!("conn open rejected: {refused}"),
+        None => anyhow!("{what} (code {code})"),
+    }
+}
+
+/// One side of a resumable conn: its local socket and what it takes to
+/// carry the conn on over another stream.
+pub struct ResumableConn {
+    token: ResumeToken,
+    tcp_read: Box<dyn AsyncRead + Unpin + Send + Sync>,
+    tcp_write: Box<dyn AsyncWrite + Unpin + Send + Sync>,
+    out: Mutex<Outbound>,
+    /// Delivered so far of the peer's side.
+    delivered: watch::Sender<Delivered>,
+    /// The peer's highest ack of ours.
+    acked: watch::Sender<u64>,
+    counters: Counters,
+    reattach: Reattach,
+}
+
+impl ResumableConn {
+    pub fn new<S: LocalStream>(
+        token: ResumeToken,
+        tcp_stream: S,
+        reattach: Reattach,
+        counters: Counters,
+    ) -> Self {
+        let (tcp_read, tcp_write) = tcp_stream.into_halves();
+        Self {
+            token,
+            tcp_read: Box::new(tcp_read),
+            tcp_write: Box::new(tcp_write),
+            out: Mutex::new(Outbound::new(REPLAY_BUFFER)),
+            delivered: watch::channel(Delivered::default()).0,
+            acked: watch::channel(0).0,
+            counters,
+            reattach,
+        }
+    }
+
+    /// Carry the conn over `leg` and, each time a connection under it is
+    /// lost, over the next leg [`Reattach`] comes up with, until both
+    /// sides have finished or the conn can't go on. On the server a
+    /// client may attach a new leg at any time; it replaces the current
+    /// one.
+    pub async fn run(mut self, mut leg: Leg) -> Result<()> {
+        let mut attachment = match &self.reattach {
+            Reattach::Await { points, .. } => Some(points.register(self.token)),
+            Reattach::Dial { .. } => None,
+        };
+        loop {
+            let attached = async {
+                match attachment.as_mut() {
+                    Some(a) => a.legs.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let ended = tokio::select! {
+                ended = self.run_leg(&mut leg) => LegEnd::Ended(ended),
+                Some(newer) = attached => LegEnd::Replaced(newer),
+            };
+            match ended {
+                LegEnd::Ended(Ok(())) => return Ok(()),
+                LegEnd::Ended(Err(LegError::Failed(e))) => {
+                    abort(&mut leg);
+                    return Err(e);
+                }
+                LegEnd::Ended(Err(LegError::Lost(reason))) => {
+                    leg = self.next_leg(reason, attachment.as_mut()).await?;
+                }
+                LegEnd::Replaced(newer) => {
+                    debug!("conn attached to a newer stream");
+                    abort(&mut leg);
+                    leg = self.adopt(newer).await?;
+                }
+            }
+            info!("conn resumed");
+        }
+    }
+
+    /// A leg for the conn after its connection was lost with `reason`.
+    async fn next_leg(
+        &self,
+        reason: ConnectionError,
+        attachment: Option<&mut Attachment>,
+    ) -> Result<Leg> {
+        match (&self.reattach, attachment) {
+            (Reattach::Dial { current, hold }, _) => {
+                info!(%reason, "connection lost; conn waiting to resume");
+                let mut current = current.clone();
+                let connection = tokio::time::timeout(
+                    *hold,
+                    current.wait_for(|c| c.as_ref().is_some_and(|c| c.close_reason().is_none())),
+                )
+                .await
+                .map_err(|_| anyhow!("no connection to resume the conn on within {hold:?}"))?
+                .map_err(|_| anyhow!("client shut down"))?
+                .clone()
+                .ok_or_else(|| anyhow!("client shut down"))?;
+                let (mut send, mut recv) = connection.open_bi().await?;
+                let received = self.delivered.borrow().ack();
+                let peer_received =
+                    send_attach_conn(self.token, received, &mut send, &mut recv).await?;
+                Ok(Leg {
+                    send,
+                    recv,
+                    peer_received,
+                })
+            }
+            (Reattach::Await { grace, .. }, Some(attachment)) => {
+                if let ConnectionError::ApplicationClosed(_) = reason {
+                    return Err(anyhow!("client closed the connection"));
+                }
+                info!(%reason, "connection lost; conn waiting to resume");
+                let newer = tokio::time::timeout(*grace, attachment.legs.recv())
+                    .await
+                    .map_err(|_| anyhow!("conn not resumed within {grace:?}"))?
+                    .ok_or_else(|| anyhow!("conn no longer attachable"))?;
+                self.adopt(newer).await
+            }
+            (Reattach::Await { .. }, None) => Err(anyhow!("conn isn't attachable")),
+        }
+    }
+
+    /// Server: answer a leg the client attached with what we've
+    /// delivered, making it the conn's leg from here on.
+    async fn adopt(&self, mut leg: Leg) -> Result<Leg> {
+        let received = self.delivered.borrow().ack();
+        reply_open_conn(&mut leg.send, &OpenConnResponse::Attached { received }).await?;
+        Ok(leg)
+    }
+
+    /// Carry the conn over one leg: replay what the peer is missing,
+    /// then move payload and acks both ways. `Ok` once both sides have
+    /// finished.
+    async fn run_leg(&mut self, leg: &mut Leg) -> Result<(), LegError> {
+        let Self {
+            tcp_read,
+            tcp_write,
+            out,
+            delivered,
+            acked,
+            counters,
+            ..
+        } = self;
+        let (out, counters) = (&*out, &*counters);
+        let (replay, replay_fin) = lock(out)
+            .replay(leg.peer_received)
+            .map_err(LegError::Failed)?;
+        acked.send_if_modified(|a| bump(a, leg.peer_received));
+        let Leg { send, recv, .. } = leg;
+
+        let outbound = async {
+            for chunk in replay.chunks(MAX_FRAME) {
+                write_data(send, chunk).await?;
+            }
+            if replay_fin {
+                send.write_all(&[FIN]).await?;
+            }
+            let mut delivered_rx = delivered.subscribe();
+            let mut acked_rx = acked.subscribe();
+            let mut last_ack = delivered_rx.borrow_and_update().ack();
+            let mut buf = vec![0u8; MAX_FRAME];
+            loop {
+                let now = *delivered_rx.borrow_and_update();
+                if now.ack() != last_ack {
+                    last_ack = now.ack();
+                    let mut frame = [0u8; 9];
+                    frame[0] = ACK;
+                    frame[1..].copy_from_slice(&last_ack.to_le_bytes());
+                    send.write_all(&frame).await?;
+                }
+                let (fin, end) = {
+                    let out = lock(out);
+                    (out.fin, out.end())
+                };
+                if fin && *acked_rx.borrow_and_update() >= end && now.fin {
+                    let _ = send.finish();
+                    return Ok(());
+                }
+                tokio::select! {
+                    _ = delivered_rx.changed() => {}
+                    _ = acked_rx.changed() => {}
+                    read = tcp_read.read(&mut buf), if !fin => match read {
+                        Ok(0) => {
+                            lock(out).fin = true;
+                            send.write_all(&[FIN]).await?;
+                        }
+                        Ok(n) => {
+                            // Into the replay buffer first, so the bytes
+                            // survive this leg going away mid-write.
+                            lock(out).push(&buf[..n]);
+                            if let Some(c) = counters {
+                                c.add_out(n as u64);
+                            }
+                            write_data(send, &buf[..n]).await?;
+                        }
+                        Err(e) => return Err(LegError::Failed(e.into())),
+                    },
+                }
+            }
+        };
+
+        let inbound = async {
+            let mut tag = [0u8; 1];
+            loop {
+                match recv.read_exact(&mut tag).await {
+                    Ok(()) => {}
+                    Err(ReadExactError::FinishedEarly(0)) => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                }
+                match tag[0] {
+                    DATA => {
+                        let mut len = [0u8; 4];
+                        recv.read_exact(&mut len).await?;
+                        let len = u32::from_le_bytes(len) as usize;
+                        if len > MAX_FRAME {
+                            return Err(LegError::Failed(anyhow!("oversized frame ({len} bytes)")));
+                        }
+                        let mut data = vec![0u8; len];
+                        recv.read_exact(&mut data).await?;
+                        let mut rest = &data[..];
+                        while !rest.is_empty() {
+                            // One write at a time, so `delivered` stays
+                            // exact if this leg is dropped in between.
+                            let n = tcp_write
+                                .write(rest)
+                                .await
+                                .map_err(|e| LegError::Failed(e.into()))?;
+                            rest = &rest[n..];
+                            delivered.send_modify(|d| d.bytes += n as u64);
+                            if let Some(c) = counters {
+                                c.add_in(n as u64);
+                            }
+                        }
+                    }
+                    ACK => {
+                        let mut ack = [0u8; 8];
+                        recv.read_exact(&mut ack).await?;
+                        let ack = u64::from_le_bytes(ack);
+                        if !lock(out).ack(ack) {
+                            return Err(LegError::Failed(anyhow!(
+                                "peer acked {ack} bytes, more than were sent"
+                            )));
+                        }
+                        acked.send_if_modified(|a| bump(a, ack));
+                    }
+                    FIN => {
+                        if !delivered.borrow().fin {
+                            let _ = tcp_write.shutdown().await;
+                            delivered.send_modify(|d| d.fin = true);
+                        }
+                    }
+                    other => {
+                        return Err(LegError::Failed(anyhow!("unknown frame tag {other}")));
+                    }
+                }
+            }
+        };
+
+        tokio::try_join!(outbound, inbound).map(|_| ())
+    }
+}
+
+/// How [`ResumableConn::run`]'s wait on a leg ended.
+enum LegEnd {
+    Ended(Result<(), LegError>),
+    Replaced(Leg),
+}
+
+fn lock(out: &Mutex<Outbound>) -> MutexGuard<'_, Outbound> {
+    out.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Raise `current` to `to`; whether it moved.
+fn bump(current: &mut u64, to: u64) -> bool {
+    if to > *current {
+        *current = to;
+        true
+    } else {
+        false
+    }
+}
+
+async fn write_data(send: &mut SendStream, data: &[u8]) -> Result<(), WriteError> {
+    let mut header = [0u8; 5];
+    header[0] = DATA;
+    header[1..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    send.write_all(&header).await?;
+    send.write_all(data).await
+}
+
+fn abort(leg: &mut Leg) {
+    let _ = leg.send.reset(VarInt::from_u32(ABORT_CODE));
+    let _ = leg.recv.stop(VarInt::from_u32(ABORT_CODE));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acks_trim_the_buffer() {
+        let mut out = Outbound::new(16);
+        out.push(b"hello");
+        out.push(b"world");
+        assert!(out.ack(5));
+        assert_eq!(out.start, 5);
+        assert_eq!(out.buf.iter().copied().collect::<Vec<_>>(), b"world");
+        assert!(!out.ack(11));
+        out.fin = true;
+        assert!(out.ack(11));
+        assert!(out.buf.is_empty());
+        assert_eq!(out.start, 10);
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest_bytes() {
+        let mut out = Outbound::new(4);
+        out.push(b"abcdef");
+        assert_eq!(out.start, 2);
+        assert_eq!(out.buf.iter().copied().collect::<Vec<_>>(), b"cdef");
+        assert!(out.replay(1).is_err());
+        assert_eq!(out.replay(3).unwrap(), (b"def".to_vec(), false));
+    }
+
+    #[test]
+    fn replay_resends_a_missing_fin() {
+        let mut out = Outbound::new(16);
+        out.push(b"abc");
+        out.fin = true;
+        assert_eq!(out.replay(1).unwrap(), (b"bc".to_vec(), true));
+        assert_eq!(out.replay(3).unwrap(), (Vec::new(), true));
+        assert_eq!(out.replay(4).unwrap(), (Vec::new(), false));
+        assert!(out.replay(5).is_err());
+    }
+}
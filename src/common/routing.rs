@@ -0,0 +1,329 @@
+//! Split-tunnel routing for forward SOCKS listeners.
+//!
+//! The client can route each SOCKS CONNECT by its destination: connect
+//! to it straight from the client, carry it over the tunnel, or refuse
+//! it. [`RouteRules`] are checked in order and the first rule that
+//! matches decides; a destination no rule matches goes through the
+//! tunnel, as it would with no rules at all. Every rule counts its hits,
+//! and the bytes of the `direct` conns it sends around the tunnel.
+
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use super::remote::HostPort;
+
+/// What happens to a CONNECT a rule matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Connect from the client itself, bypassing the tunnel.
+    Direct,
+    /// Carry it over the tunnel.
+    Tunnel,
+    /// Refuse it ("connection not allowed by ruleset").
+    Reject,
+}
+
+impl Route {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::Tunnel => "tunnel",
+            Self::Reject => "reject",
+        }
+    }
+}
+
+/// An IP network, `addr/prefix`. A bare address is a network of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("invalid network `{s}`: bad address"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| anyhow!("invalid network `{s}`: prefix must be 0-{max}"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Whether the first `prefix` bits of `net` and `ip` agree.
+fn prefix_matches(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let whole = usize::from(prefix / 8);
+    if net[..whole] != ip[..whole] {
+        return false;
+    }
+    let rest = prefix % 8;
+    rest == 0 || (net[whole] ^ ip[whole]) & (0xFF << (8 - rest)) == 0
+}
+
+/// One split-tunnel rule. A destination matches when it fits every
+/// criterion the rule sets — one of its networks, one of its domain
+/// suffixes, one of its ports; a rule that sets none matches them all.
+/// Networks only match IP-literal destinations and domains only
+/// hostnames: a hostname isn't resolved to check it against a network.
+#[derive(Debug)]
+pub struct RouteRule {
+    route: Route,
+    cidrs: Vec<Cidr>,
+    domains: Vec<String>,
+    ports: Vec<u16>,
+    hits: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl RouteRule {
+    /// A domain `example.com` matches it and every name under it; a
+    /// leading `.` is accepted and means the same.
+    pub fn new(route: Route, cidrs: Vec<Cidr>, domains: Vec<String>, ports: Vec<u16>) -> Self {
+        let domains = domains
+            .into_iter()
+            .map(|d| {
+                d.trim_start_matches('.')
+                    .trim_end_matches('.')
+                    .to_ascii_lowercase()
+            })
+            .collect();
+        Self {
+            route,
+            cidrs,
+            domains,
+            ports,
+            hits: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+        }
+    }
+
+    fn matches(&self, target: &HostPort) -> bool {
+        if !self.ports.is_empty() && !self.ports.contains(&target.port) {
+            return false;
+        }
+        let ip = target.host.parse::<IpAddr>().ok();
+        if !self.cidrs.is_empty() {
+            let Some(ip) = ip else { return false };
+            if !self.cidrs.iter().any(|c| c.contains(ip)) {
+                return false;
+            }
+        }
+        if !self.domains.is_empty() {
+            if ip.is_some() {
+                return false;
+            }
+            let host = target.host.trim_end_matches('.').to_ascii_lowercase();
+            let under = |d: &String| {
+                host.strip_suffix(d.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+            };
+            if !self.domains.iter().any(under) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The rule that decided a destination's route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteMatch {
+    /// The rule's position in the list, counting from 1.
+    pub rule: usize,
+    pub route: Route,
+    /// The rule's hits so far, this one included.
+    pub hits: u64,
+}
+
+/// One rule's counters, as [`RouteRules::stats`] reports them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteStats {
+    pub route: Route,
+    pub hits: u64,
+    /// Bytes the rule's `direct` conns carried, counted as each closes.
+    /// Tunneled conns are counted by the server like any other.
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// A client's split-tunnel rules, in order. Empty (the default) means
+/// every CONNECT goes through the tunnel.
+#[derive(Clone, Debug, Default)]
+pub struct RouteRules(Arc<Vec<RouteRule>>);
+
+impl RouteRules {
+    pub fn new(rules: Vec<RouteRule>) -> Self {
+        Self(Arc::new(rules))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The first rule `target` matches, counting the hit; `None` if no
+    /// rule does, so the CONNECT is tunneled.
+    pub fn route(&self, target: &HostPort) -> Option<RouteMatch> {
+        let (index, rule) = self
+            .0
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(target))?;
+        let hits = rule.hits.fetch_add(1, Ordering::Relaxed) + 1;
+        Some(RouteMatch {
+            rule: index + 1,
+            route: rule.route,
+            hits,
+        })
+    }
+
+    /// Add a closed `direct` conn's bytes to the rule `hit` names.
+    pub fn count_bytes(&self, hit: &RouteMatch, bytes_in: u64, bytes_out: u64) {
+        if let Some(rule) = self.0.get(hit.rule - 1) {
+            rule.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+            rule.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+        }
+    }
+
+    /// Each rule's hits so far, in order.
+    pub fn hits(&self) -> Vec<u64> {
+        self.0
+            .iter()
+            .map(|rule| rule.hits.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Each rule's counters so far, in order.
+    pub fn stats(&self) -> Vec<RouteStats> {
+        self.0
+            .iter()
+            .map(|rule| RouteStats {
+                route: rule.route,
+                hits: rule.hits.load(Ordering::Relaxed),
+                bytes_in: rule.bytes_in.load(Ordering::Relaxed),
+                bytes_out: rule.bytes_out.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let net = cidr("10.0.0.0/8");
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(!net.contains(ip("11.0.0.1")));
+        assert!(net.contains(ip("::ffff:10.0.0.1")));
+        assert!(cidr("192.168.1.0/23").contains(ip("192.168.0.255")));
+        assert!(!cidr("192.168.1.0/23").contains(ip("192.168.2.0")));
+        assert!(cidr("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+        assert!(cidr("fd00::/8").contains(ip("fd12::1")));
+        assert!(cidr("127.0.0.1").contains(ip("127.0.0.1")));
+        assert!(!cidr("127.0.0.1").contains(ip("127.0.0.2")));
+    }
+
+    #[test]
+    fn cidr_rejects_bad_input() {
+        for bad in ["10.0.0.0/33", "::/129", "example.com/8", "10.0.0.0/x", ""] {
+            assert!(bad.parse::<Cidr>().is_err(), "accepted {bad:?}");
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules = RouteRules::new(vec![
+            RouteRule::new(Route::Reject, vec![], vec![], vec![25]),
+            RouteRule::new(Route::Direct, vec![cidr("10.0.0.0/8")], vec![], vec![]),
+            RouteRule::new(
+                Route::Direct,
+                vec![],
+                vec![".Corp.Example.com".into()],
+                vec![],
+            ),
+            RouteRule::new(Route::Tunnel, vec![], vec![], vec![]),
+        ]);
+        let route = |host: &str, port| rules.route(&HostPort::new(host, port)).map(|m| m.route);
+        assert_eq!(route("10.0.0.1", 25), Some(Route::Reject));
+        assert_eq!(route("10.0.0.1", 443), Some(Route::Direct));
+        assert_eq!(route("git.corp.example.com", 22), Some(Route::Direct));
+        assert_eq!(route("CORP.example.com.", 22), Some(Route::Direct));
+        assert_eq!(route("notcorp.example.com", 22), Some(Route::Tunnel));
+        assert_eq!(route("11.0.0.1", 443), Some(Route::Tunnel));
+        assert_eq!(rules.hits(), vec![1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn criteria_combine() {
+        let rules = RouteRules::new(vec![RouteRule::new(
+            Route::Direct,
+            vec![cidr("192.168.0.0/16")],
+            vec![],
+            vec![22, 80],
+        )]);
+        let hit = rules.route(&HostPort::new("192.168.5.5", 80)).unwrap();
+        assert_eq!((hit.rule, hit.route, hit.hits), (1, Route::Direct, 1));
+        rules.count_bytes(&hit, 5, 7);
+        let stats = RouteStats {
+            route: Route::Direct,
+            hits: 1,
+            bytes_in: 5,
+            bytes_out: 7,
+        };
+        assert_eq!(rules.stats(), vec![stats]);
+        assert!(rules.route(&HostPort::new("192.168.5.5", 443)).is_none());
+        // Hostnames aren't resolved to check them against networks.
+        assert!(rules.route(&HostPort::new("router.lan", 80)).is_none());
+        assert!(RouteRules::default()
+            .route(&HostPort::new("10.0.0.1", 80))
+            .is_none());
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -9,13 +12,18 @@ use quinn::{Connection, RecvStream, SendStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use super::remote::{DynamicTarget, HostPort, OpenConn, RemoteRequest};
+use super::datagram::Datagrams;
+use super::listener::{Uplink, UplinkWatch};
+use super::remote::{BindReply, DynamicTarget, HostPort, OpenConn, RemoteKind, RemoteRequest};
+use super::routing::{Route, RouteMatch, RouteRules};
 use super::tcp::{tunnel_tcp_stream, Counters, TunnelHandleOpt};
-use super::tunnel::send_open_conn;
-use super::udp::{read_datagram, write_datagram};
-use anyhow::{anyhow, Result};
+use super::tunnel::{receive_bind_reply, send_bind_reply, send_open_conn, OpenConnError};
+use super::udp::open_udp_conn;
+use super::utils::route_source;
+use anyhow::{anyhow, Context, Result};
 
 /// Max UDP datagram payload SOCKS5 will accept on either direction. IPv4
 /// caps a UDP payload at 65 507 bytes; round up so we never need to grow.
@@ -26,35 +34,134 @@ const SOCKS_MAX_DATAGRAM: usize = 65_535;
 /// debug log) instead of blocking the receive loop.
 const SOCKS_UDP_CHANNEL_CAPACITY: usize = 4096;
 
+/// How long a partly reassembled UDP-over-SOCKS5 datagram waits for its
+/// remaining fragments. RFC 1928 §7 asks for no less than 5 seconds.
+const SOCKS_UDP_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// How long a per (src, target) UDP-over-SOCKS5 conn may sit idle before
 /// we tear down the QUIC stream. Mirrors the static UDP forward timeout so
 /// the two paths age out resources in lockstep.
 const SOCKS_UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// Username/password pairs a SOCKS listener accepts (RFC 1929). Empty
+/// means the listener asks for no authentication.
+#[derive(Clone, Default)]
+pub struct SocksUsers(Arc<HashMap<String, String>>);
+
+impl SocksUsers {
+    /// Read a credentials file: one `user:pass` per line; blank lines
+    /// and lines starting with `#` are skipped.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid credentials file {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut users = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, pass) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("line {}: expected user:pass", i + 1))?;
+            if user.is_empty() || user.len() > 255 || pass.len() > 255 {
+                return Err(anyhow!(
+                    "line {}: username must be 1-255 bytes, password at most 255",
+                    i + 1
+                ));
+            }
+            users.insert(user.to_string(), pass.to_string());
+        }
+        Ok(Self(Arc::new(users)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// These users plus the credentials `remote` itself declares.
+    pub(crate) fn for_remote(&self, remote: &RemoteRequest) -> Self {
+        match &remote.kind {
+            RemoteKind::Socks5 {
+                auth: Some((user, pass)),
+                ..
+            }
+            | RemoteKind::Http {
+                auth: Some((user, pass)),
+                ..
+            } => {
+                let mut users = (*self.0).clone();
+                users.insert(user.clone(), pass.clone());
+                Self(Arc::new(users))
+            }
+            _ => self.clone(),
+        }
+    }
+
+    pub(crate) fn verify(&self, user: &str, pass: &str) -> bool {
+        self.0
+            .get(user)
+            .is_some_and(|p| constant_time_eq(p.as_bytes(), pass.as_bytes()))
+    }
+}
+
+/// `a == b` in time that depends only on the lengths, so a client
+/// can't find a password byte by byte by timing its login attempts.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl fmt::Debug for SocksUsers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Usernames only; never the passwords.
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        f.debug_tuple("SocksUsers").field(&names).finish()
+    }
+}
+
+/// Serve SOCKS (5, or 4/4a) on the already-bound `listener` of a socks remote,
+/// carrying each request over the uplink current once its handshake
+/// is done. With any `users` (or credentials in `remote` itself),
+/// clients must authenticate as one of them. CONNECTs are first checked
+/// against the split-tunnel `rules`, which may send them around the
+/// tunnel or refuse them.
 pub async fn tunnel_socks_client(
-    quic_connection: Connection,
+    listener: TcpListener,
     remote: RemoteRequest,
+    uplink: UplinkWatch,
     handle: TunnelHandleOpt,
-    tunnel_id: u64,
+    users: SocksUsers,
+    rules: RouteRules,
 ) -> Result<()> {
-    let local_addr = remote.local_socket_addr();
-    let listener = TcpListener::bind(local_addr).await?;
-    info!(addr = %local_addr, proto = "socks5", "listening");
+    let users = users.for_remote(&remote);
+    info!(
+        addr = %listener.local_addr()?,
+        proto = "socks5",
+        auth = !users.is_empty(),
+        rules = rules.len(),
+        "listening"
+    );
 
     let local_counter = AtomicUsize::new(0);
 
     loop {
         let (mut local_conn, peer) = listener.accept().await?;
-        let connection = quic_connection.clone();
+        let uplink = uplink.clone();
         let remote = remote.clone();
         let tunnel_handle = handle.clone();
+        let users = users.clone();
+        let rules = rules.clone();
         let local_id = (local_counter.fetch_add(1, Ordering::Relaxed) + 1) as u64;
 
         // Fire-and-forget: each accepted SOCKS5 connection runs to completion
         // in its own task so the accept loop never blocks on a slow handshake
         // (regression of #20 §1).
         tokio::spawn(async move {
-            let request = match socks_handshake(&mut local_conn).await {
+            let (user, request) = match socks_handshake(&mut local_conn, &users).await {
                 Ok(r) => r,
                 Err(e) => {
                     let span = info_span!("socks5", peer = %peer);
@@ -63,9 +170,71 @@ pub async fn tunnel_socks_client(
                     return;
                 }
             };
+            // Split-tunnel rules only see CONNECTs, and see them before
+            // waiting on an uplink: a direct route works while the
+            // tunnel is down.
+            if let SocksRequest::Tcp(TcpCommand::Connect(version), target) = &request {
+                if let Some(hit) = rules.route(target) {
+                    let span = info_span!("socks5", peer = %peer);
+                    span.in_scope(|| {
+                        info!(
+                            target = %target,
+                            rule = hit.rule,
+                            route = hit.route.name(),
+                            hits = hit.hits,
+                            "split-tunnel rule matched"
+                        )
+                    });
+                    match hit.route {
+                        Route::Tunnel => {}
+                        Route::Reject => {
+                            // 0x02 = connection not allowed by ruleset
+                            let _ = local_conn.write_all(&version.failed(0x02)).await;
+                            return;
+                        }
+                        Route::Direct => {
+                            let span = info_span!(
+                                "conn",
+                                conn_id = local_id,
+                                peer = %peer,
+                                target = %target,
+                                proto = version.proto(),
+                                route = "direct",
+                            );
+                            connect_direct(local_conn, *version, target, &rules, &hit)
+                                .instrument(span)
+                                .await;
+                            return;
+                        }
+                    }
+                }
+            }
+            let Uplink {
+                connection,
+                tunnel_id,
+                datagrams,
+                socks_bind,
+                ..
+            } = match uplink.get().await {
+                Ok(u) => u,
+                Err(e) => {
+                    let span = info_span!("socks5", peer = %peer);
+                    let _g = span.enter();
+                    info!(error = %e, "conn dropped");
+                    return;
+                }
+            };
 
             match request {
-                SocksRequest::Connect(target) => {
+                SocksRequest::Tcp(TcpCommand::Bind, _) if !socks_bind => {
+                    let span = info_span!("socks5", peer = %peer);
+                    let _g = span.enter();
+                    // 0x07 = command not supported
+                    let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+                    let _ = write_socks_reply(&mut local_conn, 0x07, unspecified).await;
+                    warn!("BIND refused: peer doesn't support it");
+                }
+                SocksRequest::Tcp(command, target) => {
                     let (send, recv) = match connection.open_bi().await {
                         Ok(stream) => stream,
                         Err(e) => {
@@ -76,13 +245,13 @@ pub async fn tunnel_socks_client(
                         }
                     };
 
-                    // One admin conn per SOCKS CONNECT, labelled
-                    // with the SOCKS client's source plus the target
-                    // it asked for so operators can spot which CONNECT
-                    // is blocking on what.
+                    // One admin conn per SOCKS CONNECT or BIND,
+                    // labelled with the SOCKS client's source plus the
+                    // target it asked for so operators can spot which
+                    // request is blocking on what.
                     let conn_guard = tunnel_handle
                         .as_ref()
-                        .map(|h| h.open_conn(Some(format!("{peer}=>{target}"))));
+                        .map(|h| h.open_conn(Some(format!("{peer}=>{target}")), user.clone()));
                     let conn_id = conn_guard.as_ref().map(|g| g.id()).unwrap_or(local_id);
                     let counters = conn_guard.as_ref().map(|g| g.counters());
 
@@ -92,7 +261,7 @@ pub async fn tunnel_socks_client(
                         tunnel_id,
                         peer = %peer,
                         target = %target,
-                        proto = "socks5/tcp",
+                        proto = command.proto(),
                     );
                     async move {
                         info!("conn opened");
@@ -102,7 +271,9 @@ pub async fn tunnel_socks_client(
                             send,
                             recv,
                             tunnel_id,
+                            command,
                             target,
+                            user,
                             counters.clone(),
                         )
                         .await;
@@ -126,10 +297,12 @@ pub async fn tunnel_socks_client(
                         debug!("UDP ASSOCIATE requested");
                         if let Err(e) = handle_socks_udp_associate(
                             connection,
+                            datagrams,
                             local_conn,
                             &remote,
                             tunnel_handle,
                             peer,
+                            user,
                             tunnel_id,
                         )
                         .await
@@ -145,53 +318,208 @@ pub async fn tunnel_socks_client(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn start_client_dynamic_tunnel(
     mut socks_conn: TcpStream,
     mut send_channel: SendStream,
     mut recv_channel: RecvStream,
     tunnel_id: u64,
+    command: TcpCommand,
     target: HostPort,
+    user: Option<String>,
     counters: Counters,
 ) -> Result<()> {
+    let dynamic = match command {
+        TcpCommand::Connect(_) => DynamicTarget::Tcp(target),
+        TcpCommand::Bind => DynamicTarget::Bind(target),
+    };
     send_open_conn(
         &OpenConn {
             tunnel_id,
-            dynamic: Some(DynamicTarget::Tcp(target)),
+            dynamic: Some(dynamic),
+            datagram_flow: None,
+            optimistic: false,
+            resume: None,
+            user,
+            protocol: None,
         },
         &mut send_channel,
         &mut recv_channel,
     )
     .await?;
 
-    socks_conn
-        .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
-        .await?;
+    match command {
+        TcpCommand::Connect(version) => socks_conn.write_all(version.granted()).await?,
+        TcpCommand::Bind => {
+            if let Err(e) = relay_bind_replies(&mut socks_conn, &mut recv_channel).await {
+                // 0x04 = host unreachable, 0x01 = general SOCKS server failure
+                let rep = match e.downcast_ref::<OpenConnError>() {
+                    Some(OpenConnError::DialFailed) => 0x04,
+                    _ => 0x01,
+                };
+                let unspecified = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+                let _ = write_socks_reply(&mut socks_conn, rep, unspecified).await;
+                return Err(e);
+            }
+        }
+    }
 
     tunnel_tcp_stream(socks_conn, send_channel, recv_channel, counters).await?;
 
     Ok(())
 }
 
-/// Decoded SOCKS5 client request: either a TCP CONNECT (with the target
-/// host:port we'll reach via the tunnel) or a UDP ASSOCIATE (whose target is
-/// per-datagram and parsed later from each UDP packet's header).
+/// Carry a CONNECT the split-tunnel rules route around the tunnel: dial
+/// `target` from here and splice it to the SOCKS client, counting its
+/// bytes against the rule `hit` names.
+async fn connect_direct(
+    mut socks_conn: TcpStream,
+    version: SocksVersion,
+    target: &HostPort,
+    rules: &RouteRules,
+    hit: &RouteMatch,
+) {
+    info!("conn opened");
+    let started = std::time::Instant::now();
+    let result = async {
+        let mut outbound = match TcpStream::connect(target.to_addr_string()).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // 0x05 = connection refused
+                let _ = socks_conn.write_all(&version.failed(0x05)).await;
+                return Err(e).context("direct connect failed");
+            }
+        };
+        if let Err(e) = outbound.set_nodelay(true) {
+            debug!(error = %e, "set_nodelay failed");
+        }
+        socks_conn.write_all(version.granted()).await?;
+        let (bytes_out, bytes_in) =
+            tokio::io::copy_bidirectional(&mut socks_conn, &mut outbound).await?;
+        Ok::<_, anyhow::Error>((bytes_in, bytes_out))
+    }
+    .await;
+    let dur_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok((bytes_in, bytes_out)) => {
+            rules.count_bytes(hit, bytes_in, bytes_out);
+            info!(bytes_in, bytes_out, dur_ms, "conn closed");
+        }
+        Err(e) => warn!(dur_ms, error = %e, "conn closed (error)"),
+    }
+}
+
+/// Pass the far side's two [`BindReply`]s on to the SOCKS client as
+/// BIND's two replies: the address it's listening on, then the address
+/// of the host that connected.
+async fn relay_bind_replies(socks_conn: &mut TcpStream, recv: &mut RecvStream) -> Result<()> {
+    let BindReply::Listening(bound) = receive_bind_reply(recv).await? else {
+        return Err(anyhow!("BIND: expected the listening address first"));
+    };
+    write_socks_reply(socks_conn, 0x00, bound).await?;
+    let BindReply::Accepted(peer) = receive_bind_reply(recv).await? else {
+        return Err(anyhow!("BIND: expected the accepted conn's address"));
+    };
+    write_socks_reply(socks_conn, 0x00, peer).await
+}
+
+/// Decoded SOCKS client request: either one TCP conn through the tunnel
+/// (a CONNECT or BIND, with the host:port from the request) or a SOCKS5
+/// UDP ASSOCIATE (whose target is per-datagram and parsed later from each
+/// UDP packet's header).
+#[derive(Debug, PartialEq, Eq)]
 enum SocksRequest {
-    Connect(HostPort),
+    Tcp(TcpCommand, HostPort),
     UdpAssociate,
 }
 
-async fn socks_handshake(conn: &mut TcpStream) -> Result<SocksRequest> {
+/// What a [`SocksRequest::Tcp`] asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TcpCommand {
+    /// Reach the target; answered in the client's protocol version.
+    Connect(SocksVersion),
+    /// SOCKS5 BIND: have the far side accept one conn from the target.
+    Bind,
+}
+
+impl TcpCommand {
+    fn proto(self) -> &'static str {
+        match self {
+            Self::Connect(version) => version.proto(),
+            Self::Bind => "socks5/bind",
+        }
+    }
+}
+
+/// Which SOCKS protocol a client spoke; CONNECT is answered in kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SocksVersion {
+    /// SOCKS4, and SOCKS4a when the client sent a hostname.
+    V4,
+    V5,
+}
+
+impl SocksVersion {
+    /// The reply to a CONNECT the far side reached. The bound address
+    /// is left zeroed; no client we've met looks at it.
+    fn granted(self) -> &'static [u8] {
+        match self {
+            // VN=0 CD=0x5A (granted) DSTPORT DSTIP
+            Self::V4 => &[0x00, 0x5A, 0, 0, 0, 0, 0, 0],
+            Self::V5 => &[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0],
+        }
+    }
+
+    /// The reply to a CONNECT that didn't go through; `rep` is the
+    /// SOCKS5 reply code, which SOCKS4 can only render as "rejected or
+    /// failed".
+    fn failed(self, rep: u8) -> Vec<u8> {
+        match self {
+            Self::V4 => vec![0x00, 0x5B, 0, 0, 0, 0, 0, 0],
+            Self::V5 => vec![0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0],
+        }
+    }
+
+    fn proto(self) -> &'static str {
+        match self {
+            Self::V4 => "socks4/tcp",
+            Self::V5 => "socks5/tcp",
+        }
+    }
+}
+
+/// Read the client's request, telling SOCKS4/4a and SOCKS5 apart by the
+/// version byte. For SOCKS5, negotiate a method and authenticate against
+/// `users` if there are any. Returns the username the client
+/// authenticated as along with the request.
+async fn socks_handshake(
+    conn: &mut TcpStream,
+    users: &SocksUsers,
+) -> Result<(Option<String>, SocksRequest)> {
     let mut buf = [0u8; 256];
-    conn.read_exact(&mut buf[..2]).await?;
+    conn.read_exact(&mut buf[..1]).await?;
 
-    if buf[0] != 0x05 {
-        return Err(anyhow!("Unsupported SOCKS version: {}", buf[0]));
+    match buf[0] {
+        0x04 => return socks4_request(conn, users).await.map(|r| (None, r)),
+        0x05 => {}
+        other => return Err(anyhow!("Unsupported SOCKS version: {}", other)),
     }
 
+    conn.read_exact(&mut buf[1..2]).await?;
     let methods_len = buf[1] as usize;
     conn.read_exact(&mut buf[..methods_len]).await?;
 
-    conn.write_all(&[0x05, 0x00]).await?;
+    let user = if users.is_empty() {
+        conn.write_all(&[0x05, 0x00]).await?;
+        None
+    } else if buf[..methods_len].contains(&0x02) {
+        conn.write_all(&[0x05, 0x02]).await?;
+        Some(authenticate(conn, users).await?)
+    } else {
+        // 0xFF = no acceptable methods
+        conn.write_all(&[0x05, 0xFF]).await?;
+        return Err(anyhow!("client doesn't offer username/password auth"));
+    };
 
     conn.read_exact(&mut buf[..4]).await?;
 
@@ -201,8 +529,12 @@ async fn socks_handshake(conn: &mut TcpStream) -> Result<SocksRequest> {
     let target = read_socks_addr(conn, atyp).await?;
 
     match cmd {
-        0x01 => Ok(SocksRequest::Connect(target)),
-        0x03 => Ok(SocksRequest::UdpAssociate),
+        0x01 => Ok((
+            user,
+            SocksRequest::Tcp(TcpCommand::Connect(SocksVersion::V5), target),
+        )),
+        0x02 => Ok((user, SocksRequest::Tcp(TcpCommand::Bind, target))),
+        0x03 => Ok((user, SocksRequest::UdpAssociate)),
         _ => {
             // 0x07 = command not supported
             conn.write_all(&[0x05, 0x07]).await?;
@@ -211,6 +543,83 @@ async fn socks_handshake(conn: &mut TcpStream) -> Result<SocksRequest> {
     }
 }
 
+/// The rest of a SOCKS4 request, after its version byte:
+/// `CD DSTPORT DSTIP USERID NUL`, and for SOCKS4a (a `DSTIP` of
+/// `0.0.0.x`, `x` non-zero) a `HOSTNAME NUL` the far side resolves.
+/// Only CONNECT is supported. SOCKS4 has no way to send a password, so
+/// a listener with `users` turns SOCKS4 clients away.
+async fn socks4_request(conn: &mut TcpStream, users: &SocksUsers) -> Result<SocksRequest> {
+    let mut head = [0u8; 7];
+    conn.read_exact(&mut head).await?;
+    let cmd = head[0];
+    let port = u16::from_be_bytes([head[1], head[2]]);
+    let ip = Ipv4Addr::new(head[3], head[4], head[5], head[6]);
+    // The USERID is only an ident string; we don't act on it.
+    read_nul_terminated(conn).await?;
+
+    let host = if ip.octets()[..3] == [0, 0, 0] && ip.octets()[3] != 0 {
+        let host = read_nul_terminated(conn).await?;
+        String::from_utf8(host).map_err(|_| anyhow!("SOCKS4a hostname isn't UTF-8"))?
+    } else {
+        ip.to_string()
+    };
+
+    // 0x5B = request rejected or failed
+    if !users.is_empty() {
+        conn.write_all(&[0x00, 0x5B, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(anyhow!("SOCKS4 client on a listener that requires a login"));
+    }
+    if cmd != 0x01 {
+        conn.write_all(&[0x00, 0x5B, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(anyhow!("Unsupported SOCKS4 command: {}", cmd));
+    }
+    Ok(SocksRequest::Tcp(
+        TcpCommand::Connect(SocksVersion::V4),
+        HostPort::new(host, port),
+    ))
+}
+
+/// Read a NUL-terminated SOCKS4 field, without the NUL. Fields are
+/// capped at 255 bytes so a client can't make us buffer forever.
+async fn read_nul_terminated(conn: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut field = Vec::new();
+    loop {
+        let byte = conn.read_u8().await?;
+        if byte == 0 {
+            return Ok(field);
+        }
+        if field.len() == 255 {
+            return Err(anyhow!("SOCKS4 field longer than 255 bytes"));
+        }
+        field.push(byte);
+    }
+}
+
+/// RFC 1929 username/password sub-negotiation:
+/// `VER=1 ULEN UNAME PLEN PASSWD`, answered with `VER=1 STATUS`.
+async fn authenticate(conn: &mut TcpStream, users: &SocksUsers) -> Result<String> {
+    let mut head = [0u8; 2];
+    conn.read_exact(&mut head).await?;
+    if head[0] != 0x01 {
+        return Err(anyhow!("Unsupported auth version: {}", head[0]));
+    }
+    let mut user = vec![0u8; head[1] as usize];
+    conn.read_exact(&mut user).await?;
+    let mut len = [0u8; 1];
+    conn.read_exact(&mut len).await?;
+    let mut pass = vec![0u8; len[0] as usize];
+    conn.read_exact(&mut pass).await?;
+
+    let user = String::from_utf8_lossy(&user).into_owned();
+    if users.verify(&user, &String::from_utf8_lossy(&pass)) {
+        conn.write_all(&[0x01, 0x00]).await?;
+        Ok(user)
+    } else {
+        conn.write_all(&[0x01, 0x01]).await?;
+        Err(anyhow!("authentication failed for user {user:?}"))
+    }
+}
+
 /// Read the address portion (`ATYP DST.ADDR DST.PORT`) of a SOCKS5 request
 /// or UDP datagram. `atyp` is the already-consumed address-type byte.
 async fn read_socks_addr(conn: &mut TcpStream, atyp: u8) -> Result<HostPort> {
@@ -254,6 +663,127 @@ async fn read_socks_addr(conn: &mut TcpStream, atyp: u8) -> Result<HostPort> {
     }
 }
 
+/// Write a SOCKS5 reply: VER=5 REP RSV=00 ATYP BND.ADDR BND.PORT. For UDP
+/// ASSOCIATE, BND is our UDP relay socket; BIND sends two, the far side's
+/// listener and then the host that connected to it.
+async fn write_socks_reply(conn: &mut TcpStream, rep: u8, addr: SocketAddr) -> Result<()> {
+    let mut reply = vec![0x05, rep, 0x00];
+    match addr.ip() {
+        IpAddr::V4(v4) => {
+            reply.push(0x01);
+            reply.extend_from_slice(&v4.octets());
+        }
+        IpAddr::V6(v6) => {
+            reply.push(0x04);
+            reply.extend_from_slice(&v6.octets());
+        }
+    }
+    reply.extend_from_slice(&addr.port().to_be_bytes());
+    conn.write_all(&reply).await?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// SOCKS5 BIND
+// ---------------------------------------------------------------------------
+//
+// RFC 1928 §4: the SOCKS server listens for one inbound conn on behalf of
+// the client, typically an FTP server's active-mode data conn. DST.ADDR
+// names the host expected to connect. The first reply carries the listening
+// address for the client to pass on; the second, once that host connects,
+// carries its address, and the control conn carries its bytes from then on.
+//
+// Over the tunnel, the far side listens (see `tunnel_socks_bind_server`) and
+// the conn is spliced back through the BIND's bi-stream.
+
+/// How long the far side of a BIND waits for its one inbound conn.
+const SOCKS_BIND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Far side of a BIND: listen on the address `expected` would reach us
+/// at, report it, and carry the first conn from `expected`'s host (from
+/// any host, if it's written as `0.0.0.0` or `::`) over this bi-stream.
+/// Listening for any host, it reports the address `tunnel_peer`, the
+/// other end of the QUIC connection, reaches us by.
+/// A host that doesn't resolve is refused rather than left open to all.
+/// The listener is dropped after that one accept, after
+/// [`SOCKS_BIND_TIMEOUT`], or when the opener gives up.
+pub async fn tunnel_socks_bind_server(
+    mut recv_channel: RecvStream,
+    mut send_channel: SendStream,
+    expected: HostPort,
+    tunnel_peer: SocketAddr,
+    counters: Counters,
+) -> Result<()> {
+    let any_host = expected
+        .host
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| ip.is_unspecified());
+    let expected_ips: Vec<IpAddr> = if any_host {
+        Vec::new()
+    } else {
+        match tokio::net::lookup_host((expected.host.as_str(), expected.port)).await {
+            Ok(addrs) => addrs.map(|a| a.ip()).collect(),
+            Err(e) => {
+                OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+                return Err(anyhow!("BIND: can't resolve {expected}: {e}"));
+            }
+        }
+    };
+    if !any_host && expected_ips.is_empty() {
+        OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+        return Err(anyhow!("BIND: {expected} resolved to no addresses"));
+    }
+    // BND.ADDR has to be an address the connecting host can use, so
+    // never the wildcard the listener may be on.
+    let local_ip = expected_ips
+        .first()
+        .and_then(|ip| route_source(SocketAddr::new(*ip, expected.port)));
+    let tunnel_peer = SocketAddr::new(tunnel_peer.ip().to_canonical(), tunnel_peer.port());
+    let Some(reported_ip) = local_ip.or_else(|| route_source(tunnel_peer)) else {
+        OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+        return Err(anyhow!("BIND: no local address to report for {expected}"));
+    };
+    let bind_ip = local_ip.unwrap_or(match reported_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    });
+    let listener = match TcpListener::bind((bind_ip, 0)).await {
+        Ok(l) => l,
+        Err(e) => {
+            OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+            return Err(e.into());
+        }
+    };
+    let bound = SocketAddr::new(reported_ip, listener.local_addr()?.port());
+    debug!(addr = %bound, expected = %expected, "BIND listening");
+    send_bind_reply(&mut send_channel, &BindReply::Listening(bound)).await?;
+
+    let accept = async {
+        loop {
+            let (conn, peer) = listener.accept().await?;
+            if any_host || expected_ips.contains(&peer.ip().to_canonical()) {
+                return Ok::<_, std::io::Error>((conn, peer));
+            }
+            debug!(peer = %peer, "BIND: dropping conn from unexpected host");
+        }
+    };
+    let (conn, peer) = tokio::select! {
+        accepted = tokio::time::timeout(SOCKS_BIND_TIMEOUT, accept) => match accepted {
+            Ok(accepted) => accepted?,
+            Err(_) => {
+                OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+                return Err(anyhow!("BIND: no conn within {SOCKS_BIND_TIMEOUT:?}"));
+            }
+        },
+        _ = send_channel.stopped() => return Err(anyhow!("BIND abandoned by the opener")),
+    };
+    drop(listener);
+    debug!(peer = %peer, "BIND accepted");
+    send_bind_reply(&mut send_channel, &BindReply::Accepted(peer)).await?;
+
+    tunnel_tcp_stream(conn, send_channel, recv_channel, counters).await
+}
+
 // ---------------------------------------------------------------------------
 // SOCKS5 UDP ASSOCIATE
 // ---------------------------------------------------------------------------
@@ -270,14 +800,18 @@ async fn read_socks_addr(conn: &mut TcpStream, atyp: u8) -> Result<HostPort> {
 //   +----+------+------+----------+----------+----------+
 //
 // The TCP control connection must stay open: when it closes, the UDP
-// association is torn down. We support FRAG=0 only (no reassembly).
+// association is torn down. Fragmented datagrams (FRAG != 0) are
+// reassembled as §7 describes (see `Reassembly`) before they're relayed.
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_socks_udp_associate(
     quic_connection: Connection,
+    datagrams: Datagrams,
     mut tcp_conn: TcpStream,
     original_remote: &RemoteRequest,
     handle: TunnelHandleOpt,
     socks_peer: SocketAddr,
+    user: Option<String>,
     tunnel_id: u64,
 ) -> Result<()> {
     // Bind a UDP socket on the same local IP we listen on. Port 0 lets the
@@ -290,7 +824,7 @@ async fn handle_socks_udp_associate(
     let bound = udp_socket.local_addr()?;
     debug!(addr = %bound, "UDP ASSOCIATE relay bound");
 
-    write_socks_udp_associate_reply(&mut tcp_conn, bound).await?;
+    write_socks_reply(&mut tcp_conn, 0x00, bound).await?;
 
     // Spawn the relay; abort it when the TCP control connection closes.
     let relay_handle = tokio::spawn({
@@ -298,8 +832,10 @@ async fn handle_socks_udp_associate(
         let socket = udp_socket.clone();
         let handle = handle.clone();
         async move {
-            if let Err(e) =
-                run_socks_udp_relay(connection, tunnel_id, socket, handle, socks_peer).await
+            if let Err(e) = run_socks_udp_relay(
+                connection, datagrams, tunnel_id, socket, handle, socks_peer, user,
+            )
+            .await
             {
                 debug!(error = %e, "UDP relay ended");
             }
@@ -320,25 +856,6 @@ async fn handle_socks_udp_associate(
     Ok(())
 }
 
-/// Write the SOCKS5 reply for UDP ASSOCIATE: VER=5 REP=00 RSV=00 ATYP
-/// BND.ADDR BND.PORT — where BND.ADDR/PORT is our UDP relay socket.
-async fn write_socks_udp_associate_reply(conn: &mut TcpStream, addr: SocketAddr) -> Result<()> {
-    let mut reply = vec![0x05, 0x00, 0x00];
-    match addr.ip() {
-        IpAddr::V4(v4) => {
-            reply.push(0x01);
-            reply.extend_from_slice(&v4.octets());
-        }
-        IpAddr::V6(v6) => {
-            reply.push(0x04);
-            reply.extend_from_slice(&v6.octets());
-        }
-    }
-    reply.extend_from_slice(&addr.port().to_be_bytes());
-    conn.write_all(&reply).await?;
-    Ok(())
-}
-
 /// Per-(source, target) UDP-over-SOCKS5 relay loop. Reads datagrams from the
 /// SOCKS-bound UDP socket, parses the SOCKS5 UDP header to discover the
 /// ultimate target, and ferries the inner payload through a per-target QUIC
@@ -346,10 +863,12 @@ async fn write_socks_udp_associate_reply(conn: &mut TcpStream, addr: SocketAddr)
 /// UDP framing and sent to the original SOCKS UDP source.
 async fn run_socks_udp_relay(
     quic_connection: Connection,
+    datagrams: Datagrams,
     tunnel_id: u64,
     udp_socket: Arc<UdpSocket>,
     handle: TunnelHandleOpt,
     socks_peer: SocketAddr,
+    user: Option<String>,
 ) -> Result<()> {
     // Key by (source, target): this preserves source isolation (so two
     // different SOCKS clients sharing the same association never see each
@@ -357,6 +876,7 @@ async fn run_socks_udp_relay(
     // QUIC stream can be wrapped with the correct DST.ADDR/PORT header).
     let conns: Arc<DashMap<(SocketAddr, HostPort), mpsc::Sender<Bytes>>> = Arc::new(DashMap::new());
 
+    let mut reassembly = Reassembly::default();
     let mut buf = vec![0u8; SOCKS_MAX_DATAGRAM];
     loop {
         let (n, src) = udp_socket.recv_from(&mut buf).await?;
@@ -367,6 +887,16 @@ async fn run_socks_udp_relay(
                 continue;
             }
         };
+        let payload = match buf[2] {
+            0 => {
+                reassembly.standalone(src);
+                Bytes::copy_from_slice(payload)
+            }
+            frag => match reassembly.push(src, frag, &target, payload, Instant::now()) {
+                Some(datagram) => datagram,
+                None => continue,
+            },
+        };
 
         let key = (src, target.clone());
         let mut existing = conns.get(&key).map(|e| e.value().clone());
@@ -383,6 +913,7 @@ async fn run_socks_udp_relay(
                 conns.insert(key.clone(), tx.clone());
                 spawn_socks_udp_conn(
                     quic_connection.clone(),
+                    datagrams.clone(),
                     tunnel_id,
                     udp_socket.clone(),
                     src,
@@ -391,6 +922,7 @@ async fn run_socks_udp_relay(
                     conns.clone(),
                     handle.clone(),
                     socks_peer,
+                    user.clone(),
                 );
                 tx
             }
@@ -398,7 +930,7 @@ async fn run_socks_udp_relay(
 
         // UDP is unreliable: drop on backpressure or after the conn
         // terminated rather than blocking the receive loop.
-        if let Err(e) = tx.try_send(Bytes::copy_from_slice(payload)) {
+        if let Err(e) = tx.try_send(payload) {
             debug!(peer = %src, target = %target, error = %e, "dropping udp datagram");
         }
     }
@@ -407,6 +939,7 @@ async fn run_socks_udp_relay(
 #[allow(clippy::too_many_arguments)]
 fn spawn_socks_udp_conn(
     quic_connection: Connection,
+    datagrams: Datagrams,
     tunnel_id: u64,
     udp_socket: Arc<UdpSocket>,
     source: SocketAddr,
@@ -415,6 +948,7 @@ fn spawn_socks_udp_conn(
     conns: Arc<DashMap<(SocketAddr, HostPort), mpsc::Sender<Bytes>>>,
     handle: TunnelHandleOpt,
     socks_peer: SocketAddr,
+    user: Option<String>,
 ) {
     let key = (source, target.clone());
     tokio::spawn(async move {
@@ -423,7 +957,7 @@ fn spawn_socks_udp_conn(
         // SOCKS UDP association.
         let conn_guard = handle
             .as_ref()
-            .map(|h| h.open_conn(Some(format!("{socks_peer}=>{target}"))));
+            .map(|h| h.open_conn(Some(format!("{socks_peer}=>{target}")), user.clone()));
         let conn_id = conn_guard.as_ref().map(|g| g.id()).unwrap_or(0);
         let counters = conn_guard.as_ref().map(|g| g.counters());
         let span = info_span!(
@@ -439,10 +973,12 @@ fn spawn_socks_udp_conn(
             let started = std::time::Instant::now();
             let result = run_socks_udp_conn(
                 quic_connection,
+                datagrams,
                 tunnel_id,
                 udp_socket,
                 source,
                 target,
+                user,
                 rx,
                 counters.clone(),
             )
@@ -467,37 +1003,38 @@ fn spawn_socks_udp_conn(
 #[allow(clippy::too_many_arguments)]
 async fn run_socks_udp_conn(
     quic_connection: Connection,
+    datagrams: Datagrams,
     tunnel_id: u64,
     udp_socket: Arc<UdpSocket>,
     source: SocketAddr,
     target: HostPort,
+    user: Option<String>,
     mut rx: mpsc::Receiver<Bytes>,
     counters: Counters,
 ) -> Result<()> {
     // Open a fresh QUIC bi-stream and announce it as a SOCKS5 dynamic
     // UDP conn under the parent SOCKS5 tunnel — the server uses the
     // attached `DynamicTarget::Udp(target)` to dial the right
-    // destination. Same length-prefixed datagram framing as the
+    // destination. Same datagram-flow / stream-fallback transport as the
     // static UDP forward path beyond this point.
-    let (mut send_channel, mut recv_channel) = quic_connection.open_bi().await?;
-    send_open_conn(
-        &OpenConn {
-            tunnel_id,
-            dynamic: Some(DynamicTarget::Udp(target.clone())),
-        },
-        &mut send_channel,
-        &mut recv_channel,
+    let (mut sender, mut receiver) = open_udp_conn(
+        &quic_connection,
+        &datagrams,
+        tunnel_id,
+        Some(DynamicTarget::Udp(target.clone())),
+        user,
+        None,
     )
     .await?;
 
-    // Forward SOCKS-side datagrams onto the QUIC stream until the rx side
+    // Forward SOCKS-side datagrams to the QUIC peer until the rx side
     // closes (relay loop dropped the conn) or the conn sits idle long
     // enough to age out.
     let local_to_quic = async {
         loop {
             match tokio::time::timeout(SOCKS_UDP_IDLE_TIMEOUT, rx.recv()).await {
                 Ok(Some(payload)) => {
-                    write_datagram(&mut send_channel, &payload).await?;
+                    sender.send(&payload).await?;
                     if let Some(c) = counters.as_ref() {
                         c.add_out(payload.len() as u64);
                     }
@@ -518,7 +1055,7 @@ async fn run_socks_udp_conn(
         let mut buf = vec![0u8; SOCKS_MAX_DATAGRAM];
         let mut wrap = Vec::with_capacity(SOCKS_MAX_DATAGRAM + 32);
         loop {
-            let payload = read_datagram(&mut recv_channel, &mut buf).await?;
+            let payload = receiver.recv(&mut buf).await?;
             wrap_socks_udp_reply(&target, payload, &mut wrap);
             udp_socket.send_to(&wrap, source).await?;
             if let Some(c) = counters.as_ref() {
@@ -533,16 +1070,102 @@ async fn run_socks_udp_conn(
     }
 }
 
+/// RFC 1928 §7 fragment reassembly for one UDP association. A non-zero
+/// FRAG holds the fragment's position in its sequence (1 to 127), with
+/// the high bit set on the last one. There is a single queue, so an
+/// association buffers at most one datagram of at most
+/// [`SOCKS_MAX_DATAGRAM`] bytes. The queue is abandoned when its timer
+/// runs out, on a fragment that leaves a gap, goes back, or names
+/// another target, and on an unfragmented datagram from its source.
+#[derive(Default)]
+struct Reassembly {
+    queue: Option<FragmentQueue>,
+}
+
+struct FragmentQueue {
+    source: SocketAddr,
+    target: HostPort,
+    /// Position of the last fragment queued.
+    position: u8,
+    payload: Vec<u8>,
+    deadline: Instant,
+}
+
+impl Reassembly {
+    /// An unfragmented datagram arrived from `source`. FRAG 0 is below
+    /// any position seen, so its pending sequence is abandoned.
+    fn standalone(&mut self, source: SocketAddr) {
+        if self.queue.as_ref().is_some_and(|q| q.source == source) {
+            self.queue = None;
+        }
+    }
+
+    /// Queue fragment `frag` (non-zero) of a datagram from `source` to
+    /// `target`. Returns the whole datagram once its last fragment is in.
+    fn push(
+        &mut self,
+        source: SocketAddr,
+        frag: u8,
+        target: &HostPort,
+        data: &[u8],
+        now: Instant,
+    ) -> Option<Bytes> {
+        if self.queue.as_ref().is_some_and(|q| q.deadline <= now) {
+            debug!("UDP reassembly timed out");
+            self.queue = None;
+        }
+        let position = frag & 0x7F;
+        let last = frag & 0x80 != 0;
+
+        if let Some(queue) = &mut self.queue {
+            if queue.source != source {
+                // The queue is taken until its sequence completes or
+                // times out.
+                debug!(peer = %source, "dropping fragment: reassembly queue busy");
+                return None;
+            }
+            if position == queue.position + 1 && queue.target == *target {
+                if queue.payload.len() + data.len() > SOCKS_MAX_DATAGRAM {
+                    debug!(peer = %source, "dropping datagram: reassembled size over limit");
+                    self.queue = None;
+                    return None;
+                }
+                queue.payload.extend_from_slice(data);
+                queue.position = position;
+                if last {
+                    return self.queue.take().map(|q| Bytes::from(q.payload));
+                }
+                return None;
+            }
+            debug!(peer = %source, "UDP reassembly abandoned");
+            self.queue = None;
+        }
+
+        // Only a first fragment can start a sequence.
+        if position != 1 {
+            return None;
+        }
+        if last {
+            return Some(Bytes::copy_from_slice(data));
+        }
+        self.queue = Some(FragmentQueue {
+            source,
+            target: target.clone(),
+            position,
+            payload: data.to_vec(),
+            deadline: now + SOCKS_UDP_REASSEMBLY_TIMEOUT,
+        });
+        None
+    }
+}
+
 /// Parse a SOCKS5 UDP datagram header, returning the target the datagram is
-/// destined for and the inner payload slice. Rejects fragmented datagrams
-/// (FRAG != 0) — the spec allows but does not require their support.
+/// destined for and the inner payload slice. FRAG (`buf[2]`) is left to the
+/// caller; see [`Reassembly`].
 fn parse_socks_udp_header(buf: &[u8]) -> Result<(HostPort, &[u8])> {
     if buf.len() < 4 {
         return Err(anyhow!("UDP header too short"));
     }
-    if buf[2] != 0 {
-        return Err(anyhow!("UDP fragmentation not supported"));
-    }
     let atyp = buf[3];
     let (target, hdr_len) = match atyp {
         0x01 => {
@@ -609,6 +1232,176 @@ fn wrap_socks_udp_reply(target: &HostPort, payload: &[u8], buf: &mut Vec<u8>) {
 mod tests {
     use super::*;
 
+    /// Run `socks_handshake` against `users` with `client` playing the
+    /// SOCKS client; returns the handshake's result and what it sent.
+    async fn handshake(
+        users: &SocksUsers,
+        client: &[u8],
+    ) -> (Result<(Option<String>, SocksRequest)>, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut conn, _) = listener.accept().await.unwrap();
+        peer.write_all(client).await.unwrap();
+        let result = socks_handshake(&mut conn, users).await;
+        drop(conn);
+        let mut sent = Vec::new();
+        peer.read_to_end(&mut sent).await.unwrap();
+        (result, sent)
+    }
+
+    /// Greeting offering `methods`, then an RFC 1929 login if `login`.
+    /// A handshake that fails stops reading here, so only the ones
+    /// meant to succeed get a request appended (see [`connect`]).
+    fn greeting(methods: &[u8], login: Option<(&str, &str)>) -> Vec<u8> {
+        let mut out = vec![0x05, methods.len() as u8];
+        out.extend_from_slice(methods);
+        if let Some((user, pass)) = login {
+            out.extend_from_slice(&[0x01, user.len() as u8]);
+            out.extend_from_slice(user.as_bytes());
+            out.push(pass.len() as u8);
+            out.extend_from_slice(pass.as_bytes());
+        }
+        out
+    }
+
+    /// `greeting` followed by CONNECT 1.2.3.4:80.
+    fn connect(mut greeting: Vec<u8>) -> Vec<u8> {
+        greeting.extend_from_slice(&[0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0, 80]);
+        greeting
+    }
+
+    fn tcp(command: TcpCommand, host: &str, port: u16) -> SocksRequest {
+        SocksRequest::Tcp(command, HostPort::new(host, port))
+    }
+
+    #[test]
+    fn users_file_skips_comments_and_blank_lines() {
+        let users = SocksUsers::parse("# team\nalice:s3:cret\n\n  bob:hunter2  \n").unwrap();
+        assert!(users.verify("alice", "s3:cret"));
+        assert!(users.verify("bob", "hunter2"));
+        assert!(!users.verify("bob", "hunter3"));
+        assert!(!users.verify("bob", "hunter"));
+        assert!(!users.verify("bob", "hunter22"));
+        assert!(!users.verify("carol", "hunter2"));
+        assert_eq!(format!("{users:?}"), r#"SocksUsers(["alice", "bob"])"#);
+        let err = SocksUsers::parse("alice:x\nbob\n").unwrap_err();
+        assert!(
+            err.to_string().contains("line 2"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_without_users_needs_no_auth() {
+        let (result, sent) =
+            handshake(&SocksUsers::default(), &connect(greeting(&[0x00], None))).await;
+        assert_eq!(
+            result.unwrap(),
+            (
+                None,
+                tcp(TcpCommand::Connect(SocksVersion::V5), "1.2.3.4", 80)
+            )
+        );
+        assert_eq!(sent, [0x05, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn handshake_authenticates_user() {
+        let users = SocksUsers::parse("alice:s3cret").unwrap();
+        let client = connect(greeting(&[0x00, 0x02], Some(("alice", "s3cret"))));
+        let (result, sent) = handshake(&users, &client).await;
+        assert_eq!(
+            result.unwrap(),
+            (
+                Some("alice".into()),
+                tcp(TcpCommand::Connect(SocksVersion::V5), "1.2.3.4", 80)
+            )
+        );
+        assert_eq!(sent, [0x05, 0x02, 0x01, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_wrong_password() {
+        let users = SocksUsers::parse("alice:s3cret").unwrap();
+        let client = greeting(&[0x02], Some(("alice", "guess")));
+        let (result, sent) = handshake(&users, &client).await;
+        assert!(result.is_err());
+        assert_eq!(sent, [0x05, 0x02, 0x01, 0x01]);
+    }
+
+    #[tokio::test]
+    async fn handshake_refuses_client_without_password_method() {
+        let users = SocksUsers::parse("alice:s3cret").unwrap();
+        let (result, sent) = handshake(&users, &greeting(&[0x00], None)).await;
+        assert!(result.is_err());
+        assert_eq!(sent, [0x05, 0xFF]);
+    }
+
+    #[tokio::test]
+    async fn handshake_reads_bind() {
+        // BIND, expecting a conn from 10.0.0.7 (any port)
+        let mut client = greeting(&[0x00], None);
+        client.extend_from_slice(&[0x05, 0x02, 0x00, 0x01, 10, 0, 0, 7, 0, 0]);
+        let (result, sent) = handshake(&SocksUsers::default(), &client).await;
+        assert_eq!(
+            result.unwrap(),
+            (None, tcp(TcpCommand::Bind, "10.0.0.7", 0))
+        );
+        assert_eq!(sent, [0x05, 0x00], "replies wait for the far side");
+    }
+
+    #[tokio::test]
+    async fn handshake_reads_socks4_and_socks4a() {
+        // VN=4 CD=1 PORT=80 IP=1.2.3.4 USERID="me"
+        let socks4 = [4, 1, 0, 80, 1, 2, 3, 4, b'm', b'e', 0];
+        let (result, sent) = handshake(&SocksUsers::default(), &socks4).await;
+        assert_eq!(
+            result.unwrap(),
+            (
+                None,
+                tcp(TcpCommand::Connect(SocksVersion::V4), "1.2.3.4", 80)
+            )
+        );
+        assert!(sent.is_empty(), "reply waits for the far side");
+
+        // IP=0.0.0.1, empty USERID, then HOSTNAME
+        let mut socks4a = vec![4, 1, 0x01, 0xBB, 0, 0, 0, 1, 0];
+        socks4a.extend_from_slice(b"example.com\0");
+        let (result, _) = handshake(&SocksUsers::default(), &socks4a).await;
+        assert_eq!(
+            result.unwrap(),
+            (
+                None,
+                tcp(TcpCommand::Connect(SocksVersion::V4), "example.com", 443)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_socks4_when_users_are_required() {
+        let users = SocksUsers::parse("alice:s3cret").unwrap();
+        let (result, sent) = handshake(&users, &[4, 1, 0, 80, 1, 2, 3, 4, 0]).await;
+        assert!(result.is_err());
+        assert_eq!(sent, [0x00, 0x5B, 0, 0, 0, 0, 0, 0]);
+
+        // BIND isn't supported over SOCKS4 either.
+        let (result, sent) = handshake(&SocksUsers::default(), &[4, 2, 0, 80, 1, 2, 3, 4, 0]).await;
+        assert!(result.is_err());
+        assert_eq!(sent[1], 0x5B);
+    }
+
+    #[test]
+    fn remote_credentials_join_the_file_users() {
+        let users = SocksUsers::parse("bob:hunter2").unwrap();
+        let remote: RemoteRequest = "alice:s3cret@socks".parse().unwrap();
+        let joined = users.for_remote(&remote);
+        assert!(joined.verify("alice", "s3cret"));
+        assert!(joined.verify("bob", "hunter2"));
+        assert!(!users.verify("alice", "s3cret"));
+    }
+
     #[test]
     fn parse_udp_header_ipv4() {
         // RSV RSV FRAG ATYP=1 IP=1.2.3.4 PORT=53 DATA="abc"
@@ -644,9 +1437,94 @@ mod tests {
     }
 
     #[test]
-    fn parse_udp_header_rejects_fragmentation() {
-        let buf = [0, 0, 1, 1, 1, 2, 3, 4, 0, 53];
-        assert!(parse_socks_udp_header(&buf).is_err());
+    fn parse_udp_header_accepts_fragments() {
+        let buf = [0, 0, 0x81, 1, 1, 2, 3, 4, 0, 53, b'x'];
+        let (target, payload) = parse_socks_udp_header(&buf).unwrap();
+        assert_eq!(target, HostPort::new("1.2.3.4", 53));
+        assert_eq!(payload, b"x");
+    }
+
+    fn udp_source(port: u16) -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    #[test]
+    fn reassembles_fragments_in_order() {
+        let (src, target, now) = (udp_source(1), HostPort::new("1.2.3.4", 53), Instant::now());
+        let mut r = Reassembly::default();
+        assert_eq!(r.push(src, 1, &target, b"ab", now), None);
+        assert_eq!(r.push(src, 2, &target, b"cd", now), None);
+        assert_eq!(
+            r.push(src, 0x83, &target, b"e", now).as_deref(),
+            Some(&b"abcde"[..])
+        );
+        assert!(r.queue.is_none());
+        // A sequence of one.
+        assert_eq!(
+            r.push(src, 0x81, &target, b"f", now).as_deref(),
+            Some(&b"f"[..])
+        );
+    }
+
+    #[test]
+    fn reassembly_abandons_broken_sequences() {
+        let (src, target, now) = (udp_source(1), HostPort::new("1.2.3.4", 53), Instant::now());
+        let mut r = Reassembly::default();
+
+        // A gap: fragment 2 was lost.
+        r.push(src, 1, &target, b"a", now);
+        assert_eq!(r.push(src, 0x83, &target, b"c", now), None);
+        assert!(r.queue.is_none());
+
+        // Going back to 1 starts over.
+        r.push(src, 1, &target, b"a", now);
+        r.push(src, 2, &target, b"b", now);
+        r.push(src, 1, &target, b"x", now);
+        assert_eq!(
+            r.push(src, 0x82, &target, b"y", now).as_deref(),
+            Some(&b"xy"[..])
+        );
+
+        // An unfragmented datagram from the same source.
+        r.push(src, 1, &target, b"a", now);
+        r.standalone(src);
+        assert_eq!(r.push(src, 0x82, &target, b"b", now), None);
+
+        // Another target mid-sequence.
+        r.push(src, 1, &target, b"a", now);
+        assert_eq!(
+            r.push(src, 0x82, &HostPort::new("5.6.7.8", 53), b"b", now),
+            None
+        );
+        assert!(r.queue.is_none());
+    }
+
+    #[test]
+    fn reassembly_times_out() {
+        let (src, target, now) = (udp_source(1), HostPort::new("1.2.3.4", 53), Instant::now());
+        let mut r = Reassembly::default();
+        r.push(src, 1, &target, b"a", now);
+        let later = now + SOCKS_UDP_REASSEMBLY_TIMEOUT;
+        assert_eq!(r.push(src, 0x82, &target, b"b", later), None);
+        assert!(r.queue.is_none());
+    }
+
+    #[test]
+    fn reassembly_is_bounded() {
+        let (src, target, now) = (udp_source(1), HostPort::new("1.2.3.4", 53), Instant::now());
+        let mut r = Reassembly::default();
+
+        // One queue per association: another source waits its turn.
+        r.push(src, 1, &target, b"a", now);
+        assert_eq!(r.push(udp_source(2), 0x81, &target, b"z", now), None);
+        r.standalone(udp_source(2));
+        assert!(r.queue.is_some());
+
+        // No more than a datagram's worth of payload.
+        let chunk = vec![0u8; SOCKS_MAX_DATAGRAM / 2 + 1];
+        r.push(src, 2, &target, &chunk, now);
+        assert_eq!(r.push(src, 0x83, &target, &chunk, now), None);
+        assert!(r.queue.is_none());
     }
 
     #[test]
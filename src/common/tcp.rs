@@ -3,16 +3,20 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use quinn::{Connection, RecvStream, SendStream, VarInt};
+#[cfg(unix)]
+use tokio::net::{unix, UnixListener, UnixStream};
 use tokio::{
-    io::{AsyncRead, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{tcp, TcpListener, TcpStream},
     sync::Notify,
 };
-use tracing::{debug, info, info_span, Instrument};
+use tracing::{debug, info, info_span, warn, Instrument};
 
 use crate::common::counted::{CountedReader, TunnelCounters};
-use crate::common::remote::OpenConn;
-use crate::common::tunnel::send_open_conn;
+use crate::common::listener::{Uplink, UplinkWatch};
+use crate::common::remote::{ConnResume, OpenConn, ResumeToken};
+use crate::common::resumable::{Leg, Reattach, ResumableConn};
+use crate::common::tunnel::{send_open_conn, send_open_conn_optimistic, OpenConnError};
 use crate::server::state::TunnelHandle;
 
 use super::remote::RemoteRequest;
@@ -61,22 +65,55 @@ fn count_out<R: AsyncRead + Unpin + Send + 'static>(
     }
 }
 
-pub async fn tunnel_tcp_stream(
-    tcp_stream: TcpStream,
+/// A local socket a conn is carried to or from: TCP, or a unix domain
+/// socket for [`RemoteKind::Unix`](super::remote::RemoteKind::Unix)
+/// tunnels.
+pub trait LocalStream: Send + 'static {
+    type Read: AsyncRead + Unpin + Send + Sync + 'static;
+    type Write: AsyncWrite + Unpin + Send + Sync + 'static;
+
+    /// Split into owned halves, set up for tunneling.
+    fn into_halves(self) -> (Self::Read, Self::Write);
+}
+
+impl LocalStream for TcpStream {
+    type Read = tcp::OwnedReadHalf;
+    type Write = tcp::OwnedWriteHalf;
+
+    fn into_halves(self) -> (Self::Read, Self::Write) {
+        // Disable Nagle on the TCP leg of the tunnel. Tunneled traffic is
+        // opaque to us, so coalescing small writes can deadlock for ~40ms
+        // against the peer's delayed-ACK timer (classic Nagle/delayed-ACK
+        // interaction). All tunneled TCP — forward, reverse, SOCKS, and
+        // resumable — is split here, so this single call covers every path.
+        if let Err(e) = self.set_nodelay(true) {
+            debug!(error = %e, "set_nodelay failed");
+        }
+        self.into_split()
+    }
+}
+
+#[cfg(unix)]
+impl LocalStream for UnixStream {
+    type Read = unix::OwnedReadHalf;
+    type Write = unix::OwnedWriteHalf;
+
+    fn into_halves(self) -> (Self::Read, Self::Write) {
+        self.into_split()
+    }
+}
+
+/// Carry `tcp_stream` over the bi-stream until both directions are
+/// done. A graceful close on one side is passed on as a half-close
+/// (FIN) while the other direction drains; an error on either resets
+/// both.
+pub async fn tunnel_tcp_stream<S: LocalStream>(
+    tcp_stream: S,
     mut send_channel: SendStream,
     recv_channel: RecvStream,
     counters: Counters,
 ) -> Result<()> {
-    // Disable Nagle on the TCP leg of the tunnel. Tunneled traffic is opaque
-    // to us, so coalescing small writes can deadlock for ~40ms against the
-    // peer's delayed-ACK timer (classic Nagle/delayed-ACK interaction). All
-    // tunneled TCP — forward, reverse, and SOCKS — flows through here, so
-    // this single call covers every path.
-    if let Err(e) = tcp_stream.set_nodelay(true) {
-        debug!(error = %e, "set_nodelay failed");
-    }
-
-    let (tcp_recv, mut tcp_send) = tcp_stream.into_split();
+    let (tcp_recv, mut tcp_send) = tcp_stream.into_halves();
 
     // BufReader+copy_buf on both halves gives us a 256 KB transfer chunk
     // size end-to-end instead of the 8 KB hidden inside tokio::io::copy.
@@ -202,6 +239,19 @@ pub async fn tunnel_tcp_stream(
         (Err(e), _) => debug!(direction = "tx", error = %e, "stream copy error"),
         (_, Err(e)) => debug!(direction = "rx", error = %e, "stream copy error"),
     }
+    // A peer refusing an optimistic OpenConn resets the stream with a
+    // typed code; surface that as the conn's error instead of a clean
+    // close, since no payload ever reached the target.
+    if let Some(refusal) = [&c2s, &s2c]
+        .into_iter()
+        .filter_map(|r| r.as_ref().err())
+        .find_map(|e| {
+            e.downcast_ref::<std::io::Error>()
+                .and_then(OpenConnError::from_io_error)
+        })
+    {
+        return Err(anyhow::anyhow!("conn open rejected: {refusal}"));
+    }
     Ok(())
 }
 
@@ -226,14 +276,17 @@ enum CopyOutcome {
 /// time. SOCKS5 stdio is likewise rejected — stdio is a single conn,
 /// SOCKS is many.
 pub async fn tunnel_stdio_client(quic_connection: Connection, tunnel_id: u64) -> Result<()> {
-    use crate::common::tunnel::send_open_conn;
-
     debug!("opening stdio tunnel");
     let (mut send, mut recv) = quic_connection.open_bi().await?;
     send_open_conn(
         &OpenConn {
             tunnel_id,
             dynamic: None,
+            datagram_flow: None,
+            optimistic: false,
+            resume: None,
+            user: None,
+            protocol: None,
         },
         &mut send,
         &mut recv,
@@ -311,18 +364,18 @@ pub async fn tunnel_stdio_client(quic_connection: Connection, tunnel_id: u64) ->
     Ok(())
 }
 
+/// Accept local TCP conns for one static tunnel on its already-bound
+/// `listener` (see [`LocalListener`](super::listener::LocalListener))
+/// and carry each over a fresh bi-stream of the current `uplink`. A
+/// conn accepted while there is none waits for it; if the uplink is
+/// optimistic, the [`OpenConn`] is pipelined with the first payload
+/// bytes.
 pub async fn tunnel_tcp_client(
-    quic_connection: Connection,
-    remote: RemoteRequest,
+    listener: TcpListener,
+    uplink: UplinkWatch,
     handle: TunnelHandleOpt,
-    tunnel_id: u64,
 ) -> Result<()> {
-    // Use SocketAddr's Display so IPv6 literals come out bracketed
-    // (`[::1]:8080`) — a manual `format!("{ip}:{port}")` on an IPv6
-    // `IpAddr` produces `::1:8080`, which `TcpListener::bind` rejects.
-    let local_addr = remote.local_socket_addr();
-    let listener = TcpListener::bind(local_addr).await?;
-    info!(addr = %local_addr, "listening");
+    info!(addr = %listener.local_addr()?, "listening");
 
     // Local fallback counter for log-only correlation when admin
     // tracking is disabled (handle is None, i.e. forward client side).
@@ -330,89 +383,257 @@ pub async fn tunnel_tcp_client(
 
     loop {
         let (local_socket, peer) = listener.accept().await?;
-        let connection = quic_connection.clone();
-        let handle = handle.clone();
         let local_id = (local_counter.fetch_add(1, Ordering::Relaxed) + 1) as u64;
+        tokio::spawn(carry_local_conn(
+            local_socket,
+            peer.to_string(),
+            uplink.clone(),
+            handle.clone(),
+            local_id,
+        ));
+    }
+}
 
-        tokio::spawn(async move {
-            let (mut send, mut recv) = match connection.open_bi().await {
-                Ok(s) => s,
-                Err(e) => {
-                    let span = info_span!("conn", conn_id = local_id, tunnel_id, peer = %peer);
-                    let _g = span.enter();
-                    debug!(error = %e, "open_bi failed");
-                    return Err::<(), anyhow::Error>(e.into());
-                }
-            };
+/// [`tunnel_tcp_client`] for a unix socket `listener`. Its peers are
+/// usually unnamed, so conns are labelled with the listener's path.
+#[cfg(unix)]
+pub async fn tunnel_unix_client(
+    listener: UnixListener,
+    uplink: UplinkWatch,
+    handle: TunnelHandleOpt,
+) -> Result<()> {
+    let path = listener
+        .local_addr()?
+        .as_pathname()
+        .map(|p| format!("unix:{}", p.display()))
+        .unwrap_or_else(|| "unix".into());
+    info!(path = %path, "listening");
 
-            // Tell the peer which tunnel this stream belongs to.
-            // Static TCP tunnels carry no `dynamic` payload — the
-            // peer already knows the target from the tunnel's
-            // declaration in the session hello.
-            if let Err(e) = send_open_conn(
-                &OpenConn {
-                    tunnel_id,
-                    dynamic: None,
-                },
-                &mut send,
-                &mut recv,
-            )
-            .await
-            {
-                let span = info_span!("conn", conn_id = local_id, tunnel_id, peer = %peer);
-                let _g = span.enter();
-                debug!(error = %e, "OpenConn rejected");
-                return Err(e);
-            }
+    let local_counter = AtomicUsize::new(0);
 
-            // Register this accepted connection as a conn against
-            // the parent tunnel (when admin tracking is enabled).
-            // The `ConnGuard` removes it again on drop, regardless
-            // of how the stream below ends.
-            let conn_guard = handle.as_ref().map(|h| h.open_conn(Some(peer.to_string())));
-            let conn_id = conn_guard.as_ref().map(|g| g.id()).unwrap_or(local_id);
-            let counters = conn_guard.as_ref().map(|g| g.counters());
-
-            let span = info_span!("conn", conn_id, tunnel_id, peer = %peer);
-            async move {
-                info!("conn opened");
-                let started = std::time::Instant::now();
-                let result = tunnel_tcp_stream(local_socket, send, recv, counters.clone()).await;
-                let dur_ms = started.elapsed().as_millis() as u64;
-                let snap = counters.as_ref().map(|c| c.snapshot());
-                match (&result, snap) {
-                    (Ok(()), Some((bytes_in, bytes_out))) => {
-                        info!(bytes_in, bytes_out, dur_ms, "conn closed")
-                    }
-                    (Ok(()), None) => info!(dur_ms, "conn closed"),
-                    (Err(e), Some((bytes_in, bytes_out))) => {
-                        debug!(bytes_in, bytes_out, dur_ms, error = %e, "conn closed (error)")
-                    }
-                    (Err(e), None) => debug!(dur_ms, error = %e, "conn closed (error)"),
-                }
-                drop(conn_guard);
-                result
+    loop {
+        let (local_socket, _) = listener.accept().await?;
+        let local_id = (local_counter.fetch_add(1, Ordering::Relaxed) + 1) as u64;
+        tokio::spawn(carry_local_conn(
+            local_socket,
+            path.clone(),
+            uplink.clone(),
+            handle.clone(),
+            local_id,
+        ));
+    }
+}
+
+/// Carry one accepted local conn over a fresh bi-stream of `uplink`.
+async fn carry_local_conn<S: LocalStream>(
+    local_socket: S,
+    peer: String,
+    uplink: UplinkWatch,
+    handle: TunnelHandleOpt,
+    local_id: u64,
+) -> Result<()> {
+    let Uplink {
+        connection,
+        tunnel_id,
+        optimistic,
+        resumable,
+        protocol,
+        ..
+    } = match uplink.get().await {
+        Ok(u) => u,
+        Err(e) => {
+            let span = info_span!("conn", conn_id = local_id, peer = %peer);
+            let _g = span.enter();
+            info!(error = %e, "conn dropped");
+            return Err(e);
+        }
+    };
+    let (mut send, mut recv) = match connection.open_bi().await {
+        Ok(s) => s,
+        Err(e) => {
+            let span = info_span!("conn", conn_id = local_id, tunnel_id, peer = %peer);
+            let _g = span.enter();
+            debug!(error = %e, "open_bi failed");
+            return Err(e.into());
+        }
+    };
+
+    // Tell the peer which tunnel this stream belongs to.
+    // Static TCP tunnels carry no `dynamic` payload — the
+    // peer already knows the target from the tunnel's
+    // declaration in the session hello — and there's nothing
+    // to learn from its verdict before streaming, so when the
+    // peer supports it the open is optimistic: the local
+    // socket's first bytes follow the frame in the same
+    // flight, and a refusal shows up as a stream reset inside
+    // `tunnel_tcp_stream`. A resumable conn is named here
+    // so it can be carried on over a later connection.
+    let resume = resumable.and_then(|r| {
+        let token = ResumeToken::generate()
+            .inspect_err(|e| warn!(error = %e, "conn won't be resumable"))
+            .ok()?;
+        Some((token, r))
+    });
+    let open = OpenConn {
+        tunnel_id,
+        dynamic: None,
+        datagram_flow: None,
+        optimistic,
+        resume: resume.as_ref().map(|(token, _)| ConnResume::New(*token)),
+        user: None,
+        protocol,
+    };
+    let opened = if optimistic {
+        send_open_conn_optimistic(&open, &mut send).await
+    } else {
+        send_open_conn(&open, &mut send, &mut recv).await
+    };
+    if let Err(e) = opened {
+        let span = info_span!("conn", conn_id = local_id, tunnel_id, peer = %peer);
+        let _g = span.enter();
+        debug!(error = %e, "OpenConn failed");
+        return Err(e);
+    }
+
+    // Register this accepted connection as a conn against
+    // the parent tunnel (when admin tracking is enabled).
+    // The `ConnGuard` removes it again on drop, regardless
+    // of how the stream below ends.
+    let conn_guard = handle
+        .as_ref()
+        .map(|h| h.open_conn(Some(peer.clone()), None));
+    let conn_id = conn_guard.as_ref().map(|g| g.id()).unwrap_or(local_id);
+    let counters = conn_guard.as_ref().map(|g| g.counters());
+
+    let span = info_span!("conn", conn_id, tunnel_id, peer = %peer);
+    async move {
+        info!("conn opened");
+        let started = std::time::Instant::now();
+        let result = match resume {
+            Some((token, reattach)) => {
+                let leg = Leg {
+                    send,
+                    recv,
+                    peer_received: 0,
+                };
+                ResumableConn::new(token, local_socket, reattach, counters.clone())
+                    .run(leg)
+                    .await
+            }
+            None => tunnel_tcp_stream(local_socket, send, recv, counters.clone()).await,
+        };
+        let dur_ms = started.elapsed().as_millis() as u64;
+        let snap = counters.as_ref().map(|c| c.snapshot());
+        match (&result, snap) {
+            (Ok(()), Some((bytes_in, bytes_out))) => {
+                info!(bytes_in, bytes_out, dur_ms, "conn closed")
+            }
+            (Ok(()), None) => info!(dur_ms, "conn closed"),
+            (Err(e), Some((bytes_in, bytes_out))) => {
+                debug!(bytes_in, bytes_out, dur_ms, error = %e, "conn closed (error)")
             }
-            .instrument(span)
-            .await
-        });
+            (Err(e), None) => debug!(dur_ms, error = %e, "conn closed (error)"),
+        }
+        drop(conn_guard);
+        result
     }
+    .instrument(span)
+    .await
 }
 
+/// Dial `request`'s target and carry the conn that arrived on this
+/// bi-stream to it. `resume` is set for a resumable conn: its token and
+/// how it waits for the client to carry it on after a loss.
 pub async fn tunnel_tcp_server(
     recv_channel: RecvStream,
     send_channel: SendStream,
     request: RemoteRequest,
     counters: Counters,
+    resume: Option<(ResumeToken, Reattach)>,
 ) -> Result<()> {
     let remote_addr = request
         .remote_addr_string()
         .ok_or_else(|| anyhow::anyhow!("TCP server tunnel requires a host:port remote"))?;
     debug!(target = %remote_addr, "dialing");
-    let tcp_stream = TcpStream::connect(&remote_addr).await?;
-    debug!(target = %remote_addr, "dialed");
+    let dialed = TcpStream::connect(&remote_addr).await;
+    carry_dialed(
+        dialed,
+        &remote_addr,
+        recv_channel,
+        send_channel,
+        counters,
+        resume,
+    )
+    .await
+}
 
-    tunnel_tcp_stream(tcp_stream, send_channel, recv_channel, counters).await?;
+/// [`tunnel_tcp_server`] for a unix socket target at `path`.
+pub async fn tunnel_unix_server(
+    recv_channel: RecvStream,
+    send_channel: SendStream,
+    path: &std::path::Path,
+    counters: Counters,
+    resume: Option<(ResumeToken, Reattach)>,
+) -> Result<()> {
+    let target = format!("unix:{}", path.display());
+    debug!(target = %target, "dialing");
+    #[cfg(unix)]
+    {
+        let dialed = UnixStream::connect(path).await;
+        carry_dialed(
+            dialed,
+            &target,
+            recv_channel,
+            send_channel,
+            counters,
+            resume,
+        )
+        .await
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (counters, resume);
+        let (mut recv_channel, mut send_channel) = (recv_channel, send_channel);
+        OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+        Err(anyhow::anyhow!(
+            "can't dial {target}: unix sockets aren't supported on this platform"
+        ))
+    }
+}
+
+/// Carry the conn on this bi-stream to the freshly `dialed` target.
+async fn carry_dialed<S: LocalStream>(
+    dialed: std::io::Result<S>,
+    target: &str,
+    mut recv_channel: RecvStream,
+    mut send_channel: SendStream,
+    counters: Counters,
+    resume: Option<(ResumeToken, Reattach)>,
+) -> Result<()> {
+    let stream = match dialed {
+        Ok(s) => s,
+        Err(e) => {
+            // Tell the opener why, rather than a bare FIN it can't tell
+            // apart from the target accepting and closing immediately.
+            OpenConnError::DialFailed.reset(&mut send_channel, &mut recv_channel);
+            return Err(e.into());
+        }
+    };
+    debug!(target = %target, "dialed");
+
+    match resume {
+        Some((token, reattach)) => {
+            let leg = Leg {
+                send: send_channel,
+                recv: recv_channel,
+                peer_received: 0,
+            };
+            ResumableConn::new(token, stream, reattach, counters)
+                .run(leg)
+                .await?
+        }
+        None => tunnel_tcp_stream(stream, send_channel, recv_channel, counters).await?,
+    }
 
     Ok(())
 }
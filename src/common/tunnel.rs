@@ -8,24 +8,60 @@
 //!
 //! * **Session hello.** The first bi-stream of every QUIC connection
 //!   carries one [`SessionHello`] (client → server) and one
-//!   [`SessionHelloResponse`] (server → client). The server uses the
-//!   hello to validate every requested remote against `--allow-reverse`
-//!   / `--allow-socks` in one shot, assigns each declaration a stable
-//!   `tunnel_id`, and either accepts the whole batch or rejects the
-//!   session entirely. There is no half-accept.
+//!   [`SessionHelloResponse`] (server → client). The server checks
+//!   every requested remote against `--allow-reverse` / `--allow-socks`,
+//!   binds the listeners of reverse ones, and reports one
+//!   [`TunnelStatus`] per declaration: a stable `tunnel_id`, or why the
+//!   remote isn't running. The rest of the session goes ahead, unless
+//!   the client asked for a strict hello, in which case any refusal
+//!   rejects the session as a whole. Both frames also carry
+//!   the sender's [`PeerInfo`]: protocol version, software version and
+//!   capability names. A peer whose protocol version this build
+//!   doesn't support fails the hello with an explicit "incompatible
+//!   protocol version" reason, and optional features are only used
+//!   when both sides advertised them.
+//!
+//! * **Tunnel control.** Neither side finishes the hello bi-stream.
+//!   It stays open as the connection's control stream, on which the
+//!   client can send [`TunnelControl`] requests (add or remove one
+//!   tunnel) at any time; the server answers each, in order, with one
+//!   [`TunnelControlResponse`]. The client finishing the stream just
+//!   means no more changes are coming.
 //!
 //! * **Conn opener.** Every subsequent data-plane bi-stream (in either
 //!   direction) opens with one [`OpenConn`] frame keyed by a previously
 //!   negotiated `tunnel_id`. The receiving side replies with one
 //!   [`OpenConnResponse`] and, on `Ok`, the bi-stream is handed off to
 //!   the data-plane handler — no further control framing.
+//!
+//!   An *optimistic* opener ([`OpenConn::optimistic`]) skips that round
+//!   trip: it writes the frame and starts streaming payload right
+//!   behind it. The receiving side then sends no response at all on
+//!   success, and on failure resets both halves of the stream with an
+//!   [`OpenConnError`] code instead of writing `Failed`.
+//!
+//!   A *resumable* conn ([`OpenConn::resume`]) frames its payload
+//!   instead of handing the raw stream over (see
+//!   [`crate::common::resumable`]). If the connection is lost, the
+//!   client carries it on over a later one with an `OpenConn` that
+//!   attaches a new stream to it, answered by
+//!   [`OpenConnResponse::Attached`].
+//!
+//!   A SOCKS5 BIND conn gets two more frames after `Ok`, both
+//!   [`BindReply`]s from the receiving side, before the stream is
+//!   handed off.
+
+use std::fmt;
+use std::io;
 
 use anyhow::{anyhow, Context, Result};
-use quinn::{RecvStream, SendStream};
-use tokio::io::AsyncWriteExt;
+use quinn::{ReadError, ReadExactError, RecvStream, SendStream, VarInt, WriteError};
 use tracing::debug;
 
-use crate::common::remote::{OpenConn, OpenConnResponse, SessionHello, SessionHelloResponse};
+use crate::common::remote::{
+    BindReply, ConnResume, OpenConn, OpenConnResponse, PeerInfo, ResumeToken, SessionHello,
+    SessionHelloResponse, TunnelControl, TunnelControlResponse, TunnelStatus,
+};
 use crate::common::utils::SerdeHelper;
 
 /// Hard cap on a single control message body. Generous compared to
@@ -44,10 +80,28 @@ async fn write_framed<T: SerdeHelper>(send: &mut SendStream, msg: &T) -> Result<
 }
 
 async fn read_framed<T: SerdeHelper>(recv: &mut RecvStream) -> Result<T> {
+    read_framed_or_finish(recv)
+        .await?
+        .ok_or_else(|| anyhow!("failed to read control message length: stream finished"))
+}
+
+/// [`read_framed`], except that the peer finishing the stream cleanly
+/// between two messages yields `None` rather than an error.
+async fn read_framed_or_finish<T: SerdeHelper>(recv: &mut RecvStream) -> Result<Option<T>> {
     let mut len_buf = [0u8; 4];
-    recv.read_exact(&mut len_buf)
-        .await
-        .map_err(|e| anyhow!("failed to read control message length: {e}"))?;
+    match recv.read_exact(&mut len_buf).await {
+        Ok(()) => {}
+        Err(ReadExactError::FinishedEarly(0)) => return Ok(None),
+        Err(e) => {
+            // A typed refusal stays typed, so callers can downcast it.
+            if let ReadExactError::ReadError(ReadError::Reset(code)) = e {
+                if let Some(refusal) = OpenConnError::from_code(code) {
+                    return Err(refusal.into());
+                }
+            }
+            return Err(anyhow!("failed to read control message length: {e}"));
+        }
+    }
     let len = u32::from_le_bytes(len_buf) as usize;
     if len > MAX_CONTROL_MSG {
         return Err(anyhow!(
@@ -58,7 +112,7 @@ async fn read_framed<T: SerdeHelper>(recv: &mut RecvStream) -> Result<T> {
     recv.read_exact(&mut body)
         .await
         .map_err(|e| anyhow!("failed to read control message body: {e}"))?;
-    T::from_bytes(body)
+    T::from_bytes(body).map(Some)
 }
 
 // ---------------------------------------------------------------------------
@@ -66,39 +120,56 @@ async fn read_framed<T: SerdeHelper>(recv: &mut RecvStream) -> Result<T> {
 // ---------------------------------------------------------------------------
 
 /// Client side of the hello: send the full tunnel-declaration batch
-/// and wait for the server's verdict. On success, returns the list of
-/// server-assigned `tunnel_id`s in the same order as `hello.remotes`.
+/// and wait for the server's verdict. On success, returns one
+/// [`TunnelStatus`] per remote, in the same order as `hello.remotes`,
+/// the server's [`PeerInfo`] (already checked for compatibility) and
+/// the session's [`ResumeToken`], if the server issued one.
+/// The streams stay open for [`client_send_tunnel_control`].
 pub async fn client_send_session_hello(
     hello: &SessionHello,
     send: &mut SendStream,
     recv: &mut RecvStream,
-) -> Result<Vec<u64>> {
+) -> Result<(Vec<TunnelStatus>, PeerInfo, Option<ResumeToken>)> {
     debug!(remotes = hello.remotes.len(), "sending session hello");
     write_framed(send, hello).await?;
-    send.shutdown().await?;
     match read_framed::<SessionHelloResponse>(recv).await? {
-        SessionHelloResponse::Ok { tunnel_ids } => {
-            if tunnel_ids.len() != hello.remotes.len() {
+        SessionHelloResponse::Ok {
+            tunnels,
+            peer,
+            resume_token,
+        } => {
+            peer.check_compatible()
+                .map_err(|reason| anyhow!("server has {reason}"))?;
+            if tunnels.len() != hello.remotes.len() {
                 return Err(anyhow!(
-                    "server returned {} tunnel ids for {} remotes",
-                    tunnel_ids.len(),
+                    "server returned {} tunnel statuses for {} remotes",
+                    tunnels.len(),
                     hello.remotes.len()
                 ));
             }
-            debug!(count = tunnel_ids.len(), "session hello accepted");
-            Ok(tunnel_ids)
+            debug!(
+                count = tunnels.len(),
+                server_version = %peer.software_version,
+                "session hello accepted"
+            );
+            Ok((tunnels, peer, resume_token))
         }
         SessionHelloResponse::Failed(reason) => Err(anyhow!("server rejected session: {reason}")),
     }
 }
 
 /// Server side of the hello: receive the batch. The caller is
-/// responsible for validating each remote (against `--allow-reverse` /
-/// `--allow-socks`), assigning ids, and replying with
-/// [`server_reply_session_hello`].
+/// responsible for checking the client's [`PeerInfo`], validating each
+/// remote (against `--allow-reverse` / `--allow-socks`), assigning ids,
+/// and replying with [`server_reply_session_hello`].
 pub async fn server_receive_session_hello(recv: &mut RecvStream) -> Result<SessionHello> {
     let hello: SessionHello = read_framed(recv).await?;
-    debug!(remotes = hello.remotes.len(), "received session hello");
+    debug!(
+        remotes = hello.remotes.len(),
+        protocol = hello.peer.protocol_version,
+        client_version = %hello.peer.software_version,
+        "received session hello"
+    );
     Ok(hello)
 }
 
@@ -106,9 +177,36 @@ pub async fn server_reply_session_hello(
     send: &mut SendStream,
     response: &SessionHelloResponse,
 ) -> Result<()> {
-    write_framed(send, response).await?;
-    send.shutdown().await?;
-    Ok(())
+    write_framed(send, response).await
+}
+
+// ---------------------------------------------------------------------------
+// Live tunnel changes
+// ---------------------------------------------------------------------------
+
+/// Client side: send one [`TunnelControl`] on the control stream and
+/// wait for its response. `Failed` is returned as a value, not an
+/// error — only a broken stream is an `Err`.
+pub async fn client_send_tunnel_control(
+    request: &TunnelControl,
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+) -> Result<TunnelControlResponse> {
+    write_framed(send, request).await?;
+    read_framed(recv).await
+}
+
+/// Server side: wait for the next [`TunnelControl`]. `None` once the
+/// client has finished the control stream.
+pub async fn server_receive_tunnel_control(recv: &mut RecvStream) -> Result<Option<TunnelControl>> {
+    read_framed_or_finish(recv).await
+}
+
+pub async fn server_reply_tunnel_control(
+    send: &mut SendStream,
+    response: &TunnelControlResponse,
+) -> Result<()> {
+    write_framed(send, response).await
 }
 
 // ---------------------------------------------------------------------------
@@ -127,9 +225,46 @@ pub async fn send_open_conn(
     match read_framed::<OpenConnResponse>(recv).await? {
         OpenConnResponse::Ok => Ok(()),
         OpenConnResponse::Failed(reason) => Err(anyhow!("conn open rejected: {reason}")),
+        OpenConnResponse::Attached { .. } => Err(anyhow!("unexpected Attached reply to OpenConn")),
     }
 }
 
+/// Client side of [`ConnResume::Attach`]: carry the resumable conn
+/// `token` names on over this fresh bi-stream, having delivered
+/// `received` bytes of the server's side. Returns how much of ours the
+/// server has delivered.
+pub async fn send_attach_conn(
+    token: ResumeToken,
+    received: u64,
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+) -> Result<u64> {
+    let open = OpenConn {
+        tunnel_id: 0,
+        dynamic: None,
+        datagram_flow: None,
+        optimistic: false,
+        resume: Some(ConnResume::Attach { token, received }),
+        user: None,
+        protocol: None,
+    };
+    write_framed(send, &open).await?;
+    match read_framed::<OpenConnResponse>(recv).await? {
+        OpenConnResponse::Attached { received } => Ok(received),
+        OpenConnResponse::Failed(reason) => Err(anyhow!("conn attach rejected: {reason}")),
+        OpenConnResponse::Ok => Err(anyhow!("server doesn't know resumable conns")),
+    }
+}
+
+/// Optimistic variant of [`send_open_conn`]: write the frame and return
+/// immediately so the caller can start streaming payload behind it. A
+/// refusal surfaces later as a reset carrying an [`OpenConnError`] code
+/// (see [`OpenConnError::from_io_error`]).
+pub async fn send_open_conn_optimistic(open: &OpenConn, send: &mut SendStream) -> Result<()> {
+    debug_assert!(open.optimistic);
+    write_framed(send, open).await
+}
+
 pub async fn receive_open_conn(recv: &mut RecvStream) -> Result<OpenConn> {
     read_framed(recv).await
 }
@@ -137,3 +272,140 @@ pub async fn receive_open_conn(recv: &mut RecvStream) -> Result<OpenConn> {
 pub async fn reply_open_conn(send: &mut SendStream, response: &OpenConnResponse) -> Result<()> {
     write_framed(send, response).await
 }
+
+/// Receiving side of a BIND conn: report where it's listening, or who
+/// connected.
+pub async fn send_bind_reply(send: &mut SendStream, reply: &BindReply) -> Result<()> {
+    write_framed(send, reply).await
+}
+
+pub async fn receive_bind_reply(recv: &mut RecvStream) -> Result<BindReply> {
+    read_framed(recv).await
+}
+
+/// Receiving side: accept `open`. Writes `Ok` unless the opener is
+/// optimistic, in which case it isn't reading a response and the
+/// stream carries payload from here on.
+pub async fn accept_open_conn(open: &OpenConn, send: &mut SendStream) -> Result<()> {
+    if open.optimistic {
+        return Ok(());
+    }
+    reply_open_conn(send, &OpenConnResponse::Ok).await
+}
+
+/// Receiving side: refuse `open`. A waiting opener gets `Failed(reason)`;
+/// an optimistic one is already streaming, so both halves of the stream
+/// are reset with `error`'s code instead.
+pub async fn refuse_open_conn(
+    open: &OpenConn,
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    error: OpenConnError,
+    reason: &str,
+) {
+    if open.optimistic {
+        error.reset(send, recv);
+    } else {
+        let _ = reply_open_conn(send, &OpenConnResponse::Failed(reason.to_string())).await;
+    }
+}
+
+/// Why the receiving side refused a conn, as carried in the
+/// application error code of RESET_STREAM and STOP_SENDING. Code 0 is
+/// the data plane's generic abort and deliberately not used here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenConnError {
+    /// `tunnel_id` isn't one the receiver negotiated.
+    UnknownTunnel,
+    /// The tunnel exists but the conn can't be dispatched on it.
+    Rejected,
+    /// The receiver couldn't connect to the tunnel's target.
+    DialFailed,
+}
+
+impl OpenConnError {
+    pub fn code(self) -> VarInt {
+        VarInt::from_u32(match self {
+            Self::UnknownTunnel => 1,
+            Self::Rejected => 2,
+            Self::DialFailed => 3,
+        })
+    }
+
+    pub fn from_code(code: VarInt) -> Option<Self> {
+        match code.into_inner() {
+            1 => Some(Self::UnknownTunnel),
+            2 => Some(Self::Rejected),
+            3 => Some(Self::DialFailed),
+            _ => None,
+        }
+    }
+
+    /// Reset `send` and stop `recv` with this code, so the opener sees
+    /// it whichever direction it's blocked on.
+    pub fn reset(self, send: &mut SendStream, recv: &mut RecvStream) {
+        let _ = send.reset(self.code());
+        let _ = recv.stop(self.code());
+    }
+
+    /// Recover the refusal from an I/O error raised by a data stream the
+    /// peer reset or stopped. `None` for any other error.
+    pub fn from_io_error(e: &io::Error) -> Option<Self> {
+        let inner = e.get_ref()?;
+        let code = match (
+            inner.downcast_ref::<ReadError>(),
+            inner.downcast_ref::<WriteError>(),
+        ) {
+            (Some(ReadError::Reset(code)), _) => *code,
+            (_, Some(WriteError::Stopped(code))) => *code,
+            _ => return None,
+        };
+        Self::from_code(code)
+    }
+}
+
+impl fmt::Display for OpenConnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnknownTunnel => "unknown tunnel id",
+            Self::Rejected => "conn rejected",
+            Self::DialFailed => "target unreachable",
+        })
+    }
+}
+
+impl std::error::Error for OpenConnError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_conn_error_code_roundtrip() {
+        for e in [
+            OpenConnError::UnknownTunnel,
+            OpenConnError::Rejected,
+            OpenConnError::DialFailed,
+        ] {
+            assert_eq!(OpenConnError::from_code(e.code()), Some(e));
+        }
+        assert_eq!(OpenConnError::from_code(VarInt::from_u32(0)), None);
+    }
+
+    #[test]
+    fn open_conn_error_from_reset_io_error() {
+        let code = OpenConnError::DialFailed.code();
+        let read: io::Error = ReadError::Reset(code).into();
+        assert_eq!(
+            OpenConnError::from_io_error(&read),
+            Some(OpenConnError::DialFailed)
+        );
+        let write: io::Error = WriteError::Stopped(code).into();
+        assert_eq!(
+            OpenConnError::from_io_error(&write),
+            Some(OpenConnError::DialFailed)
+        );
+        let other = io::Error::new(io::ErrorKind::BrokenPipe, "boom");
+        assert_eq!(OpenConnError::from_io_error(&other), None);
+    }
+}
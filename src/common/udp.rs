@@ -8,9 +8,12 @@ use quinn::{Connection, RecvStream, SendStream};
 use std::net::SocketAddr;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tracing::{debug, info, info_span, warn, Instrument};
 
-use crate::common::remote::OpenConn;
+use crate::common::datagram::{DatagramFlow, Datagrams, FlowReceiver, FlowSender};
+use crate::common::listener::{Uplink, UplinkWatch};
+use crate::common::remote::{DynamicTarget, OpenConn, Protocol};
 use crate::common::tcp::{Counters, TunnelHandleOpt};
 use crate::common::tunnel::send_open_conn;
 
@@ -68,30 +71,184 @@ pub(crate) async fn read_datagram<'a>(
     Ok(&buf[..len])
 }
 
-/// Server-side: pair a single QUIC stream with a freshly bound UDP socket
+/// Stream-framed datagrams still queued while a conn runs in datagram
+/// mode. Only oversize payloads take this path, so it stays small.
+const STREAM_FALLBACK_CAPACITY: usize = 64;
+
+/// Send half of a UDP conn. Payloads go out as QUIC DATAGRAM frames on
+/// the conn's negotiated flow when there is one and the payload fits;
+/// otherwise they are framed onto the bi-stream with [`write_datagram`].
+pub(crate) struct UdpSender {
+    send: SendStream,
+    flow: Option<FlowSender>,
+}
+
+impl UdpSender {
+    pub(crate) async fn send(&mut self, payload: &[u8]) -> Result<()> {
+        if let Some(flow) = &self.flow {
+            if flow.send(payload).await? {
+                return Ok(());
+            }
+        }
+        write_datagram(&mut self.send, payload).await
+    }
+}
+
+/// Receive half of a UDP conn. Without a datagram flow this is just
+/// [`read_datagram`] on the bi-stream. With one, payloads can arrive on
+/// either path, so a helper task drains the stream framing into a channel
+/// (`read_datagram` isn't cancel-safe, and we must not lose half a frame
+/// when the DATAGRAM side wins the race).
+pub(crate) struct UdpReceiver {
+    inner: UdpReceiverInner,
+}
+
+enum UdpReceiverInner {
+    Stream(RecvStream),
+    Flow {
+        datagrams: FlowReceiver,
+        stream: mpsc::Receiver<Bytes>,
+        reader: JoinHandle<()>,
+    },
+}
+
+impl UdpReceiver {
+    /// Read the next datagram into `buf`, returning the slice that holds
+    /// the payload. `buf` must be at least `MAX_DATAGRAM` bytes.
+    pub(crate) async fn recv<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a [u8]> {
+        let (datagrams, stream) = match &mut self.inner {
+            UdpReceiverInner::Stream(recv) => return read_datagram(recv, buf).await,
+            UdpReceiverInner::Flow {
+                datagrams, stream, ..
+            } => (datagrams, stream),
+        };
+        let payload = tokio::select! {
+            d = datagrams.recv() => d.ok_or_else(|| anyhow!("datagram flow closed"))?,
+            s = stream.recv() => s.ok_or_else(|| anyhow!("udp stream closed"))?,
+        };
+        if payload.len() > buf.len() {
+            return Err(anyhow!(
+                "datagram length {} exceeds local buffer {}",
+                payload.len(),
+                buf.len()
+            ));
+        }
+        buf[..payload.len()].copy_from_slice(&payload);
+        Ok(&buf[..payload.len()])
+    }
+}
+
+impl Drop for UdpReceiver {
+    fn drop(&mut self) {
+        if let UdpReceiverInner::Flow { reader, .. } = &self.inner {
+            reader.abort();
+        }
+    }
+}
+
+/// Pair a conn's bi-stream with its (optional) datagram flow.
+pub(crate) fn udp_channel(
+    send: SendStream,
+    mut recv: RecvStream,
+    flow: Option<DatagramFlow>,
+) -> (UdpSender, UdpReceiver) {
+    let Some(flow) = flow else {
+        return (
+            UdpSender { send, flow: None },
+            UdpReceiver {
+                inner: UdpReceiverInner::Stream(recv),
+            },
+        );
+    };
+    let (flow_tx, flow_rx) = flow.split();
+    let (tx, rx) = mpsc::channel(STREAM_FALLBACK_CAPACITY);
+    let reader = tokio::spawn(async move {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        while let Ok(payload) = read_datagram(&mut recv, &mut buf).await {
+            if tx.send(Bytes::copy_from_slice(payload)).await.is_err() {
+                break;
+            }
+        }
+    });
+    (
+        UdpSender {
+            send,
+            flow: Some(flow_tx),
+        },
+        UdpReceiver {
+            inner: UdpReceiverInner::Flow {
+                datagrams: flow_rx,
+                stream: rx,
+                reader,
+            },
+        },
+    )
+}
+
+/// Responder side of a UDP conn: register the datagram flow the opener
+/// proposed in its [`OpenConn::datagram_flow`], if any. Must run before
+/// the `OpenConnResponse::Ok` goes out so no datagram can outrun the
+/// registration.
+pub(crate) fn accept_datagram_flow(
+    datagrams: &Datagrams,
+    flow_id: Option<u64>,
+) -> Result<Option<DatagramFlow>> {
+    flow_id.map(|id| datagrams.open_flow(id)).transpose()
+}
+
+/// Opener side of a UDP conn: open a bi-stream, register a datagram flow
+/// keyed by its stream id (when the peer negotiated datagrams), and
+/// announce both with an [`OpenConn`].
+pub(crate) async fn open_udp_conn(
+    quic_connection: &Connection,
+    datagrams: &Datagrams,
+    tunnel_id: u64,
+    dynamic: Option<DynamicTarget>,
+    user: Option<String>,
+    protocol: Option<Protocol>,
+) -> Result<(UdpSender, UdpReceiver)> {
+    let (mut send, mut recv) = quic_connection.open_bi().await?;
+    let flow = if datagrams.enabled() {
+        Some(datagrams.open_flow(u64::from(send.id()))?)
+    } else {
+        None
+    };
+    send_open_conn(
+        &OpenConn {
+            tunnel_id,
+            dynamic,
+            datagram_flow: flow.as_ref().map(|f| f.id()),
+            optimistic: false,
+            resume: None,
+            user,
+            protocol,
+        },
+        &mut send,
+        &mut recv,
+    )
+    .await?;
+    Ok(udp_channel(send, recv, flow))
+}
+
+/// Server-side: pair a single QUIC conn with a freshly bound UDP socket
 /// and shuttle datagrams between them. Used both for forward UDP (server end
 /// of `tunnel_udp_server`) and for the per-source sessions on the reverse
 /// path. The `udp_address` is the *peer* we're talking to on the UDP side
 /// (either the upstream target on the server, or the local app on the
 /// client).
-pub async fn tunnel_udp_stream(
+async fn tunnel_udp_stream(
     udp_socket: Arc<UdpSocket>,
     udp_address: SocketAddr,
-    send_channel: SendStream,
-    recv_channel: RecvStream,
+    sender: UdpSender,
+    receiver: UdpReceiver,
     counters: Counters,
 ) -> Result<()> {
     // tokio::join! (not try_join!) so a write error in one direction does not
     // cancel an in-flight read in the other (#20 §3). UDP is unreliable so we
     // just log and let the connection close.
     let (l2q, q2l) = tokio::join!(
-        pump_socket_to_stream(
-            udp_socket.clone(),
-            udp_address,
-            send_channel,
-            counters.clone()
-        ),
-        pump_stream_to_socket(udp_socket, udp_address, recv_channel, counters),
+        pump_socket_to_stream(udp_socket.clone(), udp_address, sender, counters.clone()),
+        pump_stream_to_socket(udp_socket, udp_address, receiver, counters),
     );
     if let Err(e) = l2q {
         debug!(direction = "tx", error = %e, "udp pump error");
@@ -103,13 +260,13 @@ pub async fn tunnel_udp_stream(
 }
 
 /// Read datagrams arriving on `udp_socket` from `udp_address` and forward
-/// them onto the QUIC `send` stream. Returns only on error — the trailing
+/// them to the QUIC peer. Returns only on error — the trailing
 /// `loop {}` has type `!`, which coerces to `Result<()>` without a stub
 /// return statement.
 async fn pump_socket_to_stream(
     udp_socket: Arc<UdpSocket>,
     udp_address: SocketAddr,
-    mut send: SendStream,
+    mut sender: UdpSender,
     counters: Counters,
 ) -> Result<()> {
     let mut buf = vec![0u8; MAX_DATAGRAM];
@@ -119,10 +276,10 @@ async fn pump_socket_to_stream(
             debug!(from = %received_addr, expected = %udp_address, "dropping unexpected udp source");
             continue;
         }
-        write_datagram(&mut send, &buf[..len]).await?;
-        // Datagrams pumped onto the QUIC stream count toward `bytes_out`
-        // (data we forward to the QUIC peer). UDP framing overhead is a
-        // few bytes per datagram and intentionally not included.
+        sender.send(&buf[..len]).await?;
+        // Datagrams forwarded to the QUIC peer count toward `bytes_out`.
+        // Framing overhead (stream length prefix or datagram flow tag) is
+        // a few bytes per datagram and intentionally not included.
         if let Some(c) = counters.as_ref() {
             c.add_out(len as u64);
         }
@@ -132,12 +289,12 @@ async fn pump_socket_to_stream(
 async fn pump_stream_to_socket(
     udp_socket: Arc<UdpSocket>,
     udp_address: SocketAddr,
-    mut recv: RecvStream,
+    mut receiver: UdpReceiver,
     counters: Counters,
 ) -> Result<()> {
     let mut buf = vec![0u8; MAX_DATAGRAM];
     loop {
-        let payload = read_datagram(&mut recv, &mut buf).await?;
+        let payload = receiver.recv(&mut buf).await?;
         udp_socket.send_to(payload, &udp_address).await?;
         if let Some(c) = counters.as_ref() {
             c.add_in(payload.len() as u64);
@@ -146,18 +303,17 @@ async fn pump_stream_to_socket(
 }
 
 /// Client-side forward UDP: accept datagrams from any number of local
-/// senders and multiplex each source onto its own QUIC bi-stream. Conns
-/// are torn down after `CONN_IDLE_TIMEOUT` of inactivity.
+/// senders on the already-bound `udp_socket` and multiplex each source
+/// onto its own QUIC bi-stream of the current `uplink`. Conns are torn
+/// down after `CONN_IDLE_TIMEOUT` of inactivity. A new source's
+/// datagrams queue while it waits for an uplink.
 pub async fn tunnel_udp_client(
-    quic_connection: Connection,
-    remote: RemoteRequest,
+    udp_socket: UdpSocket,
+    uplink: UplinkWatch,
     handle: TunnelHandleOpt,
-    tunnel_id: u64,
 ) -> Result<()> {
-    let listen_addr = remote.local_socket_addr();
-    let udp_socket = Arc::new(UdpSocket::bind(listen_addr).await?);
-
-    info!(addr = %listen_addr, proto = "udp", "listening");
+    info!(addr = %udp_socket.local_addr()?, proto = "udp", "listening");
+    let udp_socket = Arc::new(udp_socket);
 
     // DashMap (sharded RwLock) replaces a single global Mutex<HashMap> so the
     // per-source lookup on every received datagram doesn't serialize the
@@ -196,13 +352,12 @@ pub async fn tunnel_udp_client(
                 let (tx, rx) = mpsc::channel(CONN_CHANNEL_CAPACITY);
                 conns.insert(src, tx.clone());
                 spawn_udp_conn(
-                    quic_connection.clone(),
+                    uplink.clone(),
                     udp_socket.clone(),
                     src,
                     rx,
                     conns.clone(),
                     handle.clone(),
-                    tunnel_id,
                 );
                 tx
             }
@@ -216,37 +371,38 @@ pub async fn tunnel_udp_client(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 fn spawn_udp_conn(
-    quic_connection: Connection,
+    uplink: UplinkWatch,
     udp_socket: Arc<UdpSocket>,
     source: SocketAddr,
     rx: mpsc::Receiver<Bytes>,
     conns: Arc<DashMap<SocketAddr, mpsc::Sender<Bytes>>>,
     handle: TunnelHandleOpt,
-    tunnel_id: u64,
 ) {
     tokio::spawn(async move {
+        let uplink = match uplink.get().await {
+            Ok(u) => u,
+            Err(e) => {
+                let span = info_span!("conn", peer = %source, proto = "udp");
+                let _g = span.enter();
+                info!(error = %e, "conn dropped");
+                conns.remove(&source);
+                return;
+            }
+        };
         // Per-source UDP aggregator → one admin-side conn, scoped to
         // the lifetime of the spawn.
         let conn_guard = handle
             .as_ref()
-            .map(|h| h.open_conn(Some(source.to_string())));
+            .map(|h| h.open_conn(Some(source.to_string()), None));
         let conn_id = conn_guard.as_ref().map(|g| g.id()).unwrap_or(0);
         let counters = conn_guard.as_ref().map(|g| g.counters());
+        let tunnel_id = uplink.tunnel_id;
         let span = info_span!("conn", conn_id, tunnel_id, peer = %source, proto = "udp");
         async move {
             info!("conn opened");
             let started = std::time::Instant::now();
-            let result = run_udp_conn(
-                quic_connection,
-                tunnel_id,
-                udp_socket,
-                source,
-                rx,
-                counters.clone(),
-            )
-            .await;
+            let result = run_udp_conn(uplink, udp_socket, source, rx, counters.clone()).await;
             let dur_ms = started.elapsed().as_millis() as u64;
             let snap = counters.as_ref().map(|c| c.snapshot());
             match (&result, snap) {
@@ -268,31 +424,29 @@ fn spawn_udp_conn(
 }
 
 async fn run_udp_conn(
-    quic_connection: Connection,
-    tunnel_id: u64,
+    uplink: Uplink,
     udp_socket: Arc<UdpSocket>,
     source: SocketAddr,
     mut rx: mpsc::Receiver<Bytes>,
     counters: Counters,
 ) -> Result<()> {
-    let (mut send_channel, mut recv_channel) = quic_connection.open_bi().await?;
-    send_open_conn(
-        &OpenConn {
-            tunnel_id,
-            dynamic: None,
-        },
-        &mut send_channel,
-        &mut recv_channel,
+    let (mut sender, mut receiver) = open_udp_conn(
+        &uplink.connection,
+        &uplink.datagrams,
+        uplink.tunnel_id,
+        None,
+        None,
+        uplink.protocol,
     )
     .await?;
 
-    // Forward locally-received datagrams onto the QUIC stream until the local
+    // Forward locally-received datagrams to the QUIC peer until the local
     // sender goes silent for CONN_IDLE_TIMEOUT.
     let local_to_quic = async {
         loop {
             match tokio::time::timeout(CONN_IDLE_TIMEOUT, rx.recv()).await {
                 Ok(Some(payload)) => {
-                    write_datagram(&mut send_channel, &payload).await?;
+                    sender.send(&payload).await?;
                     if let Some(c) = counters.as_ref() {
                         c.add_out(payload.len() as u64);
                     }
@@ -306,13 +460,13 @@ async fn run_udp_conn(
         }
     };
 
-    // Forward replies from the QUIC stream back to the original local sender.
+    // Forward replies from the QUIC peer back to the original local sender.
     // The inner `loop` only exits via `?`, so the function body has type `!`
     // and no trailing `Ok` is needed.
     let quic_to_local = async {
         let mut buf = vec![0u8; MAX_DATAGRAM];
         loop {
-            let payload = read_datagram(&mut recv_channel, &mut buf).await?;
+            let payload = receiver.recv(&mut buf).await?;
             udp_socket.send_to(payload, &source).await?;
             if let Some(c) = counters.as_ref() {
                 c.add_in(payload.len() as u64);
@@ -333,6 +487,7 @@ pub async fn tunnel_udp_server(
     send_channel: SendStream,
     request: RemoteRequest,
     counters: Counters,
+    flow: Option<DatagramFlow>,
 ) -> Result<()> {
     let remote_addr: SocketAddr = request
         .remote_addr_string()
@@ -349,16 +504,10 @@ pub async fn tunnel_udp_server(
     };
     let udp_socket = Arc::new(UdpSocket::bind(bind_addr).await?);
 
-    debug!(target = %remote_addr, "udp dial");
+    debug!(target = %remote_addr, datagrams = flow.is_some(), "udp dial");
 
-    tunnel_udp_stream(
-        udp_socket,
-        remote_addr,
-        send_channel,
-        recv_channel,
-        counters,
-    )
-    .await?;
+    let (sender, receiver) = udp_channel(send_channel, recv_channel, flow);
+    tunnel_udp_stream(udp_socket, remote_addr, sender, receiver, counters).await?;
 
     Ok(())
 }
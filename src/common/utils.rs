@@ -1,3 +1,5 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
 use anyhow::{Error, Result};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -14,3 +16,28 @@ pub trait SerdeHelper: Serialize + DeserializeOwned {
         rmp_serde::to_vec(self).map_err(Error::new)
     }
 }
+
+/// The local address the OS would send to `dest` from right now, or
+/// `None` if it has no route there (interface down, network gone).
+/// Connecting a UDP socket only consults the routing table; nothing is
+/// sent.
+pub fn route_source(dest: SocketAddr) -> Option<IpAddr> {
+    let wildcard: SocketAddr = match dest {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let probe = std::net::UdpSocket::bind(wildcard).ok()?;
+    probe.connect(dest).ok()?;
+    probe.local_addr().ok().map(|a| a.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_source_finds_loopback() {
+        let server: SocketAddr = (Ipv4Addr::LOCALHOST, 9).into();
+        assert_eq!(route_source(server), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    }
+}
@@ -130,12 +130,22 @@ struct ClientSummary {
     total_conns: u64,
     bytes_in: u64,
     bytes_out: u64,
+    // Set while the server holds the session for the client to resume.
+    #[serde(default)]
+    detached_at_ms: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ClientDetail {
     #[serde(flatten)]
     summary: ClientSummary,
+    // Absent from servers that predate version negotiation.
+    #[serde(default)]
+    protocol_version: Option<u32>,
+    #[serde(default)]
+    client_version: Option<String>,
+    #[serde(default)]
+    capabilities: Vec<String>,
     tunnels: Vec<TunnelRow>,
 }
 
@@ -146,11 +156,34 @@ struct TunnelRow {
     direction: String,
     kind: String,
     spec: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    range: Option<String>,
+    #[serde(default)]
+    bound_addr: Option<String>,
     opened_at_ms: u64,
     active_conn_count: u64,
     total_conns: u64,
     bytes_in: u64,
     bytes_out: u64,
+    #[serde(default)]
+    protocols: Option<ProtocolsRow>,
+}
+
+/// A `/both` tunnel's counters, split by protocol.
+#[derive(Debug, Deserialize)]
+struct ProtocolsRow {
+    tcp: ProtocolTotalsRow,
+    udp: ProtocolTotalsRow,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProtocolTotalsRow {
+    active_conn_count: u64,
+    total_conns: u64,
+    bytes_in: u64,
+    bytes_out: u64,
 }
 
 #[derive(Debug, Deserialize)]
@@ -167,6 +200,8 @@ struct ConnRow {
     client_id: u64,
     opened_at_ms: u64,
     peer: Option<String>,
+    #[serde(default)]
+    user: Option<String>,
     bytes_in: u64,
     bytes_out: u64,
 }
@@ -247,6 +282,21 @@ pub fn render_client_detail(payload: Value, format: Format) -> Result<String> {
         s.bytes_in,
         s.bytes_out
     );
+    if let Some(detached) = s.detached_at_ms {
+        out.push_str(&format!("detached-ms       {detached}\n"));
+    }
+    if let Some(protocol) = d.protocol_version {
+        out.push_str(&format!(
+            "protocol          {}\nclient-version    {}\ncapabilities      {}\n",
+            protocol,
+            d.client_version.as_deref().unwrap_or("-"),
+            if d.capabilities.is_empty() {
+                "-".to_string()
+            } else {
+                d.capabilities.join(",")
+            }
+        ));
+    }
     if !d.tunnels.is_empty() {
         out.push('\n');
         out.push_str(&render_tunnel_rows(d.tunnels));
@@ -261,18 +311,34 @@ pub fn render_tunnel_detail(payload: Value, format: Format) -> Result<String> {
     let d: TunnelDetail = serde_json::from_value(payload)?;
     let s = &d.summary;
     let mut out = format!(
-        "id                {}\nclient            {}\ndirection         {}\nkind              {}\nspec              {}\nopened-ms         {}\nactive-conns      {}\ntotal-conns       {}\nbytes-in          {}\nbytes-out         {}\n",
+        "id                {}\nclient            {}\ndirection         {}\nkind              {}\nname              {}\nspec              {}\nrange             {}\nbound             {}\nopened-ms         {}\nactive-conns      {}\ntotal-conns       {}\nbytes-in          {}\nbytes-out         {}\n",
         s.id,
         s.client_id,
         s.direction,
         s.kind,
+        s.name.as_deref().unwrap_or("-"),
         s.spec,
+        s.range.as_deref().unwrap_or("-"),
+        s.bound_addr.as_deref().unwrap_or("-"),
         s.opened_at_ms,
         s.active_conn_count,
         s.total_conns,
         s.bytes_in,
         s.bytes_out
     );
+    if let Some(p) = &s.protocols {
+        for (name, t) in [("tcp", &p.tcp), ("udp", &p.udp)] {
+            out.push_str(&format!(
+                "{:<18}{} active / {} total\n{:<18}{} in / {} out\n",
+                format!("{name}-conns"),
+                t.active_conn_count,
+                t.total_conns,
+                format!("{name}-bytes"),
+                t.bytes_in,
+                t.bytes_out
+            ));
+        }
+    }
     if !d.conns.is_empty() {
         out.push('\n');
         out.push_str(&render_conn_rows(d.conns));
@@ -289,7 +355,16 @@ pub fn render_conns(payload: Value, format: Format) -> Result<String> {
 }
 
 fn render_conn_rows(rows: Vec<ConnRow>) -> String {
-    let mut t = Table::new(&["ID", "TUNNEL", "CLIENT", "OPENED-MS", "PEER", "IN", "OUT"]);
+    let mut t = Table::new(&[
+        "ID",
+        "TUNNEL",
+        "CLIENT",
+        "OPENED-MS",
+        "PEER",
+        "USER",
+        "IN",
+        "OUT",
+    ]);
     for r in rows {
         t.row([
             r.id.to_string(),
@@ -297,6 +372,7 @@ fn render_conn_rows(rows: Vec<ConnRow>) -> String {
             r.client_id.to_string(),
             r.opened_at_ms.to_string(),
             r.peer.unwrap_or_else(|| "-".into()),
+            r.user.unwrap_or_else(|| "-".into()),
             r.bytes_in.to_string(),
             r.bytes_out.to_string(),
         ]);
@@ -318,7 +394,9 @@ fn render_tunnel_rows(rows: Vec<TunnelRow>) -> String {
         "CLIENT",
         "DIR",
         "KIND",
+        "NAME",
         "SPEC",
+        "BOUND",
         "OPENED-MS",
         "ACTIVE",
         "TOTAL",
@@ -331,7 +409,9 @@ fn render_tunnel_rows(rows: Vec<TunnelRow>) -> String {
             r.client_id.to_string(),
             r.direction,
             r.kind,
+            r.name.unwrap_or_else(|| "-".into()),
             r.spec,
+            r.bound_addr.unwrap_or_else(|| "-".into()),
             r.opened_at_ms.to_string(),
             r.active_conn_count.to_string(),
             r.total_conns.to_string(),
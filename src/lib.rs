@@ -1,12 +1,16 @@
 #![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
 
+use client::ClientStats;
 use common::proxy::ProxyConfig;
 use common::quic::Congestion;
 use common::remote::RemoteRequest;
+use common::routing::RouteRules;
+use common::socks::SocksUsers;
 use common::tls::{ClientTlsConfig, ServerTlsConfig};
 use std::fmt;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, error};
 
@@ -33,9 +37,18 @@ pub struct ServerConfig {
     /// server can gate the per-target dynamic streams a SOCKS5 client
     /// manufactures, even though their `kind` is plain `Tcp` / `Udp`.
     /// Reverse `R:socks` listener requests use `RemoteKind::Socks5` directly
-    /// and additionally require `allow_reverse`. `false` (the default) means
-    /// the server rejects all SOCKS5 traffic at the control-plane handshake.
+    /// and additionally require `allow_reverse`. `http` proxy remotes are
+    /// gated the same way. `false` (the default) means the server rejects
+    /// all SOCKS5 and HTTP proxy traffic at the control-plane handshake.
     pub allow_socks: bool,
+    /// Allow remotes whose server side is a unix socket: forward remotes
+    /// that dial one, and reverse remotes that listen on one. `false` (the
+    /// default) keeps clients to inet addresses on the server host.
+    pub allow_unix: bool,
+    /// Credentials every `R:socks` and `R:http` listener accepts (RFC 1929,
+    /// or Basic auth), on top of any a remote declares itself. Empty (the
+    /// default) leaves listeners without credentials of their own open.
+    pub socks_users: SocksUsers,
     pub tls: ServerTlsConfig,
     pub congestion: Congestion,
     /// Maximum number of concurrent client *connections* the server will
@@ -51,6 +64,16 @@ pub struct ServerConfig {
     /// to client/tunnel metadata) and serves `GET /api/v1/...` on it. See
     /// [`server::admin`] for the route table.
     pub admin_socket: Option<PathBuf>,
+    /// How long the server holds a client's session — its reverse
+    /// listeners, tunnels and admin-API entries — after the connection
+    /// is lost (timed out or reset, not closed), waiting for the client
+    /// to reconnect and resume it. Conns accepted on its reverse
+    /// listeners meanwhile wait for it. Zero disables resumption.
+    pub reconnect_grace: Duration,
+    /// TCP port to also accept sessions on, carried as QUIC over TLS for
+    /// clients whose network drops UDP (see [`common::fallback`]). `None`
+    /// (the default) listens on UDP only.
+    pub tcp_port: Option<u16>,
 }
 
 /// The server address the client was asked to connect to. Carries the full
@@ -65,11 +88,14 @@ pub struct ServerEndpoint {
     /// All resolved candidate addresses, in the order returned by the
     /// resolver. The client tries them concurrently with RFC 8305 Happy
     /// Eyeballs, so a host that resolves to both A and AAAA still connects
-    /// quickly when only one family is reachable.
+    /// quickly when only one family is reachable. Empty when the client
+    /// leaves resolving `host` to a `socks5h://` proxy.
     pub addrs: Vec<SocketAddr>,
     /// The host portion of the input as the user typed it: a DNS name (e.g.
     /// `example.com`), an IPv4 literal, or an IPv6 literal without brackets.
     pub host: String,
+    /// The port portion of the input.
+    pub port: u16,
 }
 
 impl ServerEndpoint {
@@ -77,11 +103,12 @@ impl ServerEndpoint {
     /// places that just want a single representative `SocketAddr` (e.g.
     /// "Listening on …" lines).
     pub fn primary(&self) -> SocketAddr {
-        // Construction always populates at least one address (parse_server_addr
-        // errors out otherwise), and the test helpers do the same. If a
-        // downstream embedder bypasses both and ships an empty list, that's a
-        // genuine programmer error — surface it instead of silently picking
-        // 0.0.0.0:0 and corrupting downstream behaviour.
+        // The client resolves the host up front unless a `socks5h://` or HTTP
+        // proxy does it (and then never asks for an address), and the test
+        // helpers populate the list too. If a downstream embedder ships an
+        // empty list anyway, that's a genuine programmer error — surface it
+        // instead of silently picking 0.0.0.0:0 and corrupting downstream
+        // behaviour.
         *self
             .addrs
             .first()
@@ -94,8 +121,14 @@ impl fmt::Display for ServerEndpoint {
         // For the common single-address case, render exactly as before so
         // existing log formats stay stable. With multiple resolved addresses,
         // show the host plus the full list so operators can see what Happy
-        // Eyeballs is racing against.
-        if self.addrs.len() == 1 {
+        // Eyeballs is racing against. Unresolved, it's just `host:port`.
+        if self.addrs.is_empty() {
+            if self.host.contains(':') {
+                write!(f, "[{}]:{}", self.host, self.port)
+            } else {
+                write!(f, "{}:{}", self.host, self.port)
+            }
+        } else if self.addrs.len() == 1 {
             let addr = self.addrs[0];
             if self.host == addr.ip().to_string() {
                 write!(f, "{addr}")
@@ -127,6 +160,29 @@ pub struct ClientConfig {
     /// SOCKS5 UDP ASSOCIATE per RFC 1928 §4). `None` means connect
     /// directly.
     pub proxy: Option<ProxyConfig>,
+    /// CA certificates (PEM) an `https://` or `masque://` proxy is also
+    /// trusted by, on top of the system's roots.
+    pub proxy_ca: Option<PathBuf>,
+    /// Fail the whole session hello if the server refuses any tunnel,
+    /// instead of running the ones it accepted.
+    pub strict_tunnels: bool,
+    /// Offer resumable TCP conns: static TCP tunnels' conns carry on
+    /// over the next connection after a reconnect instead of being cut.
+    pub resumable_conns: bool,
+    /// Credentials every forward `socks` and `http` listener accepts
+    /// (RFC 1929, or Basic auth), on top of any a remote declares itself.
+    pub socks_users: SocksUsers,
+    /// Split-tunnel rules forward `socks` listeners route each CONNECT
+    /// by: direct from the client, through the tunnel, or refused.
+    /// Empty (the default) tunnels everything. Each rule counts its
+    /// hits; hold on to a clone to read them.
+    pub socks_rules: RouteRules,
+    /// Reach the server over TLS-over-TCP when UDP doesn't get through;
+    /// see [`TcpFallback`]. `None` means QUIC over UDP only.
+    pub tcp_fallback: Option<TcpFallback>,
+    /// Counters the client keeps while it runs; hold on to a clone of
+    /// the `Arc` to read them.
+    pub stats: Arc<ClientStats>,
 }
 
 /// Controls the client's reconnect-on-disconnect behaviour.
@@ -158,6 +214,19 @@ impl Default for ReconnectConfig {
     }
 }
 
+/// The client's fallback transport: the same QUIC connection, carried
+/// over TLS on a TCP port the server also listens on (its `tcp_port`).
+/// Meant for networks that drop outbound UDP.
+#[derive(Debug, Clone, Copy)]
+pub struct TcpFallback {
+    /// The server's TCP port; the host is the one QUIC connects to.
+    pub port: u16,
+    /// Skip UDP and always connect over TCP. Otherwise each connect
+    /// attempt starts over UDP and races TCP against it once the QUIC
+    /// handshake has gone a few seconds without an answer.
+    pub only: bool,
+}
+
 /// Build a multi-thread tokio runtime and run the server to completion.
 ///
 /// This is the synchronous convenience entry point used by `main`. Embedders
@@ -184,6 +253,21 @@ pub fn run_client(config: ClientConfig) {
     }
 }
 
+/// [`run_client`], re-reading the remote set with `reload` on SIGHUP and
+/// applying the difference without reconnecting. See
+/// [`client::run_async_with_reload`].
+pub fn run_client_with_reload<F>(config: ClientConfig, reload: F)
+where
+    F: Fn() -> anyhow::Result<Vec<RemoteRequest>> + Send + 'static,
+{
+    debug!("starting client runtime");
+    let result =
+        build_runtime().and_then(|rt| rt.block_on(client::run_async_with_reload(config, reload)));
+    if let Err(e) = result {
+        error!(error = %e, "client exited with error");
+    }
+}
+
 fn build_runtime() -> anyhow::Result<tokio::runtime::Runtime> {
     tokio::runtime::Runtime::new().map_err(Into::into)
 }
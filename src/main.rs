@@ -6,14 +6,22 @@ use clap::{
 };
 
 mod config_file;
-use config_file::{ClientSection, CongestionStr, LogFormatStr, ServerSection};
+use config_file::{
+    ClientSection, CongestionStr, LogFormatStr, RemoteEntry, RouteStr, ServerSection,
+    SocksRuleSection,
+};
 use rusnel::cert;
-use rusnel::common::proxy::ProxyConfig;
+use rusnel::common::proxy::{proxy_from_env, ProxyConfig};
 use rusnel::common::quic::Congestion;
 use rusnel::common::remote::RemoteRequest;
+use rusnel::common::routing::{Cidr, Route, RouteRule, RouteRules};
+use rusnel::common::socks::SocksUsers;
 use rusnel::common::tls::{parse_fingerprint, ClientTlsConfig, ServerTlsConfig};
 use rusnel::embedded::{self, Materialized};
-use rusnel::{run_client, run_server, ClientConfig, ReconnectConfig, ServerConfig, ServerEndpoint};
+use rusnel::{
+    run_client, run_client_with_reload, run_server, ClientConfig, ReconnectConfig, ServerConfig,
+    ServerEndpoint, TcpFallback,
+};
 
 /// CLI mirror of `rusnel::common::quic::Congestion`. Kept separate so that
 /// clap's `ValueEnum` derive lives in the binary crate and doesn't pull
@@ -81,45 +89,61 @@ fn max_retries_from_cli(raw: i64) -> Result<Option<u32>, String> {
     }
 }
 
-/// Resolve `host:port` to a `ServerEndpoint`, preserving the original host
-/// string so it can be reused as the TLS SNI value (see `client_server_name`).
-/// Used as a clap `value_parser` so resolution failures surface as a
-/// `clap::Error` (consistent formatting + exit code 2) instead of a panic
-/// (#20 §4).
-///
-/// We collect *all* resolved addresses (not just the first), and reorder them
-/// per RFC 8305: alternate address families starting with the resolver's
-/// preferred family. The client races them with Happy Eyeballs so a host like
-/// `localhost` that resolves to both `[::1]` and `127.0.0.1` connects quickly
-/// even when only one family has a listener — matching what curl, `ssh`, and
-/// chisel's Go-based client do out of the box.
+/// Split `host:port` into a yet-unresolved `ServerEndpoint`, preserving the
+/// original host string so it can be reused as the TLS SNI value (see
+/// `client_server_name`). Used as a clap `value_parser` so malformed input
+/// surfaces as a `clap::Error` (consistent formatting + exit code 2) instead
+/// of a panic (#20 §4). [`resolve_server`] fills in the addresses once the
+/// proxy is known, since a `socks5h://` or HTTP one resolves the host
+/// itself.
 fn parse_server_addr(s: &str) -> Result<ServerEndpoint, String> {
     // Split host:port without losing the host. We do this manually instead of
     // relying on `SocketAddr::from_str` because we want to accept DNS names
     // too, not just IP literals.
-    let host = if let Some(rest) = s.strip_prefix('[') {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
         // IPv6 literal form: `[addr]:port`.
-        let close = rest
-            .find(']')
+        let (host, port) = rest
+            .split_once(']')
             .ok_or_else(|| format!("malformed IPv6 address in `{s}` (missing `]`)"))?;
-        rest[..close].to_string()
+        let port = port
+            .strip_prefix(':')
+            .ok_or_else(|| format!("expected host:port in `{s}`"))?;
+        (host, port)
     } else {
         s.rsplit_once(':')
             .ok_or_else(|| format!("expected host:port in `{s}`"))?
-            .0
-            .to_string()
     };
+    if host.is_empty() {
+        return Err(format!("expected host:port in `{s}`"));
+    }
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid port in server address `{s}`"))?;
+    Ok(ServerEndpoint {
+        addrs: Vec::new(),
+        host: host.to_string(),
+        port,
+    })
+}
 
-    let resolved: Vec<_> = s
+/// Resolve the server's host to its addresses.
+///
+/// We collect *all* resolved addresses (not just the first), and reorder them
+/// per RFC 8305: alternate address families starting with the resolver's
+/// preferred family. The client races them with Happy Eyeballs so a host like
+/// `localhost` that resolves to both `[::1]` and `127.0.0.1` connects quickly
+/// even when only one family has a listener — matching what curl, `ssh`, and
+/// chisel's Go-based client do out of the box.
+fn resolve_server(server: &mut ServerEndpoint) -> Result<(), String> {
+    let resolved: Vec<_> = (server.host.as_str(), server.port)
         .to_socket_addrs()
-        .map_err(|e| format!("failed to resolve server address `{s}`: {e}"))?
+        .map_err(|e| format!("failed to resolve server address `{server}`: {e}"))?
         .collect();
     if resolved.is_empty() {
-        return Err(format!("no addresses found for server `{s}`"));
+        return Err(format!("no addresses found for server `{server}`"));
     }
-
-    let addrs = interleave_address_families(resolved);
-    Ok(ServerEndpoint { addrs, host })
+    server.addrs = interleave_address_families(resolved);
+    Ok(())
 }
 
 /// Reorder resolved addresses per RFC 8305 §4: the first address keeps its
@@ -163,10 +187,56 @@ fn interleave_address_families(resolved: Vec<std::net::SocketAddr>) -> Vec<std::
     out
 }
 
-/// Parse a remote spec via `RemoteRequest::from_str`, surfacing parse errors
+/// One `<remote>` argument: a single remote, or one per port of a range.
+#[derive(Clone, Debug)]
+struct RemoteSpec(Vec<RemoteRequest>);
+
+/// Parse a remote spec via `RemoteRequest::expand`, surfacing parse errors
 /// as `clap` errors instead of `eprintln! + process::exit` (#20 §4 + §5).
-fn parse_remote(s: &str) -> Result<RemoteRequest, String> {
-    RemoteRequest::from_str(s).map_err(|e| format!("invalid remote `{s}`: {e}"))
+fn parse_remote(s: &str) -> Result<RemoteSpec, String> {
+    RemoteRequest::expand(s)
+        .map(RemoteSpec)
+        .map_err(|e| format!("invalid remote `{s}`: {e}"))
+}
+
+/// Parse a `[client].remotes` entry. The table form's `name` labels the
+/// remote the way a `name=` prefix does, so the spec can't carry its own.
+fn parse_file_remote(entry: &RemoteEntry) -> Result<Vec<RemoteRequest>, String> {
+    let RemoteEntry::Named(named) = entry else {
+        return parse_remote(entry.spec()).map(|spec| spec.0);
+    };
+    let unnamed = parse_remote(&named.remote)?;
+    if let Some(own) = unnamed.0.iter().find_map(|r| r.name.as_deref()) {
+        return Err(format!(
+            "remote `{}` is already named `{own}`; drop the prefix or the `name` key",
+            named.remote
+        ));
+    }
+    rusnel::common::remote::validate_name(&named.name).map_err(|e| e.to_string())?;
+    parse_remote(&format!("{}={}", named.name, named.remote)).map(|spec| spec.0)
+}
+
+/// Tunnel names pick a tunnel out in `rusnel ctl`, so one client can't
+/// use a name twice.
+fn check_unique_names(remotes: &[RemoteRequest]) -> Result<(), String> {
+    let mut seen = std::collections::HashSet::new();
+    for name in remotes.iter().filter_map(|r| r.name.as_deref()) {
+        if !seen.insert(name) {
+            return Err(format!(
+                "tunnel name `{name}` is used by more than one remote"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A `ctl tunnel` argument: a numeric tunnel id, or a tunnel name.
+#[cfg(unix)]
+fn parse_tunnel_key(s: &str) -> Result<String, String> {
+    if s.parse::<u64>().is_err() {
+        rusnel::common::remote::validate_name(s).map_err(|e| e.to_string())?;
+    }
+    Ok(s.to_string())
 }
 
 fn parse_proxy(s: &str) -> Result<ProxyConfig, String> {
@@ -211,10 +281,24 @@ enum Mode {
         #[arg(long, default_value_t = false)]
         allow_reverse: bool,
 
-        /// Allow clients to specify SOCKS5 remotes. `R:socks` additionally requires `--allow-reverse`.
+        /// Allow clients to specify SOCKS5 and HTTP proxy remotes. `R:socks` and `R:http` additionally require `--allow-reverse`.
         #[arg(long, default_value_t = false)]
         allow_socks: bool,
 
+        /// Allow clients to specify remotes that dial or listen on unix sockets on the server.
+        #[arg(long, default_value_t = false)]
+        allow_unix: bool,
+
+        /// Require SOCKS5 clients of `R:socks` listeners to log in as a user from this file.
+        ///
+        /// One `user:pass` per line; blank lines and `#` comments are
+        /// skipped. A remote's own `user:pass@` credentials are accepted
+        /// as well. `R:http` listeners take the same users as Basic proxy
+        /// auth. The username is recorded on each conn (`rusnel ctl
+        /// conns`).
+        #[arg(long, value_name = "PATH")]
+        socks_auth_file: Option<PathBuf>,
+
         /// Disable all TLS authentication. MITM-vulnerable; for testing only.
         ///
         /// Uses an ephemeral self-signed certificate and accepts any client.
@@ -266,6 +350,25 @@ enum Mode {
         #[arg(long, value_name = "N", default_value_t = 0)]
         max_connections: usize,
 
+        /// How long to hold a lost client's session for it to resume, in seconds (default 30; 0 disables).
+        ///
+        /// When a client's connection times out or is reset, its reverse
+        /// listeners stay bound and its tunnels registered for this long.
+        /// Conns arriving on those listeners meanwhile wait for the client;
+        /// if it reconnects in time, it takes the session over and they
+        /// go through. A client that closes its connection on purpose is
+        /// released right away.
+        #[arg(long, value_name = "SECONDS", default_value = "30", value_parser = parse_duration_secs)]
+        reconnect_grace: Duration,
+
+        /// Also accept clients on this TCP port, carrying QUIC over TLS.
+        ///
+        /// For clients on networks that drop UDP (see the client's
+        /// `--tcp-port`). Sessions arriving this way are served exactly
+        /// like the ones over UDP. Off by default.
+        #[arg(long, value_name = "PORT")]
+        tcp_port: Option<u16>,
+
         /// Path to the admin HTTP API unix socket.
         ///
         /// Defaults to `~/.rusnel/admin.sock` (auto-created with mode
@@ -310,6 +413,8 @@ enum Mode {
         /// file; the positional `<server>` and `<remote>...` are also
         /// supplied by `server = "..."` and `remotes = [...]` in the
         /// file when omitted from the CLI. Unknown keys are rejected.
+        /// When the remotes come from the file, SIGHUP re-reads its
+        /// `remotes` list and adds/removes tunnels on the live session.
         #[arg(long, value_name = "PATH")]
         config: Option<PathBuf>,
 
@@ -326,7 +431,7 @@ enum Mode {
     ■ local-port defaults to remote-port.
     ■ remote-port is required*.
     ■ remote-host defaults to 0.0.0.0 (server localhost).
-    ■ protocol defaults to tcp.
+    ■ protocol defaults to tcp; both (or tcp+udp) carries the two on the same port.
 
 which shares <remote-host>:<remote-port> from the server to the client as <local-host>:<local-port>, or:
 
@@ -343,27 +448,63 @@ sharing <remote-host>:<remote-port> from the client to the server\'s <local-host
         192.168.1.14:5000:google.com:80
         socks
         5000:socks
+        8080:http
         R:2222:localhost:22
         R:socks
         R:5000:socks
+        R:3128:http
+        R:0:localhost:22
         1.1.1.1:53/udp
         [::1]:80
         [::1]:5000:[2001:db8::1]:80
         stdio:example.com:22
+        5000-5010:example.com:5000-5010
+        R:6000-6100:localhost:6000-6100/udp
+        53:dns.internal:53/both
+        unix:/tmp/docker.sock:unix:/var/run/docker.sock
+        R:9000:unix:/run/app.sock
+        ssh=R:2222:localhost:22
 
     IPv6 literals must be wrapped in [brackets] (same convention as URLs and ssh -L).
 
     When the Rusnel server has --allow-reverse enabled, remotes can be prefixed with R to denote that they are reversed.
 
+    A reverse remote with local-port 0 (R:0:localhost:22, R:0:socks) lets the server pick a free port.
+    The client logs the address the server bound and prints it to stdout as a JSON line,
+    {"event":"tunnel_bound","tunnel_id":…,"spec":…,"bound_addr":…}, unless a stdio remote owns stdout.
+
     Remotes can specify "socks" in place of remote-host and remote-port.
     The default local host and port for a "socks" remote is 127.0.0.1:1080.
+    A "socks" remote prefixed with user:pass@ (alice:s3cret@socks,
+    R:alice:s3cret@0.0.0.0:1080:socks) makes its listener require that
+    username and password (RFC 1929); see also --socks-auth-file.
+
+    Remotes can specify "http" the same way for an HTTP proxy listener that
+    takes CONNECT host:port and plain requests with absolute http:// URIs.
+    Its default local host and port is 127.0.0.1:3128; a user:pass@ prefix
+    requires those credentials as Basic Proxy-Authorization. The server
+    gates it with --allow-socks, like "socks".
 
     Remotes can specify "stdio" in place of <local-host>:<local-port> to pipe
     the client process's stdin/stdout to/from the tunnel instead of binding a
     local listener (useful as an `ssh -o ProxyCommand` target). Stdio remotes
     are forward-only.
+
+    A port may be a first-last range; the remote stands for one tunnel per
+    port. A range on one side needs a range of the same length on the other
+    (a lone range, as in example.com:8000-8010, serves both). Ranges span at
+    most 512 ports and only apply to tcp and udp remotes.
+
+    Either end of a tcp remote can be a unix socket, written unix:/abs/path:
+    unix:/tmp/docker.sock:unix:/var/run/docker.sock or R:9000:unix:/run/app.sock.
+    A lone unix:/path serves both ends. The server gates unix sockets on its
+    own host with --allow-unix.
+
+    A remote prefixed with name= (ssh=R:2222:localhost:22) is a named tunnel.
+    Names are letters, digits, '-', '_' and '.', not all digits, and unique
+    per client; they show up in logs and `rusnel ctl tunnel <name>`.
         "#)]
-        remotes: Vec<RemoteRequest>,
+        remotes: Vec<RemoteSpec>,
 
         /// Disable server certificate verification. MITM-vulnerable; for testing only.
         #[arg(long, default_value_t = false)]
@@ -428,15 +569,69 @@ sharing <remote-host>:<remote-port> from the client to the server\'s <local-host
         #[arg(long, value_name = "SECONDS", default_value = "300", value_parser = parse_duration_secs)]
         max_retry_interval: Duration,
 
-        /// Route the QUIC connection through a SOCKS5 proxy via UDP ASSOCIATE. Form: `socks5://[user:pass@]host:port`.
+        /// Route the connection through a proxy. Form: `socks5://`, `socks5h://`, `http://`, `https://` or `masque://` + `[user:pass@]host:port`.
         ///
-        /// `socks://` is accepted as an alias. HTTP CONNECT is not supported
-        /// because it cannot carry UDP/QUIC. The proxy must permit UDP
-        /// ASSOCIATE; many corporate / hotel HTTP proxies do not. A fresh
-        /// UDP association is opened on every (re)connect.
+        /// A SOCKS5 proxy (`socks://` is an alias) carries QUIC itself via
+        /// UDP ASSOCIATE, which it must permit, and an HTTP/3 MASQUE proxy
+        /// via CONNECT-UDP (RFC 9298); a fresh UDP association or tunnel
+        /// is opened on every (re)connect. Through `socks5h://` the proxy,
+        /// not the client, resolves the server's name. HTTP CONNECT can't carry UDP, so
+        /// through an HTTP proxy the client uses the TCP fallback
+        /// transport: the server must listen on TCP, at --tcp-port if
+        /// given, else at the same port number as over UDP, and the proxy
+        /// resolves the server's name. Defaults to
+        /// $HTTPS_PROXY, else $ALL_PROXY, unless $NO_PROXY exempts the
+        /// server.
         #[arg(long, value_name = "URL", value_parser = parse_proxy)]
         proxy: Option<ProxyConfig>,
 
+        /// Trust the CA certificates (PEM) in this file for an `https://` or `masque://` proxy.
+        ///
+        /// On top of the system's trusted roots, for a proxy whose CA
+        /// isn't installed there.
+        #[arg(long, value_name = "PATH")]
+        proxy_ca: Option<PathBuf>,
+
+        /// Fail the connect attempt if the server refuses any remote.
+        ///
+        /// By default a remote the server rejects (policy) or can't bind
+        /// (reverse port already in use) is logged and the others come up
+        /// anyway. With this flag the whole session is refused instead, and
+        /// the client keeps retrying with backoff.
+        #[arg(long, default_value_t = false)]
+        strict_tunnels: bool,
+
+        /// Let static TCP tunnels' conns survive a reconnect.
+        ///
+        /// Each conn keeps up to 4 MiB of unacknowledged data per direction
+        /// and, once the client is back, carries on over the new connection
+        /// with nothing lost. Needs a server with a non-zero
+        /// --reconnect-grace; a conn that can't be resumed is closed.
+        #[arg(long, default_value_t = false)]
+        resumable_conns: bool,
+
+        /// Require SOCKS5 clients of forward `socks` listeners to log in as a user from this file.
+        ///
+        /// One `user:pass` per line; blank lines and `#` comments are
+        /// skipped. A remote's own `user:pass@` credentials are accepted
+        /// as well. Forward `http` listeners take the same users as Basic
+        /// proxy auth.
+        #[arg(long, value_name = "PATH")]
+        socks_auth_file: Option<PathBuf>,
+
+        /// The server's TCP fallback port (its --tcp-port), for networks that drop UDP.
+        ///
+        /// Each connect attempt starts over UDP as usual; if the QUIC
+        /// handshake gets no answer within a few seconds, the same
+        /// connection is also tried as QUIC over TLS on this TCP port,
+        /// and whichever gets through first is used.
+        #[arg(long, value_name = "PORT")]
+        tcp_port: Option<u16>,
+
+        /// Always connect over TCP to --tcp-port, never over UDP.
+        #[arg(long, default_value_t = false, requires = "tcp_port")]
+        tcp_only: bool,
+
         /// enable verbose logging (rusnel modules at debug level)
         #[arg(short('v'), long("verbose"), default_value_t = false)]
         is_verbose: bool,
@@ -502,13 +697,15 @@ enum CtlAction {
     Tunnels,
     /// Show full detail for one tunnel, including its active conns.
     Tunnel {
-        /// Tunnel id from `ctl tunnels`.
-        id: u64,
+        /// Tunnel id or name from `ctl tunnels`.
+        #[arg(value_parser = parse_tunnel_key)]
+        id: String,
     },
     /// List active conns going through one tunnel.
     TunnelConns {
-        /// Tunnel id from `ctl tunnels`.
-        id: u64,
+        /// Tunnel id or name from `ctl tunnels`.
+        #[arg(value_parser = parse_tunnel_key)]
+        id: String,
     },
     /// List every active conn across every tunnel.
     Conns,
@@ -783,6 +980,8 @@ struct ServerCli {
     port: u16,
     allow_reverse: bool,
     allow_socks: bool,
+    allow_unix: bool,
+    socks_auth_file: Option<PathBuf>,
     insecure: bool,
     tls_self_signed: bool,
     tls_state_dir: Option<PathBuf>,
@@ -791,6 +990,8 @@ struct ServerCli {
     tls_ca: Option<PathBuf>,
     congestion: CongestionArg,
     max_connections: usize,
+    reconnect_grace: Duration,
+    tcp_port: Option<u16>,
     admin_socket: Option<PathBuf>,
     no_admin_socket: bool,
     is_verbose: bool,
@@ -831,6 +1032,16 @@ fn merge_server_with_file(
             cli_explicit(matches, "allow_socks"),
             file.allow_socks,
         ),
+        allow_unix: pick(
+            cli.allow_unix,
+            cli_explicit(matches, "allow_unix"),
+            file.allow_unix,
+        ),
+        socks_auth_file: pick(
+            cli.socks_auth_file,
+            cli_explicit(matches, "socks_auth_file"),
+            file.socks_auth_file.map(Some),
+        ),
         insecure: if cli_set_tls {
             cli.insecure
         } else {
@@ -871,6 +1082,16 @@ fn merge_server_with_file(
             cli_explicit(matches, "max_connections"),
             file.max_connections,
         ),
+        reconnect_grace: pick(
+            cli.reconnect_grace,
+            cli_explicit(matches, "reconnect_grace"),
+            file.reconnect_grace.map(Duration::from_secs),
+        ),
+        tcp_port: pick(
+            cli.tcp_port,
+            cli_explicit(matches, "tcp_port"),
+            file.tcp_port.map(Some),
+        ),
         admin_socket: pick(
             cli.admin_socket,
             cli_explicit(matches, "admin_socket"),
@@ -909,6 +1130,14 @@ struct ClientCli {
     max_retry_count: i64,
     max_retry_interval: Duration,
     proxy: Option<ProxyConfig>,
+    proxy_ca: Option<PathBuf>,
+    strict_tunnels: bool,
+    resumable_conns: bool,
+    socks_auth_file: Option<PathBuf>,
+    /// Only ever set from the config file.
+    socks_rules: RouteRules,
+    tcp_port: Option<u16>,
+    tcp_only: bool,
     is_verbose: bool,
     is_debug: bool,
     is_quiet: bool,
@@ -942,10 +1171,12 @@ fn merge_client_with_file(
             Some(list) => list
                 .iter()
                 .map(|r| {
-                    parse_remote(r)
-                        .map_err(|e| format!("[client].remotes entry `{r}` in config file: {e}"))
+                    parse_file_remote(r).map_err(|e| {
+                        format!("[client].remotes entry `{}` in config file: {e}", r.spec())
+                    })
                 })
-                .collect::<Result<Vec<_>, _>>()?,
+                .collect::<Result<Vec<_>, _>>()?
+                .concat(),
             None => Vec::new(),
         }
     } else {
@@ -1022,6 +1253,40 @@ fn merge_client_with_file(
         ),
         max_retry_interval,
         proxy,
+        proxy_ca: pick(
+            cli.proxy_ca,
+            cli_explicit(matches, "proxy_ca"),
+            file.proxy_ca.map(Some),
+        ),
+        strict_tunnels: pick(
+            cli.strict_tunnels,
+            cli_explicit(matches, "strict_tunnels"),
+            file.strict_tunnels,
+        ),
+        resumable_conns: pick(
+            cli.resumable_conns,
+            cli_explicit(matches, "resumable_conns"),
+            file.resumable_conns,
+        ),
+        socks_auth_file: pick(
+            cli.socks_auth_file,
+            cli_explicit(matches, "socks_auth_file"),
+            file.socks_auth_file.map(Some),
+        ),
+        socks_rules: match file.socks_rules {
+            Some(entries) => parse_socks_rules(entries)?,
+            None => cli.socks_rules,
+        },
+        tcp_port: pick(
+            cli.tcp_port,
+            cli_explicit(matches, "tcp_port"),
+            file.tcp_port.map(Some),
+        ),
+        tcp_only: pick(
+            cli.tcp_only,
+            cli_explicit(matches, "tcp_only"),
+            file.tcp_only,
+        ),
         is_verbose: pick(
             cli.is_verbose,
             cli_explicit(matches, "is_verbose"),
@@ -1037,6 +1302,41 @@ fn merge_client_with_file(
     })
 }
 
+/// Build `[[client.socks_rules]]` into the rules the listeners check.
+fn parse_socks_rules(entries: Vec<SocksRuleSection>) -> Result<RouteRules, String> {
+    let rules = entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let cidrs = entry
+                .cidr
+                .iter()
+                .map(|c| c.parse::<Cidr>())
+                .collect::<anyhow::Result<Vec<_>>>()
+                .map_err(|e| {
+                    format!("[[client.socks_rules]] entry {} in config file: {e}", i + 1)
+                })?;
+            Ok(RouteRule::new(
+                entry.action.into(),
+                cidrs,
+                entry.domain,
+                entry.port,
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(RouteRules::new(rules))
+}
+
+impl From<RouteStr> for Route {
+    fn from(r: RouteStr) -> Self {
+        match r {
+            RouteStr::Direct => Route::Direct,
+            RouteStr::Tunnel => Route::Tunnel,
+            RouteStr::Reject => Route::Reject,
+        }
+    }
+}
+
 impl From<CongestionStr> for CongestionArg {
     fn from(c: CongestionStr) -> Self {
         match c {
@@ -1086,6 +1386,8 @@ fn main() {
             port,
             allow_reverse,
             allow_socks,
+            allow_unix,
+            socks_auth_file,
             insecure,
             tls_self_signed,
             tls_state_dir,
@@ -1094,6 +1396,8 @@ fn main() {
             tls_ca,
             congestion,
             max_connections,
+            reconnect_grace,
+            tcp_port,
             admin_socket,
             no_admin_socket,
             is_verbose,
@@ -1117,6 +1421,8 @@ fn main() {
                     port,
                     allow_reverse,
                     allow_socks,
+                    allow_unix,
+                    socks_auth_file,
                     insecure,
                     tls_self_signed,
                     tls_state_dir,
@@ -1125,6 +1431,8 @@ fn main() {
                     tls_ca,
                     congestion,
                     max_connections,
+                    reconnect_grace,
+                    tcp_port,
                     admin_socket,
                     no_admin_socket,
                     is_verbose,
@@ -1140,6 +1448,8 @@ fn main() {
                 port,
                 allow_reverse,
                 allow_socks,
+                allow_unix,
+                socks_auth_file,
                 insecure,
                 tls_self_signed,
                 tls_state_dir,
@@ -1148,6 +1458,8 @@ fn main() {
                 tls_ca,
                 congestion,
                 max_connections,
+                reconnect_grace,
+                tcp_port,
                 admin_socket,
                 no_admin_socket,
                 is_verbose,
@@ -1190,6 +1502,8 @@ fn main() {
                 port,
                 allow_reverse,
                 allow_socks,
+                allow_unix,
+                socks_users: load_socks_users(socks_auth_file.as_deref()),
                 tls,
                 congestion: congestion.into(),
                 max_connections: if max_connections == 0 {
@@ -1197,6 +1511,8 @@ fn main() {
                 } else {
                     Some(max_connections)
                 },
+                reconnect_grace,
+                tcp_port,
                 // Admin API is on by default at `~/.rusnel/admin.sock`
                 // — opt out with `--no-admin-socket`, override with
                 // `--admin-socket <PATH>`. Clap enforces the
@@ -1241,6 +1557,12 @@ fn main() {
             max_retry_count,
             max_retry_interval,
             proxy,
+            proxy_ca,
+            strict_tunnels,
+            resumable_conns,
+            socks_auth_file,
+            tcp_port,
+            tcp_only,
             is_verbose,
             is_debug,
             is_quiet,
@@ -1256,10 +1578,13 @@ fn main() {
                 None => None,
             };
             let sm = sub_matches.expect("client subcommand has matches");
+            // Remotes that come from the config file (none on the CLI)
+            // can be changed at runtime: SIGHUP re-reads the file.
+            let reload_path = config.filter(|_| remotes.is_empty());
             let merged = merge_client_with_file(
                 ClientCli {
                     server,
-                    remotes,
+                    remotes: remotes.into_iter().flat_map(|spec| spec.0).collect(),
                     insecure,
                     tls_fingerprint,
                     tls_ca,
@@ -1270,6 +1595,13 @@ fn main() {
                     max_retry_count,
                     max_retry_interval,
                     proxy,
+                    proxy_ca,
+                    strict_tunnels,
+                    resumable_conns,
+                    socks_auth_file,
+                    socks_rules: RouteRules::default(),
+                    tcp_port,
+                    tcp_only,
                     is_verbose,
                     is_debug,
                     is_quiet,
@@ -1295,12 +1627,19 @@ fn main() {
                 max_retry_count,
                 max_retry_interval,
                 proxy,
+                proxy_ca,
+                strict_tunnels,
+                resumable_conns,
+                socks_auth_file,
+                socks_rules,
+                tcp_port,
+                tcp_only,
                 is_verbose,
                 is_debug,
                 is_quiet,
                 log_format,
             } = merged;
-            let server = match server {
+            let mut server = match server {
                 Some(s) => s,
                 None => Args::command()
                     .error(
@@ -1319,12 +1658,56 @@ fn main() {
                     )
                     .exit();
             }
+            if let Err(e) = check_unique_names(&remotes) {
+                Args::command().error(ErrorKind::InvalidValue, e).exit();
+            }
+            // Clap checks these on the command line; the config file can
+            // still combine them.
+            if tcp_only && tcp_port.is_none() {
+                Args::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "tcp_only needs the server's TCP port: pass --tcp-port or set `tcp_port`",
+                    )
+                    .exit();
+            }
+            // Without a proxy of its own, the client takes the one the
+            // environment names for the server.
+            let (proxy, proxy_var) = match proxy {
+                Some(p) => (Some(p), None),
+                None => match proxy_from_env(&server.host) {
+                    Ok(Some((var, p))) => (Some(p), Some(var)),
+                    Ok(None) => (None, None),
+                    Err(msg) => Args::command().error(ErrorKind::InvalidValue, msg).exit(),
+                },
+            };
+            // A `socks5h://` or HTTP proxy resolves the server's name; the
+            // client may not even be able to (split DNS).
+            if !proxy
+                .as_ref()
+                .is_some_and(ProxyConfig::resolves_server_name)
+            {
+                if let Err(msg) = resolve_server(&mut server) {
+                    Args::command().error(ErrorKind::InvalidValue, msg).exit();
+                }
+            }
+            if tcp_port.is_some() && proxy.as_ref().is_some_and(ProxyConfig::carries_udp) {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "the TCP fallback transport can't go through a SOCKS5 or MASQUE proxy",
+                    )
+                    .exit();
+            }
             init_logging(LoggingOpts {
                 verbose: is_verbose,
                 debug: is_debug,
                 quiet: is_quiet,
                 format: log_format,
             });
+            if let Some(var) = proxy_var {
+                info!(var, "using proxy from the environment");
+            }
 
             let embedded = match embedded::materialize() {
                 Ok(m) => m,
@@ -1365,9 +1748,24 @@ fn main() {
                 congestion: congestion.into(),
                 reconnect,
                 proxy,
+                proxy_ca,
+                strict_tunnels,
+                resumable_conns,
+                socks_users: load_socks_users(socks_auth_file.as_deref()),
+                socks_rules,
+                tcp_fallback: tcp_port.map(|port| TcpFallback {
+                    port,
+                    only: tcp_only,
+                }),
+                stats: Default::default(),
             };
             debug!(?client_config, "client config resolved");
-            run_client(client_config);
+            match reload_path {
+                Some(path) => {
+                    run_client_with_reload(client_config, move || reload_client_remotes(&path))
+                }
+                None => run_client(client_config),
+            }
         }
         #[cfg(unix)]
         Mode::Ctl {
@@ -1397,6 +1795,40 @@ fn main() {
     }
 }
 
+/// The credentials in `--socks-auth-file`, if given; exits on a file
+/// that can't be read or parsed.
+fn load_socks_users(path: Option<&std::path::Path>) -> SocksUsers {
+    match path.map(SocksUsers::from_file).transpose() {
+        Ok(users) => users.unwrap_or_default(),
+        Err(e) => Args::command()
+            .error(ErrorKind::InvalidValue, format!("{e:#}"))
+            .exit(),
+    }
+}
+
+/// Re-read `[client].remotes` from the config file on SIGHUP. Held to
+/// the same rules as at startup: every entry must parse and the list
+/// can't be empty.
+fn reload_client_remotes(path: &std::path::Path) -> anyhow::Result<Vec<RemoteRequest>> {
+    let remotes = config_file::load(path)?
+        .client
+        .and_then(|c| c.remotes)
+        .unwrap_or_default();
+    if remotes.is_empty() {
+        anyhow::bail!("no remotes in the [client] section of `{}`", path.display());
+    }
+    let remotes = remotes
+        .iter()
+        .map(|r| {
+            parse_file_remote(r)
+                .map_err(|e| anyhow::anyhow!("[client].remotes entry `{}`: {e}", r.spec()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .concat();
+    check_unique_names(&remotes).map_err(|e| anyhow::anyhow!(e))?;
+    Ok(remotes)
+}
+
 #[cfg(unix)]
 fn run_ctl(socket: &std::path::Path, json: bool, action: CtlAction) -> anyhow::Result<()> {
     use rusnel::ctl::{self, Format};
@@ -1519,6 +1951,69 @@ mod tests {
         let got = interleave_address_families(vec![v6(1), v6(2)]);
         assert_eq!(got, vec![v6(1), v6(2)]);
     }
+
+    #[test]
+    fn server_addr_is_split_without_resolving() {
+        // Would fail to resolve; the proxy may be the only one who can.
+        let server = parse_server_addr("tunnel.invalid:8443").unwrap();
+        assert_eq!(server.host, "tunnel.invalid");
+        assert_eq!(server.port, 8443);
+        assert!(server.addrs.is_empty());
+        assert_eq!(server.to_string(), "tunnel.invalid:8443");
+
+        let server = parse_server_addr("[::1]:8080").unwrap();
+        assert_eq!(server.host, "::1");
+        assert_eq!(server.to_string(), "[::1]:8080");
+
+        assert!(parse_server_addr("tunnel.invalid").is_err());
+        assert!(parse_server_addr("tunnel.invalid:http").is_err());
+        assert!(parse_server_addr("[::1]8080").is_err());
+    }
+
+    #[test]
+    fn socks_rules_report_the_bad_entry() {
+        let entry = |cidr: &str| SocksRuleSection {
+            action: RouteStr::Direct,
+            cidr: vec![cidr.into()],
+            domain: vec![],
+            port: vec![],
+        };
+        let rules = parse_socks_rules(vec![entry("10.0.0.0/8"), entry("fd00::/8")]).unwrap();
+        assert_eq!(rules.len(), 2);
+        let err = parse_socks_rules(vec![entry("10.0.0.0/8"), entry("10.0.0.0/40")]).unwrap_err();
+        assert!(err.contains("entry 2"), "got: {err}");
+    }
+
+    #[test]
+    fn named_file_remotes() {
+        let named = |name: &str, remote: &str| {
+            RemoteEntry::Named(config_file::NamedRemoteSection {
+                name: name.into(),
+                remote: remote.into(),
+            })
+        };
+        let remotes = parse_file_remote(&named("ssh", "R:2222:localhost:22")).unwrap();
+        assert_eq!(remotes[0].name.as_deref(), Some("ssh"));
+        let remotes = parse_file_remote(&named("rtp", "5000-5001:media:5000-5001/udp")).unwrap();
+        assert_eq!(remotes[1].name.as_deref(), Some("rtp-5001"));
+        let spec = parse_file_remote(&RemoteEntry::Spec("web=8080:web:80".into())).unwrap();
+        assert_eq!(spec[0].name.as_deref(), Some("web"));
+
+        let err = parse_file_remote(&named("ssh", "other=2222:localhost:22")).unwrap_err();
+        assert!(err.contains("already named `other`"), "got: {err}");
+        assert!(parse_file_remote(&named("22", "2222:localhost:22")).is_err());
+
+        let twice = [spec.clone(), spec].concat();
+        assert!(check_unique_names(&twice).unwrap_err().contains("`web`"));
+    }
+
+    #[test]
+    fn resolve_server_fills_in_addresses() {
+        let mut server = parse_server_addr("127.0.0.1:8080").unwrap();
+        resolve_server(&mut server).unwrap();
+        assert_eq!(server.addrs, vec!["127.0.0.1:8080".parse().unwrap()]);
+        assert_eq!(server.to_string(), "127.0.0.1:8080");
+    }
 }
 
 /// Minimal subscriber for one-shot subcommands (`ctl`, `cert`). No
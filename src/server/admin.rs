@@ -12,6 +12,7 @@
 
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use axum::extract::{Path as AxumPath, Query, State};
@@ -28,7 +29,7 @@ use tracing::{debug, info, warn};
 
 use super::state::{
     self, server_info, ClientDetailDto, ClientSummaryDto, ConnDto, ServerInfoDto, ServerState,
-    TunnelDetailDto, TunnelDto,
+    TunnelDetailDto, TunnelDto, TunnelEntry,
 };
 
 /// Default cap on the `/api/v1/history` response when the caller doesn't
@@ -150,14 +151,7 @@ async fn get_client(
     AxumPath(id): AxumPath<u64>,
 ) -> Result<Json<ClientDetailDto>, ApiError> {
     let entry = state.client(id).ok_or(ApiError::NotFound)?;
-    let summary = ClientSummaryDto::from_entry(&entry);
-    let mut tunnels: Vec<TunnelDto> = entry
-        .tunnels
-        .iter()
-        .map(|t| TunnelDto::from_entry(t.value()))
-        .collect();
-    tunnels.sort_by_key(|t| t.id);
-    Ok(Json(ClientDetailDto { summary, tunnels }))
+    Ok(Json(ClientDetailDto::from_entry(&entry)))
 }
 
 async fn list_client_tunnels(
@@ -184,11 +178,30 @@ async fn list_tunnels(State(state): State<ServerState>) -> Json<Vec<TunnelDto>>
     Json(out)
 }
 
+/// Resolve a `/tunnels/:id` path segment, which is a tunnel id or name.
+fn find_tunnel(state: &ServerState, key: &str) -> Result<Arc<TunnelEntry>, ApiError> {
+    if let Ok(id) = key.parse::<u64>() {
+        return state.tunnel(id).ok_or(ApiError::NotFound);
+    }
+    let mut named = state.tunnels_named(key);
+    match named.len() {
+        0 => Err(ApiError::NotFound),
+        1 => Ok(named.remove(0)),
+        _ => {
+            let mut ids: Vec<u64> = named.iter().map(|t| t.id).collect();
+            ids.sort_unstable();
+            Err(ApiError::Ambiguous(format!(
+                "name `{key}` matches tunnels {ids:?}; use an id"
+            )))
+        }
+    }
+}
+
 async fn get_tunnel(
     State(state): State<ServerState>,
-    AxumPath(id): AxumPath<u64>,
+    AxumPath(key): AxumPath<String>,
 ) -> Result<Json<TunnelDetailDto>, ApiError> {
-    let entry = state.tunnel(id).ok_or(ApiError::NotFound)?;
+    let entry = find_tunnel(&state, &key)?;
     let summary = TunnelDto::from_entry(&entry);
     let mut conns: Vec<ConnDto> = entry
         .conns
@@ -201,9 +214,9 @@ async fn get_tunnel(
 
 async fn list_tunnel_conns(
     State(state): State<ServerState>,
-    AxumPath(id): AxumPath<u64>,
+    AxumPath(key): AxumPath<String>,
 ) -> Result<Json<Vec<ConnDto>>, ApiError> {
-    let entry = state.tunnel(id).ok_or(ApiError::NotFound)?;
+    let entry = find_tunnel(&state, &key)?;
     let mut out: Vec<ConnDto> = entry
         .conns
         .iter()
@@ -265,6 +278,8 @@ async fn list_history(
 /// a 404 response without pulling in `axum::http::Error` boilerplate.
 enum ApiError {
     NotFound,
+    /// A name that picks out more than one tunnel.
+    Ambiguous(String),
 }
 
 impl IntoResponse for ApiError {
@@ -275,6 +290,11 @@ impl IntoResponse for ApiError {
                 Json(serde_json::json!({"error": "not found"})),
             )
                 .into_response(),
+            ApiError::Ambiguous(msg) => (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({"error": msg})),
+            )
+                .into_response(),
         }
     }
 }
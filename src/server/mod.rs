@@ -3,33 +3,46 @@
 // spawns it.
 #[cfg(unix)]
 pub mod admin;
+mod resume;
 pub mod state;
 
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 #[cfg(unix)]
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 
-use quinn::{Connection, ConnectionError, VarInt};
+use quinn::{Connection, ConnectionError, RecvStream, SendStream, VarInt, ZeroRttAccepted};
 use tokio::signal;
-use tokio::sync::Semaphore;
-use tokio::task::JoinSet;
+use tokio::sync::{mpsc, oneshot, watch, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::common::quic::create_server_endpoint;
+use crate::common::datagram::{DatagramRouter, Datagrams};
+use crate::common::listener::{LocalListener, Uplink, UplinkWatch};
+use crate::common::quic::{create_server_endpoint, create_server_endpoint_with_fallback};
 use crate::common::remote::{
-    Direction, DynamicTarget, RemoteKind, RemoteRequest, SessionHelloResponse,
+    validate_name, Capability, ConnResume, Direction, DynamicTarget, HostPort, OpenConnResponse,
+    PeerInfo, Protocol, RemoteKind, RemoteRequest, ResumeToken, SessionHelloResponse, StreamEnd,
+    TunnelControl, TunnelControlResponse, TunnelStatus, PROTOCOL_VERSION,
 };
-use crate::common::socks::tunnel_socks_client;
-use crate::common::tcp::{tunnel_tcp_client, tunnel_tcp_server};
+use crate::common::resumable::{AttachPoints, Leg, Reattach};
+use crate::common::routing::RouteRules;
+use crate::common::socks::{tunnel_socks_bind_server, SocksUsers};
+use crate::common::tcp::{tunnel_tcp_server, tunnel_unix_server};
 use crate::common::tunnel::{
-    reply_open_conn, server_receive_session_hello, server_reply_session_hello,
+    accept_open_conn, refuse_open_conn, reply_open_conn, server_receive_session_hello,
+    server_receive_tunnel_control, server_reply_session_hello, server_reply_tunnel_control,
+    OpenConnError,
 };
-use crate::common::udp::{tunnel_udp_client, tunnel_udp_server};
+use crate::common::udp::{accept_datagram_flow, tunnel_udp_server};
 use crate::ServerConfig;
 
+use self::resume::{HeldSession, HeldTunnel, ResumeStore};
 use self::state::{ServerState, TunnelEntry, TunnelHandle};
 
 /// Application-level QUIC close codes the server uses. We pick chisel-ish
@@ -37,9 +50,31 @@ use self::state::{ServerState, TunnelEntry, TunnelHandle};
 /// layer treats the numeric value as opaque.
 const CLOSE_CODE_SERVER_SHUTDOWN: u32 = 0;
 
+/// How long a rejected session hello's `Failed` reply is given to reach
+/// the client before the connection is dropped. Without it the implicit
+/// close on drop usually overtakes the reply and the client only sees
+/// "connection lost" instead of the reason.
+const HELLO_REJECT_FLUSH: Duration = Duration::from_secs(2);
+
 pub async fn run_async(config: ServerConfig) -> Result<()> {
-    let endpoint =
-        create_server_endpoint(config.host, config.port, &config.tls, config.congestion)?;
+    let (endpoint, fallback_handle) = match config.tcp_port {
+        Some(tcp_port) => {
+            let (endpoint, fallback) = create_server_endpoint_with_fallback(
+                config.host,
+                config.port,
+                tcp_port,
+                &config.tls,
+                config.congestion,
+            )
+            .await?;
+            info!(addr = %fallback.local_addr()?, "server listening on TCP fallback");
+            (endpoint, Some(tokio::spawn(fallback.serve())))
+        }
+        None => (
+            create_server_endpoint(config.host, config.port, &config.tls, config.congestion)?,
+            None,
+        ),
+    };
     let listen_addr = endpoint.local_addr()?;
     info!(addr = %listen_addr, "server listening");
 
@@ -73,6 +108,16 @@ pub async fn run_async(config: ServerConfig) -> Result<()> {
     let connection_limiter: Option<Arc<Semaphore>> =
         config.max_connections.map(|n| Arc::new(Semaphore::new(n)));
 
+    let settings = SessionSettings {
+        allow_reverse: config.allow_reverse,
+        allow_socks: config.allow_socks,
+        allow_unix: config.allow_unix,
+        socks_users: config.socks_users.clone(),
+        reconnect_grace: config.reconnect_grace,
+        sessions: ResumeStore::default(),
+        conns: AttachPoints::default(),
+    };
+
     // Race the accept loop against ^C. On signal, gracefully close the
     // endpoint so every connected client receives a CONNECTION_CLOSE frame
     // (with the reason "server received ^C") instead of having to wait out
@@ -87,6 +132,9 @@ pub async fn run_async(config: ServerConfig) -> Result<()> {
                 info!("shutdown signal received, notifying clients");
                 endpoint.close(VarInt::from_u32(CLOSE_CODE_SERVER_SHUTDOWN), b"server received ^C");
                 endpoint.wait_idle().await;
+                if let Some(h) = &fallback_handle {
+                    h.abort();
+                }
                 #[cfg(unix)]
                 {
                     if let Some(h) = admin_handle {
@@ -125,20 +173,13 @@ pub async fn run_async(config: ServerConfig) -> Result<()> {
                     None
                 };
 
-                let allow_reverse = config.allow_reverse;
-                let allow_socks = config.allow_socks;
+                let settings = settings.clone();
                 let state_for_client = state.clone();
                 tokio::spawn(
                     async move {
                         info!("connected");
-                        match handle_client_connection(
-                            conn,
-                            allow_reverse,
-                            allow_socks,
-                            client_id,
-                            state_for_client,
-                        )
-                        .await
+                        match handle_client_connection(conn, client_id, state_for_client, settings)
+                            .await
                         {
                             Ok(reason) => info!(reason = %reason, "disconnected"),
                             Err(e) => error!(error = %e, "session failed"),
@@ -151,9 +192,39 @@ pub async fn run_async(config: ServerConfig) -> Result<()> {
             }
         }
     }
+    if let Some(h) = &fallback_handle {
+        h.abort();
+    }
     Ok(())
 }
 
+/// Server-wide settings every client session runs under.
+#[derive(Clone)]
+struct SessionSettings {
+    allow_reverse: bool,
+    allow_socks: bool,
+    allow_unix: bool,
+    /// Credentials `R:socks` listeners accept, besides a remote's own.
+    socks_users: SocksUsers,
+    /// How long a lost session is held for its client to resume; zero
+    /// turns resumption off.
+    reconnect_grace: Duration,
+    sessions: ResumeStore,
+    /// Resumable conns, for clients to attach new streams to.
+    conns: AttachPoints,
+}
+
+impl SessionSettings {
+    /// How a resumable conn waits for its client after a loss: as long
+    /// as a lost session is held.
+    fn reattach(&self) -> Reattach {
+        Reattach::Await {
+            points: self.conns.clone(),
+            grace: self.reconnect_grace,
+        }
+    }
+}
+
 #[cfg(unix)]
 fn spawn_admin(state: ServerState, path: PathBuf) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
@@ -166,21 +237,29 @@ fn spawn_admin(state: ServerState, path: PathBuf) -> tokio::task::JoinHandle<()>
 /// Per-connection accept loop. Returns `Ok(reason)` on a clean disconnect
 /// (returning the human-readable reason so the caller can log it), and
 /// `Err` on protocol-level failure.
-/// All per-tunnel work is scoped to the lifetime of this function via a
-/// [`JoinSet`]. When the connection ends — for any reason — we abort every
-/// outstanding tunnel task. This matters most for *reverse* tunnels, where
-/// the server-side runs a long-lived `TcpListener` / `UdpSocket` bound to a
-/// local port: without the abort, those tasks would keep accepting forever
+/// Per-conn work is scoped to the lifetime of this function via a
+/// [`JoinSet`] that is aborted when the connection ends, for any reason.
+/// Reverse tunnels, whose server side is a long-lived `TcpListener` /
+/// `UdpSocket` bound to a local port, are [`ReverseHandler`]s that close
+/// their listener on drop: with the session, unless it's held for the
+/// client to resume (see [`resume`]), in which case they're released when
+/// its grace period runs out. Without that, they would keep accepting
 /// against a dead QUIC connection, holding the port until the server
 /// process exited.
 async fn handle_client_connection(
     conn: quinn::Incoming,
-    allow_reverse: bool,
-    allow_socks: bool,
     client_id: u64,
     state: ServerState,
+    settings: SessionSettings,
 ) -> Result<String> {
-    let connection = conn.await?;
+    // Take the connection at 0.5-RTT so a resuming client's SessionHello,
+    // sent as 0-RTT early data, is answered in our first flight. For
+    // incoming connections `into_0rtt` always succeeds; the `Err` arm only
+    // exists to satisfy the type.
+    let (connection, handshake) = match conn.accept()?.into_0rtt() {
+        Ok((connection, handshake)) => (connection, Some(handshake)),
+        Err(connecting) => (connecting.await?, None),
+    };
     let mut tunnels: JoinSet<()> = JoinSet::new();
 
     // Register this client with the observability state for the lifetime
@@ -191,19 +270,26 @@ async fn handle_client_connection(
 
     // ---------------------------------------------------------------
     // Session hello: the very first bi-stream the client opens carries
-    // its full set of tunnel declarations. Validate them as a batch and
-    // either accept the whole session (assigning `tunnel_id`s) or reject
-    // it. This replaces the legacy per-stream `RemoteRequest` handshake.
+    // its full set of tunnel declarations. Each is checked against
+    // policy (and, for reverse tunnels, bound) on its own; the reply
+    // reports a status per remote so one bad tunnel doesn't cost the
+    // client the rest. A strict hello gets the old all-or-nothing
+    // behaviour instead.
     // ---------------------------------------------------------------
+    let mut handshake = handshake;
     let hello_outcome = perform_session_hello(
         &connection,
+        &mut handshake,
         &state,
         &client_entry,
-        allow_reverse,
-        allow_socks,
+        &settings,
     )
     .await;
-    let registered_tunnels = match hello_outcome {
+    let AcceptedHello {
+        tunnels: registered_tunnels,
+        control: (control_send, control_recv),
+        resume_token,
+    } = match hello_outcome {
         Ok(t) => t,
         Err(e) => {
             // Rejected sessions still count as a disconnect so they
@@ -214,34 +300,70 @@ async fn handle_client_connection(
             return Err(e);
         }
     };
+    // Demultiplexes inbound QUIC DATAGRAM frames to this client's UDP
+    // conns; our own UDP conns only propose datagram flows if the
+    // client negotiated them.
+    let datagrams = DatagramRouter::spawn(
+        connection.clone(),
+        client_entry.supports(Capability::Datagrams),
+    );
+    let control = ControlContext {
+        connection: &connection,
+        datagrams: &datagrams,
+        state: &state,
+        client: &client_entry,
+        settings: &settings,
+    };
+
+    // Dialing upstreams for OpenConn waits for the handshake too (see
+    // `await_handshake`). A hello with reverse tunnels has already
+    // waited for it before binding their listeners.
+    if let Err(e) = await_handshake(&connection, &mut handshake).await {
+        state.deregister_client(client_id, e.to_string());
+        return Err(e);
+    }
 
     info!(count = registered_tunnels.len(), "session established");
 
-    // Reverse handlers own long-lived local sockets — bind them as
-    // soon as the hello is accepted, before any conn flows. Forward
-    // tunnels are passive on the server side; their conns arrive as
-    // OpenConn frames on the per-conn loop below.
-    for tunnel in &registered_tunnels {
+    // Reverse listeners were bound during the hello (or are still
+    // running from the session this one resumed); start serving them
+    // over this connection before any conn flows. Forward tunnels are
+    // passive on the server side; their conns arrive as OpenConn frames
+    // on the per-conn loop below. Reverse handlers are kept by tunnel id
+    // so a live `RemoveTunnel` can release just that listener.
+    let mut reverse_handlers: HashMap<u64, ReverseHandler> = HashMap::new();
+    for (tunnel, listener) in registered_tunnels {
         let dir = match tunnel.direction {
             Direction::Forward => "forward",
             Direction::Reverse => "reverse",
         };
+        let handler = match listener {
+            Some(ReverseListener::Bound(listener)) => {
+                Some(control.spawn_reverse_handler(tunnel.clone(), listener))
+            }
+            Some(ReverseListener::Running(handler)) => {
+                handler.attach(control.uplink(&tunnel));
+                Some(handler)
+            }
+            None => None,
+        };
         info!(
             tunnel_id = tunnel.id,
             dir,
             spec = %tunnel.spec,
             "tunnel registered"
         );
-        if matches!(tunnel.direction, Direction::Reverse) {
-            spawn_reverse_handler(
-                connection.clone(),
-                state.clone(),
-                tunnel.clone(),
-                &mut tunnels,
-            );
+        if let Some(handler) = handler {
+            reverse_handlers.insert(tunnel.id, handler);
         }
     }
 
+    // Live tunnel changes arrive on the hello stream. The reader runs
+    // as its own task (frame reads aren't cancel-safe) and hands each
+    // request to this loop, which owns the tunnel set.
+    let (control_tx, mut control_rx) = mpsc::channel(1);
+    tunnels.spawn(run_tunnel_control(control_send, control_recv, control_tx).in_current_span());
+
     let outcome = loop {
         let quic_connection = connection.clone();
 
@@ -252,6 +374,12 @@ async fn handle_client_connection(
         // immediately resolve to `None` and we'd spin.
         let stream_result = tokio::select! {
             r = quic_connection.accept_bi() => r,
+            Some((request, reply)) = control_rx.recv() => {
+                let response =
+                    apply_tunnel_control(request, &control, &mut reverse_handlers).await;
+                let _ = reply.send(response);
+                continue;
+            }
             Some(joined) = tunnels.join_next(), if !tunnels.is_empty() => {
                 if let Err(e) = joined {
                     if !e.is_cancelled() {
@@ -292,7 +420,13 @@ async fn handle_client_connection(
         };
 
         let state_for_conn = state.clone();
-        let fut = handle_open_conn(stream, state_for_conn);
+        let fut = handle_open_conn(
+            stream,
+            quic_connection.remote_address(),
+            state_for_conn,
+            datagrams.clone(),
+            settings.clone(),
+        );
         tunnels.spawn(async move {
             if let Err(e) = fut.await {
                 error!(error = %e, "conn failed");
@@ -300,9 +434,8 @@ async fn handle_client_connection(
         });
     };
 
-    // Abort any per-tunnel task that's still running. Forward tunnels
-    // self-clean once the QUIC stream errors, but reverse tunnels own a
-    // local listener that needs an explicit abort to release the port.
+    // Abort any per-conn task that's still running. Forward conns
+    // self-clean once the QUIC stream errors anyway.
     let aborted = tunnels.len();
     tunnels.shutdown().await;
     if aborted > 0 {
@@ -313,132 +446,625 @@ async fn handle_client_connection(
         Ok(r) => r.clone(),
         Err(e) => format!("error: {e}"),
     };
-    state.deregister_client(client_id, reason);
+    // A connection that died under the client, rather than being
+    // closed by either side, may be resumed.
+    let lost = matches!(
+        connection.close_reason(),
+        Some(
+            ConnectionError::TimedOut
+                | ConnectionError::Reset
+                | ConnectionError::ConnectionClosed(_)
+        )
+    );
+    let session = HeldSession {
+        client: client_entry.clone(),
+        tunnels: client_entry
+            .tunnels
+            .iter()
+            .map(|t| HeldTunnel {
+                tunnel: t.value().clone(),
+                handler: reverse_handlers.remove(t.key()),
+            })
+            .collect(),
+    };
+    for handler in session.tunnels.iter().filter_map(|t| t.handler.as_ref()) {
+        // Conns accepted from here on wait for the client to resume.
+        handler.detach();
+    }
+    let held = match resume_token {
+        Some(token) => settings.sessions.hold(
+            token,
+            session,
+            lost,
+            settings.reconnect_grace,
+            &state,
+            &reason,
+        ),
+        None => Err(session),
+    };
+    match held {
+        Ok(()) => info!(
+            grace = ?settings.reconnect_grace,
+            "holding session for the client to resume"
+        ),
+        Err(session) => {
+            // Dropping the session closes its reverse listeners.
+            drop(session);
+            state.deregister_client(client_id, reason);
+        }
+    }
 
     outcome
 }
 
-/// Read the client's [`SessionHello`], validate every requested
-/// remote, and reply with the assigned `tunnel_id`s (or a failure).
-/// On success returns the freshly registered tunnel entries in the
-/// same order as `hello.remotes`.
+/// A tunnel accepted by the session hello, with its reverse listener
+/// if it has one.
+type HelloTunnel = (Arc<TunnelEntry>, Option<ReverseListener>);
+
+/// A reverse tunnel's listener as the hello leaves it.
+enum ReverseListener {
+    /// Bound for a new tunnel; not serving yet.
+    Bound(LocalListener),
+    /// Carried over from the session this one resumed.
+    Running(ReverseHandler),
+}
+
+/// How a remote the hello accepted comes to run.
+enum HelloAccept {
+    /// A new tunnel, with its listener if it's a reverse one.
+    New(Option<LocalListener>),
+    /// Carried over from the resumed session.
+    Resumed(HeldTunnel),
+}
+
+/// What an accepted session hello leaves the session with.
+struct AcceptedHello {
+    /// The accepted tunnels, in the order of `hello.remotes`.
+    tunnels: Vec<HelloTunnel>,
+    /// The hello bi-stream, which carries [`TunnelControl`] requests
+    /// from here on.
+    control: (SendStream, RecvStream),
+    /// Issued to the client if the session can be resumed.
+    resume_token: Option<ResumeToken>,
+}
+
+/// Read the client's [`SessionHello`], check its protocol version,
+/// take over the session it resumes (if we hold it), then check (and
+/// for new reverse tunnels, bind) every requested remote and reply with
+/// a [`TunnelStatus`] for each. A strict hello is instead rejected
+/// outright if any remote fails.
 async fn perform_session_hello(
     connection: &Connection,
+    handshake: &mut Option<ZeroRttAccepted>,
     state: &ServerState,
     client: &Arc<state::ClientEntry>,
-    allow_reverse: bool,
-    allow_socks: bool,
-) -> Result<Vec<Arc<TunnelEntry>>> {
+    settings: &SessionSettings,
+) -> Result<AcceptedHello> {
     let (mut send, mut recv) = connection.accept_bi().await?;
-    let hello = server_receive_session_hello(&mut recv).await?;
-
-    if let Err(reason) = validate_remotes(&hello.remotes, allow_reverse, allow_socks) {
-        let resp = SessionHelloResponse::Failed(reason.clone());
-        let _ = server_reply_session_hello(&mut send, &resp).await;
-        return Err(anyhow::anyhow!(reason));
+    let hello = match server_receive_session_hello(&mut recv).await {
+        Ok(hello) => hello,
+        Err(e) => {
+            // Most likely a client speaking a newer protocol whose hello
+            // doesn't decode here; tell it so rather than just hanging up.
+            let reason = format!(
+                "unreadable session hello (server speaks protocol {PROTOCOL_VERSION}): {e}"
+            );
+            return Err(reject_session_hello(send, reason).await);
+        }
+    };
+    if let Err(reason) = hello.peer.check_compatible() {
+        return Err(reject_session_hello(send, format!("client has {reason}")).await);
     }
 
-    let tunnels = state.register_tunnels(client, &hello.remotes);
-    let tunnel_ids: Vec<u64> = tunnels.iter().map(|t| t.id).collect();
-    server_reply_session_hello(&mut send, &SessionHelloResponse::Ok { tunnel_ids }).await?;
-    Ok(tunnels)
-}
+    // Taking a session over may close the connection it's running on,
+    // which a replayed 0-RTT hello mustn't be able to do.
+    let resumed = match hello.resume {
+        Some(token) if !settings.reconnect_grace.is_zero() => {
+            await_handshake(connection, handshake).await?;
+            settings.sessions.take(token).await
+        }
+        _ => None,
+    };
 
-/// Static validation of a hello batch against the server's policy.
-/// Returns the *first* offending reason — operators rarely care about
-/// the rest, and surfacing only one keeps the rejection log tidy.
-fn validate_remotes(
-    remotes: &[RemoteRequest],
-    allow_reverse: bool,
-    allow_socks: bool,
-) -> Result<(), String> {
-    for r in remotes {
-        if r.is_reversed() && !allow_reverse {
-            return Err(format!("Reverse remotes are not allowed ({r})"));
+    // Names pick tunnels out in the admin API, so a client gets each
+    // one once; a later remote reusing a name is rejected.
+    let mut names = HashSet::new();
+    // `Ok` says how the tunnel will run; `Err` is the status it's
+    // reported with instead.
+    let mut outcomes: Vec<Result<HelloAccept, TunnelStatus>> = hello
+        .remotes
+        .iter()
+        .map(|r| {
+            check_remote(r, settings)
+                .and_then(|()| match &r.name {
+                    Some(name) if !names.insert(name.as_str()) => Err(name_in_use(r, name)),
+                    _ => Ok(()),
+                })
+                .map(|()| HelloAccept::New(None))
+                .map_err(TunnelStatus::Rejected)
+        })
+        .collect();
+    if let Some(mut held) = resumed {
+        for (remote, outcome) in hello.remotes.iter().zip(outcomes.iter_mut()) {
+            let Some(i) = outcome
+                .is_ok()
+                .then(|| held.tunnels.iter().position(|t| t.tunnel.matches(remote)))
+                .flatten()
+            else {
+                continue;
+            };
+            let tunnel = held.tunnels.swap_remove(i);
+            state.adopt_tunnel(&held.client, client, &tunnel.tunnel);
+            *outcome = Ok(HelloAccept::Resumed(tunnel));
+        }
+        info!(
+            from_client = held.client.id,
+            dropped = held.tunnels.len(),
+            "resuming session"
+        );
+        // Tunnels the client no longer declares go, freeing their ports
+        // for the new ones below.
+        drop(held.tunnels);
+        state.deregister_client(held.client.id, format!("resumed as client {}", client.id));
+    }
+
+    let binds_reverse = hello
+        .remotes
+        .iter()
+        .zip(&outcomes)
+        .any(|(r, outcome)| r.is_reversed() && matches!(outcome, Ok(HelloAccept::New(_))));
+    if binds_reverse {
+        await_handshake(connection, handshake).await?;
+    }
+    for (remote, outcome) in hello.remotes.iter().zip(outcomes.iter_mut()) {
+        if !(remote.is_reversed() && matches!(outcome, Ok(HelloAccept::New(_)))) {
+            continue;
         }
-        if r.is_socks() && !allow_socks {
-            return Err(format!("SOCKS5 remotes are not allowed ({r})"));
+        *outcome = match LocalListener::bind(remote).await {
+            Ok(listener) => Ok(HelloAccept::New(Some(listener))),
+            Err(e) => Err(TunnelStatus::BindFailed(format!("{e:#}"))),
+        };
+    }
+
+    if hello.strict {
+        let failure = outcomes.iter().find_map(|outcome| match outcome {
+            Err(TunnelStatus::Rejected(reason) | TunnelStatus::BindFailed(reason)) => {
+                Some(reason.clone())
+            }
+            _ => None,
+        });
+        if let Some(reason) = failure {
+            return Err(reject_session_hello(send, reason).await);
         }
     }
+
+    client.set_peer(hello.peer);
+    let new: Vec<RemoteRequest> = hello
+        .remotes
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| matches!(outcome, Ok(HelloAccept::New(_))))
+        .map(|(r, _)| r.clone())
+        .collect();
+    let mut registered = state.register_tunnels(client, &new).into_iter();
+    let mut tunnels = Vec::with_capacity(outcomes.len());
+    let mut statuses = Vec::with_capacity(outcomes.len());
+    for (remote, outcome) in hello.remotes.iter().zip(outcomes) {
+        let (tunnel, listener) = match outcome {
+            Ok(HelloAccept::New(listener)) => {
+                let tunnel = registered
+                    .next()
+                    .expect("register_tunnels returns one entry per remote");
+                bind_address(&tunnel, listener.as_ref());
+                (tunnel, listener.map(ReverseListener::Bound))
+            }
+            Ok(HelloAccept::Resumed(HeldTunnel { tunnel, handler })) => {
+                (tunnel, handler.map(ReverseListener::Running))
+            }
+            Err(status) => {
+                warn!(spec = %remote, status = ?status, "tunnel not accepted");
+                statuses.push(status);
+                continue;
+            }
+        };
+        statuses.push(TunnelStatus::Accepted {
+            tunnel_id: tunnel.id,
+            bound: tunnel.bound_addr(),
+        });
+        tunnels.push((tunnel, listener));
+    }
+
+    let resume_token =
+        if client.supports(Capability::SessionResume) && !settings.reconnect_grace.is_zero() {
+            ResumeToken::generate()
+                .inspect_err(|e| warn!(error = %e, "session won't be resumable"))
+                .ok()
+        } else {
+            None
+        };
+    // Resumable conns wait out the same grace as lost sessions, so
+    // without one there's nothing to offer.
+    let peer = if settings.reconnect_grace.is_zero() {
+        PeerInfo::local().without(Capability::ResumableConns)
+    } else {
+        PeerInfo::local()
+    };
+    let resp = SessionHelloResponse::Ok {
+        tunnels: statuses,
+        peer,
+        resume_token,
+    };
+    server_reply_session_hello(&mut send, &resp).await?;
+    if let Some(token) = resume_token {
+        settings.sessions.register(token, connection.clone());
+    }
+    Ok(AcceptedHello {
+        tunnels,
+        control: (send, recv),
+        resume_token,
+    })
+}
+
+/// Wait for the QUIC handshake of a connection accepted with 0-RTT, if
+/// it hasn't been waited for yet.
+///
+/// Early data can be replayed and the peer isn't authenticated until
+/// the handshake completes, so everything with side effects beyond our
+/// own bookkeeping — binding reverse listeners, dialing upstreams for
+/// OpenConn — waits for it. Answering a hello with only forward
+/// tunnels early is fine: it only assigns ids, and a replayed
+/// connection never gets past here. (The future's value says whether
+/// 0-RTT was used, not whether the handshake succeeded — a failed
+/// handshake shows up as a close reason.)
+async fn await_handshake(
+    connection: &Connection,
+    handshake: &mut Option<ZeroRttAccepted>,
+) -> Result<()> {
+    let Some(handshake) = handshake.take() else {
+        return Ok(());
+    };
+    let early = handshake.await;
+    if let Some(reason) = connection.close_reason() {
+        return Err(anyhow::anyhow!("handshake failed: {reason}"));
+    }
+    debug!(early, "handshake complete");
     Ok(())
 }
 
-fn spawn_reverse_handler(
-    connection: Connection,
-    state: ServerState,
-    tunnel: Arc<TunnelEntry>,
-    tasks: &mut JoinSet<()>,
+/// Answer the hello with `Failed(reason)` and wait (bounded by
+/// [`HELLO_REJECT_FLUSH`]) for the client to receive it. Returns the
+/// error the session fails with.
+async fn reject_session_hello(mut send: SendStream, reason: String) -> anyhow::Error {
+    let resp = SessionHelloResponse::Failed(reason.clone());
+    if server_reply_session_hello(&mut send, &resp).await.is_ok() && send.finish().is_ok() {
+        let _ = tokio::time::timeout(HELLO_REJECT_FLUSH, send.stopped()).await;
+    }
+    anyhow::anyhow!(reason)
+}
+
+/// Read [`TunnelControl`] requests off the control stream, pass each to
+/// the session loop and write back its answer. Returns once the client
+/// finishes the stream or the session loop is gone.
+async fn run_tunnel_control(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    requests: mpsc::Sender<(TunnelControl, oneshot::Sender<TunnelControlResponse>)>,
 ) {
-    let handle = Arc::new(TunnelHandle::new(state, tunnel.clone()));
-    let span = info_span!(
-        "tunnel",
-        tunnel_id = tunnel.id,
-        dir = "reverse",
-        spec = %tunnel.spec,
-    );
-    tasks.spawn(
-        async move {
-            // Reconstruct the `RemoteRequest` from the stored tunnel
-            // declaration so the existing handlers (which still take a
-            // `RemoteRequest` for local-bind / target lookup) keep
-            // working unchanged.
-            let request = RemoteRequest::new(tunnel.direction, tunnel.kind.clone());
-            let result = match &tunnel.kind {
-                RemoteKind::Tcp { .. } => {
-                    tunnel_tcp_client(connection, request, Some(handle), tunnel.id).await
-                }
-                RemoteKind::Udp { .. } => {
-                    tunnel_udp_client(connection, request, Some(handle), tunnel.id).await
+    loop {
+        let request = match server_receive_tunnel_control(&mut recv).await {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                debug!("control stream finished");
+                return;
+            }
+            Err(e) => {
+                debug!(error = %e, "control stream ended");
+                return;
+            }
+        };
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if requests.send((request, reply_tx)).await.is_err() {
+            return;
+        }
+        let Ok(response) = reply_rx.await else { return };
+        if let Err(e) = server_reply_tunnel_control(&mut send, &response).await {
+            debug!(error = %e, "control reply failed");
+            return;
+        }
+    }
+}
+
+/// The parts of a client session a live tunnel change acts on.
+struct ControlContext<'a> {
+    connection: &'a Connection,
+    datagrams: &'a Datagrams,
+    state: &'a ServerState,
+    client: &'a Arc<state::ClientEntry>,
+    settings: &'a SessionSettings,
+}
+
+impl ControlContext<'_> {
+    /// This connection, as the uplink of `tunnel`'s reverse conns.
+    fn uplink(&self, tunnel: &TunnelEntry) -> Uplink {
+        Uplink {
+            connection: self.connection.clone(),
+            tunnel_id: tunnel.id,
+            datagrams: self.datagrams.clone(),
+            optimistic: self.client.supports(Capability::OptimisticOpen),
+            resumable: self.resumable().then(|| self.settings.reattach()),
+            socks_bind: self.client.supports(Capability::SocksBind),
+            protocol: None,
+        }
+    }
+
+    /// Whether this client's static TCP conns are resumable: it asked
+    /// for them, and lost sessions are held long enough to resume.
+    fn resumable(&self) -> bool {
+        self.client.supports(Capability::ResumableConns) && !self.settings.reconnect_grace.is_zero()
+    }
+
+    /// Start serving a reverse tunnel's freshly bound `listener` over
+    /// this connection.
+    fn spawn_reverse_handler(
+        &self,
+        tunnel: Arc<TunnelEntry>,
+        listener: LocalListener,
+    ) -> ReverseHandler {
+        let (uplink, current) = watch::channel(Some(self.uplink(&tunnel)));
+        let handle = Arc::new(TunnelHandle::new(self.state.clone(), tunnel.clone()));
+        let socks_users = self.settings.socks_users.clone();
+        // A held session's conns wait for the client for as long as
+        // the session is held.
+        let uplink_watch = UplinkWatch::new(current, self.settings.reconnect_grace);
+        let span = info_span!(
+            "tunnel",
+            tunnel_id = tunnel.id,
+            name = tunnel.name.as_deref().unwrap_or("-"),
+            dir = "reverse",
+            spec = %tunnel.spec,
+        );
+        let task = tokio::spawn(
+            async move {
+                // Reconstruct the `RemoteRequest` from the stored tunnel
+                // declaration so the shared handlers (which still take a
+                // `RemoteRequest` for target lookup) keep working unchanged.
+                let request = RemoteRequest::new(tunnel.direction, tunnel.kind.clone());
+                let result = listener
+                    .serve(
+                        uplink_watch,
+                        request,
+                        Some(handle),
+                        socks_users,
+                        // Split-tunnel rules are a client setting.
+                        RouteRules::default(),
+                    )
+                    .await;
+                if let Err(e) = result {
+                    error!(error = %e, "reverse handler failed");
                 }
-                RemoteKind::Socks5 { .. } => {
-                    tunnel_socks_client(connection, request, Some(handle), tunnel.id).await
+            }
+            .instrument(span),
+        );
+        ReverseHandler { task, uplink }
+    }
+}
+
+/// A reverse tunnel's running server-side listener. Its conns go out
+/// over whichever connection currently serves the session; dropping
+/// the handler closes the listener, while conns it already accepted
+/// run on their own tasks and finish normally.
+struct ReverseHandler {
+    task: JoinHandle<()>,
+    uplink: watch::Sender<Option<Uplink>>,
+}
+
+impl ReverseHandler {
+    /// Serve conns over `uplink` from now on.
+    fn attach(&self, uplink: Uplink) {
+        self.uplink.send_replace(Some(uplink));
+    }
+
+    /// Hold conns until [`attach`](Self::attach)ed again.
+    fn detach(&self) {
+        self.uplink.send_replace(None);
+    }
+}
+
+impl Drop for ReverseHandler {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Apply one [`TunnelControl`] request: the single-tunnel equivalent of
+/// what the hello does for the initial batch, or its reverse.
+async fn apply_tunnel_control(
+    request: TunnelControl,
+    ctx: &ControlContext<'_>,
+    reverse_handlers: &mut HashMap<u64, ReverseHandler>,
+) -> TunnelControlResponse {
+    match request {
+        TunnelControl::AddTunnel(remote) => {
+            let taken = remote.name.as_deref().filter(|name| {
+                ctx.client
+                    .tunnels
+                    .iter()
+                    .any(|t| t.name.as_deref() == Some(*name))
+            });
+            let checked = match taken {
+                Some(name) => Err(name_in_use(&remote, name)),
+                None => check_remote(&remote, ctx.settings),
+            };
+            if let Err(reason) = checked {
+                warn!(reason = %reason, "tunnel add rejected");
+                return TunnelControlResponse::Failed(reason);
+            }
+            // Bind before registering, so a taken port is reported to
+            // the client instead of leaving a tunnel that never listens.
+            let listener = if remote.is_reversed() {
+                match LocalListener::bind(&remote).await {
+                    Ok(listener) => Some(listener),
+                    Err(e) => {
+                        let reason = format!("{e:#}");
+                        warn!(reason = %reason, "tunnel add failed to bind");
+                        return TunnelControlResponse::Failed(reason);
+                    }
                 }
+            } else {
+                None
+            };
+            let Some(tunnel) = ctx.state.register_tunnels(ctx.client, &[remote]).pop() else {
+                return TunnelControlResponse::Failed("tunnel registration failed".into());
             };
-            if let Err(e) = result {
-                error!(error = %e, "reverse handler failed");
+            let bound = bind_address(&tunnel, listener.as_ref());
+            info!(tunnel_id = tunnel.id, spec = %tunnel.spec, "tunnel added");
+            if let Some(listener) = listener {
+                let handler = ctx.spawn_reverse_handler(tunnel.clone(), listener);
+                reverse_handlers.insert(tunnel.id, handler);
+            }
+            TunnelControlResponse::Added {
+                tunnel_id: tunnel.id,
+                bound,
             }
         }
-        .instrument(span),
-    );
+        TunnelControl::RemoveTunnel { tunnel_id } => {
+            let Some(tunnel) = ctx.state.remove_tunnel(ctx.client, tunnel_id) else {
+                return TunnelControlResponse::Failed(format!("unknown tunnel id {tunnel_id}"));
+            };
+            // Only the listener goes; conns it already accepted run on
+            // their own tasks and finish normally.
+            reverse_handlers.remove(&tunnel_id);
+            info!(tunnel_id, spec = %tunnel.spec, "tunnel removed");
+            TunnelControlResponse::Removed
+        }
+    }
+}
+
+/// Record where `tunnel`'s pre-bound listener (if any) ended up, for
+/// the admin API and the client's hello / `AddTunnel` answer.
+fn bind_address(tunnel: &TunnelEntry, listener: Option<&LocalListener>) -> Option<SocketAddr> {
+    let addr = listener?.local_addr().ok()?;
+    tunnel.set_bound_addr(addr);
+    Some(addr)
+}
+
+/// Check one requested remote against the server's policy.
+fn check_remote(r: &RemoteRequest, settings: &SessionSettings) -> Result<(), String> {
+    if r.is_reversed() && !settings.allow_reverse {
+        return Err(format!("Reverse remotes are not allowed ({r})"));
+    }
+    if r.is_socks() && !settings.allow_socks {
+        return Err(format!("SOCKS5 remotes are not allowed ({r})"));
+    }
+    if r.is_http() && !settings.allow_socks {
+        return Err(format!("HTTP proxy remotes are not allowed ({r})"));
+    }
+    if r.server_unix_path().is_some() && !settings.allow_unix {
+        return Err(format!("Unix socket remotes are not allowed ({r})"));
+    }
+    if let Some(name) = &r.name {
+        validate_name(name).map_err(|e| format!("{e} ({r})"))?;
+    }
+    Ok(())
+}
+
+fn name_in_use(r: &RemoteRequest, name: &str) -> String {
+    format!("Tunnel name `{name}` is already in use ({r})")
 }
 
 /// Per-conn dispatcher. Receives one [`OpenConn`] frame, looks up its
 /// parent tunnel, registers a `ConnGuard`, and hands the bi-stream off
-/// to the appropriate data-plane handler.
+/// to the appropriate data-plane handler. A stream attached to an
+/// existing resumable conn goes to that conn instead.
 async fn handle_open_conn(
     (mut send, mut recv): (quinn::SendStream, quinn::RecvStream),
+    client_addr: SocketAddr,
     state: ServerState,
+    datagrams: Datagrams,
+    settings: SessionSettings,
 ) -> Result<()> {
-    use crate::common::remote::OpenConnResponse;
     let open = crate::common::tunnel::receive_open_conn(&mut recv).await?;
 
+    if let Some(ConnResume::Attach { token, received }) = open.resume {
+        let leg = Leg {
+            send,
+            recv,
+            peer_received: received,
+        };
+        let Err(mut leg) = settings.conns.attach(token, leg) else {
+            return Ok(());
+        };
+        let reason = "unknown or expired conn";
+        reply_open_conn(&mut leg.send, &OpenConnResponse::Failed(reason.into())).await?;
+        let _ = leg.send.finish();
+        return Err(anyhow::anyhow!(reason));
+    }
+
     let tunnel = match state.tunnel(open.tunnel_id) {
         Some(t) => t,
         None => {
-            let _ = reply_open_conn(
+            let reason = format!("unknown tunnel id {}", open.tunnel_id);
+            refuse_open_conn(
+                &open,
                 &mut send,
-                &OpenConnResponse::Failed(format!("unknown tunnel id {}", open.tunnel_id)),
+                &mut recv,
+                OpenConnError::UnknownTunnel,
+                &reason,
             )
             .await;
-            return Err(anyhow::anyhow!("unknown tunnel id {}", open.tunnel_id));
+            return Err(anyhow::anyhow!(reason));
         }
     };
 
     // Resolve the per-conn target. Static tunnels carry it in
     // `tunnel.kind`; SOCKS5 dynamic streams carry it in `open.dynamic`
     // (per CONNECT or per UDP target the SOCKS handler just decoded).
-    let dispatch = match resolve_dispatch(&tunnel, open.dynamic.as_ref()) {
+    let dispatch = match resolve_dispatch(&tunnel, open.dynamic.as_ref(), open.protocol) {
         Ok(d) => d,
         Err(e) => {
-            let _ = reply_open_conn(&mut send, &OpenConnResponse::Failed(e.to_string())).await;
+            let reason = e.to_string();
+            refuse_open_conn(
+                &open,
+                &mut send,
+                &mut recv,
+                OpenConnError::Rejected,
+                &reason,
+            )
+            .await;
             return Err(e);
         }
     };
 
-    reply_open_conn(&mut send, &OpenConnResponse::Ok).await?;
+    // UDP conns may carry their payload as QUIC datagrams; register the
+    // proposed flow before acking so none can arrive unrouted.
+    let flow = match &dispatch {
+        ForwardDispatch::Udp(_) => match accept_datagram_flow(&datagrams, open.datagram_flow) {
+            Ok(f) => f,
+            Err(e) => {
+                let reason = e.to_string();
+                refuse_open_conn(
+                    &open,
+                    &mut send,
+                    &mut recv,
+                    OpenConnError::Rejected,
+                    &reason,
+                )
+                .await;
+                return Err(e);
+            }
+        },
+        ForwardDispatch::Tcp(_) | ForwardDispatch::Unix(_) | ForwardDispatch::Bind(_) => None,
+    };
+
+    // Optimistic openers are already streaming and get no `Ok`; a
+    // failed dial from here on is reported by `tunnel_tcp_server`.
+    accept_open_conn(&open, &mut send).await?;
 
     let peer = dispatch.peer_label();
-    let conn = state.register_conn(&tunnel, peer.clone());
+    let protocol = open
+        .protocol
+        .filter(|_| matches!(tunnel.kind, RemoteKind::Both { .. }));
+    let conn = state.register_conn(&tunnel, peer.clone(), open.user.clone(), protocol);
     let conn_id = conn.id();
     let counters = conn.counters();
 
@@ -446,17 +1072,35 @@ async fn handle_open_conn(
         "conn",
         conn_id = conn_id,
         tunnel_id = tunnel.id,
+        name = tunnel.name.as_deref().unwrap_or("-"),
         peer = peer.as_deref().unwrap_or("-"),
     );
-    async move {
+    // A resumable conn outlives this connection, so it can't run in
+    // the connection's task set; it ends on its own once it can't be
+    // resumed.
+    let resume = match (&dispatch, open.resume) {
+        (ForwardDispatch::Tcp(_) | ForwardDispatch::Unix(_), Some(ConnResume::New(token))) => {
+            Some((token, settings.reattach()))
+        }
+        _ => None,
+    };
+    let detached = resume.is_some();
+    let conn_task = async move {
         info!("conn opened");
         let started = std::time::Instant::now();
         let result = match dispatch {
             ForwardDispatch::Tcp(req) => {
-                tunnel_tcp_server(recv, send, req, Some(counters.clone())).await
+                tunnel_tcp_server(recv, send, req, Some(counters.clone()), resume).await
+            }
+            ForwardDispatch::Unix(path) => {
+                tunnel_unix_server(recv, send, &path, Some(counters.clone()), resume).await
             }
             ForwardDispatch::Udp(req) => {
-                tunnel_udp_server(recv, send, req, Some(counters.clone())).await
+                tunnel_udp_server(recv, send, req, Some(counters.clone()), flow).await
+            }
+            ForwardDispatch::Bind(expected) => {
+                tunnel_socks_bind_server(recv, send, expected, client_addr, Some(counters.clone()))
+                    .await
             }
         };
         let (bytes_in, bytes_out) = counters.snapshot();
@@ -472,8 +1116,12 @@ async fn handle_open_conn(
         drop(conn);
         result
     }
-    .instrument(span)
-    .await
+    .instrument(span);
+    if detached {
+        tokio::spawn(conn_task.in_current_span());
+        return Ok(());
+    }
+    conn_task.await
 }
 
 /// What the conn's data-plane handler will do, plus the synthetic
@@ -483,12 +1131,18 @@ async fn handle_open_conn(
 enum ForwardDispatch {
     Tcp(RemoteRequest),
     Udp(RemoteRequest),
+    /// Dial the unix socket at this path.
+    Unix(PathBuf),
+    /// SOCKS5 BIND: accept one conn from this host.
+    Bind(HostPort),
 }
 
 impl ForwardDispatch {
     fn peer_label(&self) -> Option<String> {
         match self {
             ForwardDispatch::Tcp(r) | ForwardDispatch::Udp(r) => r.remote_addr_string(),
+            ForwardDispatch::Unix(path) => Some(format!("unix:{}", path.display())),
+            ForwardDispatch::Bind(expected) => Some(format!("BIND from {expected}")),
         }
     }
 }
@@ -496,6 +1150,7 @@ impl ForwardDispatch {
 fn resolve_dispatch(
     tunnel: &TunnelEntry,
     dynamic: Option<&DynamicTarget>,
+    protocol: Option<Protocol>,
 ) -> Result<ForwardDispatch> {
     if !matches!(tunnel.direction, Direction::Forward) {
         return Err(anyhow::anyhow!(
@@ -518,16 +1173,17 @@ fn resolve_dispatch(
                 remote: remote.clone(),
             },
         ))),
-        (RemoteKind::Socks5 { local }, Some(DynamicTarget::Tcp(target))) => {
-            Ok(ForwardDispatch::Tcp(RemoteRequest::new(
-                Direction::Forward,
-                RemoteKind::Tcp {
-                    local: *local,
-                    remote: target.clone(),
-                },
-            )))
-        }
-        (RemoteKind::Socks5 { local }, Some(DynamicTarget::Udp(target))) => {
+        (
+            RemoteKind::Socks5 { local, .. } | RemoteKind::Http { local, .. },
+            Some(DynamicTarget::Tcp(target)),
+        ) => Ok(ForwardDispatch::Tcp(RemoteRequest::new(
+            Direction::Forward,
+            RemoteKind::Tcp {
+                local: *local,
+                remote: target.clone(),
+            },
+        ))),
+        (RemoteKind::Socks5 { local, .. }, Some(DynamicTarget::Udp(target))) => {
             Ok(ForwardDispatch::Udp(RemoteRequest::new(
                 Direction::Forward,
                 RemoteKind::Udp {
@@ -536,8 +1192,45 @@ fn resolve_dispatch(
                 },
             )))
         }
-        (RemoteKind::Socks5 { .. }, None) => Err(anyhow::anyhow!(
-            "OpenConn on SOCKS5 tunnel {} requires a `dynamic` target",
+        (RemoteKind::Socks5 { .. }, Some(DynamicTarget::Bind(expected))) => {
+            Ok(ForwardDispatch::Bind(expected.clone()))
+        }
+        (
+            RemoteKind::Unix {
+                remote: StreamEnd::Tcp(remote),
+                ..
+            },
+            None,
+        ) => Ok(ForwardDispatch::Tcp(RemoteRequest::new(
+            Direction::Forward,
+            RemoteKind::Tcp {
+                local: tunnel.kind.local(),
+                remote: remote.clone(),
+            },
+        ))),
+        (
+            RemoteKind::Unix {
+                remote: StreamEnd::Unix(path),
+                ..
+            },
+            None,
+        ) => Ok(ForwardDispatch::Unix(path.clone())),
+        (RemoteKind::Both { .. }, None) => match protocol.and_then(|p| tunnel.kind.half(p)) {
+            Some(kind @ RemoteKind::Tcp { .. }) => Ok(ForwardDispatch::Tcp(RemoteRequest::new(
+                Direction::Forward,
+                kind,
+            ))),
+            Some(kind) => Ok(ForwardDispatch::Udp(RemoteRequest::new(
+                Direction::Forward,
+                kind,
+            ))),
+            None => Err(anyhow::anyhow!(
+                "OpenConn on tcp+udp tunnel {} didn't name its protocol",
+                tunnel.id
+            )),
+        },
+        (RemoteKind::Socks5 { .. } | RemoteKind::Http { .. }, None) => Err(anyhow::anyhow!(
+            "OpenConn on proxy tunnel {} requires a `dynamic` target",
             tunnel.id
         )),
         (_, Some(_)) => Err(anyhow::anyhow!(
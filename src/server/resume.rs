@@ -0,0 +1,161 @@
+//! Sessions held for resumption.
+//!
+//! A client that negotiates `session-resume` gets a [`ResumeToken`] in
+//! its hello response. If its connection is then lost — timed out or
+//! reset, not closed on purpose — the server keeps the session's
+//! reverse listeners bound and its tunnels registered for the reconnect
+//! grace period; conns accepted meanwhile wait for the client to come
+//! back. A hello carrying the token takes the session over, otherwise
+//! it's released once the grace period is up.
+//!
+//! The client often notices a dead path before the server does. A
+//! token presented while its session still looks live closes that
+//! connection and takes the session over from it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use quinn::{Connection, VarInt};
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use tracing::info;
+
+use super::state::{ClientEntry, ServerState, TunnelEntry};
+use super::ReverseHandler;
+use crate::common::remote::ResumeToken;
+
+/// Close code for a connection whose session a resuming client took
+/// over.
+pub const CLOSE_CODE_RESUMED: u32 = 2;
+
+/// How long a resuming hello waits for the live session it closed to
+/// hand over.
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A session's tunnels while no connection serves them.
+pub struct HeldSession {
+    pub client: Arc<ClientEntry>,
+    pub tunnels: Vec<HeldTunnel>,
+}
+
+pub struct HeldTunnel {
+    pub tunnel: Arc<TunnelEntry>,
+    /// A reverse tunnel's listener, still bound.
+    pub handler: Option<ReverseHandler>,
+}
+
+enum Slot {
+    /// Running on this connection.
+    Live(Connection),
+    /// Its connection was closed by a resuming hello, which waits here
+    /// for the session.
+    Superseded(oneshot::Sender<HeldSession>),
+    /// Connection lost; released when `expiry` fires.
+    Held {
+        session: HeldSession,
+        expiry: AbortHandle,
+    },
+}
+
+/// Every session a resume token was issued for, by token.
+#[derive(Clone, Default)]
+pub struct ResumeStore {
+    slots: Arc<Mutex<HashMap<ResumeToken, Slot>>>,
+}
+
+impl ResumeStore {
+    /// Record the session running on `connection` under `token`.
+    pub fn register(&self, token: ResumeToken, connection: Connection) {
+        self.lock().insert(token, Slot::Live(connection));
+    }
+
+    /// Take over the session `token` names, closing its connection
+    /// first if it's still up. `None` if there's no such session (any
+    /// more).
+    pub async fn take(&self, token: ResumeToken) -> Option<HeldSession> {
+        let handoff = {
+            let mut slots = self.lock();
+            match slots.remove(&token)? {
+                Slot::Held { session, expiry } => {
+                    expiry.abort();
+                    return Some(session);
+                }
+                Slot::Live(connection) => {
+                    let (tx, rx) = oneshot::channel();
+                    slots.insert(token, Slot::Superseded(tx));
+                    connection.close(
+                        VarInt::from_u32(CLOSE_CODE_RESUMED),
+                        b"session resumed on a new connection",
+                    );
+                    rx
+                }
+                // Another hello got there first.
+                slot @ Slot::Superseded(_) => {
+                    slots.insert(token, slot);
+                    return None;
+                }
+            }
+        };
+        tokio::time::timeout(HANDOFF_TIMEOUT, handoff)
+            .await
+            .ok()?
+            .ok()
+    }
+
+    /// The connection of `token`'s session has ended with `reason`.
+    /// Hands the session to the hello that superseded it, or holds it
+    /// for `grace` if the connection was `lost`. Otherwise the session
+    /// is given back for the caller to release.
+    pub fn hold(
+        &self,
+        token: ResumeToken,
+        session: HeldSession,
+        lost: bool,
+        grace: Duration,
+        state: &ServerState,
+        reason: &str,
+    ) -> Result<(), HeldSession> {
+        let mut slots = self.lock();
+        match slots.remove(&token) {
+            Some(Slot::Superseded(handoff)) => handoff.send(session),
+            Some(Slot::Live(_)) if lost => {
+                session.client.detach();
+                let expiry = self.spawn_expiry(token, grace, state.clone(), reason.to_string());
+                slots.insert(token, Slot::Held { session, expiry });
+                Ok(())
+            }
+            _ => Err(session),
+        }
+    }
+
+    fn spawn_expiry(
+        &self,
+        token: ResumeToken,
+        grace: Duration,
+        state: ServerState,
+        reason: String,
+    ) -> AbortHandle {
+        let store = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            let Some(Slot::Held { session, .. }) = store.lock().remove(&token) else {
+                return;
+            };
+            info!(
+                client_id = session.client.id,
+                "session not resumed in time; releasing it"
+            );
+            state.deregister_client(
+                session.client.id,
+                format!("{reason}; not resumed within {grace:?}"),
+            );
+            // Dropping the session closes its listeners.
+        })
+        .abort_handle()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ResumeToken, Slot>> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -16,21 +16,28 @@
 //!   accepted local TCP socket; for reverse it's one accepted remote
 //!   TCP socket on the server side; for UDP it's a per-source
 //!   aggregator; for SOCKS5 it's a per-CONNECT or per-target UDP
-//!   relay. Carries its own live `bytes_in` / `bytes_out`.
+//!   relay. Carries its own live `bytes_in` / `bytes_out`. A `/both`
+//!   tunnel's conns also record which of its protocols they use, and
+//!   the tunnel keeps separate totals for each.
 //!
 //! Lifecycle hooks (driven from [`super`]):
 //! * client connect → [`ServerState::register_client`]
 //! * session hello → [`ServerState::register_tunnels`] pre-allocates one
-//!   [`TunnelEntry`] per declared remote in a single batch
+//!   [`TunnelEntry`] per declared remote in a single batch; a live
+//!   `AddTunnel` goes through the same call with a batch of one
+//! * live `RemoveTunnel` → [`ServerState::remove_tunnel`]
 //! * data-plane stream / accepted-conn → [`ServerState::register_conn`]
 //!   (dropped via [`ConnGuard`])
 //! * client disconnect → [`ServerState::deregister_client`] which fans
-//!   out [`HistoryEntry`]s and cleans up tunnels + conns.
+//!   out [`HistoryEntry`]s and cleans up tunnels + conns. A session
+//!   held for resumption is [`ClientEntry::detach`]ed instead, and
+//!   deregistered once its grace period runs out or a resuming client
+//!   has [`ServerState::adopt_tunnel`]ed what it wants to keep.
 
 use std::collections::VecDeque;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, OnceLock, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use dashmap::DashMap;
@@ -38,7 +45,7 @@ use quinn::Connection;
 use serde::Serialize;
 
 use crate::common::counted::TunnelCounters;
-use crate::common::remote::{Direction, RemoteKind, RemoteRequest};
+use crate::common::remote::{Capability, Direction, PeerInfo, Protocol, RemoteKind, RemoteRequest};
 
 /// Cap on the recent-disconnects ring buffer. Picked small so a long-running
 /// server doesn't accumulate unbounded state — operators wanting durable
@@ -68,6 +75,16 @@ pub struct ClientEntry {
     /// admin routes can grab a snapshot without holding the shard lock
     /// across JSON serialization.
     pub tunnels: DashMap<u64, Arc<TunnelEntry>>,
+    /// Tunnels removed mid-session, so their bytes (including those of
+    /// conns still draining through them) stay in this client's totals
+    /// and its eventual [`HistoryEntry`].
+    retired_tunnels: RwLock<RetiredTunnels>,
+    /// What the client said about itself in its session hello. Unset
+    /// until the hello is accepted.
+    peer: OnceLock<NegotiatedPeer>,
+    /// When the connection was lost, if the session is being held for
+    /// the client to resume.
+    detached_at: OnceLock<SystemTime>,
     /// Live QUIC handle. Kept around for phase-2 write endpoints
     /// (`DELETE /clients/:id` → `connection.close(...)`); no read path
     /// dereferences it today.
@@ -75,35 +92,100 @@ pub struct ClientEntry {
     pub conn: Connection,
 }
 
+/// A client's [`PeerInfo`] together with the capabilities this server
+/// negotiated from it.
+#[derive(Debug, Clone)]
+pub struct NegotiatedPeer {
+    pub info: PeerInfo,
+    pub capabilities: Vec<Capability>,
+}
+
 impl ClientEntry {
+    /// Record the client's accepted hello. Later calls are ignored.
+    pub fn set_peer(&self, info: PeerInfo) {
+        let capabilities = info.negotiate();
+        let _ = self.peer.set(NegotiatedPeer { info, capabilities });
+    }
+
+    pub fn peer(&self) -> Option<&NegotiatedPeer> {
+        self.peer.get()
+    }
+
+    /// Mark the session as held for resumption after its connection
+    /// was lost.
+    pub fn detach(&self) {
+        let _ = self.detached_at.set(SystemTime::now());
+    }
+
+    /// Whether `capability` was negotiated with this client. `false`
+    /// before the hello.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.peer()
+            .is_some_and(|p| p.capabilities.contains(&capability))
+    }
+
     /// `(active_bytes_in, active_bytes_out, cumulative_bytes_in, cumulative_bytes_out)`
     /// summed across this client's tunnels.
     pub fn totals(&self) -> ClientTotals {
-        let mut t = ClientTotals::default();
-        for entry in self.tunnels.iter() {
-            let tot = entry.value().totals();
-            t.active_in += tot.active_in;
-            t.active_out += tot.active_out;
-            t.cumulative_in += tot.cumulative_in;
-            t.cumulative_out += tot.cumulative_out;
-            t.active_conns += tot.active_conns;
-            t.total_conns += tot.total_conns;
+        let (mut t, draining) = self
+            .retired_tunnels
+            .read()
+            .map(|r| (r.drained, r.draining.clone()))
+            .unwrap_or_default();
+        let live = self.tunnels.iter().map(|e| e.value().clone());
+        for entry in live.chain(draining) {
+            t.add(entry.totals());
         }
         t
     }
 }
 
+/// A client's removed tunnels. One is only held on to while conns are
+/// still draining through it; after that its counters are final, so
+/// they're folded into `drained` and the entry is dropped.
+#[derive(Debug, Default)]
+struct RetiredTunnels {
+    draining: Vec<Arc<TunnelEntry>>,
+    drained: ClientTotals,
+}
+
+impl RetiredTunnels {
+    /// Retire `tunnel`, folding away every retired tunnel whose conns
+    /// have all closed by now (`tunnel` itself included).
+    fn retire(&mut self, tunnel: Arc<TunnelEntry>) {
+        self.draining.push(tunnel);
+        let drained = &mut self.drained;
+        self.draining.retain(|t| {
+            if !t.conns.is_empty() {
+                return true;
+            }
+            drained.add(t.totals());
+            false
+        });
+    }
+}
+
 /// Live per-tunnel record (a remote declaration).
 #[derive(Debug)]
 pub struct TunnelEntry {
     pub id: u64,
-    pub client_id: u64,
+    /// Owning client; changes when a resumed session adopts the tunnel.
+    client_id: AtomicU64,
     pub direction: Direction,
     pub kind: RemoteKind,
     /// Human-readable spec produced by [`RemoteRequest`]'s `Display`,
     /// e.g. `R:5000=>socks` or `1080=>1.1.1.1:53/udp`.
     pub spec: String,
+    /// The name the client gave the tunnel, if any.
+    pub name: Option<String>,
+    /// [`RemoteRequest::range_spec`]: the whole port range this tunnel
+    /// is one port of, e.g. `5000-5010=>host:5000-5010/tcp`.
+    pub range: Option<String>,
     pub opened_at: SystemTime,
+    /// Where a reverse tunnel's listener actually bound; differs from
+    /// the declared address when the client asked for port 0. Unset
+    /// for forward tunnels.
+    bound_addr: OnceLock<SocketAddr>,
     /// Active conns, keyed by global conn id.
     pub conns: DashMap<u64, Arc<ConnEntry>>,
     /// Sum of `bytes_in` across every conn that has *closed* on this
@@ -114,6 +196,18 @@ pub struct TunnelEntry {
     /// Lifetime conn count, including closed ones. Useful for "how
     /// many connects have I served on this tunnel".
     total_conns: AtomicU64,
+    /// The share of the counters above that a `/both` tunnel's TCP
+    /// and UDP conns account for.
+    tcp: ProtocolCumulative,
+    udp: ProtocolCumulative,
+}
+
+/// Closed-conn totals of one protocol of a `/both` tunnel.
+#[derive(Debug, Default)]
+struct ProtocolCumulative {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    total_conns: AtomicU64,
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -136,7 +230,37 @@ pub struct ClientTotals {
     pub total_conns: u64,
 }
 
+impl ClientTotals {
+    fn add(&mut self, tot: TunnelTotals) {
+        self.active_in += tot.active_in;
+        self.active_out += tot.active_out;
+        self.cumulative_in += tot.cumulative_in;
+        self.cumulative_out += tot.cumulative_out;
+        self.active_conns += tot.active_conns;
+        self.total_conns += tot.total_conns;
+    }
+}
+
 impl TunnelEntry {
+    pub fn client_id(&self) -> u64 {
+        self.client_id.load(Ordering::Relaxed)
+    }
+
+    /// Whether `remote` declares this tunnel.
+    pub fn matches(&self, remote: &RemoteRequest) -> bool {
+        self.direction == remote.direction && self.kind == remote.kind
+    }
+
+    /// Record the address the tunnel's listener bound. Later calls are
+    /// ignored.
+    pub fn set_bound_addr(&self, addr: SocketAddr) {
+        let _ = self.bound_addr.set(addr);
+    }
+
+    pub fn bound_addr(&self) -> Option<SocketAddr> {
+        self.bound_addr.get().copied()
+    }
+
     pub fn totals(&self) -> TunnelTotals {
         let mut active_in = 0u64;
         let mut active_out = 0u64;
@@ -154,6 +278,30 @@ impl TunnelEntry {
             total_conns: self.total_conns.load(Ordering::Relaxed),
         }
     }
+
+    /// [`Self::totals`] of the conns a `/both` tunnel carried over
+    /// `protocol`.
+    pub fn protocol_totals(&self, protocol: Protocol) -> TunnelTotals {
+        let mut t = TunnelTotals::default();
+        for c in self.conns.iter().filter(|c| c.protocol == Some(protocol)) {
+            let (i, o) = c.value().counters.snapshot();
+            t.active_in += i;
+            t.active_out += o;
+            t.active_conns += 1;
+        }
+        let cumulative = self.cumulative(protocol);
+        t.cumulative_in = cumulative.bytes_in.load(Ordering::Relaxed);
+        t.cumulative_out = cumulative.bytes_out.load(Ordering::Relaxed);
+        t.total_conns = cumulative.total_conns.load(Ordering::Relaxed);
+        t
+    }
+
+    fn cumulative(&self, protocol: Protocol) -> &ProtocolCumulative {
+        match protocol {
+            Protocol::Tcp => &self.tcp,
+            Protocol::Udp => &self.udp,
+        }
+    }
 }
 
 /// Live per-conn record. Each conn's `counters` is shared directly
@@ -169,6 +317,11 @@ pub struct ConnEntry {
     /// "→8.8.8.8:53", …). Origin depends on the handler that registered
     /// the conn and is intentionally free-form for now.
     pub peer: Option<String>,
+    /// Username a SOCKS conn's client authenticated as, when its
+    /// listener requires credentials.
+    pub user: Option<String>,
+    /// Which half of a `/both` tunnel the conn belongs to.
+    pub protocol: Option<Protocol>,
     pub counters: Arc<TunnelCounters>,
 }
 
@@ -262,6 +415,9 @@ impl ServerState {
             remote,
             connected_at: SystemTime::now(),
             tunnels: DashMap::new(),
+            retired_tunnels: RwLock::default(),
+            peer: OnceLock::new(),
+            detached_at: OnceLock::new(),
             conn,
         });
         self.inner.clients.insert(id, entry.clone());
@@ -288,6 +444,13 @@ impl ServerState {
                 }
             }
         }
+        if let Ok(retired) = entry.retired_tunnels.read() {
+            for tunnel in retired.draining.iter() {
+                for c in tunnel.conns.iter() {
+                    self.inner.conns.remove(&c.value().id);
+                }
+            }
+        }
 
         let h = HistoryEntry {
             client_id: entry.id,
@@ -326,15 +489,20 @@ impl ServerState {
                 let id = self.inner.next_tunnel_id.fetch_add(1, Ordering::Relaxed) + 1;
                 let entry = Arc::new(TunnelEntry {
                     id,
-                    client_id: client.id,
+                    client_id: AtomicU64::new(client.id),
                     direction: req.direction,
                     kind: req.kind.clone(),
                     spec: req.to_string(),
+                    name: req.name.clone(),
+                    range: req.range_spec(),
                     opened_at: SystemTime::now(),
+                    bound_addr: OnceLock::new(),
                     conns: DashMap::new(),
                     cumulative_in: AtomicU64::new(0),
                     cumulative_out: AtomicU64::new(0),
                     total_conns: AtomicU64::new(0),
+                    tcp: ProtocolCumulative::default(),
+                    udp: ProtocolCumulative::default(),
                 });
                 client.tunnels.insert(id, entry.clone());
                 self.inner.tunnels.insert(id, entry.clone());
@@ -343,10 +511,43 @@ impl ServerState {
             .collect()
     }
 
+    /// Remove one of `client`'s tunnels (a live `RemoveTunnel`). New
+    /// conns naming its id are refused from here on; conns already
+    /// open keep running and stay visible under `/conns` until they
+    /// close. Returns `None` if the id isn't one of `client`'s tunnels.
+    pub fn remove_tunnel(&self, client: &ClientEntry, tunnel_id: u64) -> Option<Arc<TunnelEntry>> {
+        let (_, tunnel) = client.tunnels.remove(&tunnel_id)?;
+        self.inner.tunnels.remove(&tunnel_id);
+        if let Ok(mut retired) = client.retired_tunnels.write() {
+            retired.retire(tunnel.clone());
+        }
+        Some(tunnel)
+    }
+
+    /// Move `tunnel` from the held session of client `from` to the
+    /// client resuming it. Its id, counters and listener address carry
+    /// over.
+    pub fn adopt_tunnel(&self, from: &ClientEntry, to: &ClientEntry, tunnel: &Arc<TunnelEntry>) {
+        from.tunnels.remove(&tunnel.id);
+        tunnel.client_id.store(to.id, Ordering::Relaxed);
+        to.tunnels.insert(tunnel.id, tunnel.clone());
+    }
+
     pub fn tunnel(&self, id: u64) -> Option<Arc<TunnelEntry>> {
         self.inner.tunnels.get(&id).map(|e| e.value().clone())
     }
 
+    /// Every live tunnel with the given name. Names are unique per client
+    /// but not across clients, so this can find more than one.
+    pub fn tunnels_named(&self, name: &str) -> Vec<Arc<TunnelEntry>> {
+        self.inner
+            .tunnels
+            .iter()
+            .filter(|e| e.value().name.as_deref() == Some(name))
+            .map(|e| e.value().clone())
+            .collect()
+    }
+
     pub fn tunnels_snapshot(&self) -> Vec<Arc<TunnelEntry>> {
         self.inner
             .tunnels
@@ -363,20 +564,32 @@ impl ServerState {
     /// exposes [`ConnGuard::counters`] which is what the data-plane
     /// handler should pass to [`crate::common::tcp::tunnel_tcp_stream`]
     /// / udp / socks.
-    pub fn register_conn(&self, tunnel: &Arc<TunnelEntry>, peer: Option<String>) -> ConnGuard {
+    pub fn register_conn(
+        &self,
+        tunnel: &Arc<TunnelEntry>,
+        peer: Option<String>,
+        user: Option<String>,
+        protocol: Option<Protocol>,
+    ) -> ConnGuard {
         let id = self.inner.next_conn_id.fetch_add(1, Ordering::Relaxed) + 1;
         let counters = TunnelCounters::new();
         let entry = Arc::new(ConnEntry {
             id,
             tunnel_id: tunnel.id,
-            client_id: tunnel.client_id,
+            client_id: tunnel.client_id(),
             opened_at: SystemTime::now(),
             peer,
+            user,
+            protocol,
             counters: counters.clone(),
         });
         tunnel.conns.insert(id, entry.clone());
         self.inner.conns.insert(id, entry.clone());
         tunnel.total_conns.fetch_add(1, Ordering::Relaxed);
+        if let Some(protocol) = protocol {
+            let cumulative = tunnel.cumulative(protocol);
+            cumulative.total_conns.fetch_add(1, Ordering::Relaxed);
+        }
         ConnGuard {
             state: self.clone(),
             tunnel: tunnel.clone(),
@@ -399,6 +612,11 @@ impl ServerState {
         let (i, o) = conn.counters.snapshot();
         tunnel.cumulative_in.fetch_add(i, Ordering::Relaxed);
         tunnel.cumulative_out.fetch_add(o, Ordering::Relaxed);
+        if let Some(protocol) = conn.protocol {
+            let cumulative = tunnel.cumulative(protocol);
+            cumulative.bytes_in.fetch_add(i, Ordering::Relaxed);
+            cumulative.bytes_out.fetch_add(o, Ordering::Relaxed);
+        }
         tunnel.conns.remove(&conn.id);
         self.inner.conns.remove(&conn.id);
     }
@@ -465,15 +683,29 @@ impl Drop for ConnGuard {
 pub struct TunnelHandle {
     state: ServerState,
     tunnel: Arc<TunnelEntry>,
+    protocol: Option<Protocol>,
 }
 
 impl TunnelHandle {
     pub fn new(state: ServerState, tunnel: Arc<TunnelEntry>) -> Self {
-        Self { state, tunnel }
+        Self {
+            state,
+            tunnel,
+            protocol: None,
+        }
+    }
+
+    /// The same tunnel, for the conns of one half of a `/both` tunnel.
+    pub fn for_protocol(&self, protocol: Protocol) -> Self {
+        Self {
+            protocol: Some(protocol),
+            ..self.clone()
+        }
     }
 
-    pub fn open_conn(&self, peer: Option<String>) -> ConnGuard {
-        self.state.register_conn(&self.tunnel, peer)
+    pub fn open_conn(&self, peer: Option<String>, user: Option<String>) -> ConnGuard {
+        self.state
+            .register_conn(&self.tunnel, peer, user, self.protocol)
     }
 }
 
@@ -497,6 +729,8 @@ pub struct ClientSummaryDto {
     pub id: u64,
     pub remote: String,
     pub connected_at_ms: u64,
+    /// Set while the session is held for the client to resume.
+    pub detached_at_ms: Option<u64>,
     pub tunnel_count: usize,
     pub active_conn_count: u64,
     pub total_conns: u64,
@@ -508,6 +742,11 @@ pub struct ClientSummaryDto {
 pub struct ClientDetailDto {
     #[serde(flatten)]
     pub summary: ClientSummaryDto,
+    /// Wire protocol the client speaks; `None` until its hello is in.
+    pub protocol_version: Option<u32>,
+    pub client_version: Option<String>,
+    /// Capabilities negotiated for this session.
+    pub capabilities: Vec<&'static str>,
     pub tunnels: Vec<TunnelDto>,
 }
 
@@ -518,6 +757,11 @@ pub struct TunnelDto {
     pub direction: &'static str,
     pub kind: &'static str,
     pub spec: String,
+    pub name: Option<String>,
+    /// The port range the tunnel belongs to, if it came from one.
+    pub range: Option<String>,
+    /// Actual listener address of a reverse tunnel.
+    pub bound_addr: Option<String>,
     pub opened_at_ms: u64,
     pub active_conn_count: u64,
     pub total_conns: u64,
@@ -525,6 +769,22 @@ pub struct TunnelDto {
     pub active_bytes_out: u64,
     pub bytes_in: u64,
     pub bytes_out: u64,
+    /// A `/both` tunnel's counters, split by protocol.
+    pub protocols: Option<ProtocolsDto>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProtocolsDto {
+    pub tcp: ProtocolTotalsDto,
+    pub udp: ProtocolTotalsDto,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProtocolTotalsDto {
+    pub active_conn_count: u64,
+    pub total_conns: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
 }
 
 #[derive(Debug, Serialize)]
@@ -541,6 +801,9 @@ pub struct ConnDto {
     pub client_id: u64,
     pub opened_at_ms: u64,
     pub peer: Option<String>,
+    pub user: Option<String>,
+    /// `tcp` or `udp`, for a conn of a `/both` tunnel.
+    pub protocol: Option<String>,
     pub bytes_in: u64,
     pub bytes_out: u64,
 }
@@ -552,6 +815,7 @@ impl ClientSummaryDto {
             id: entry.id,
             remote: entry.remote.to_string(),
             connected_at_ms: unix_ms(entry.connected_at),
+            detached_at_ms: entry.detached_at.get().map(|t| unix_ms(*t)),
             tunnel_count: entry.tunnels.len(),
             active_conn_count: t.active_conns,
             total_conns: t.total_conns,
@@ -561,12 +825,33 @@ impl ClientSummaryDto {
     }
 }
 
+impl ClientDetailDto {
+    pub fn from_entry(entry: &ClientEntry) -> Self {
+        let peer = entry.peer();
+        let mut tunnels: Vec<TunnelDto> = entry
+            .tunnels
+            .iter()
+            .map(|t| TunnelDto::from_entry(t.value()))
+            .collect();
+        tunnels.sort_by_key(|t| t.id);
+        Self {
+            summary: ClientSummaryDto::from_entry(entry),
+            protocol_version: peer.map(|p| p.info.protocol_version),
+            client_version: peer.map(|p| p.info.software_version.clone()),
+            capabilities: peer
+                .map(|p| p.capabilities.iter().map(|c| c.name()).collect())
+                .unwrap_or_default(),
+            tunnels,
+        }
+    }
+}
+
 impl TunnelDto {
     pub fn from_entry(entry: &TunnelEntry) -> Self {
         let t = entry.totals();
         Self {
             id: entry.id,
-            client_id: entry.client_id,
+            client_id: entry.client_id(),
             direction: match entry.direction {
                 Direction::Forward => "forward",
                 Direction::Reverse => "reverse",
@@ -574,9 +859,15 @@ impl TunnelDto {
             kind: match entry.kind {
                 RemoteKind::Tcp { .. } => "tcp",
                 RemoteKind::Udp { .. } => "udp",
+                RemoteKind::Both { .. } => "both",
                 RemoteKind::Socks5 { .. } => "socks5",
+                RemoteKind::Http { .. } => "http",
+                RemoteKind::Unix { .. } => "unix",
             },
             spec: entry.spec.clone(),
+            name: entry.name.clone(),
+            range: entry.range.clone(),
+            bound_addr: entry.bound_addr().map(|a| a.to_string()),
             opened_at_ms: unix_ms(entry.opened_at),
             active_conn_count: t.active_conns,
             total_conns: t.total_conns,
@@ -584,6 +875,21 @@ impl TunnelDto {
             active_bytes_out: t.active_out,
             bytes_in: t.active_in + t.cumulative_in,
             bytes_out: t.active_out + t.cumulative_out,
+            protocols: matches!(entry.kind, RemoteKind::Both { .. }).then(|| ProtocolsDto {
+                tcp: ProtocolTotalsDto::from_totals(entry.protocol_totals(Protocol::Tcp)),
+                udp: ProtocolTotalsDto::from_totals(entry.protocol_totals(Protocol::Udp)),
+            }),
+        }
+    }
+}
+
+impl ProtocolTotalsDto {
+    fn from_totals(t: TunnelTotals) -> Self {
+        Self {
+            active_conn_count: t.active_conns,
+            total_conns: t.total_conns,
+            bytes_in: t.active_in + t.cumulative_in,
+            bytes_out: t.active_out + t.cumulative_out,
         }
     }
 }
@@ -597,6 +903,8 @@ impl ConnDto {
             client_id: entry.client_id,
             opened_at_ms: unix_ms(entry.opened_at),
             peer: entry.peer.clone(),
+            user: entry.user.clone(),
+            protocol: entry.protocol.map(|p| p.to_string()),
             bytes_in: i,
             bytes_out: o,
         }
@@ -647,4 +955,48 @@ mod tests {
         assert_eq!(snap.len(), 10);
         assert!(snap[0].client_id > snap.last().unwrap().client_id);
     }
+
+    fn tunnel(id: u64) -> Arc<TunnelEntry> {
+        let req: RemoteRequest = "8000:127.0.0.1:80".parse().unwrap();
+        Arc::new(TunnelEntry {
+            id,
+            client_id: AtomicU64::new(1),
+            direction: req.direction,
+            kind: req.kind.clone(),
+            spec: req.to_string(),
+            name: None,
+            range: None,
+            opened_at: SystemTime::now(),
+            bound_addr: OnceLock::new(),
+            conns: DashMap::new(),
+            cumulative_in: AtomicU64::new(0),
+            cumulative_out: AtomicU64::new(0),
+            total_conns: AtomicU64::new(0),
+            tcp: ProtocolCumulative::default(),
+            udp: ProtocolCumulative::default(),
+        })
+    }
+
+    #[test]
+    fn retired_tunnels_are_dropped_once_drained() {
+        let state = ServerState::new("127.0.0.1:0".parse().unwrap());
+        let mut retired = RetiredTunnels::default();
+
+        let busy = tunnel(1);
+        let conn = state.register_conn(&busy, None, None, None);
+        conn.counters().add_in(100);
+        retired.retire(busy);
+        retired.retire(tunnel(2));
+        assert_eq!(
+            retired.draining.len(),
+            1,
+            "an idle tunnel is folded at once"
+        );
+
+        drop(conn);
+        retired.retire(tunnel(3));
+        assert!(retired.draining.is_empty());
+        assert_eq!(retired.drained.cumulative_in, 100);
+        assert_eq!(retired.drained.total_conns, 1);
+    }
 }
@@ -18,7 +18,7 @@ use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use rusnel::common::remote::RemoteRequest;
+use rusnel::common::remote::{RemoteRequest, PROTOCOL_VERSION};
 use rusnel::common::tls::{ClientTlsConfig, ServerTlsConfig};
 use rusnel::ctl;
 use rusnel::{ClientConfig, ReconnectConfig, ServerConfig, ServerEndpoint};
@@ -82,9 +82,13 @@ async fn admin_api_lifecycle() {
         port: server_port,
         allow_reverse: true,
         allow_socks: true,
+        allow_unix: true,
+        socks_users: Default::default(),
         tls: ServerTlsConfig::Insecure,
         congestion: Default::default(),
         max_connections: None,
+        reconnect_grace: Duration::from_secs(30),
+        tcp_port: None,
         admin_socket: Some(socket_path.clone()),
     };
     let server_handle = tokio::spawn(async move {
@@ -110,12 +114,20 @@ async fn admin_api_lifecycle() {
         server: ServerEndpoint {
             addrs: vec![client_addr],
             host: client_addr.ip().to_string(),
+            port: client_addr.port(),
         },
         remotes: vec![remote.clone()],
         tls: ClientTlsConfig::Insecure,
         congestion: Default::default(),
         reconnect: ReconnectConfig::default(),
         proxy: None,
+        proxy_ca: None,
+        strict_tunnels: false,
+        resumable_conns: false,
+        socks_users: Default::default(),
+        socks_rules: Default::default(),
+        tcp_fallback: None,
+        stats: Default::default(),
     };
     let client_handle = tokio::spawn(async move {
         let _ = rusnel::client::run_async(client_config).await;
@@ -149,6 +161,8 @@ async fn admin_api_lifecycle() {
     let tunnels = await_tunnels(&socket_path, 1).await;
     assert_eq!(tunnels[0]["client_id"].as_u64().unwrap(), client_id);
     assert_eq!(tunnels[0]["spec"].as_str().unwrap(), remote_display);
+    // Only reverse tunnels have a server-side listener address.
+    assert!(tunnels[0]["bound_addr"].is_null());
     let tunnel_id = tunnels[0]["id"].as_u64().unwrap();
 
     // Tunnel-vs-conn distinction: a *single* tunnel can carry many
@@ -268,6 +282,31 @@ async fn admin_api_lifecycle() {
     let detail_tunnels = detail["tunnels"].as_array().unwrap();
     assert_eq!(detail_tunnels.len(), 1);
     assert_eq!(detail_tunnels[0]["spec"].as_str().unwrap(), remote_display);
+    // ... along with what was negotiated in the session hello.
+    assert_eq!(
+        detail["protocol_version"].as_u64().unwrap(),
+        u64::from(PROTOCOL_VERSION)
+    );
+    assert_eq!(
+        detail["client_version"].as_str().unwrap(),
+        env!("CARGO_PKG_VERSION")
+    );
+    let capabilities: Vec<&str> = detail["capabilities"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|c| c.as_str())
+        .collect();
+    assert_eq!(
+        capabilities,
+        [
+            "datagrams",
+            "optimistic-open",
+            "tunnel-control",
+            "session-resume",
+            "socks-bind"
+        ]
+    );
 
     // Unknown client → 404.
     let err = ctl::get(&socket_path, "/api/v1/clients/999999")
@@ -21,7 +21,7 @@ use rcgen::{
     generate_simple_self_signed, BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair,
     SanType,
 };
-use rusnel::common::quic::{create_client_endpoint, Congestion};
+use rusnel::common::quic::{create_client_endpoint, new_session_cache, Congestion};
 use rusnel::common::remote::RemoteRequest;
 use rusnel::common::tls::{cert_sha256, ClientTlsConfig, ServerTlsConfig};
 use rustls::pki_types::CertificateDer;
@@ -139,6 +139,7 @@ async fn fingerprint_pin_rejects_mismatched_server_cert() {
             },
             Congestion::default(),
             server_addr,
+            &new_session_cache(),
         )
         .unwrap();
         let connect_result = endpoint.connect(server_addr, "rusnel").unwrap().await;
@@ -404,6 +405,7 @@ async fn mtls_rejects_client_with_no_cert() {
             },
             Congestion::default(),
             server_addr,
+            &new_session_cache(),
         )
         .unwrap();
         let result = probe_auth_outcome(
@@ -457,6 +459,7 @@ async fn mtls_rejects_client_signed_by_wrong_ca() {
             },
             Congestion::default(),
             server_addr,
+            &new_session_cache(),
         )
         .unwrap();
         let result = probe_auth_outcome(
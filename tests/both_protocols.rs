@@ -0,0 +1,158 @@
+//! Integration tests for `/both` remotes: one tunnel carries TCP and UDP
+//! on the same port, forward and reverse, and the admin API counts each
+//! protocol separately.
+
+mod common;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use common::{get_available_port, start_tunnel, TEST_TIMEOUT};
+
+/// Echo TCP and UDP on the same port, prefixing each reply with the
+/// protocol that carried it.
+async fn spawn_dual_echo() -> u16 {
+    let (listener, socket) = loop {
+        let port = get_available_port();
+        let Ok(socket) = UdpSocket::bind(("127.0.0.1", port)).await else {
+            continue;
+        };
+        if let Ok(listener) = TcpListener::bind(("127.0.0.1", port)).await {
+            break (listener, socket);
+        }
+    };
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                while let Ok(n) = conn.read(&mut buf).await {
+                    if n == 0 || conn.write_all(b"tcp:").await.is_err() {
+                        return;
+                    }
+                    if conn.write_all(&buf[..n]).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+            let reply = [b"udp:", &buf[..n]].concat();
+            let _ = socket.send_to(&reply, from).await;
+        }
+    });
+    port
+}
+
+async fn tcp_round_trip(port: u16) -> Vec<u8> {
+    let mut conn = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    conn.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 8];
+    conn.read_exact(&mut buf).await.unwrap();
+    buf.to_vec()
+}
+
+/// Send until a reply arrives; the tunnel's UDP side may need a moment.
+async fn udp_round_trip(port: u16) -> Vec<u8> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 64];
+    loop {
+        socket.send_to(b"ping", ("127.0.0.1", port)).await.unwrap();
+        if let Ok(Ok(n)) = timeout(Duration::from_millis(300), socket.recv(&mut buf)).await {
+            return buf[..n].to_vec();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_both_forward_and_reverse() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let upstream = spawn_dual_echo().await;
+        let forward = get_available_port();
+        let reverse = get_available_port();
+
+        let remotes = vec![
+            RemoteRequest::from_str(&format!("127.0.0.1:{forward}:127.0.0.1:{upstream}/both"))
+                .unwrap(),
+            RemoteRequest::from_str(&format!(
+                "R:127.0.0.1:{reverse}:127.0.0.1:{upstream}/tcp+udp"
+            ))
+            .unwrap(),
+        ];
+        let _env = start_tunnel(server_port, true, remotes).await;
+
+        for port in [forward, reverse] {
+            assert_eq!(tcp_round_trip(port).await, b"tcp:ping");
+            assert_eq!(udp_round_trip(port).await, b"udp:ping");
+        }
+    })
+    .await
+    .expect("test_both_forward_and_reverse timed out");
+}
+
+/// The admin API shows a `/both` remote as one tunnel, with its TCP and
+/// UDP conns counted apart.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_both_admin_counts_each_protocol() {
+    use std::path::PathBuf;
+
+    use common::{client_config, init_crypto, server_config, STARTUP_DELAY};
+    use rusnel::ctl;
+
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let upstream = spawn_dual_echo().await;
+        let local = get_available_port();
+        let admin = PathBuf::from(format!("/tmp/rusnel-both-{}.sock", std::process::id()));
+
+        let mut sc = server_config(server_port, false);
+        sc.admin_socket = Some(admin.clone());
+        let server = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+        let remote =
+            RemoteRequest::from_str(&format!("127.0.0.1:{local}:127.0.0.1:{upstream}/both"))
+                .unwrap();
+        let cc = client_config(server_port, vec![remote]);
+        let client = tokio::spawn(async move {
+            let _ = rusnel::client::run_async(cc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+
+        assert_eq!(tcp_round_trip(local).await, b"tcp:ping");
+        assert_eq!(udp_round_trip(local).await, b"udp:ping");
+
+        let tunnels = ctl::get(&admin, "/api/v1/tunnels").await.unwrap();
+        let tunnels = tunnels.as_array().unwrap();
+        assert_eq!(tunnels.len(), 1);
+        let tunnel = &tunnels[0];
+        assert_eq!(tunnel["kind"], "both");
+        assert_eq!(tunnel["total_conns"], 2);
+        for protocol in ["tcp", "udp"] {
+            let counts = &tunnel["protocols"][protocol];
+            assert_eq!(counts["total_conns"], 1, "{protocol}: {tunnel}");
+            assert!(
+                counts["bytes_out"].as_u64().unwrap() >= 4,
+                "{protocol}: {tunnel}"
+            );
+        }
+
+        client.abort();
+        server.abort();
+        let _ = std::fs::remove_file(&admin);
+    })
+    .await
+    .expect("test_both_admin_counts_each_protocol timed out");
+}
@@ -17,7 +17,7 @@ use rusnel::common::remote::RemoteRequest;
 use rusnel::common::tls::{ClientTlsConfig, ServerTlsConfig};
 use rusnel::{ClientConfig, ReconnectConfig, ServerConfig, ServerEndpoint};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpListener, TcpStream};
 
 pub const TEST_TIMEOUT: Duration = Duration::from_secs(20);
 pub const STARTUP_DELAY: Duration = Duration::from_millis(500);
@@ -99,9 +99,13 @@ pub fn server_config_with_tls(
         port,
         allow_reverse,
         allow_socks: true,
+        allow_unix: true,
+        socks_users: Default::default(),
         tls,
         congestion: Default::default(),
         max_connections: None,
+        reconnect_grace: Duration::from_secs(30),
+        tcp_port: None,
         admin_socket: None,
     }
 }
@@ -120,12 +124,20 @@ pub fn client_config_with_tls(
         server: ServerEndpoint {
             addrs: vec![addr],
             host: addr.ip().to_string(),
+            port: addr.port(),
         },
         remotes,
         tls,
         congestion: Default::default(),
         reconnect: ReconnectConfig::default(),
         proxy: None,
+        proxy_ca: None,
+        strict_tunnels: false,
+        resumable_conns: false,
+        socks_users: Default::default(),
+        socks_rules: Default::default(),
+        tcp_fallback: None,
+        stats: Default::default(),
     }
 }
 
@@ -183,6 +195,21 @@ pub async fn start_tunnel_with_flags(
     }
 }
 
+/// Spawn a tiny TCP echo server and return its address.
+pub async fn spawn_tcp_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = conn.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+    addr
+}
+
 /// Perform a SOCKS5 no-auth handshake and CONNECT to an IPv4 target.
 /// Returns the open stream (already past the SOCKS reply) ready for app data.
 pub async fn socks5_connect_ipv4(
@@ -328,3 +355,68 @@ pub async fn socks5_connect_domain(
 
     conn
 }
+
+/// Send a SOCKS4 CONNECT to an IPv4 target, or a SOCKS4a one to `domain`
+/// if given (in which case `target_ip` is ignored). Returns the open stream
+/// (already past the SOCKS reply) ready for app data.
+pub async fn socks4_connect(
+    socks_addr: &str,
+    target_ip: [u8; 4],
+    target_port: u16,
+    domain: Option<&str>,
+) -> TcpStream {
+    let mut conn = TcpStream::connect(socks_addr).await.unwrap();
+
+    let mut req = vec![0x04, 0x01]; // version, CONNECT
+    req.extend_from_slice(&target_port.to_be_bytes());
+    match domain {
+        // 0.0.0.1 marks a SOCKS4a request.
+        Some(_) => req.extend_from_slice(&[0, 0, 0, 1]),
+        None => req.extend_from_slice(&target_ip),
+    }
+    req.extend_from_slice(b"rusnel\0"); // USERID
+    if let Some(domain) = domain {
+        req.extend_from_slice(domain.as_bytes());
+        req.push(0);
+    }
+    conn.write_all(&req).await.unwrap();
+
+    let mut reply = [0u8; 8];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0], 0x00, "SOCKS4 reply version");
+    assert_eq!(reply[1], 0x5A, "SOCKS4 reply status (0x5A = granted)");
+
+    conn
+}
+
+/// Perform a SOCKS5 no-auth handshake and BIND, expecting a conn from
+/// `peer_ip`. Returns the stream (past the first reply) and the address
+/// the far side is listening on; the second reply arrives once someone
+/// connects there.
+pub async fn socks5_bind(socks_addr: &str, peer_ip: [u8; 4]) -> (TcpStream, SocketAddr) {
+    let mut conn = TcpStream::connect(socks_addr).await.unwrap();
+
+    conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut greet_resp = [0u8; 2];
+    conn.read_exact(&mut greet_resp).await.unwrap();
+    assert_eq!(greet_resp, [0x05, 0x00]);
+
+    let mut req = vec![0x05, 0x02, 0x00, 0x01];
+    req.extend_from_slice(&peer_ip);
+    req.extend_from_slice(&[0, 0]);
+    conn.write_all(&req).await.unwrap();
+
+    let bound = socks5_read_reply_ipv4(&mut conn).await;
+    (conn, bound)
+}
+
+/// Read a successful SOCKS5 reply with an IPv4 BND.ADDR and return it.
+pub async fn socks5_read_reply_ipv4(conn: &mut TcpStream) -> SocketAddr {
+    let mut reply = [0u8; 10];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0], 0x05, "SOCKS reply version");
+    assert_eq!(reply[1], 0x00, "SOCKS reply status (0x00 = success)");
+    assert_eq!(reply[3], 0x01, "expected IPv4 ATYP");
+    let ip = Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+    SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([reply[8], reply[9]]))
+}
@@ -125,6 +125,27 @@ fn cli_flag_overrides_config_file() {
     );
 }
 
+#[test]
+fn duplicate_tunnel_names_in_config_file_rejected() {
+    let mut tmp = tempfile_in_target("rusnel-names.toml");
+    writeln!(
+        tmp.file,
+        "[client]\nserver = \"127.0.0.1:1\"\nremotes = [\n  {{ name = \"web\", remote = \"8080:web:80\" }},\n  \"web=8081:web:81\",\n]\n"
+    )
+    .unwrap();
+    let out = Command::new(rusnel_bin())
+        .args(["client", "--config"])
+        .arg(&tmp.path)
+        .output()
+        .expect("spawn rusnel");
+    assert!(!out.status.success(), "should have failed");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("tunnel name `web` is used by more than one remote"),
+        "expected duplicate-name error in stderr, got: {stderr}"
+    );
+}
+
 /// Spawn `rusnel <args>`, wait up to ~1.5 s for the "Listening on …"
 /// log line on stderr, then kill the process. Returns the captured
 /// stderr.
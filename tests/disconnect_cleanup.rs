@@ -15,7 +15,7 @@ use std::time::Duration;
 
 use common::{get_available_port, init_crypto, server_config, STARTUP_DELAY};
 use quinn::VarInt;
-use rusnel::common::quic::{create_client_endpoint, Congestion};
+use rusnel::common::quic::{create_client_endpoint, new_session_cache, Congestion};
 use rusnel::common::remote::{RemoteRequest, SessionHello};
 use rusnel::common::tls::ClientTlsConfig;
 use rusnel::common::tunnel::client_send_session_hello;
@@ -51,7 +51,7 @@ where
 /// Reverse TCP tunnel cleanup: the server binds the listener on
 /// `reverse_listen_port`. After the client gracefully closes its QUIC
 /// connection, the server's `handle_client_connection` must abort the
-/// listener task (via the JoinSet shutdown) so the port becomes immediately
+/// listener task (by dropping its handler) so the port becomes immediately
 /// rebindable. Without the fix, the listener task would loop forever and the
 /// port would stay bound until the server process exited.
 ///
@@ -82,9 +82,13 @@ async fn test_server_releases_reverse_listener_on_client_disconnect() {
         tokio::time::sleep(STARTUP_DELAY).await;
 
         let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
-        let endpoint =
-            create_client_endpoint(&ClientTlsConfig::Insecure, Congestion::Cubic, server_addr)
-                .unwrap();
+        let endpoint = create_client_endpoint(
+            &ClientTlsConfig::Insecure,
+            Congestion::Cubic,
+            server_addr,
+            &new_session_cache(),
+        )
+        .unwrap();
         let connection = endpoint
             .connect(server_addr, "127.0.0.1")
             .unwrap()
@@ -105,9 +109,7 @@ async fn test_server_releases_reverse_listener_on_client_disconnect() {
         // server spawns the reverse listener on receipt of the
         // hello reply.
         let (mut send, mut recv) = connection.open_bi().await.unwrap();
-        let hello = SessionHello {
-            remotes: vec![remote],
-        };
+        let hello = SessionHello::new(vec![remote]);
         let _ = client_send_session_hello(&hello, &mut send, &mut recv)
             .await
             .unwrap();
@@ -122,8 +124,9 @@ async fn test_server_releases_reverse_listener_on_client_disconnect() {
         );
 
         // Graceful close: the server sees ApplicationClosed and drops out
-        // of its accept loop immediately, then the JoinSet shutdown aborts
-        // the reverse listener task.
+        // of its accept loop immediately. A graceful close isn't held for
+        // resumption, so the reverse handler is dropped, aborting the
+        // listener task.
         connection.close(VarInt::from_u32(0), b"test done");
         endpoint.wait_idle().await;
 
@@ -134,7 +137,7 @@ async fn test_server_releases_reverse_listener_on_client_disconnect() {
         assert!(
             freed,
             "server never released the reverse listener on port {reverse_listen_port} \
-             after client disconnect — JoinSet abort regression"
+             after client disconnect — reverse handler abort regression"
         );
 
         server_handle.abort();
@@ -146,7 +149,7 @@ async fn test_server_releases_reverse_listener_on_client_disconnect() {
 /// Same shape as the reverse-TCP cleanup test, but for **reverse UDP**.
 /// The server binds a `UdpSocket` on the reverse-listen port and pumps
 /// datagrams back to the client over a per-source QUIC stream. The
-/// JoinSet that owns this UDP pump must be aborted on client
+/// handler that owns this UDP pump must be aborted on client
 /// disconnect so the UDP port is released.
 #[tokio::test]
 async fn test_server_releases_reverse_udp_socket_on_client_disconnect() {
@@ -163,9 +166,13 @@ async fn test_server_releases_reverse_udp_socket_on_client_disconnect() {
         tokio::time::sleep(STARTUP_DELAY).await;
 
         let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
-        let endpoint =
-            create_client_endpoint(&ClientTlsConfig::Insecure, Congestion::Cubic, server_addr)
-                .unwrap();
+        let endpoint = create_client_endpoint(
+            &ClientTlsConfig::Insecure,
+            Congestion::Cubic,
+            server_addr,
+            &new_session_cache(),
+        )
+        .unwrap();
         let connection = endpoint
             .connect(server_addr, "127.0.0.1")
             .unwrap()
@@ -177,9 +184,7 @@ async fn test_server_releases_reverse_udp_socket_on_client_disconnect() {
         ))
         .unwrap();
         let (mut send, mut recv) = connection.open_bi().await.unwrap();
-        let hello = SessionHello {
-            remotes: vec![remote],
-        };
+        let hello = SessionHello::new(vec![remote]);
         let _ = client_send_session_hello(&hello, &mut send, &mut recv)
             .await
             .unwrap();
@@ -214,7 +219,7 @@ async fn test_server_releases_reverse_udp_socket_on_client_disconnect() {
 
 /// Reverse SOCKS cleanup: server binds a `TcpListener` on the
 /// reverse-listen port and runs the SOCKS5 negotiation loop. Same
-/// JoinSet-lifecycle invariant as the plain reverse-TCP test, but the
+/// handler-lifecycle invariant as the plain reverse-TCP test, but the
 /// listener task lives in a different module (`socks.rs`) and could
 /// reasonably regress independently.
 #[tokio::test]
@@ -231,9 +236,13 @@ async fn test_server_releases_reverse_socks_listener_on_client_disconnect() {
         tokio::time::sleep(STARTUP_DELAY).await;
 
         let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
-        let endpoint =
-            create_client_endpoint(&ClientTlsConfig::Insecure, Congestion::Cubic, server_addr)
-                .unwrap();
+        let endpoint = create_client_endpoint(
+            &ClientTlsConfig::Insecure,
+            Congestion::Cubic,
+            server_addr,
+            &new_session_cache(),
+        )
+        .unwrap();
         let connection = endpoint
             .connect(server_addr, "127.0.0.1")
             .unwrap()
@@ -243,9 +252,7 @@ async fn test_server_releases_reverse_socks_listener_on_client_disconnect() {
         let remote =
             RemoteRequest::from_str(&format!("R:127.0.0.1:{reverse_listen_port}:socks")).unwrap();
         let (mut send, mut recv) = connection.open_bi().await.unwrap();
-        let hello = SessionHello {
-            remotes: vec![remote],
-        };
+        let hello = SessionHello::new(vec![remote]);
         let _ = client_send_session_hello(&hello, &mut send, &mut recv)
             .await
             .unwrap();
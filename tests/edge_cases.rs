@@ -66,10 +66,8 @@ async fn test_reverse_rejected_when_not_allowed() {
 }
 
 /// Forward `socks` requires `--allow-socks`. Without it, the server
-/// rejects the session at hello time (the post-0.8 wire protocol
-/// validates every declared remote in one batch up front), so the
-/// client never gets past reconnect — its local SOCKS listener
-/// never binds.
+/// rejects the tunnel at hello time, so the client leaves it out of
+/// the session and its local SOCKS listener never binds.
 #[tokio::test]
 async fn test_forward_socks_rejected_when_not_allowed() {
     timeout(TEST_TIMEOUT, async {
@@ -78,14 +76,12 @@ async fn test_forward_socks_rejected_when_not_allowed() {
 
         let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
 
-        // allow_reverse=false, allow_socks=false — the hello must be
-        // rejected, leaving the client looping on reconnect with no
-        // local SOCKS listener bound.
+        // allow_reverse=false, allow_socks=false — the tunnel must be
+        // rejected, leaving no local SOCKS listener bound.
         let _env = start_tunnel_with_flags(server_port, false, false, vec![remote]).await;
 
-        // Give the client a chance to settle into the reject loop, then
-        // verify the local SOCKS port is still unbindable-from-our-side
-        // (i.e. nothing is listening on it).
+        // Give the client a chance to process the hello response, then
+        // verify nothing is listening on the local SOCKS port.
         sleep(Duration::from_millis(300)).await;
         let connect_res = timeout(
             Duration::from_secs(2),
@@ -284,7 +280,7 @@ async fn test_tcp_forward_small_immediate_write_loses_data() {
     .expect("test_tcp_forward_small_immediate_write_loses_data timed out");
 }
 
-/// SOCKS5 with an unsupported command (BIND = 0x02) must elicit a reply
+/// SOCKS5 with an unsupported command (0x09, undefined) must elicit a reply
 /// with status 0x07 ("command not supported"), and the connection should be
 /// closed without crashing the SOCKS listener.
 #[tokio::test]
@@ -306,8 +302,8 @@ async fn test_socks5_unsupported_command() {
         conn.read_exact(&mut greet).await.unwrap();
         assert_eq!(greet, [0x05, 0x00]);
 
-        // BIND request to 127.0.0.1:9999.
-        let mut req = vec![0x05, 0x02, 0x00, 0x01, 127, 0, 0, 1];
+        // Command 0x09 to 127.0.0.1:9999.
+        let mut req = vec![0x05, 0x09, 0x00, 0x01, 127, 0, 0, 1];
         req.extend_from_slice(&9999u16.to_be_bytes());
         conn.write_all(&req).await.unwrap();
 
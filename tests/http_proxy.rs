@@ -0,0 +1,192 @@
+//! Integration tests for `http` proxy remotes: CONNECT through a forward
+//! listener, a plain absolute-URI request through a reverse one, Basic
+//! auth from the remote's own `user:pass@`, and the server's
+//! `--allow-socks` gate.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use common::{
+    get_available_port, spawn_tcp_echo, start_tunnel, start_tunnel_with_flags, TEST_TIMEOUT,
+};
+
+/// Read an HTTP head, through its blank line, a byte at a time.
+async fn read_head(conn: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(conn.read_u8().await.unwrap());
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// Send `CONNECT target` to the proxy at `proxy_port` and return its
+/// response head along with the stream.
+async fn http_connect(proxy_port: u16, target: SocketAddr, extra: &str) -> (String, TcpStream) {
+    let mut conn = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    let request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n{extra}\r\n");
+    conn.write_all(request.as_bytes()).await.unwrap();
+    let head = read_head(&mut conn).await;
+    (head, conn)
+}
+
+async fn check_connect_echo(mut conn: TcpStream) {
+    conn.write_all(b"hello through http proxy").await.unwrap();
+    let mut buf = [0u8; 24];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello through http proxy");
+}
+
+#[tokio::test]
+async fn test_http_connect_forward() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let proxy_port = get_available_port();
+        let echo = spawn_tcp_echo().await;
+
+        let remote = RemoteRequest::from_str(&format!("{proxy_port}:http")).unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let (head, conn) = http_connect(proxy_port, echo, "").await;
+        assert!(
+            head.starts_with("HTTP/1.1 200 "),
+            "unexpected reply: {head}"
+        );
+        check_connect_echo(conn).await;
+    })
+    .await
+    .expect("test_http_connect_forward timed out");
+}
+
+#[tokio::test]
+async fn test_http_plain_request_reverse() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let proxy_port = get_available_port();
+
+        // A one-shot origin server that hands back the head it received.
+        let origin = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap();
+        let (head_tx, head_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut conn, _) = origin.accept().await.unwrap();
+            let head = read_head(&mut conn).await;
+            conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            let _ = head_tx.send(head);
+        });
+
+        let remote = RemoteRequest::from_str(&format!("R:{proxy_port}:http")).unwrap();
+        let _env = start_tunnel(server_port, true, vec![remote]).await;
+
+        let mut conn = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+        let request = format!(
+            "GET http://{origin_addr}/path?x=1 HTTP/1.1\r\n\
+             Host: {origin_addr}\r\n\
+             Proxy-Connection: keep-alive\r\n\
+             User-Agent: rusnel-test\r\n\r\n"
+        );
+        conn.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("ok"), "{response}");
+
+        let head = head_rx.await.unwrap();
+        assert_eq!(
+            head,
+            format!(
+                "GET /path?x=1 HTTP/1.1\r\nHost: {origin_addr}\r\n\
+                 User-Agent: rusnel-test\r\nConnection: close\r\n\r\n"
+            )
+        );
+    })
+    .await
+    .expect("test_http_plain_request_reverse timed out");
+}
+
+#[tokio::test]
+async fn test_http_basic_auth() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let proxy_port = get_available_port();
+        let echo = spawn_tcp_echo().await;
+
+        let remote = RemoteRequest::from_str(&format!("alice:s3cret@{proxy_port}:http")).unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let (head, _) = http_connect(proxy_port, echo, "").await;
+        assert!(
+            head.starts_with("HTTP/1.1 407 "),
+            "unexpected reply: {head}"
+        );
+        assert!(
+            head.contains("Proxy-Authenticate: Basic"),
+            "unexpected reply: {head}"
+        );
+
+        let wrong = BASE64_STANDARD.encode("alice:guess");
+        let (head, _) = http_connect(
+            proxy_port,
+            echo,
+            &format!("Proxy-Authorization: Basic {wrong}\r\n"),
+        )
+        .await;
+        assert!(
+            head.starts_with("HTTP/1.1 407 "),
+            "unexpected reply: {head}"
+        );
+
+        let right = BASE64_STANDARD.encode("alice:s3cret");
+        let (head, conn) = http_connect(
+            proxy_port,
+            echo,
+            &format!("Proxy-Authorization: Basic {right}\r\n"),
+        )
+        .await;
+        assert!(
+            head.starts_with("HTTP/1.1 200 "),
+            "unexpected reply: {head}"
+        );
+        check_connect_echo(conn).await;
+    })
+    .await
+    .expect("test_http_basic_auth timed out");
+}
+
+/// Like `socks`, an `http` remote needs the server's `--allow-socks`;
+/// without it the listener never binds.
+#[tokio::test]
+async fn test_http_rejected_when_socks_not_allowed() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let proxy_port = get_available_port();
+
+        let remote = RemoteRequest::from_str(&format!("{proxy_port}:http")).unwrap();
+        let _env = start_tunnel_with_flags(server_port, false, false, vec![remote]).await;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let connect_res = timeout(
+            Duration::from_secs(2),
+            TcpStream::connect(("127.0.0.1", proxy_port)),
+        )
+        .await;
+        match connect_res {
+            Ok(Ok(_)) => panic!("http listener on port {proxy_port} should never bind"),
+            Ok(Err(_)) => { /* expected: connection refused */ }
+            Err(_) => panic!("connect attempt unexpectedly hung"),
+        }
+    })
+    .await
+    .expect("test_http_rejected_when_socks_not_allowed timed out");
+}
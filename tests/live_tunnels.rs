@@ -0,0 +1,176 @@
+//! Adding and removing tunnels on a live session.
+//!
+//! The client is driven through `client::run_async_with_updates`: each
+//! new remote set pushed into the watch channel is applied as
+//! `AddTunnel` / `RemoveTunnel` on the existing QUIC connection. Every
+//! test keeps one conn open across the change and checks it still
+//! works afterwards, which proves the client didn't reconnect.
+
+mod common;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use common::{
+    client_config, get_available_port, init_crypto, server_config, spawn_tcp_echo, STARTUP_DELAY,
+    TEST_TIMEOUT,
+};
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+/// Time allowed for a pushed change to reach the server and back.
+const APPLY_DELAY: Duration = Duration::from_millis(500);
+
+struct LiveEnv {
+    server: JoinHandle<()>,
+    client: JoinHandle<()>,
+    updates: watch::Sender<Vec<RemoteRequest>>,
+}
+
+impl Drop for LiveEnv {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.client.abort();
+    }
+}
+
+async fn start_live(server_port: u16, allow_reverse: bool, remotes: Vec<RemoteRequest>) -> LiveEnv {
+    init_crypto();
+    let sc = server_config(server_port, allow_reverse);
+    let server = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    sleep(STARTUP_DELAY).await;
+
+    let (updates, rx) = watch::channel(remotes.clone());
+    let cc = client_config(server_port, remotes);
+    let client = tokio::spawn(async move {
+        let _ = rusnel::client::run_async_with_updates(cc, rx).await;
+    });
+    sleep(STARTUP_DELAY).await;
+
+    LiveEnv {
+        server,
+        client,
+        updates,
+    }
+}
+
+async fn assert_echo(conn: &mut TcpStream, payload: &[u8]) {
+    conn.write_all(payload).await.unwrap();
+    let mut buf = vec![0u8; payload.len()];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, payload);
+}
+
+fn remote(spec: &str) -> RemoteRequest {
+    RemoteRequest::from_str(spec).unwrap()
+}
+
+#[tokio::test]
+async fn test_add_and_remove_forward_tunnel_live() {
+    timeout(TEST_TIMEOUT, async {
+        let echo_port = spawn_tcp_echo().await.port();
+        let server_port = get_available_port();
+        let first_port = get_available_port();
+        let second_port = get_available_port();
+        let first = remote(&format!("127.0.0.1:{first_port}:127.0.0.1:{echo_port}"));
+        let second = remote(&format!("127.0.0.1:{second_port}:127.0.0.1:{echo_port}"));
+
+        let env = start_live(server_port, false, vec![first.clone()]).await;
+        let mut kept = TcpStream::connect(("127.0.0.1", first_port)).await.unwrap();
+        assert_echo(&mut kept, b"before").await;
+        assert!(TcpStream::connect(("127.0.0.1", second_port))
+            .await
+            .is_err());
+
+        env.updates
+            .send_replace(vec![first.clone(), second.clone()]);
+        sleep(APPLY_DELAY).await;
+        let mut added = TcpStream::connect(("127.0.0.1", second_port))
+            .await
+            .unwrap();
+        assert_echo(&mut added, b"added").await;
+        assert_echo(&mut kept, b"still up").await;
+
+        // Removing a tunnel closes its listener but lets the conn it
+        // already accepted finish.
+        env.updates.send_replace(vec![first]);
+        sleep(APPLY_DELAY).await;
+        assert!(TcpStream::connect(("127.0.0.1", second_port))
+            .await
+            .is_err());
+        assert_echo(&mut added, b"draining").await;
+        assert_echo(&mut kept, b"after").await;
+    })
+    .await
+    .expect("test_add_and_remove_forward_tunnel_live timed out");
+}
+
+#[tokio::test]
+async fn test_add_and_remove_reverse_tunnel_live() {
+    timeout(TEST_TIMEOUT, async {
+        let echo_port = spawn_tcp_echo().await.port();
+        let server_port = get_available_port();
+        let forward_port = get_available_port();
+        let reverse_port = get_available_port();
+        let forward = remote(&format!("127.0.0.1:{forward_port}:127.0.0.1:{echo_port}"));
+        let reverse = remote(&format!("R:127.0.0.1:{reverse_port}:127.0.0.1:{echo_port}"));
+
+        let env = start_live(server_port, true, vec![forward.clone()]).await;
+        let mut kept = TcpStream::connect(("127.0.0.1", forward_port))
+            .await
+            .unwrap();
+        assert_echo(&mut kept, b"before").await;
+
+        env.updates
+            .send_replace(vec![forward.clone(), reverse.clone()]);
+        sleep(APPLY_DELAY).await;
+        let mut via_server = TcpStream::connect(("127.0.0.1", reverse_port))
+            .await
+            .unwrap();
+        assert_echo(&mut via_server, b"reverse").await;
+
+        env.updates.send_replace(vec![forward]);
+        sleep(APPLY_DELAY).await;
+        assert!(TcpStream::connect(("127.0.0.1", reverse_port))
+            .await
+            .is_err());
+        assert_echo(&mut kept, b"after").await;
+    })
+    .await
+    .expect("test_add_and_remove_reverse_tunnel_live timed out");
+}
+
+/// A change the server's policy refuses is skipped; the session and
+/// the other tunnels carry on.
+#[tokio::test]
+async fn test_live_add_rejected_by_policy_keeps_session() {
+    timeout(TEST_TIMEOUT, async {
+        let echo_port = spawn_tcp_echo().await.port();
+        let server_port = get_available_port();
+        let forward_port = get_available_port();
+        let reverse_port = get_available_port();
+        let forward = remote(&format!("127.0.0.1:{forward_port}:127.0.0.1:{echo_port}"));
+        let reverse = remote(&format!("R:127.0.0.1:{reverse_port}:127.0.0.1:{echo_port}"));
+
+        let env = start_live(server_port, false, vec![forward.clone()]).await;
+        let mut kept = TcpStream::connect(("127.0.0.1", forward_port))
+            .await
+            .unwrap();
+        assert_echo(&mut kept, b"before").await;
+
+        env.updates.send_replace(vec![forward, reverse]);
+        sleep(APPLY_DELAY).await;
+        assert!(TcpStream::connect(("127.0.0.1", reverse_port))
+            .await
+            .is_err());
+        assert_echo(&mut kept, b"after").await;
+    })
+    .await
+    .expect("test_live_add_rejected_by_policy_keeps_session timed out");
+}
@@ -0,0 +1,225 @@
+//! Integration test for `--proxy masque://...`: QUIC carried through an
+//! HTTP/3 MASQUE proxy with `CONNECT-UDP` (RFC 9298).
+//!
+//! The proxy here is a minimal in-process stand-in: an HTTP/3 server
+//! that accepts one extended `CONNECT` per tunnel, answers 200 and
+//! relays HTTP datagrams to the requested UDP target and back. Its
+//! self-signed certificate is handed to the client as its `proxy_ca`,
+//! so the test runs offline.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use quinn::crypto::rustls::QuicServerConfig;
+use rusnel::common::masque::{get_varint, put_varint};
+use rusnel::common::proxy::ProxyConfig;
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+mod common;
+use common::{
+    client_config, get_available_port, get_available_udp_port, init_crypto, server_config,
+    spawn_tcp_echo, STARTUP_DELAY, TEST_TIMEOUT,
+};
+
+type Targets = Arc<Mutex<Vec<String>>>;
+
+/// The stand-in's certificate on disk, removed again on drop.
+struct CaFile(PathBuf);
+
+impl Drop for CaFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Start the MASQUE stand-in, requiring `Proxy-Authorization: Basic`
+/// with `credentials` (already base64-encoded). Returns its address,
+/// the paths it was asked to `CONNECT-UDP` to and its certificate for
+/// the client to trust.
+async fn spawn_masque_proxy(credentials: &'static str) -> (SocketAddr, Targets, CaFile) {
+    let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+    let ca = CaFile(
+        PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("rusnel-it-masque-{}.pem", std::process::id())),
+    );
+    std::fs::create_dir_all(env!("CARGO_TARGET_TMPDIR")).expect("create CARGO_TARGET_TMPDIR");
+    std::fs::write(&ca.0, cert.cert.pem()).unwrap();
+
+    let mut tls = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert.cert.der().clone()],
+            rustls::pki_types::PrivatePkcs8KeyDer::from(cert.signing_key.serialize_der()).into(),
+        )
+        .unwrap();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let mut config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls).unwrap()));
+    let mut transport = quinn::TransportConfig::default();
+    transport.initial_mtu(1400);
+    config.transport_config(Arc::new(transport));
+    let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = endpoint.local_addr().unwrap();
+
+    let targets = Targets::default();
+    let seen = targets.clone();
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let Ok(conn) = incoming.await else { return };
+                if let Err(e) = serve_connection(conn, credentials, seen).await {
+                    debug!(error = %e, "MASQUE stand-in connection ended");
+                }
+            });
+        }
+    });
+    (addr, targets, ca)
+}
+
+async fn serve_connection(
+    conn: quinn::Connection,
+    credentials: &str,
+    seen: Targets,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut h3 = h3::server::builder()
+        .enable_extended_connect(true)
+        .enable_datagram(true)
+        .build::<_, Bytes>(h3_quinn::Connection::new(conn.clone()))
+        .await?;
+    while let Some(resolver) = h3.accept().await? {
+        let (request, mut stream) = resolver.resolve_request().await?;
+        assert_eq!(request.method(), http::Method::CONNECT);
+        assert_eq!(
+            request.extensions().get::<h3::ext::Protocol>(),
+            Some(&h3::ext::Protocol::CONNECT_UDP)
+        );
+        let path = request.uri().path().to_string();
+        seen.lock().await.push(path.clone());
+        if request
+            .headers()
+            .get("proxy-authorization")
+            .map(|v| v.as_bytes())
+            != Some(format!("Basic {credentials}").as_bytes())
+        {
+            stream
+                .send_response(http::Response::builder().status(407).body(())?)
+                .await?;
+            continue;
+        }
+        // `/.well-known/masque/udp/{host}/{port}/`
+        let mut segments = path.trim_end_matches('/').rsplit('/');
+        let port: u16 = segments.next().unwrap_or_default().parse()?;
+        let host: IpAddr = segments
+            .next()
+            .unwrap_or_default()
+            .replace("%3A", ":")
+            .parse()?;
+        stream
+            .send_response(
+                http::Response::builder()
+                    .status(200)
+                    .header("capsule-protocol", "?1")
+                    .body(())?,
+            )
+            .await?;
+
+        let quarter_id = stream.id().into_inner() / 4;
+        let udp = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        udp.connect(SocketAddr::new(host, port)).await?;
+        let conn = conn.clone();
+        tokio::spawn(async move {
+            // Holding the stream keeps the tunnel open.
+            let _stream = stream;
+            let to_target = async {
+                while let Ok(mut datagram) = conn.read_datagram().await {
+                    if get_varint(&mut datagram) != Some(quarter_id)
+                        || get_varint(&mut datagram) != Some(0)
+                    {
+                        continue;
+                    }
+                    let _ = udp.send(&datagram).await;
+                }
+            };
+            let from_target = async {
+                let mut buf = vec![0u8; 65535];
+                while let Ok(n) = udp.recv(&mut buf).await {
+                    let mut datagram = BytesMut::new();
+                    put_varint(&mut datagram, quarter_id);
+                    put_varint(&mut datagram, 0);
+                    datagram.extend_from_slice(&buf[..n]);
+                    let _ = conn.send_datagram(datagram.freeze());
+                }
+            };
+            tokio::select! {
+                _ = to_target => {}
+                _ = from_target => {}
+            }
+        });
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_forward_through_masque_proxy() {
+    init_crypto();
+
+    let server_port = get_available_udp_port();
+    let sc = server_config(server_port, false);
+    let server_handle = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    // base64("alice:s3cret")
+    let (proxy_addr, targets, ca) = spawn_masque_proxy("YWxpY2U6czNjcmV0").await;
+
+    let echo_addr = spawn_tcp_echo().await;
+    let local_port = get_available_port();
+    let spec = format!("{local_port}:{}:{}/tcp", echo_addr.ip(), echo_addr.port());
+    let mut cc = client_config(server_port, vec![RemoteRequest::from_str(&spec).unwrap()]);
+    cc.proxy = Some(ProxyConfig::from_str(&format!("masque://alice:s3cret@{proxy_addr}")).unwrap());
+    cc.proxy_ca = Some(ca.0.clone());
+    let client_handle = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+
+    let result = tokio::time::timeout(TEST_TIMEOUT, async {
+        let mut conn = loop {
+            if let Ok(conn) = TcpStream::connect(("127.0.0.1", local_port)).await {
+                break conn;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+        conn.write_all(b"hello via masque proxy").await.unwrap();
+        let mut got = vec![0u8; 22];
+        conn.read_exact(&mut got).await.unwrap();
+        got
+    })
+    .await
+    .expect("timed out");
+
+    assert_eq!(&result[..], b"hello via masque proxy");
+    let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), server_port);
+    assert_eq!(
+        *targets.lock().await,
+        vec![format!(
+            "/.well-known/masque/udp/{}/{}/",
+            server.ip(),
+            server.port()
+        )]
+    );
+
+    server_handle.abort();
+    client_handle.abort();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
+}
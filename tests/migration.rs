@@ -0,0 +1,96 @@
+//! Client-side connection migration.
+//!
+//! Rebinding a client endpoint moves its connection to a new local
+//! socket; the server follows it, and the session and its tunnels keep
+//! working without a reconnect.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use common::{get_available_port, init_crypto, server_config, STARTUP_DELAY, TEST_TIMEOUT};
+use quinn::VarInt;
+use rusnel::common::quic::{
+    create_client_endpoint, new_session_cache, rebind_client_endpoint, Congestion,
+};
+use rusnel::common::remote::{OpenConn, RemoteRequest, SessionHello, TunnelStatus};
+use rusnel::common::tls::ClientTlsConfig;
+use rusnel::common::tunnel::{client_send_session_hello, send_open_conn};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn test_rebind_keeps_the_session() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let sc = server_config(server_port, false);
+        let server = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+
+        // Upstream that greets every conn.
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut s, _)) = upstream.accept().await {
+                let _ = s.write_all(b"hello").await;
+            }
+        });
+
+        let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+        let endpoint = create_client_endpoint(
+            &ClientTlsConfig::Insecure,
+            Congestion::Cubic,
+            server_addr,
+            &new_session_cache(),
+        )
+        .unwrap();
+        let connection = endpoint
+            .connect(server_addr, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let spec = format!("0:127.0.0.1:{upstream_port}");
+        let hello = SessionHello::new(vec![RemoteRequest::from_str(&spec).unwrap()]);
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        let (tunnels, _, _) = client_send_session_hello(&hello, &mut send, &mut recv)
+            .await
+            .unwrap();
+        let TunnelStatus::Accepted { tunnel_id, .. } = tunnels[0] else {
+            panic!("tunnel not accepted: {:?}", tunnels[0]);
+        };
+
+        let before = endpoint.local_addr().unwrap();
+        let after = rebind_client_endpoint(&endpoint).unwrap();
+        assert_ne!(before.port(), after.port());
+
+        // A conn opened after the move runs over the same connection.
+        let (mut conn_send, mut conn_recv) = connection.open_bi().await.unwrap();
+        let open = OpenConn {
+            tunnel_id,
+            dynamic: None,
+            datagram_flow: None,
+            optimistic: false,
+            resume: None,
+            user: None,
+            protocol: None,
+        };
+        send_open_conn(&open, &mut conn_send, &mut conn_recv)
+            .await
+            .unwrap();
+        let mut got = [0u8; 5];
+        conn_recv.read_exact(&mut got).await.unwrap();
+        assert_eq!(&got, b"hello");
+        assert!(connection.close_reason().is_none());
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_rebind_keeps_the_session timed out");
+}
@@ -0,0 +1,84 @@
+//! Integration tests for named tunnels: the admin API finds a tunnel by
+//! the name its client gave it, and says so when a name is ambiguous.
+#![cfg(unix)]
+
+mod common;
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rusnel::common::remote::RemoteRequest;
+use rusnel::ctl;
+use tokio::time::{sleep, timeout};
+
+use common::{
+    client_config, get_available_port, init_crypto, server_config, STARTUP_DELAY, TEST_TIMEOUT,
+};
+
+fn remote(spec: &str) -> RemoteRequest {
+    RemoteRequest::from_str(spec).unwrap()
+}
+
+#[tokio::test]
+async fn test_admin_finds_tunnels_by_name() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let admin = PathBuf::from(format!("/tmp/rusnel-names-{}.sock", std::process::id()));
+
+        let mut sc = server_config(server_port, true);
+        sc.admin_socket = Some(admin.clone());
+        let server = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        sleep(STARTUP_DELAY).await;
+
+        // Two clients, each with a `web` tunnel; only the first has `ssh`.
+        let mut clients = Vec::new();
+        for remotes in [
+            vec![
+                remote(&format!(
+                    "web=127.0.0.1:{}:127.0.0.1:80",
+                    get_available_port()
+                )),
+                remote(&format!(
+                    "ssh=R:127.0.0.1:{}:127.0.0.1:22",
+                    get_available_port()
+                )),
+            ],
+            vec![remote(&format!(
+                "web=127.0.0.1:{}:127.0.0.1:80",
+                get_available_port()
+            ))],
+        ] {
+            let cc = client_config(server_port, remotes);
+            clients.push(tokio::spawn(async move {
+                let _ = rusnel::client::run_async(cc).await;
+            }));
+        }
+        sleep(STARTUP_DELAY).await;
+
+        let ssh = ctl::get(&admin, "/api/v1/tunnels/ssh").await.unwrap();
+        assert_eq!(ssh["name"], "ssh");
+        assert_eq!(ssh["direction"], "reverse");
+        let by_id = ctl::get(&admin, &format!("/api/v1/tunnels/{}", ssh["id"]))
+            .await
+            .unwrap();
+        assert_eq!(by_id["name"], "ssh");
+        let conns = ctl::get(&admin, "/api/v1/tunnels/ssh/conns").await.unwrap();
+        assert!(conns.as_array().unwrap().is_empty());
+
+        let err = ctl::get(&admin, "/api/v1/tunnels/web").await.unwrap_err();
+        assert!(err.to_string().contains("409"), "{err}");
+        let err = ctl::get(&admin, "/api/v1/tunnels/nope").await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+
+        for client in clients {
+            client.abort();
+        }
+        server.abort();
+        let _ = std::fs::remove_file(&admin);
+    })
+    .await
+    .expect("test_admin_finds_tunnels_by_name timed out");
+}
@@ -0,0 +1,165 @@
+//! Optimistic (pipelined) `OpenConn` against a real server.
+//!
+//! These drive the wire protocol by hand so they can assert what the
+//! server does *not* send: an optimistic opener gets no
+//! `OpenConnResponse` in front of the payload on success, and on failure
+//! sees its stream reset with a typed [`OpenConnError`] code instead.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use common::{get_available_port, init_crypto, server_config, STARTUP_DELAY, TEST_TIMEOUT};
+use quinn::{Connection, Endpoint, ReadError, VarInt};
+use rusnel::common::quic::{create_client_endpoint, new_session_cache, Congestion};
+use rusnel::common::remote::{OpenConn, RemoteRequest, SessionHello, TunnelStatus};
+use rusnel::common::tls::ClientTlsConfig;
+use rusnel::common::tunnel::{client_send_session_hello, send_open_conn_optimistic, OpenConnError};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+/// Start a server, connect a bare QUIC client and declare `remote`.
+/// Returns the connection and the tunnel id the server assigned.
+async fn open_session(remote: &str) -> (tokio::task::JoinHandle<()>, Endpoint, Connection, u64) {
+    init_crypto();
+    let server_port = get_available_port();
+    let sc = server_config(server_port, false);
+    let server = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+    let endpoint = create_client_endpoint(
+        &ClientTlsConfig::Insecure,
+        Congestion::Cubic,
+        server_addr,
+        &new_session_cache(),
+    )
+    .unwrap();
+    let connection = endpoint
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let hello = SessionHello::new(vec![RemoteRequest::from_str(remote).unwrap()]);
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    let (tunnels, _, _) = client_send_session_hello(&hello, &mut send, &mut recv)
+        .await
+        .unwrap();
+    let TunnelStatus::Accepted { tunnel_id, .. } = tunnels[0] else {
+        panic!("tunnel not accepted: {:?}", tunnels[0]);
+    };
+    (server, endpoint, connection, tunnel_id)
+}
+
+fn optimistic(tunnel_id: u64) -> OpenConn {
+    OpenConn {
+        tunnel_id,
+        dynamic: None,
+        datagram_flow: None,
+        optimistic: true,
+        resume: None,
+        user: None,
+        protocol: None,
+    }
+}
+
+/// Payload written right behind the frame reaches the target, and the
+/// echo comes back with no `OpenConnResponse` ahead of it.
+#[tokio::test]
+async fn test_optimistic_open_streams_without_response() {
+    timeout(TEST_TIMEOUT, async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_port = target.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut sock, _) = target.accept().await.unwrap();
+            let (mut r, mut w) = sock.split();
+            let _ = tokio::io::copy(&mut r, &mut w).await;
+        });
+
+        let (server, endpoint, connection, tunnel_id) =
+            open_session(&format!("9:127.0.0.1:{target_port}")).await;
+
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send_open_conn_optimistic(&optimistic(tunnel_id), &mut send)
+            .await
+            .unwrap();
+        send.write_all(b"pipelined").await.unwrap();
+        send.finish().unwrap();
+
+        let echoed = recv.read_to_end(1024).await.unwrap();
+        assert_eq!(echoed, b"pipelined");
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_optimistic_open_streams_without_response timed out");
+}
+
+/// A target nobody listens on: the server resets the stream with
+/// `DialFailed` rather than just closing it.
+#[tokio::test]
+async fn test_optimistic_open_dial_failure_resets_with_code() {
+    timeout(TEST_TIMEOUT, async {
+        let dead_port = get_available_port();
+        let (server, endpoint, connection, tunnel_id) =
+            open_session(&format!("9:127.0.0.1:{dead_port}")).await;
+
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send_open_conn_optimistic(&optimistic(tunnel_id), &mut send)
+            .await
+            .unwrap();
+        let _ = send.write_all(b"x").await;
+
+        let mut buf = [0u8; 16];
+        match recv.read(&mut buf).await {
+            Err(ReadError::Reset(code)) => {
+                assert_eq!(
+                    OpenConnError::from_code(code),
+                    Some(OpenConnError::DialFailed)
+                )
+            }
+            other => panic!("expected DialFailed reset, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_optimistic_open_dial_failure_resets_with_code timed out");
+}
+
+/// An id the server never assigned is refused with `UnknownTunnel`.
+#[tokio::test]
+async fn test_optimistic_open_unknown_tunnel_resets_with_code() {
+    timeout(TEST_TIMEOUT, async {
+        let target_port = get_available_port();
+        let (server, endpoint, connection, tunnel_id) =
+            open_session(&format!("9:127.0.0.1:{target_port}")).await;
+
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send_open_conn_optimistic(&optimistic(tunnel_id + 1000), &mut send)
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        match recv.read(&mut buf).await {
+            Err(ReadError::Reset(code)) => assert_eq!(
+                OpenConnError::from_code(code),
+                Some(OpenConnError::UnknownTunnel)
+            ),
+            other => panic!("expected UnknownTunnel reset, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_optimistic_open_unknown_tunnel_resets_with_code timed out");
+}
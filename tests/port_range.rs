@@ -0,0 +1,80 @@
+//! Integration test for port-range remotes: a forward and a reverse
+//! range each expand to one tunnel per port, and every port of the local
+//! range reaches the matching port of the remote one.
+
+mod common;
+
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use common::{get_available_port, start_tunnel, TEST_TIMEOUT};
+
+/// `n` consecutive ports, each reserved like [`get_available_port`].
+fn consecutive_ports(n: usize) -> u16 {
+    let mut run = vec![get_available_port()];
+    while run.len() < n {
+        let port = get_available_port();
+        if port == run[run.len() - 1] + 1 {
+            run.push(port);
+        } else {
+            run = vec![port];
+        }
+    }
+    run[0]
+}
+
+/// Listen on `port` and answer every conn with the port number.
+async fn spawn_port_teller(port: u16) {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            let _ = conn.write_all(&port.to_be_bytes()).await;
+        }
+    });
+}
+
+async fn reached_port(port: u16) -> u16 {
+    let mut conn = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    conn.read_u16().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_port_range_forward_and_reverse() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let upstream = consecutive_ports(3);
+        for port in upstream..upstream + 3 {
+            spawn_port_teller(port).await;
+        }
+        let forward = consecutive_ports(3);
+        let reverse = consecutive_ports(2);
+
+        let mut remotes = RemoteRequest::expand(&format!(
+            "127.0.0.1:{forward}-{}:127.0.0.1:{upstream}-{}",
+            forward + 2,
+            upstream + 2
+        ))
+        .unwrap();
+        remotes.extend(
+            RemoteRequest::expand(&format!(
+                "R:127.0.0.1:{reverse}-{}:127.0.0.1:{upstream}-{}",
+                reverse + 1,
+                upstream + 1
+            ))
+            .unwrap(),
+        );
+        assert_eq!(remotes.len(), 5);
+        let _env = start_tunnel(server_port, true, remotes).await;
+
+        for i in 0..3 {
+            assert_eq!(reached_port(forward + i).await, upstream + i);
+        }
+        for i in 0..2 {
+            assert_eq!(reached_port(reverse + i).await, upstream + i);
+        }
+    })
+    .await
+    .expect("test_port_range_forward_and_reverse timed out");
+}
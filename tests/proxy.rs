@@ -1,4 +1,5 @@
-//! Integration tests for `--proxy socks5://...` client support.
+//! Integration tests for `--proxy socks5://...` / `socks5h://...` / `http://...`
+//! support.
 //!
 //! These tests stand up a *minimal* in-process SOCKS5 UDP relay (just enough
 //! of RFC 1928 to satisfy Rusnel's client) and verify that a Rusnel client
@@ -9,6 +10,9 @@
 //! The relay sits between the client's UDP socket and the server's UDP
 //! socket, so it exercises the full SOCKS5 UDP framing path — wrap on the
 //! way out, unwrap on the way in.
+//!
+//! The HTTP proxy is just as minimal: Basic auth, `CONNECT`, then bytes
+//! copied both ways between the client and the server's TCP listener.
 
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
@@ -25,10 +29,14 @@ use tokio::sync::Mutex;
 
 mod common;
 use common::{
-    get_available_port, get_available_udp_port, init_crypto, server_config, STARTUP_DELAY,
-    TEST_TIMEOUT,
+    client_config, get_available_port, get_available_udp_port, init_crypto, server_config,
+    spawn_tcp_echo, STARTUP_DELAY, TEST_TIMEOUT,
 };
 
+/// The one name the test SOCKS5 relay and HTTP proxy resolve (to
+/// 127.0.0.1); nothing else can.
+const SPLIT_DNS_NAME: &str = "rusnel-server.test";
+
 /// Minimal SOCKS5 UDP relay sufficient for Rusnel's client. Returns the
 /// proxy's TCP listen address (what `--proxy socks5://addr` should point
 /// to) and a tokio task handle that owns the relay.
@@ -137,18 +145,34 @@ async fn handle_socks5_client(tcp: &mut TcpStream) -> std::io::Result<()> {
                     if pkt[0] != 0 || pkt[1] != 0 || pkt[2] != 0 {
                         continue;
                     }
-                    let target = match pkt[3] {
-                        0x01 if pkt.len() >= 10 => SocketAddr::new(
-                            IpAddr::V4(Ipv4Addr::new(pkt[4], pkt[5], pkt[6], pkt[7])),
-                            u16::from_be_bytes([pkt[8], pkt[9]]),
+                    let (target, header_len) = match pkt[3] {
+                        0x01 if pkt.len() >= 10 => (
+                            SocketAddr::new(
+                                IpAddr::V4(Ipv4Addr::new(pkt[4], pkt[5], pkt[6], pkt[7])),
+                                u16::from_be_bytes([pkt[8], pkt[9]]),
+                            ),
+                            10,
                         ),
+                        // Names resolve against the relay's own "DNS": just
+                        // SPLIT_DNS_NAME, which the client can't resolve.
+                        0x03 if pkt.len() >= 7 + pkt[4] as usize => {
+                            let end = 5 + pkt[4] as usize;
+                            if &pkt[5..end] != SPLIT_DNS_NAME.as_bytes() {
+                                continue;
+                            }
+                            let port = u16::from_be_bytes([pkt[end], pkt[end + 1]]);
+                            (
+                                SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+                                end + 2,
+                            )
+                        }
                         _ => continue,
                     };
                     {
                         let mut guard = last_target.lock().await;
                         *guard = Some(target);
                     }
-                    let payload = &pkt[10..];
+                    let payload = &pkt[header_len..];
                     let _ = relay_udp.send_to(payload, target).await;
                 } else {
                     // Inbound from a target server. Wrap with SOCKS5 header
@@ -182,25 +206,6 @@ async fn handle_socks5_client(tcp: &mut TcpStream) -> std::io::Result<()> {
     Ok(())
 }
 
-/// Spawn a tiny TCP echo server and return its address.
-async fn spawn_tcp_echo() -> SocketAddr {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
-    tokio::spawn(async move {
-        loop {
-            let (mut conn, _) = match listener.accept().await {
-                Ok(x) => x,
-                Err(_) => return,
-            };
-            tokio::spawn(async move {
-                let (mut r, mut w) = conn.split();
-                let _ = tokio::io::copy(&mut r, &mut w).await;
-            });
-        }
-    });
-    addr
-}
-
 #[tokio::test]
 async fn tcp_forward_through_socks5_proxy() {
     init_crypto();
@@ -227,12 +232,20 @@ async fn tcp_forward_through_socks5_proxy() {
         server: ServerEndpoint {
             addrs: vec![server_addr],
             host: server_addr.ip().to_string(),
+            port: server_addr.port(),
         },
         remotes: vec![remote],
         tls: ClientTlsConfig::Insecure,
         congestion: Default::default(),
         reconnect: ReconnectConfig::default(),
         proxy: Some(ProxyConfig::from_str(&format!("socks5://{proxy_addr}")).unwrap()),
+        proxy_ca: None,
+        strict_tunnels: false,
+        resumable_conns: false,
+        socks_users: Default::default(),
+        socks_rules: Default::default(),
+        tcp_fallback: None,
+        stats: Default::default(),
     };
     let client_handle = tokio::spawn(async move {
         let _ = rusnel::client::run_async(cc).await;
@@ -265,3 +278,241 @@ async fn tcp_forward_through_socks5_proxy() {
     let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
     let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
 }
+
+/// With `socks5h://` the client sends the server's name for the proxy to
+/// resolve, and takes replies from wherever the proxy resolved it to.
+#[tokio::test]
+async fn tcp_forward_through_socks5h_proxy() {
+    init_crypto();
+
+    let server_port = get_available_udp_port();
+    let local_port = get_available_port();
+    let echo_addr = spawn_tcp_echo().await;
+
+    let sc = server_config(server_port, false);
+    let server_handle = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let (proxy_addr, _proxy_handle) = spawn_socks5_relay().await;
+
+    let remote_spec = format!("{}:{}:{}/tcp", local_port, echo_addr.ip(), echo_addr.port());
+    let mut cc = client_config(
+        server_port,
+        vec![RemoteRequest::from_str(&remote_spec).unwrap()],
+    );
+    // Unresolved: only the proxy knows the name.
+    cc.server = ServerEndpoint {
+        addrs: Vec::new(),
+        host: SPLIT_DNS_NAME.to_string(),
+        port: server_port,
+    };
+    cc.proxy = Some(ProxyConfig::from_str(&format!("socks5h://{proxy_addr}")).unwrap());
+    let client_handle = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+
+    let result = tokio::time::timeout(TEST_TIMEOUT, async {
+        let mut conn = loop {
+            if let Ok(conn) = TcpStream::connect(("127.0.0.1", local_port)).await {
+                break conn;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+        conn.write_all(b"hello via socks5h").await.expect("write");
+        let mut got = vec![0u8; 17];
+        conn.read_exact(&mut got).await.expect("read echo");
+        got
+    })
+    .await
+    .expect("timed out");
+
+    assert_eq!(&result[..], b"hello via socks5h");
+
+    server_handle.abort();
+    client_handle.abort();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
+}
+
+/// Minimal HTTP CONNECT proxy requiring Basic auth for `credentials`
+/// (`user:pass`, already base64-encoded). Returns its address and the
+/// targets it was asked to CONNECT to.
+async fn spawn_http_proxy(credentials: &'static str) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let targets = Arc::new(Mutex::new(Vec::new()));
+    let seen = targets.clone();
+    tokio::spawn(async move {
+        while let Ok((mut tcp, _)) = listener.accept().await {
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(tcp.read_u8().await.unwrap());
+                }
+                let head = String::from_utf8(head).unwrap();
+                let target = head
+                    .strip_prefix("CONNECT ")
+                    .and_then(|rest| rest.split_whitespace().next())
+                    .unwrap()
+                    .to_string();
+                seen.lock().await.push(target.clone());
+                if !head.contains(&format!("Proxy-Authorization: Basic {credentials}\r\n")) {
+                    let _ = tcp
+                        .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                        .await;
+                    return;
+                }
+                let upstream_addr = match target.strip_prefix(SPLIT_DNS_NAME) {
+                    Some(port) => format!("127.0.0.1{port}"),
+                    None => target,
+                };
+                let mut upstream = TcpStream::connect(upstream_addr).await.unwrap();
+                tcp.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut tcp, &mut upstream).await;
+            });
+        }
+    });
+    (addr, targets)
+}
+
+#[tokio::test]
+async fn tcp_forward_through_http_proxy() {
+    init_crypto();
+
+    // The server's TCP listener shares its UDP port's number, which is
+    // where the client goes through an HTTP proxy by default.
+    let server_port = get_available_port();
+    let local_port = get_available_port();
+    let echo_addr = spawn_tcp_echo().await;
+
+    let mut sc = server_config(server_port, false);
+    sc.tcp_port = Some(server_port);
+    let server_handle = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    // base64("alice:s3cret")
+    let (proxy_addr, targets) = spawn_http_proxy("YWxpY2U6czNjcmV0").await;
+
+    let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), server_port);
+    let remote_spec = format!("{}:{}:{}/tcp", local_port, echo_addr.ip(), echo_addr.port());
+    let remote = RemoteRequest::from_str(&remote_spec).unwrap();
+    let cc = ClientConfig {
+        server: ServerEndpoint {
+            addrs: vec![server_addr],
+            host: server_addr.ip().to_string(),
+            port: server_addr.port(),
+        },
+        remotes: vec![remote],
+        tls: ClientTlsConfig::Insecure,
+        congestion: Default::default(),
+        reconnect: ReconnectConfig::default(),
+        proxy: Some(ProxyConfig::from_str(&format!("http://alice:s3cret@{proxy_addr}")).unwrap()),
+        proxy_ca: None,
+        strict_tunnels: false,
+        resumable_conns: false,
+        socks_users: Default::default(),
+        socks_rules: Default::default(),
+        tcp_fallback: None,
+        stats: Default::default(),
+    };
+    let client_handle = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY * 3).await;
+
+    let result = tokio::time::timeout(TEST_TIMEOUT, async {
+        let mut conn = TcpStream::connect(("127.0.0.1", local_port))
+            .await
+            .expect("connect to local forward");
+        conn.write_all(b"hello via http proxy")
+            .await
+            .expect("write");
+        let mut got = vec![0u8; 20];
+        conn.read_exact(&mut got).await.expect("read echo");
+        got
+    })
+    .await
+    .expect("timed out");
+
+    assert_eq!(&result[..], b"hello via http proxy");
+    assert_eq!(
+        *targets.lock().await,
+        vec![format!("127.0.0.1:{server_port}")]
+    );
+
+    server_handle.abort();
+    client_handle.abort();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
+}
+
+/// An HTTP proxy resolves the server's name for the client, which has no
+/// address for it.
+#[tokio::test]
+async fn tcp_forward_through_http_proxy_to_unresolvable_server() {
+    init_crypto();
+
+    let server_port = get_available_port();
+    let local_port = get_available_port();
+    let echo_addr = spawn_tcp_echo().await;
+
+    let mut sc = server_config(server_port, false);
+    sc.tcp_port = Some(server_port);
+    let server_handle = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    // base64("alice:s3cret")
+    let (proxy_addr, targets) = spawn_http_proxy("YWxpY2U6czNjcmV0").await;
+
+    let remote_spec = format!("{}:{}:{}/tcp", local_port, echo_addr.ip(), echo_addr.port());
+    let mut cc = client_config(
+        server_port,
+        vec![RemoteRequest::from_str(&remote_spec).expect("remote")],
+    );
+    // Unresolved: only the proxy knows the name.
+    cc.server = ServerEndpoint {
+        addrs: Vec::new(),
+        host: SPLIT_DNS_NAME.to_string(),
+        port: server_port,
+    };
+    cc.proxy =
+        Some(ProxyConfig::from_str(&format!("http://alice:s3cret@{proxy_addr}")).expect("proxy"));
+    let client_handle = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+
+    let result = tokio::time::timeout(TEST_TIMEOUT, async {
+        let mut conn = loop {
+            if let Ok(conn) = TcpStream::connect(("127.0.0.1", local_port)).await {
+                break conn;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+        conn.write_all(b"hello by name").await.expect("write");
+        let mut got = vec![0u8; 13];
+        conn.read_exact(&mut got).await.expect("read echo");
+        got
+    })
+    .await
+    .expect("timed out");
+
+    assert_eq!(&result[..], b"hello by name");
+    assert_eq!(
+        *targets.lock().await,
+        vec![format!("{SPLIT_DNS_NAME}:{server_port}")]
+    );
+
+    server_handle.abort();
+    client_handle.abort();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
+}
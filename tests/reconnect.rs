@@ -4,6 +4,10 @@
 //!   * a server restart mid-session (connection dropped after handshake), and
 //!   * an initial-connect failure (server not yet up when the client starts).
 //!
+//! plus that forward listeners stay bound through the outage, and that a
+//! reconnect to the same server resumes the TLS session and
+//! gets its session hello accepted as 0-RTT early data.
+//!
 //! The test approach mirrors how chisel's reconnect is exercised: spawn the
 //! server, observe data flowing, abort the server, restart it on the same
 //! port, and verify the client transparently re-establishes the tunnel
@@ -16,11 +20,16 @@ use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
-use common::{client_config, get_available_port, init_crypto, STARTUP_DELAY, TEST_TIMEOUT};
+use common::{
+    client_config, get_available_port, init_crypto, server_config, STARTUP_DELAY, TEST_TIMEOUT,
+};
 use quinn::{Connection, VarInt};
-use rusnel::common::quic::{create_server_endpoint, Congestion};
-use rusnel::common::remote::RemoteRequest;
-use rusnel::common::tls::ServerTlsConfig;
+use rusnel::common::quic::{
+    create_client_endpoint, create_server_endpoint, new_session_cache, Congestion,
+};
+use rusnel::common::remote::{RemoteRequest, SessionHello};
+use rusnel::common::tls::{ClientTlsConfig, ServerTlsConfig};
+use rusnel::common::tunnel::client_send_session_hello;
 use rusnel::ReconnectConfig;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
@@ -105,12 +114,12 @@ async fn spawn_server(server_port: u16) -> ServerHandle {
 /// connection is processed by [`run_test_session`].
 async fn run_test_session(connection: Connection) {
     use rusnel::common::remote::{
-        Direction, OpenConnResponse, RemoteKind, RemoteRequest, SessionHelloResponse,
+        Direction, PeerInfo, RemoteKind, RemoteRequest, SessionHelloResponse, TunnelStatus,
     };
     use rusnel::common::tcp::tunnel_tcp_server;
     use rusnel::common::tunnel::{
-        receive_open_conn, reply_open_conn, server_receive_session_hello,
-        server_reply_session_hello,
+        accept_open_conn, receive_open_conn, refuse_open_conn, server_receive_session_hello,
+        server_reply_session_hello, OpenConnError,
     };
 
     // Session hello: read declarations, allocate fake tunnel ids, and
@@ -132,15 +141,21 @@ async fn run_test_session(connection: Connection) {
     };
     let mut tunnels: std::collections::HashMap<u64, RemoteRequest> =
         std::collections::HashMap::new();
-    let mut tunnel_ids: Vec<u64> = Vec::with_capacity(hello.remotes.len());
+    let mut statuses: Vec<TunnelStatus> = Vec::with_capacity(hello.remotes.len());
     for (i, r) in hello.remotes.iter().enumerate() {
         let id = (i as u64) + 1;
-        tunnel_ids.push(id);
+        statuses.push(TunnelStatus::Accepted {
+            tunnel_id: id,
+            bound: None,
+        });
         tunnels.insert(id, r.clone());
     }
-    if let Err(e) =
-        server_reply_session_hello(&mut hello_send, &SessionHelloResponse::Ok { tunnel_ids }).await
-    {
+    let resp = SessionHelloResponse::Ok {
+        tunnels: statuses,
+        peer: PeerInfo::local(),
+        resume_token: None,
+    };
+    if let Err(e) = server_reply_session_hello(&mut hello_send, &resp).await {
         info!("hello reply failed: {e}");
         return;
     }
@@ -164,18 +179,18 @@ async fn run_test_session(connection: Connection) {
             let parent = match tunnels.get(&open.tunnel_id) {
                 Some(p) => p.clone(),
                 None => {
-                    let _ = reply_open_conn(
+                    refuse_open_conn(
+                        &open,
                         &mut send,
-                        &OpenConnResponse::Failed("unknown tunnel".into()),
+                        &mut recv,
+                        OpenConnError::UnknownTunnel,
+                        "unknown tunnel",
                     )
                     .await;
                     return;
                 }
             };
-            if reply_open_conn(&mut send, &OpenConnResponse::Ok)
-                .await
-                .is_err()
-            {
+            if accept_open_conn(&open, &mut send).await.is_err() {
                 return;
             }
             // Only forward TCP is exercised by this test stub.
@@ -185,7 +200,7 @@ async fn run_test_session(connection: Connection) {
                 }
                 _ => return,
             };
-            if let Err(e) = tunnel_tcp_server(recv, send, req, None).await {
+            if let Err(e) = tunnel_tcp_server(recv, send, req, None, None).await {
                 info!("tunnel ended: {e}");
             }
         });
@@ -231,8 +246,8 @@ async fn assert_tunnel_works(local_port: u16, target_listener: &TcpListener) {
 }
 
 /// Server crash → client reconnects → tunnel works again on the same local
-/// port. The client is *never* restarted: its forward listener must come back
-/// after the new server-side tunnel is established.
+/// port. The client is *never* restarted: its forward listener must carry
+/// new conns over the new server-side tunnel once it is established.
 #[tokio::test]
 async fn test_client_reconnect_after_server_restart() {
     // Detecting the server crash can take up to the QUIC idle timeout
@@ -328,6 +343,62 @@ async fn test_client_reconnect_after_server_restart() {
     .expect("test_client_reconnect_after_server_restart timed out");
 }
 
+/// The forward listener stays bound while the server is gone: a conn
+/// made during the outage is accepted, held, and carried to the target
+/// once the client has reconnected.
+#[tokio::test]
+async fn test_forward_listener_holds_conn_across_server_restart() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let local_port = get_available_port();
+        let remote_port = get_available_port();
+
+        let target_listener = TcpListener::bind(format!("127.0.0.1:{remote_port}"))
+            .await
+            .unwrap();
+
+        let server_handle = spawn_server(server_port).await;
+
+        let mut cc = client_config(
+            server_port,
+            vec![RemoteRequest::from_str(&format!(
+                "127.0.0.1:{local_port}:127.0.0.1:{remote_port}"
+            ))
+            .unwrap()],
+        );
+        cc.reconnect = fast_reconnect();
+        let client_handle = tokio::spawn(async move {
+            let _ = rusnel::client::run_async(cc).await;
+        });
+
+        tokio::time::sleep(STARTUP_DELAY).await;
+        assert_tunnel_works(local_port, &target_listener).await;
+
+        server_handle.stop().await;
+        // Let the client see the CONNECTION_CLOSE and park its listener.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let mut held = TcpStream::connect(format!("127.0.0.1:{local_port}"))
+            .await
+            .expect("forward listener released during the outage");
+        let payload = b"sent while disconnected";
+        held.write_all(payload).await.unwrap();
+        held.shutdown().await.unwrap();
+
+        let _restarted = spawn_server(server_port).await;
+
+        let (mut target, _) = target_listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        target.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, payload);
+
+        client_handle.abort();
+    })
+    .await
+    .expect("test_forward_listener_holds_conn_across_server_restart timed out");
+}
+
 /// Client started before the server: the first `endpoint.connect` will fail.
 /// The client must back off and retry until the server appears, then run
 /// normally. This is the chisel `--server-not-yet-up-on-startup` scenario.
@@ -401,3 +472,68 @@ async fn test_client_reconnect_before_server_up() {
     .await
     .expect("test_client_reconnect_before_server_up timed out");
 }
+
+/// Second connection from the same endpoint (same session cache) to a
+/// real server must resume and carry its session hello as 0-RTT early
+/// data: `into_0rtt` succeeds, the hello is answered, and the server
+/// reports the early data as accepted once the handshake completes.
+#[tokio::test]
+async fn test_reconnect_sends_session_hello_as_0rtt() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let remote_port = get_available_port();
+
+        let sc = server_config(server_port, false);
+        let server_handle = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+
+        let server_addr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+        let endpoint = create_client_endpoint(
+            &ClientTlsConfig::Insecure,
+            Congestion::Cubic,
+            server_addr,
+            &new_session_cache(),
+        )
+        .unwrap();
+        let hello = SessionHello::new(vec![RemoteRequest::from_str(&format!(
+            "9:127.0.0.1:{remote_port}"
+        ))
+        .unwrap()]);
+
+        // First connection: full handshake, no ticket yet.
+        let connecting = endpoint.connect(server_addr, "localhost").unwrap();
+        let first = match connecting.into_0rtt() {
+            Ok(_) => panic!("0-RTT offered before any session ticket was issued"),
+            Err(connecting) => connecting.await.unwrap(),
+        };
+        let (mut send, mut recv) = first.open_bi().await.unwrap();
+        client_send_session_hello(&hello, &mut send, &mut recv)
+            .await
+            .unwrap();
+        // Tickets are sent just after the handshake; give them a moment
+        // to land before tearing the connection down.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        first.close(VarInt::from_u32(0), b"reconnect");
+
+        // Second connection: resumes, hello rides in early data.
+        let connecting = endpoint.connect(server_addr, "localhost").unwrap();
+        let (second, accepted) = connecting
+            .into_0rtt()
+            .unwrap_or_else(|_| panic!("no 0-RTT attempt on reconnect"));
+        let (mut send, mut recv) = second.open_bi().await.unwrap();
+        let (ids, _, _) = client_send_session_hello(&hello, &mut send, &mut recv)
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 1);
+        assert!(accepted.await, "server rejected 0-RTT early data");
+
+        second.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server_handle.abort();
+    })
+    .await
+    .expect("test_reconnect_sends_session_hello_as_0rtt timed out");
+}
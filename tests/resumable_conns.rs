@@ -0,0 +1,185 @@
+//! Resumable TCP conns (`--resumable-conns`).
+//!
+//! A forward conn runs through a UDP relay that can be cut, so the QUIC
+//! connection under it is lost while both TCP ends stay open. Slow: the
+//! loss is only noticed when the idle timeout fires.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{
+    client_config, get_available_port, init_crypto, server_config, spawn_tcp_echo, STARTUP_DELAY,
+    TEST_TIMEOUT,
+};
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// Past the idle timeout on both sides.
+const OUTAGE: Duration = Duration::from_secs(33);
+
+/// UDP relay between the client and the server that can be cut, making
+/// the path go silent the way a lost network does, and restored.
+struct Relay {
+    addr: SocketAddr,
+    cut: Arc<AtomicBool>,
+}
+
+impl Relay {
+    async fn start(server: SocketAddr) -> Self {
+        let front = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        back.connect(server).await.unwrap();
+        let addr = front.local_addr().unwrap();
+        let cut = Arc::new(AtomicBool::new(false));
+        let (client_tx, mut client_rx) = tokio::sync::watch::channel(None);
+
+        let (f, b, c) = (front.clone(), back.clone(), cut.clone());
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok((n, from)) = f.recv_from(&mut buf).await {
+                client_tx.send_replace(Some(from));
+                if !c.load(Ordering::SeqCst) {
+                    let _ = b.send(&buf[..n]).await;
+                }
+            }
+        });
+        let c = cut.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok(n) = back.recv(&mut buf).await {
+                let Some(client) = *client_rx.borrow_and_update() else {
+                    continue;
+                };
+                if !c.load(Ordering::SeqCst) {
+                    let _ = front.send_to(&buf[..n], client).await;
+                }
+            }
+        });
+        Relay { addr, cut }
+    }
+
+    fn cut(&self) {
+        self.cut.store(true, Ordering::SeqCst);
+    }
+
+    fn restore(&self) {
+        self.cut.store(false, Ordering::SeqCst);
+    }
+}
+
+/// A server with `grace` and a resumable-conns client reaching it
+/// through a relay, forwarding a local port to an echo server. Returns
+/// the relay, the local port and both tasks.
+async fn start(grace: Duration) -> (Relay, u16, JoinHandle<()>, JoinHandle<()>) {
+    init_crypto();
+    let server_port = get_available_port();
+    let mut sc = server_config(server_port, false);
+    sc.reconnect_grace = grace;
+    let server = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+    let relay = Relay::start(server_addr).await;
+    let echo_port = spawn_tcp_echo().await.port();
+    let local_port = get_available_port();
+    let spec = format!("127.0.0.1:{local_port}:127.0.0.1:{echo_port}");
+    let mut cc = client_config(
+        relay.addr.port(),
+        vec![RemoteRequest::from_str(&spec).unwrap()],
+    );
+    cc.resumable_conns = true;
+    let client = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+    (relay, local_port, server, client)
+}
+
+async fn echo(conn: &mut TcpStream, data: &[u8]) {
+    conn.write_all(data).await.unwrap();
+    let mut got = vec![0u8; data.len()];
+    conn.read_exact(&mut got).await.unwrap();
+    assert_eq!(got, data);
+}
+
+/// Without a loss a resumable conn behaves like any other, half-close
+/// included.
+#[tokio::test]
+async fn test_conn_closes_normally() {
+    timeout(TEST_TIMEOUT, async {
+        let (_relay, local_port, server, client) = start(Duration::from_secs(30)).await;
+        let mut conn = TcpStream::connect(("127.0.0.1", local_port)).await.unwrap();
+        let data = vec![7u8; 1 << 20];
+        conn.write_all(&data).await.unwrap();
+        conn.shutdown().await.unwrap();
+        let mut got = Vec::new();
+        conn.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got, data);
+
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_conn_closes_normally timed out");
+}
+
+/// A conn survives the connection under it being lost: what was written
+/// during the outage is replayed once the client is back, and the conn
+/// carries on.
+#[tokio::test]
+async fn test_conn_survives_reconnect() {
+    timeout(Duration::from_secs(90), async {
+        let (relay, local_port, server, client) = start(Duration::from_secs(30)).await;
+        let mut conn = TcpStream::connect(("127.0.0.1", local_port)).await.unwrap();
+        echo(&mut conn, b"before").await;
+
+        relay.cut();
+        conn.write_all(b"during").await.unwrap();
+        tokio::time::sleep(OUTAGE).await;
+        relay.restore();
+
+        let mut got = [0u8; 6];
+        conn.read_exact(&mut got).await.unwrap();
+        assert_eq!(&got, b"during");
+        echo(&mut conn, b"after").await;
+
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_conn_survives_reconnect timed out");
+}
+
+/// A conn the server gave up on before the client came back is closed,
+/// not left hanging.
+#[tokio::test]
+async fn test_conn_closed_after_grace() {
+    timeout(Duration::from_secs(90), async {
+        let (relay, local_port, server, client) = start(Duration::from_secs(1)).await;
+        let mut conn = TcpStream::connect(("127.0.0.1", local_port)).await.unwrap();
+        echo(&mut conn, b"before").await;
+
+        relay.cut();
+        tokio::time::sleep(OUTAGE).await;
+        relay.restore();
+
+        let mut buf = [0u8; 16];
+        let read = conn.read(&mut buf).await;
+        assert!(matches!(read, Ok(0) | Err(_)), "conn still open: {read:?}");
+
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_conn_closed_after_grace timed out");
+}
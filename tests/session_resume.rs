@@ -0,0 +1,236 @@
+//! Server-side session resumption.
+//!
+//! A hello carrying the resume token of an earlier session takes its
+//! tunnels over — same ids, reverse listeners still bound — instead of
+//! starting fresh. These speak the hello over bare QUIC connections so
+//! they can present tokens and watch reverse conns arrive directly.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{get_available_port, init_crypto, server_config, STARTUP_DELAY, TEST_TIMEOUT};
+use quinn::{Connection, ConnectionError, Endpoint, RecvStream, SendStream, VarInt};
+use rusnel::common::quic::{create_client_endpoint, new_session_cache, Congestion};
+use rusnel::common::remote::{RemoteRequest, ResumeToken, SessionHello, TunnelStatus};
+use rusnel::common::tls::ClientTlsConfig;
+use rusnel::common::tunnel::{accept_open_conn, client_send_session_hello, receive_open_conn};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+fn start_server(server_port: u16) -> JoinHandle<()> {
+    init_crypto();
+    let sc = server_config(server_port, true);
+    tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    })
+}
+
+async fn connect(addr: SocketAddr) -> (Endpoint, Connection) {
+    let endpoint = create_client_endpoint(
+        &ClientTlsConfig::Insecure,
+        Congestion::Cubic,
+        addr,
+        &new_session_cache(),
+    )
+    .unwrap();
+    let connection = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+    (endpoint, connection)
+}
+
+/// Declare `spec` on `connection`, presenting `resume` if given.
+/// Returns the tunnel id, the token issued for this session and the
+/// control stream, which has to stay open for the session to live.
+async fn hello(
+    connection: &Connection,
+    spec: &str,
+    resume: Option<ResumeToken>,
+) -> (u64, ResumeToken, SendStream, RecvStream) {
+    let hello = SessionHello {
+        resume,
+        ..SessionHello::new(vec![RemoteRequest::from_str(spec).unwrap()])
+    };
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    let (tunnels, _, token) = client_send_session_hello(&hello, &mut send, &mut recv)
+        .await
+        .unwrap();
+    let TunnelStatus::Accepted { tunnel_id, .. } = tunnels[0] else {
+        panic!("tunnel not accepted: {:?}", tunnels[0]);
+    };
+    (
+        tunnel_id,
+        token.expect("no resume token issued"),
+        send,
+        recv,
+    )
+}
+
+/// Wait for the server to open a reverse conn on `connection` and
+/// return the tunnel it names.
+async fn next_reverse_conn(connection: &Connection) -> u64 {
+    let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+    let open = receive_open_conn(&mut recv).await.unwrap();
+    accept_open_conn(&open, &mut send).await.unwrap();
+    open.tunnel_id
+}
+
+/// UDP relay between a client and the server that can be cut, making
+/// the path go silent the way a lost network does.
+struct Relay {
+    addr: SocketAddr,
+    cut: Arc<AtomicBool>,
+}
+
+impl Relay {
+    async fn start(server: SocketAddr) -> Self {
+        let front = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        back.connect(server).await.unwrap();
+        let addr = front.local_addr().unwrap();
+        let cut = Arc::new(AtomicBool::new(false));
+        let (client_tx, mut client_rx) = tokio::sync::watch::channel(None);
+
+        let (f, b, c) = (front.clone(), back.clone(), cut.clone());
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok((n, from)) = f.recv_from(&mut buf).await {
+                client_tx.send_replace(Some(from));
+                if !c.load(Ordering::SeqCst) {
+                    let _ = b.send(&buf[..n]).await;
+                }
+            }
+        });
+        let c = cut.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok(n) = back.recv(&mut buf).await {
+                let Some(client) = *client_rx.borrow_and_update() else {
+                    continue;
+                };
+                if !c.load(Ordering::SeqCst) {
+                    let _ = front.send_to(&buf[..n], client).await;
+                }
+            }
+        });
+        Relay { addr, cut }
+    }
+
+    fn cut(&self) {
+        self.cut.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A token presented while its session is still up takes the session
+/// over from the old connection, reverse listener and tunnel id
+/// included, and reverse conns go to the new connection from then on.
+#[tokio::test]
+async fn test_resume_supersedes_live_session() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let server = start_server(server_port);
+        tokio::time::sleep(STARTUP_DELAY).await;
+        let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+
+        let reverse_port = get_available_port();
+        let spec = format!("R:127.0.0.1:{reverse_port}:127.0.0.1:22");
+        let (_old_endpoint, old) = connect(server_addr).await;
+        let (old_id, token, _old_send, _old_recv) = hello(&old, &spec, None).await;
+
+        let (endpoint, new) = connect(server_addr).await;
+        let (new_id, new_token, _send, _recv) = hello(&new, &spec, Some(token)).await;
+        assert_eq!(new_id, old_id);
+        assert_ne!(new_token, token);
+        assert!(matches!(
+            old.closed().await,
+            ConnectionError::ApplicationClosed(_)
+        ));
+
+        let _conn = TcpStream::connect(("127.0.0.1", reverse_port))
+            .await
+            .unwrap();
+        assert_eq!(next_reverse_conn(&new).await, old_id);
+
+        new.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_resume_supersedes_live_session timed out");
+}
+
+/// A session closed on purpose is released straight away; its token
+/// no longer resumes anything and the hello gets a fresh session.
+#[tokio::test]
+async fn test_graceful_close_is_not_held() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let server = start_server(server_port);
+        tokio::time::sleep(STARTUP_DELAY).await;
+        let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+
+        let reverse_port = get_available_port();
+        let spec = format!("R:127.0.0.1:{reverse_port}:127.0.0.1:22");
+        let (old_endpoint, old) = connect(server_addr).await;
+        let (old_id, token, _old_send, _old_recv) = hello(&old, &spec, None).await;
+        old.close(VarInt::from_u32(0), b"bye");
+        old_endpoint.wait_idle().await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // The port is free again, so the fresh session can bind it.
+        let (endpoint, new) = connect(server_addr).await;
+        let (new_id, _, _send, _recv) = hello(&new, &spec, Some(token)).await;
+        assert_ne!(new_id, old_id);
+
+        new.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_graceful_close_is_not_held timed out");
+}
+
+/// The server holds a lost session: its reverse listener keeps
+/// accepting, and a conn that arrives while nobody serves the session
+/// waits for the client to resume and is then delivered to it.
+///
+/// Slow: the server only notices the loss when its idle timeout fires.
+#[tokio::test]
+async fn test_lost_session_is_held_and_resumed() {
+    timeout(Duration::from_secs(90), async {
+        let server_port = get_available_port();
+        let server = start_server(server_port);
+        tokio::time::sleep(STARTUP_DELAY).await;
+        let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+        let relay = Relay::start(server_addr).await;
+
+        let reverse_port = get_available_port();
+        let spec = format!("R:127.0.0.1:{reverse_port}:127.0.0.1:22");
+        let (_old_endpoint, old) = connect(relay.addr).await;
+        let (old_id, token, _old_send, _old_recv) = hello(&old, &spec, None).await;
+
+        relay.cut();
+        old.close(VarInt::from_u32(0), b"never arrives");
+        // Past the server's idle timeout, but well within the grace.
+        tokio::time::sleep(Duration::from_secs(33)).await;
+
+        let _queued = TcpStream::connect(("127.0.0.1", reverse_port))
+            .await
+            .unwrap();
+
+        let (endpoint, new) = connect(server_addr).await;
+        let (new_id, _, _send, _recv) = hello(&new, &spec, Some(token)).await;
+        assert_eq!(new_id, old_id);
+        assert_eq!(next_reverse_conn(&new).await, old_id);
+
+        new.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_lost_session_is_held_and_resumed timed out");
+}
@@ -0,0 +1,185 @@
+//! Integration tests for RFC 1929 username/password auth on SOCKS5
+//! listeners: a forward `socks` remote with its own `user:pass@`
+//! credentials, and an `R:socks` listener checking the server's
+//! credentials file. Each checks the admin API for the username
+//! recorded on the conn.
+//!
+//! Unix-only for the admin socket.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rusnel::common::remote::RemoteRequest;
+use rusnel::common::socks::SocksUsers;
+use rusnel::ctl;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use common::{
+    client_config, get_available_port, get_available_udp_port, init_crypto, server_config,
+    spawn_tcp_echo, STARTUP_DELAY, TEST_TIMEOUT,
+};
+
+fn admin_sock_path(label: &str) -> PathBuf {
+    PathBuf::from(format!(
+        "/tmp/rusnel-it-socks-auth-{label}-{}.sock",
+        std::process::id()
+    ))
+}
+
+/// Greet the SOCKS5 listener at `port` offering only username/password
+/// auth and log in. Returns the auth status byte (0 = success) and the
+/// stream, ready for a request.
+async fn socks5_login(port: u16, user: &str, pass: &str) -> (u8, TcpStream) {
+    let mut conn = loop {
+        if let Ok(conn) = TcpStream::connect(("127.0.0.1", port)).await {
+            break conn;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    conn.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+    let mut method = [0u8; 2];
+    conn.read_exact(&mut method).await.unwrap();
+    assert_eq!(
+        method,
+        [0x05, 0x02],
+        "listener should pick username/password"
+    );
+
+    let mut login = vec![0x01, user.len() as u8];
+    login.extend_from_slice(user.as_bytes());
+    login.push(pass.len() as u8);
+    login.extend_from_slice(pass.as_bytes());
+    conn.write_all(&login).await.unwrap();
+    let mut status = [0u8; 2];
+    conn.read_exact(&mut status).await.unwrap();
+    assert_eq!(status[0], 0x01, "RFC 1929 reply version");
+    (status[1], conn)
+}
+
+/// CONNECT to `target` over a logged-in `conn` and check the echo.
+async fn connect_and_echo(conn: &mut TcpStream, target: SocketAddr) {
+    let SocketAddr::V4(target) = target else {
+        panic!("expected an IPv4 target");
+    };
+    let mut req = vec![0x05, 0x01, 0x00, 0x01];
+    req.extend_from_slice(&target.ip().octets());
+    req.extend_from_slice(&target.port().to_be_bytes());
+    conn.write_all(&req).await.unwrap();
+    let mut reply = [0u8; 10];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[1], 0x00, "SOCKS reply status");
+
+    conn.write_all(b"authenticated").await.unwrap();
+    let mut got = [0u8; 13];
+    conn.read_exact(&mut got).await.unwrap();
+    assert_eq!(&got, b"authenticated");
+}
+
+/// Usernames on the server's live conns.
+async fn conn_users(socket: &std::path::Path) -> Vec<Option<String>> {
+    ctl::get(socket, "/api/v1/conns")
+        .await
+        .unwrap()
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .map(|c| c["user"].as_str().map(str::to_string))
+        .collect()
+}
+
+#[tokio::test]
+async fn forward_socks_with_remote_credentials() {
+    init_crypto();
+    let server_port = get_available_udp_port();
+    let socket_path = admin_sock_path("forward");
+    let mut sc = server_config(server_port, false);
+    sc.admin_socket = Some(socket_path.clone());
+    let server_handle = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let echo = spawn_tcp_echo().await;
+    let socks_port = get_available_port();
+    let remote =
+        RemoteRequest::from_str(&format!("alice:s3cret@127.0.0.1:{socks_port}:socks")).unwrap();
+    let cc = client_config(server_port, vec![remote]);
+    let client_handle = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        let (status, _) = socks5_login(socks_port, "alice", "wrong").await;
+        assert_ne!(status, 0x00, "a wrong password must be refused");
+
+        let (status, mut conn) = socks5_login(socks_port, "alice", "s3cret").await;
+        assert_eq!(status, 0x00);
+        connect_and_echo(&mut conn, echo).await;
+        assert_eq!(conn_users(&socket_path).await, vec![Some("alice".into())]);
+    })
+    .await
+    .expect("timed out");
+
+    server_handle.abort();
+    client_handle.abort();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
+    let _ = std::fs::remove_file(&socket_path);
+}
+
+#[tokio::test]
+async fn reverse_socks_with_server_credentials_file() {
+    init_crypto();
+    let server_port = get_available_udp_port();
+    let socket_path = admin_sock_path("reverse");
+    let mut sc = server_config(server_port, true);
+    sc.admin_socket = Some(socket_path.clone());
+    sc.socks_users = SocksUsers::parse("# operators\nbob:hunter2\n").unwrap();
+    let server_handle = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let echo = spawn_tcp_echo().await;
+    let socks_port = get_available_port();
+    let remote = RemoteRequest::from_str(&format!("R:127.0.0.1:{socks_port}:socks")).unwrap();
+    let cc = client_config(server_port, vec![remote]);
+    let client_handle = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        // A client that only offers "no auth" is turned away.
+        let mut conn = loop {
+            if let Ok(conn) = TcpStream::connect(("127.0.0.1", socks_port)).await {
+                break conn;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+        conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut method = [0u8; 2];
+        conn.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [0x05, 0xFF]);
+
+        let (status, mut conn) = socks5_login(socks_port, "bob", "hunter2").await;
+        assert_eq!(status, 0x00);
+        connect_and_echo(&mut conn, echo).await;
+        assert_eq!(conn_users(&socket_path).await, vec![Some("bob".into())]);
+    })
+    .await
+    .expect("timed out");
+
+    server_handle.abort();
+    client_handle.abort();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server_handle).await;
+    let _ = tokio::time::timeout(Duration::from_millis(200), client_handle).await;
+    let _ = std::fs::remove_file(&socket_path);
+}
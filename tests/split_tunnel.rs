@@ -0,0 +1,186 @@
+//! Integration tests for split-tunnel rules on a forward `socks`
+//! listener: a CONNECT a `direct` rule matches is dialed by the client
+//! itself, a `reject` rule refuses it with "not allowed by ruleset", and
+//! one no rule matches goes through the tunnel. Each checks the rules'
+//! counters in the client's stats.
+
+mod common;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use rusnel::common::remote::RemoteRequest;
+use rusnel::common::routing::{Route, RouteRule, RouteRules, RouteStats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use common::{
+    client_config, get_available_port, init_crypto, server_config, socks5_connect_ipv4,
+    spawn_tcp_echo, STARTUP_DELAY, TEST_TIMEOUT,
+};
+
+async fn check_echo(conn: &mut TcpStream) {
+    conn.write_all(b"split").await.unwrap();
+    let mut buf = [0u8; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"split");
+}
+
+#[tokio::test]
+async fn test_socks_split_tunnel_rules() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let direct_port = spawn_tcp_echo().await.port();
+        let tunneled_port = spawn_tcp_echo().await.port();
+        let rejected_port = get_available_port();
+
+        let sc = server_config(server_port, false);
+        let server = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+
+        let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
+        let mut cc = client_config(server_port, vec![remote]);
+        let rules = RouteRules::new(vec![
+            RouteRule::new(Route::Reject, vec![], vec![], vec![rejected_port]),
+            RouteRule::new(
+                Route::Direct,
+                vec!["127.0.0.0/8".parse().unwrap()],
+                vec![],
+                vec![direct_port],
+            ),
+        ]);
+        cc.socks_rules = rules.clone();
+        let stats = cc.stats.clone();
+        let client = tokio::spawn(async move {
+            let _ = rusnel::client::run_async(cc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+        let socks_addr = format!("127.0.0.1:{socks_port}");
+
+        let mut direct = socks5_connect_ipv4(&socks_addr, [127, 0, 0, 1], direct_port).await;
+        check_echo(&mut direct).await;
+        assert_eq!(rules.hits(), vec![0, 1]);
+
+        let mut conn = socks5_connect_ipv4(&socks_addr, [127, 0, 0, 1], tunneled_port).await;
+        check_echo(&mut conn).await;
+        assert_eq!(rules.hits(), vec![0, 1], "no rule should match");
+
+        let mut conn = TcpStream::connect(&socks_addr).await.unwrap();
+        conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut greeting = [0u8; 2];
+        conn.read_exact(&mut greeting).await.unwrap();
+        let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+        request.extend_from_slice(&rejected_port.to_be_bytes());
+        conn.write_all(&request).await.unwrap();
+        let mut reply = [0u8; 10];
+        conn.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], 0x02, "connection not allowed by ruleset");
+        assert_eq!(rules.hits(), vec![1, 1]);
+
+        // The direct conn's bytes are counted once it closes.
+        drop(direct);
+        let routes = loop {
+            let routes = stats.routes();
+            if routes[1].bytes_in > 0 {
+                break routes;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        let direct = RouteStats {
+            route: Route::Direct,
+            hits: 1,
+            bytes_in: 5,
+            bytes_out: 5,
+        };
+        assert_eq!(routes[1], direct);
+        assert_eq!(routes[0].hits, 1);
+
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_socks_split_tunnel_rules timed out");
+}
+
+/// A short admin socket path under `/tmp` (see `tests/admin.rs`).
+#[cfg(unix)]
+fn admin_sock_path() -> std::path::PathBuf {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    format!("/tmp/rusnel-it-split-{}-{nanos}.sock", std::process::id()).into()
+}
+
+/// Conns the server has carried for its one client, once it has one.
+#[cfg(unix)]
+async fn server_total_conns(admin: &std::path::Path) -> u64 {
+    loop {
+        let clients = rusnel::ctl::get(admin, "/api/v1/clients")
+            .await
+            .expect("admin API");
+        if let Some(client) = clients.as_array().and_then(|c| c.first()) {
+            return client["total_conns"].as_u64().expect("total_conns");
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// A `direct` CONNECT never reaches the server: its conn count only
+/// moves for the tunneled one.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_socks_direct_conns_bypass_the_server() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let direct_port = spawn_tcp_echo().await.port();
+        let tunneled_port = spawn_tcp_echo().await.port();
+        let admin = admin_sock_path();
+
+        let mut sc = server_config(server_port, false);
+        sc.admin_socket = Some(admin.clone());
+        let server = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+
+        let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
+        let mut cc = client_config(server_port, vec![remote]);
+        cc.socks_rules = RouteRules::new(vec![RouteRule::new(
+            Route::Direct,
+            vec![],
+            vec![],
+            vec![direct_port],
+        )]);
+        let client = tokio::spawn(async move {
+            let _ = rusnel::client::run_async(cc).await;
+        });
+        tokio::time::sleep(STARTUP_DELAY).await;
+        let socks_addr = format!("127.0.0.1:{socks_port}");
+
+        let mut direct = socks5_connect_ipv4(&socks_addr, [127, 0, 0, 1], direct_port).await;
+        check_echo(&mut direct).await;
+        assert_eq!(
+            server_total_conns(&admin).await,
+            0,
+            "direct conn reached the server"
+        );
+
+        let mut tunneled = socks5_connect_ipv4(&socks_addr, [127, 0, 0, 1], tunneled_port).await;
+        check_echo(&mut tunneled).await;
+        assert_eq!(server_total_conns(&admin).await, 1);
+
+        client.abort();
+        server.abort();
+        let _ = std::fs::remove_file(&admin);
+    })
+    .await
+    .expect("test_socks_direct_conns_bypass_the_server timed out");
+}
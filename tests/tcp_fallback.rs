@@ -0,0 +1,149 @@
+//! The TCP fallback transport: QUIC carried over TLS on the server's
+//! `tcp_port`, for networks that drop UDP.
+
+mod common;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use common::{
+    client_config, get_available_port, init_crypto, server_config, spawn_tcp_echo, STARTUP_DELAY,
+    TEST_TIMEOUT,
+};
+use rusnel::common::remote::RemoteRequest;
+use rusnel::{ServerConfig, TcpFallback};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// A server also listening on a TCP port. Returns the TCP port and the
+/// server task.
+async fn start_server(mut sc: ServerConfig) -> (u16, JoinHandle<()>) {
+    init_crypto();
+    let tcp_port = get_available_port();
+    sc.tcp_port = Some(tcp_port);
+    let server = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+    (tcp_port, server)
+}
+
+/// A client reaching `udp_port` with `fallback`, forwarding a local port
+/// to an echo server. Returns the local port and the client task.
+async fn start_client(udp_port: u16, fallback: TcpFallback) -> (u16, JoinHandle<()>) {
+    let echo_port = spawn_tcp_echo().await.port();
+    let local_port = get_available_port();
+    let spec = format!("127.0.0.1:{local_port}:127.0.0.1:{echo_port}");
+    let mut cc = client_config(udp_port, vec![RemoteRequest::from_str(&spec).unwrap()]);
+    cc.tcp_fallback = Some(fallback);
+    let client = tokio::spawn(async move {
+        let _ = rusnel::client::run_async(cc).await;
+    });
+    (local_port, client)
+}
+
+/// Connect to the forward listener until the client has a session.
+async fn connect(local_port: u16) -> TcpStream {
+    loop {
+        if let Ok(conn) = TcpStream::connect(("127.0.0.1", local_port)).await {
+            return conn;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn echo(conn: &mut TcpStream, data: &[u8]) {
+    conn.write_all(data).await.unwrap();
+    let mut got = vec![0u8; data.len()];
+    conn.read_exact(&mut got).await.unwrap();
+    assert_eq!(got, data);
+}
+
+/// `only` goes straight over TCP, and tunnels work as they do over UDP.
+#[tokio::test]
+async fn test_tcp_only() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let (tcp_port, server) = start_server(server_config(server_port, false)).await;
+        let fallback = TcpFallback {
+            port: tcp_port,
+            only: true,
+        };
+        let (local_port, client) = start_client(server_port, fallback).await;
+
+        let mut conn = connect(local_port).await;
+        echo(&mut conn, &vec![7u8; 1 << 20]).await;
+
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_tcp_only timed out");
+}
+
+/// With UDP to the server going nowhere, the client falls back to TCP.
+#[tokio::test]
+async fn test_falls_back_when_udp_is_dropped() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let (tcp_port, server) = start_server(server_config(server_port, false)).await;
+        // Swallows the client's UDP packets without answering.
+        let sink = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sink_port = sink.local_addr().unwrap().port();
+        let fallback = TcpFallback {
+            port: tcp_port,
+            only: false,
+        };
+        let (local_port, client) = start_client(sink_port, fallback).await;
+
+        let mut conn = connect(local_port).await;
+        echo(&mut conn, b"over tcp").await;
+
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_falls_back_when_udp_is_dropped timed out");
+}
+
+/// A client over TCP shows up in the admin API like any other.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_admin_lists_tcp_clients() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let socket_path = std::env::temp_dir().join(format!(
+            "rusnel-it-tcp-fallback-{}-{server_port}.sock",
+            std::process::id()
+        ));
+        let mut sc = server_config(server_port, false);
+        sc.admin_socket = Some(socket_path.clone());
+        let (tcp_port, server) = start_server(sc).await;
+        let fallback = TcpFallback {
+            port: tcp_port,
+            only: true,
+        };
+        let (local_port, client) = start_client(server_port, fallback).await;
+
+        let mut conn = connect(local_port).await;
+        echo(&mut conn, b"hello").await;
+        let clients = rusnel::ctl::get(&socket_path, "/api/v1/clients")
+            .await
+            .unwrap();
+        let clients = clients.as_array().unwrap();
+        assert_eq!(clients.len(), 1);
+        assert!(clients[0]["remote"].as_str().unwrap().contains("127.0.0.1"));
+        let tunnels = rusnel::ctl::get(&socket_path, "/api/v1/tunnels")
+            .await
+            .unwrap();
+        assert_eq!(tunnels.as_array().unwrap().len(), 1);
+
+        client.abort();
+        server.abort();
+        let _ = std::fs::remove_file(&socket_path);
+    })
+    .await
+    .expect("test_admin_lists_tcp_clients timed out");
+}
@@ -0,0 +1,320 @@
+//! Per-tunnel results of the session hello.
+//!
+//! The server answers each declared remote on its own — accepted with
+//! an id, rejected by policy, or failed to bind — so one bad tunnel no
+//! longer costs the client the rest. Most of these speak the hello over
+//! a bare QUIC connection so they can look at the statuses directly.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use common::{
+    get_available_port, init_crypto, server_config, start_tunnel, STARTUP_DELAY, TEST_TIMEOUT,
+};
+use quinn::{Connection, Endpoint, RecvStream, SendStream, VarInt};
+use rusnel::common::quic::{create_client_endpoint, new_session_cache, Congestion};
+use rusnel::common::remote::{
+    RemoteRequest, SessionHello, TunnelControl, TunnelControlResponse, TunnelStatus,
+};
+use rusnel::common::tls::ClientTlsConfig;
+use rusnel::common::tunnel::{client_send_session_hello, client_send_tunnel_control};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+async fn connect(allow_reverse: bool) -> (JoinHandle<()>, Endpoint, Connection) {
+    init_crypto();
+    let server_port = get_available_port();
+    let sc = server_config(server_port, allow_reverse);
+    let server = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+    let endpoint = create_client_endpoint(
+        &ClientTlsConfig::Insecure,
+        Congestion::Cubic,
+        server_addr,
+        &new_session_cache(),
+    )
+    .unwrap();
+    let connection = endpoint
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    (server, endpoint, connection)
+}
+
+async fn send_hello(
+    connection: &Connection,
+    hello: &SessionHello,
+) -> (anyhow::Result<Vec<TunnelStatus>>, SendStream, RecvStream) {
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    let result = client_send_session_hello(hello, &mut send, &mut recv)
+        .await
+        .map(|(tunnels, _, _)| tunnels);
+    (result, send, recv)
+}
+
+fn remote(spec: &str) -> RemoteRequest {
+    RemoteRequest::from_str(spec).unwrap()
+}
+
+#[tokio::test]
+async fn test_policy_rejection_is_per_tunnel() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect(false).await;
+
+        let port = get_available_port();
+        let hello = SessionHello::new(vec![
+            remote(&format!("127.0.0.1:{port}:127.0.0.1:{port}")),
+            remote(&format!("R:127.0.0.1:{port}:127.0.0.1:{port}")),
+        ]);
+        let (tunnels, _send, _recv) = send_hello(&connection, &hello).await;
+        let tunnels = tunnels.unwrap();
+        assert_eq!(tunnels.len(), 2);
+        assert!(matches!(tunnels[0], TunnelStatus::Accepted { .. }));
+        match &tunnels[1] {
+            TunnelStatus::Rejected(reason) => assert!(
+                reason.contains("Reverse remotes are not allowed"),
+                "unexpected reason: {reason}"
+            ),
+            other => panic!("expected Rejected, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_policy_rejection_is_per_tunnel timed out");
+}
+
+#[tokio::test]
+async fn test_reverse_bind_failure_is_reported() {
+    timeout(TEST_TIMEOUT, async {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let free_port = get_available_port();
+        let (server, endpoint, connection) = connect(true).await;
+
+        let hello = SessionHello::new(vec![
+            remote(&format!("R:127.0.0.1:{taken_port}:127.0.0.1:22")),
+            remote(&format!("R:127.0.0.1:{free_port}:127.0.0.1:22")),
+        ]);
+        let (tunnels, _send, _recv) = send_hello(&connection, &hello).await;
+        let tunnels = tunnels.unwrap();
+        match &tunnels[0] {
+            TunnelStatus::BindFailed(reason) => assert!(
+                reason.contains(&format!("failed to bind 127.0.0.1:{taken_port}")),
+                "unexpected reason: {reason}"
+            ),
+            other => panic!("expected BindFailed, got {other:?}"),
+        }
+        // The listener is up by the time the hello is answered.
+        assert!(matches!(tunnels[1], TunnelStatus::Accepted { .. }));
+        TcpStream::connect(("127.0.0.1", free_port)).await.unwrap();
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_reverse_bind_failure_is_reported timed out");
+}
+
+#[tokio::test]
+async fn test_strict_hello_fails_on_any_rejection() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect(false).await;
+
+        let port = get_available_port();
+        let hello = SessionHello {
+            strict: true,
+            ..SessionHello::new(vec![
+                remote(&format!("127.0.0.1:{port}:127.0.0.1:{port}")),
+                remote(&format!("R:127.0.0.1:{port}:127.0.0.1:{port}")),
+            ])
+        };
+        let (tunnels, _send, _recv) = send_hello(&connection, &hello).await;
+        let err = tunnels.unwrap_err().to_string();
+        assert!(
+            err.contains("Reverse remotes are not allowed"),
+            "unexpected error: {err}"
+        );
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_strict_hello_fails_on_any_rejection timed out");
+}
+
+#[tokio::test]
+async fn test_live_add_on_taken_port_fails() {
+    timeout(TEST_TIMEOUT, async {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let (server, endpoint, connection) = connect(true).await;
+
+        let (tunnels, mut send, mut recv) =
+            send_hello(&connection, &SessionHello::new(vec![])).await;
+        assert!(tunnels.unwrap().is_empty());
+
+        let add =
+            TunnelControl::AddTunnel(remote(&format!("R:127.0.0.1:{taken_port}:127.0.0.1:22")));
+        match client_send_tunnel_control(&add, &mut send, &mut recv)
+            .await
+            .unwrap()
+        {
+            TunnelControlResponse::Failed(reason) => assert!(
+                reason.contains("failed to bind"),
+                "unexpected reason: {reason}"
+            ),
+            other => panic!("expected Failed, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_live_add_on_taken_port_fails timed out");
+}
+
+/// The server checks tunnel names itself: a bad name or one the client
+/// already uses is rejected, in the hello and in live adds alike.
+#[tokio::test]
+async fn test_server_rejects_bad_and_reused_names() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect(false).await;
+
+        let named = |name: &str| {
+            let mut r = remote(&format!("127.0.0.1:{}:127.0.0.1:22", get_available_port()));
+            r.name = Some(name.into());
+            r
+        };
+        let hello = SessionHello::new(vec![named("web"), named("web"), named("1234")]);
+        let (tunnels, mut send, mut recv) = send_hello(&connection, &hello).await;
+        let tunnels = tunnels.unwrap();
+        assert!(matches!(tunnels[0], TunnelStatus::Accepted { .. }));
+        for (status, expected) in [(&tunnels[1], "already in use"), (&tunnels[2], "all digits")] {
+            match status {
+                TunnelStatus::Rejected(reason) => {
+                    assert!(reason.contains(expected), "unexpected reason: {reason}")
+                }
+                other => panic!("expected Rejected, got {other:?}"),
+            }
+        }
+
+        let add = TunnelControl::AddTunnel(named("web"));
+        match client_send_tunnel_control(&add, &mut send, &mut recv)
+            .await
+            .unwrap()
+        {
+            TunnelControlResponse::Failed(reason) => assert!(
+                reason.contains("already in use"),
+                "unexpected reason: {reason}"
+            ),
+            other => panic!("expected Failed, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_server_rejects_bad_and_reused_names timed out");
+}
+
+/// `R:0` leaves the port to the server, which reports where it bound.
+#[tokio::test]
+async fn test_reverse_port_zero_reports_bound_addr() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect(true).await;
+
+        let (tunnels, mut send, mut recv) = send_hello(
+            &connection,
+            &SessionHello::new(vec![remote("R:127.0.0.1:0:127.0.0.1:22")]),
+        )
+        .await;
+        let TunnelStatus::Accepted {
+            bound: Some(bound), ..
+        } = tunnels.unwrap()[0]
+        else {
+            panic!("expected an accepted tunnel with a bound address");
+        };
+        assert_ne!(bound.port(), 0);
+        TcpStream::connect(bound).await.unwrap();
+
+        // Live adds report theirs the same way.
+        let add = TunnelControl::AddTunnel(remote("R:127.0.0.1:0:socks"));
+        match client_send_tunnel_control(&add, &mut send, &mut recv)
+            .await
+            .unwrap()
+        {
+            TunnelControlResponse::Added {
+                bound: Some(added), ..
+            } => {
+                assert_ne!(added.port(), 0);
+                assert_ne!(added, bound);
+            }
+            other => panic!("expected Added with a bound address, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_reverse_port_zero_reports_bound_addr timed out");
+}
+
+/// A real client runs the tunnels the server accepted and skips the
+/// one it refused.
+#[tokio::test]
+async fn test_client_keeps_accepted_tunnels() {
+    timeout(TEST_TIMEOUT, async {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_port = target.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut sock, _) = target.accept().await.unwrap();
+            let (mut r, mut w) = sock.split();
+            let _ = tokio::io::copy(&mut r, &mut w).await;
+        });
+        let server_port = get_available_port();
+        let forward_port = get_available_port();
+        let reverse_port = get_available_port();
+
+        let _env = start_tunnel(
+            server_port,
+            false,
+            vec![
+                remote(&format!(
+                    "R:127.0.0.1:{reverse_port}:127.0.0.1:{target_port}"
+                )),
+                remote(&format!("127.0.0.1:{forward_port}:127.0.0.1:{target_port}")),
+            ],
+        )
+        .await;
+
+        let mut conn = TcpStream::connect(("127.0.0.1", forward_port))
+            .await
+            .unwrap();
+        conn.write_all(b"still here").await.unwrap();
+        let mut buf = [0u8; 10];
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"still here");
+        assert!(TcpStream::connect(("127.0.0.1", reverse_port))
+            .await
+            .is_err());
+    })
+    .await
+    .expect("test_client_keeps_accepted_tunnels timed out");
+}
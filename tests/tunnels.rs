@@ -1,7 +1,7 @@
 //! Basic per-tunnel-type smoke tests.
 //!
 //! These cover the happy path for each tunnel mode (TCP/UDP, forward/reverse,
-//! SOCKS5) and a multi-remote configuration. More involved scenarios live in
+//! SOCKS4/4a/5) and a multi-remote configuration. More involved scenarios live in
 //! sibling test files (see `large_transfer`, `concurrent`, `combinations`,
 //! `edge_cases`).
 
@@ -11,8 +11,9 @@ use std::net::SocketAddrV4;
 use std::str::FromStr;
 
 use common::{
-    get_available_port, get_available_udp_port, socks5_connect_ipv4, socks5_udp_associate,
-    socks5_udp_unwrap_ipv4, socks5_udp_wrap_ipv4, start_tunnel, TEST_TIMEOUT,
+    get_available_port, get_available_udp_port, socks4_connect, socks5_bind, socks5_connect_ipv4,
+    socks5_read_reply_ipv4, socks5_udp_associate, socks5_udp_unwrap_ipv4, socks5_udp_wrap_ipv4,
+    start_tunnel, TEST_TIMEOUT,
 };
 use rusnel::common::remote::RemoteRequest;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -233,6 +234,182 @@ async fn test_socks5_forward() {
     .expect("test_socks5_forward timed out");
 }
 
+#[tokio::test]
+async fn test_socks4_forward() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let target_port = get_available_port();
+
+        let target_listener = TcpListener::bind(format!("127.0.0.1:{target_port}"))
+            .await
+            .unwrap();
+
+        let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
+
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let mut socks_conn = socks4_connect(
+            &format!("127.0.0.1:{socks_port}"),
+            [127, 0, 0, 1],
+            target_port,
+            None,
+        )
+        .await;
+
+        let test_data = b"hello through socks4 proxy";
+        socks_conn.write_all(test_data).await.unwrap();
+        socks_conn.shutdown().await.unwrap();
+
+        let (mut target_stream, _) = target_listener.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let n = target_stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], test_data);
+    })
+    .await
+    .expect("test_socks4_forward timed out");
+}
+
+#[tokio::test]
+async fn test_socks4a_reverse() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let target_port = get_available_port();
+
+        let target_listener = TcpListener::bind(format!("127.0.0.1:{target_port}"))
+            .await
+            .unwrap();
+
+        let remote = RemoteRequest::from_str(&format!("R:127.0.0.1:{socks_port}:socks")).unwrap();
+
+        let _env = start_tunnel(server_port, true, vec![remote]).await;
+
+        // The hostname travels unresolved; the client resolves it.
+        let mut socks_conn = socks4_connect(
+            &format!("127.0.0.1:{socks_port}"),
+            [0, 0, 0, 0],
+            target_port,
+            Some("localhost"),
+        )
+        .await;
+
+        let test_data = b"hello through socks4a proxy";
+        socks_conn.write_all(test_data).await.unwrap();
+        socks_conn.shutdown().await.unwrap();
+
+        let (mut target_stream, _) = target_listener.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let n = target_stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], test_data);
+    })
+    .await
+    .expect("test_socks4a_reverse timed out");
+}
+
+/// BIND through a `socks` remote (`R:socks` if `reverse`): the far side
+/// listens, and the conn it accepts is spliced back to the SOCKS client.
+async fn check_socks5_bind(reverse: bool) {
+    let server_port = get_available_port();
+    let socks_port = get_available_port();
+    let prefix = if reverse { "R:" } else { "" };
+    let remote = RemoteRequest::from_str(&format!("{prefix}127.0.0.1:{socks_port}:socks")).unwrap();
+
+    let _env = start_tunnel(server_port, reverse, vec![remote]).await;
+
+    let (mut socks_conn, bound) =
+        socks5_bind(&format!("127.0.0.1:{socks_port}"), [127, 0, 0, 1]).await;
+    assert!(bound.ip().is_loopback(), "listens toward the expected peer");
+
+    let mut inbound = TcpStream::connect(bound).await.unwrap();
+    let accepted = socks5_read_reply_ipv4(&mut socks_conn).await;
+    assert_eq!(accepted, inbound.local_addr().unwrap());
+
+    inbound.write_all(b"active-mode data").await.unwrap();
+    let mut buf = [0u8; 16];
+    socks_conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"active-mode data");
+
+    socks_conn.write_all(b"and back").await.unwrap();
+    let mut buf = [0u8; 8];
+    inbound.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"and back");
+
+    // One accept only: the listener is gone.
+    assert!(TcpStream::connect(bound).await.is_err());
+}
+
+#[tokio::test]
+async fn test_socks5_bind_forward() {
+    timeout(TEST_TIMEOUT, check_socks5_bind(false))
+        .await
+        .expect("test_socks5_bind_forward timed out");
+}
+
+#[tokio::test]
+async fn test_socks5_bind_reverse() {
+    timeout(TEST_TIMEOUT, check_socks5_bind(true))
+        .await
+        .expect("test_socks5_bind_reverse timed out");
+}
+
+/// A BIND for any host (DST.ADDR 0.0.0.0) listens on every address but
+/// reports one the connecting host can use, never the wildcard.
+#[tokio::test]
+async fn test_socks5_bind_any_host_reports_routable_addr() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let (mut socks_conn, bound) =
+            socks5_bind(&format!("127.0.0.1:{socks_port}"), [0, 0, 0, 0]).await;
+        assert!(
+            !bound.ip().is_unspecified(),
+            "BND.ADDR {bound} is the wildcard"
+        );
+
+        let inbound = TcpStream::connect(bound).await.unwrap();
+        let accepted = socks5_read_reply_ipv4(&mut socks_conn).await;
+        assert_eq!(accepted, inbound.local_addr().unwrap());
+    })
+    .await
+    .expect("test_socks5_bind_any_host_reports_routable_addr timed out");
+}
+
+/// A BIND whose DST.ADDR doesn't resolve is refused with "host
+/// unreachable" instead of listening for any host.
+#[tokio::test]
+async fn test_socks5_bind_unresolvable_peer_refused() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let mut conn = TcpStream::connect(format!("127.0.0.1:{socks_port}"))
+            .await
+            .unwrap();
+        conn.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut greet_resp = [0u8; 2];
+        conn.read_exact(&mut greet_resp).await.unwrap();
+        assert_eq!(greet_resp, [0x05, 0x00]);
+
+        let host = b"peer.invalid";
+        let mut req = vec![0x05, 0x02, 0x00, 0x03, host.len() as u8];
+        req.extend_from_slice(host);
+        req.extend_from_slice(&[0, 21]);
+        conn.write_all(&req).await.unwrap();
+
+        let mut reply = [0u8; 2];
+        conn.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0x05, 0x04], "expected host unreachable");
+    })
+    .await
+    .expect("test_socks5_bind_unresolvable_peer_refused timed out");
+}
+
 #[tokio::test]
 async fn test_socks5_udp_associate_forward() {
     timeout(TEST_TIMEOUT, async {
@@ -281,6 +458,51 @@ async fn test_socks5_udp_associate_forward() {
     .expect("test_socks5_udp_associate_forward timed out");
 }
 
+#[tokio::test]
+async fn test_socks5_udp_associate_fragments() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let socks_port = get_available_port();
+        let target_port = get_available_udp_port();
+
+        let target_socket = UdpSocket::bind(format!("127.0.0.1:{target_port}"))
+            .await
+            .unwrap();
+        let target_handle = tokio::spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            let (n, peer) = target_socket.recv_from(&mut buf).await.unwrap();
+            target_socket.send_to(&buf[..n], peer).await.unwrap();
+        });
+
+        let remote = RemoteRequest::from_str(&format!("127.0.0.1:{socks_port}:socks")).unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let (_ctrl, relay_addr) = socks5_udp_associate(&format!("127.0.0.1:{socks_port}")).await;
+
+        // One datagram in three fragments (FRAG 1, 2, then 3 with the
+        // end-of-sequence bit); the target sees it whole.
+        let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = SocketAddrV4::new([127, 0, 0, 1].into(), target_port);
+        for (frag, part) in [(0x01, "reassembled "), (0x02, "by the "), (0x83, "relay")] {
+            let mut wire = socks5_udp_wrap_ipv4(target, part.as_bytes());
+            wire[2] = frag;
+            local.send_to(&wire, relay_addr).await.unwrap();
+        }
+
+        let mut buf = vec![0u8; 1500];
+        let (n, _) = tokio::time::timeout(TEST_TIMEOUT, local.recv_from(&mut buf))
+            .await
+            .expect("did not receive SOCKS5 UDP reply")
+            .unwrap();
+        let (_, _, body) = socks5_udp_unwrap_ipv4(&buf[..n]);
+        assert_eq!(body, b"reassembled by the relay");
+
+        target_handle.await.unwrap();
+    })
+    .await
+    .expect("test_socks5_udp_associate_fragments timed out");
+}
+
 #[tokio::test]
 async fn test_socks5_udp_associate_reverse() {
     timeout(TEST_TIMEOUT, async {
@@ -2,13 +2,16 @@
 //! happy path doesn't exercise.
 //!
 //! Forward UDP multiplexes by source `SocketAddr` onto per-source QUIC
-//! bi-streams (see `src/common/udp.rs::tunnel_udp_client`). The
-//! interesting behaviours that need pinning down are:
+//! conns (see `src/common/udp.rs::tunnel_udp_client`), carried as QUIC
+//! DATAGRAM frames with a stream-framing fallback for payloads too big
+//! for one. The interesting behaviours that need pinning down are:
 //!
 //!   * Replies from the target are routed back to the *correct* origin
 //!     sender, not broadcast or aliased.
 //!   * Large datagrams (close to the 65 535-byte u16 length-prefix limit)
 //!     traverse the length-framing intact.
+//!   * Small and large datagrams interleaved on one conn both arrive,
+//!     whichever path (DATAGRAM frame or stream) each one took.
 //!   * The per-source state machine survives a target that simply never
 //!     replies — an idle source must not poison the next datagram.
 
@@ -198,3 +201,63 @@ async fn test_udp_forward_silent_target_doesnt_wedge_tunnel() {
     .await
     .expect("test_udp_forward_silent_target_doesnt_wedge_tunnel timed out");
 }
+
+/// Small datagrams travel as QUIC DATAGRAM frames and oversize ones fall
+/// back to the conn's stream framing; both must reach the target and
+/// come back on the same per-source conn. Ordering across the two paths
+/// isn't guaranteed, so compare as sets.
+#[tokio::test]
+async fn test_udp_forward_mixed_datagram_and_stream_sizes() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let local_port = get_available_udp_port();
+        let remote_port = get_available_udp_port();
+
+        let target = UdpSocket::bind(format!("127.0.0.1:{remote_port}"))
+            .await
+            .unwrap();
+        let target_handle = tokio::spawn(async move {
+            let mut buf = vec![0u8; 16_384];
+            loop {
+                match target.recv_from(&mut buf).await {
+                    Ok((n, peer)) => {
+                        let _ = target.send_to(&buf[..n], peer).await;
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let remote = RemoteRequest::from_str(&format!(
+            "127.0.0.1:{local_port}:127.0.0.1:{remote_port}/udp"
+        ))
+        .unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tunnel = format!("127.0.0.1:{local_port}");
+        let small = b"small".to_vec();
+        let large: Vec<u8> = (0..6_000u32).map(|i| (i % 239) as u8).collect();
+        let mut expected = vec![small.clone(), large.clone()];
+        expected.sort();
+
+        sender.send_to(&small, &tunnel).await.unwrap();
+        sender.send_to(&large, &tunnel).await.unwrap();
+
+        let mut got = Vec::new();
+        let mut buf = vec![0u8; 16_384];
+        for _ in 0..2 {
+            let n = timeout(Duration::from_secs(5), sender.recv(&mut buf))
+                .await
+                .expect("datagram never echoed back")
+                .unwrap();
+            got.push(buf[..n].to_vec());
+        }
+        got.sort();
+        assert_eq!(got, expected, "mixed-size echoes mismatched");
+
+        target_handle.abort();
+    })
+    .await
+    .expect("test_udp_forward_mixed_datagram_and_stream_sizes timed out");
+}
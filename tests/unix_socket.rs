@@ -0,0 +1,145 @@
+//! Integration tests for unix socket remotes: a forward unix-to-unix
+//! tunnel keeps half-close semantics, a reverse tunnel reaches a unix
+//! socket on the client host, and a server without `allow_unix` refuses
+//! remotes that would touch its own sockets.
+#![cfg(unix)]
+
+mod common;
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rusnel::common::remote::RemoteRequest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixListener, UnixStream};
+use tokio::time::{sleep, timeout};
+
+use common::{
+    client_config, get_available_port, init_crypto, server_config, start_tunnel, STARTUP_DELAY,
+    TEST_TIMEOUT,
+};
+
+/// A socket path unique to this test run, short enough for `sun_path`.
+fn socket_path(name: &str) -> PathBuf {
+    let path = PathBuf::from(format!("/tmp/rusnel-{}-{name}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Echo everything back on a unix socket, then close once the peer
+/// finishes writing.
+fn spawn_unix_echo(path: &PathBuf) {
+    let listener = UnixListener::bind(path).unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = conn.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+                let _ = w.shutdown().await;
+            });
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_unix_forward_half_close() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let local = socket_path("fwd-local");
+        let upstream = socket_path("fwd-upstream");
+        spawn_unix_echo(&upstream);
+
+        let remote = RemoteRequest::from_str(&format!(
+            "unix:{}:unix:{}",
+            local.display(),
+            upstream.display()
+        ))
+        .unwrap();
+        let _env = start_tunnel(server_port, false, vec![remote]).await;
+
+        let mut conn = UnixStream::connect(&local).await.unwrap();
+        conn.write_all(b"ping over unix").await.unwrap();
+        // Our write-half close must reach the echo server, whose own
+        // close then ends our read.
+        conn.shutdown().await.unwrap();
+        let mut echoed = Vec::new();
+        conn.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, b"ping over unix");
+
+        let _ = std::fs::remove_file(&local);
+        let _ = std::fs::remove_file(&upstream);
+    })
+    .await
+    .expect("test_unix_forward_half_close timed out");
+}
+
+#[tokio::test]
+async fn test_unix_reverse_to_client_socket() {
+    timeout(TEST_TIMEOUT, async {
+        let server_port = get_available_port();
+        let listen_port = get_available_port();
+        let upstream = socket_path("rev-upstream");
+        spawn_unix_echo(&upstream);
+
+        let remote = RemoteRequest::from_str(&format!(
+            "R:127.0.0.1:{listen_port}:unix:{}",
+            upstream.display()
+        ))
+        .unwrap();
+        let _env = start_tunnel(server_port, true, vec![remote]).await;
+
+        let mut conn = TcpStream::connect(("127.0.0.1", listen_port))
+            .await
+            .unwrap();
+        conn.write_all(b"reverse").await.unwrap();
+        let mut buf = [0u8; 7];
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"reverse");
+
+        let _ = std::fs::remove_file(&upstream);
+    })
+    .await
+    .expect("test_unix_reverse_to_client_socket timed out");
+}
+
+/// A server without `allow_unix` rejects the tunnel, so the client never
+/// binds its local socket.
+#[tokio::test]
+async fn test_unix_rejected_when_not_allowed() {
+    timeout(TEST_TIMEOUT, async {
+        init_crypto();
+        let server_port = get_available_port();
+        let local = socket_path("deny-local");
+        let upstream = socket_path("deny-upstream");
+
+        let mut sc = server_config(server_port, false);
+        sc.allow_unix = false;
+        let server = tokio::spawn(async move {
+            let _ = rusnel::server::run_async(sc).await;
+        });
+        sleep(STARTUP_DELAY).await;
+
+        let remote = RemoteRequest::from_str(&format!(
+            "unix:{}:unix:{}",
+            local.display(),
+            upstream.display()
+        ))
+        .unwrap();
+        let cc = client_config(server_port, vec![remote]);
+        let client = tokio::spawn(async move {
+            let _ = rusnel::client::run_async(cc).await;
+        });
+        sleep(STARTUP_DELAY + Duration::from_millis(300)).await;
+
+        assert!(
+            UnixStream::connect(&local).await.is_err(),
+            "expected {} to never be bound",
+            local.display()
+        );
+        client.abort();
+        server.abort();
+    })
+    .await
+    .expect("test_unix_rejected_when_not_allowed timed out");
+}
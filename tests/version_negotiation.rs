@@ -0,0 +1,146 @@
+//! Protocol version and capability exchange in the session hello.
+//!
+//! These talk to a real server over a bare QUIC connection so they can
+//! send hellos no real client of this build would: one from a future
+//! protocol version, and one shaped like a pre-negotiation client's,
+//! with no version field at all.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use common::{get_available_port, init_crypto, server_config, STARTUP_DELAY, TEST_TIMEOUT};
+use quinn::{Connection, Endpoint, VarInt};
+use rusnel::common::quic::{create_client_endpoint, new_session_cache, Congestion};
+use rusnel::common::remote::{
+    PeerInfo, RemoteRequest, SessionHello, SessionHelloResponse, PROTOCOL_VERSION,
+};
+use rusnel::common::tls::ClientTlsConfig;
+use rusnel::common::tunnel::client_send_session_hello;
+use rusnel::common::utils::SerdeHelper;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+async fn connect() -> (JoinHandle<()>, Endpoint, Connection) {
+    init_crypto();
+    let server_port = get_available_port();
+    let sc = server_config(server_port, false);
+    let server = tokio::spawn(async move {
+        let _ = rusnel::server::run_async(sc).await;
+    });
+    tokio::time::sleep(STARTUP_DELAY).await;
+
+    let server_addr: SocketAddr = (IpAddr::V4(Ipv4Addr::LOCALHOST), server_port).into();
+    let endpoint = create_client_endpoint(
+        &ClientTlsConfig::Insecure,
+        Congestion::Cubic,
+        server_addr,
+        &new_session_cache(),
+    )
+    .unwrap();
+    let connection = endpoint
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    (server, endpoint, connection)
+}
+
+fn remotes() -> Vec<RemoteRequest> {
+    let port = get_available_port();
+    vec![RemoteRequest::from_str(&format!("127.0.0.1:{port}:127.0.0.1:{port}")).unwrap()]
+}
+
+#[tokio::test]
+async fn test_hello_exchanges_versions_and_capabilities() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect().await;
+
+        // Advertise one capability the server knows and one it doesn't;
+        // the unknown one must be ignored, not fail the hello.
+        let mut hello = SessionHello::new(remotes());
+        hello.peer.capabilities = vec!["tunnel-control".into(), "from-the-future".into()];
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        let (ids, server_peer, _) = client_send_session_hello(&hello, &mut send, &mut recv)
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(server_peer, PeerInfo::local());
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_hello_exchanges_versions_and_capabilities timed out");
+}
+
+#[tokio::test]
+async fn test_newer_protocol_version_rejected() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect().await;
+
+        let mut hello = SessionHello::new(remotes());
+        hello.peer.protocol_version = PROTOCOL_VERSION + 1;
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        let err = client_send_session_hello(&hello, &mut send, &mut recv)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(&format!(
+                "incompatible protocol version {}",
+                PROTOCOL_VERSION + 1
+            )),
+            "unexpected rejection: {err}"
+        );
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_newer_protocol_version_rejected timed out");
+}
+
+/// The hello of a client that predates negotiation: just the remotes.
+#[derive(Serialize)]
+struct LegacySessionHello {
+    remotes: Vec<RemoteRequest>,
+}
+
+/// A pre-negotiation client gets a `Failed` it can still decode, naming
+/// the version problem, instead of a MessagePack error or a hang-up.
+#[tokio::test]
+async fn test_pre_negotiation_client_gets_readable_rejection() {
+    timeout(TEST_TIMEOUT, async {
+        let (server, endpoint, connection) = connect().await;
+
+        let body = rmp_serde::to_vec(&LegacySessionHello { remotes: remotes() }).unwrap();
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(&(body.len() as u32).to_le_bytes())
+            .await
+            .unwrap();
+        send.write_all(&body).await.unwrap();
+
+        let mut len = [0u8; 4];
+        recv.read_exact(&mut len).await.unwrap();
+        let mut reply = vec![0u8; u32::from_le_bytes(len) as usize];
+        recv.read_exact(&mut reply).await.unwrap();
+        match SessionHelloResponse::from_bytes(reply).unwrap() {
+            SessionHelloResponse::Failed(reason) => assert!(
+                reason.contains("incompatible protocol version 0"),
+                "unexpected rejection: {reason}"
+            ),
+            other => panic!("expected Failed, got {other:?}"),
+        }
+
+        connection.close(VarInt::from_u32(0), b"test done");
+        endpoint.wait_idle().await;
+        server.abort();
+    })
+    .await
+    .expect("test_pre_negotiation_client_gets_readable_rejection timed out");
+}